@@ -0,0 +1,16 @@
+//! Checksum files as downloaded from the remote, which may be truncated,
+//! damaged or written by another version
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use syncbox::{checksum_tree::ChecksumTree, reconciler::Reconciler};
+
+fuzz_target!(|bytes: &[u8]| {
+    let Ok(prev) = ChecksumTree::from_compressed(bytes) else {
+        return;
+    };
+    // whatever loads has to survive being written back and reconciled
+    ChecksumTree::from_compressed(&prev.to_compressed(Default::default()).unwrap()).unwrap();
+    let next = ChecksumTree::with_version(prev.get_version());
+    Reconciler::reconcile(prev, &next).unwrap();
+});
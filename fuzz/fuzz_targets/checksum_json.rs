@@ -0,0 +1,20 @@
+//! The JSON inside checksum files, gzipped here so the fuzzer spends its time
+//! on the parser and on recovering cut off files rather than on gzip headers
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Write;
+use syncbox::{checksum_tree::ChecksumTree, reconciler::Reconciler};
+
+fuzz_target!(|json: &[u8]| {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(json).unwrap();
+    let bytes = encoder.finish().unwrap();
+    // the whole file, and one that ends before the gzip trailer
+    for bytes in [&bytes[..], &bytes[..bytes.len() - 8]] {
+        if let Ok(prev) = ChecksumTree::from_compressed(bytes) {
+            let next = ChecksumTree::with_version(prev.get_version());
+            Reconciler::reconcile(prev, &next).unwrap();
+        }
+    }
+});
@@ -0,0 +1,227 @@
+use crate::reconciler::Action;
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+/// What an action filter does with a planned action, see
+/// `SyncPlan::filter_actions`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ActionDecision {
+    Keep,
+    /// Left out of the plan, the remote keeps what it has there
+    Drop,
+    /// Replaced with another action
+    Rewrite(Action),
+    /// Moved after every action that isn't deferred
+    Defer,
+}
+
+/// Decides on each action in order, deferred ones go last in the order they
+/// came
+pub fn filter(
+    actions: Vec<Action>,
+    mut decide: impl FnMut(&Action) -> ActionDecision,
+) -> Vec<Action> {
+    let mut kept = Vec::with_capacity(actions.len());
+    let mut deferred = vec![];
+    for action in actions {
+        match decide(&action) {
+            ActionDecision::Keep => kept.push(action),
+            ActionDecision::Drop => {}
+            ActionDecision::Rewrite(other) => kept.push(other),
+            ActionDecision::Defer => deferred.push(action),
+        }
+    }
+    kept.extend(deferred);
+    kept
+}
+
+/// A `--filter-cmd`, run once by the shell and asked about every action in
+/// turn: it reads a line like `remove ./a.txt` and answers with a line of
+/// `keep`, `drop`, `defer` or `conflict`, which reports the action and leaves
+/// its path alone. An answer must come before the next line is read
+pub struct FilterCommand {
+    command: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    error: Option<Box<dyn Error + Send + Sync + 'static>>,
+}
+
+impl FilterCommand {
+    pub fn spawn(command: &str) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let mut child = shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not run --filter-cmd {command:?}: {e}"))?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Self {
+            command: command.to_string(),
+            child,
+            stdin,
+            stdout,
+            error: None,
+        })
+    }
+
+    /// Keeps everything after the first failure, which `finish` reports
+    pub fn decide(&mut self, action: &Action) -> ActionDecision {
+        if self.error.is_some() {
+            return ActionDecision::Keep;
+        }
+        match self.ask(action) {
+            Ok(decision) => decision,
+            Err(e) => {
+                self.error = Some(e);
+                ActionDecision::Keep
+            }
+        }
+    }
+
+    fn ask(
+        &mut self,
+        action: &Action,
+    ) -> Result<ActionDecision, Box<dyn Error + Send + Sync + 'static>> {
+        let path = action.path().to_string_lossy();
+        if path.contains('\n') {
+            return Err(
+                format!("Can't pass {path:?} to --filter-cmd, it contains a newline").into(),
+            );
+        }
+        let stdin = self.stdin.as_mut().expect("stdin is open until finished");
+        writeln!(stdin, "{} {path}", action.kind())
+            .and_then(|_| stdin.flush())
+            .map_err(|e| format!("--filter-cmd {:?} stopped reading: {e}", self.command))?;
+        let mut answer = String::new();
+        if self.stdout.read_line(&mut answer)? == 0 {
+            return Err(format!(
+                "--filter-cmd {:?} exited without answering for {path:?}",
+                self.command
+            )
+            .into());
+        }
+        decision(answer.trim(), action).ok_or_else(|| {
+            format!(
+                "--filter-cmd {:?} answered {:?} for {path:?}, expected keep, drop, defer or conflict",
+                self.command,
+                answer.trim()
+            )
+            .into()
+        })
+    }
+
+    /// Closes its input and waits for it to exit, an error if it failed
+    /// anywhere, in which case the plan it filtered is not to be executed
+    pub fn finish(mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        drop(self.stdin.take());
+        if let Some(e) = self.error.take() {
+            self.child.kill().ok();
+            self.child.wait().ok();
+            return Err(e);
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(format!("--filter-cmd {:?} failed with {status}", self.command).into());
+        }
+        Ok(())
+    }
+}
+
+/// The decision an answer of `keep`, `drop`, `defer` or `conflict` stands
+/// for, `conflict` reports the action and leaves its path alone
+pub fn decision(answer: &str, action: &Action) -> Option<ActionDecision> {
+    match answer {
+        "keep" => Some(ActionDecision::Keep),
+        "drop" => Some(ActionDecision::Drop),
+        "defer" => Some(ActionDecision::Defer),
+        "conflict" => Some(ActionDecision::Rewrite(Action::Conflict(
+            action.path().to_path_buf(),
+        ))),
+        _ => None,
+    }
+}
+
+/// `command` run by the shell of the platform
+#[cfg(unix)]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_defers_and_rewrites() {
+        let actions = vec![
+            Action::Put("./big.iso".into()),
+            Action::Remove("./uploads/a.jpg".into()),
+            Action::Put("./a.txt".into()),
+            Action::Remove("./b.txt".into()),
+        ];
+        let filtered = filter(actions, |action| match action {
+            Action::Remove(path) if path.starts_with("./uploads") => ActionDecision::Drop,
+            Action::Put(path) if path.ends_with("big.iso") => ActionDecision::Defer,
+            Action::Remove(path) => ActionDecision::Rewrite(Action::Conflict(path.clone())),
+            _ => ActionDecision::Keep,
+        });
+        assert_eq!(
+            filtered,
+            vec![
+                Action::Put("./a.txt".into()),
+                Action::Conflict("./b.txt".into()),
+                Action::Put("./big.iso".into()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn asks_the_command_about_each_action() {
+        let mut command = FilterCommand::spawn(
+            r#"while read kind path; do
+                case "$kind $path" in
+                    "remove ./uploads/"*) echo drop ;;
+                    "put ./my file.txt") echo conflict ;;
+                    *) echo keep ;;
+                esac
+            done"#,
+        )
+        .unwrap();
+        let filtered = filter(
+            vec![
+                Action::Remove("./uploads/a.jpg".into()),
+                Action::Put("./my file.txt".into()),
+                Action::Remove("./b.txt".into()),
+            ],
+            |action| command.decide(action),
+        );
+        command.finish().unwrap();
+        assert_eq!(
+            filtered,
+            vec![
+                Action::Conflict("./my file.txt".into()),
+                Action::Remove("./b.txt".into()),
+            ]
+        );
+
+        let mut command = FilterCommand::spawn("echo maybe").unwrap();
+        filter(vec![Action::Put("./a.txt".into())], |action| {
+            command.decide(action)
+        });
+        assert!(command.finish().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    transport::RemoteEntry,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// An entry of the checksum file as `syncbox ls` prints it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listed {
+    pub path: PathBuf,
+    pub kind: Kind,
+    /// Recorded size, of a directory the sum of the files below it
+    pub size: Option<u64>,
+    /// Seconds since the epoch the file last reached the remote
+    pub synced: Option<i64>,
+    /// How the live listing compares, when the remote was listed
+    pub remote: Option<Remote>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Remote {
+    /// On the remote as recorded
+    Present,
+    Missing,
+    /// On the remote with this size instead
+    Resized(u64),
+    /// On the remote but not in the checksum file
+    Untracked,
+}
+
+/// The entries of the directory at `path` in `tree`, or everything below it
+/// when `recursive`, in the order of their paths. A file is listed on its
+/// own. None when the tree has nothing at `path`
+pub fn list(tree: &ChecksumTree, path: &Path, recursive: bool) -> Option<Vec<Listed>> {
+    let mut listed = vec![];
+    match tree.get_at(path)? {
+        ChecksumElement::Directory(entries) => {
+            for (name, element) in entries {
+                collect(path.join(&**name), element, recursive, &mut listed);
+            }
+        }
+        element => collect(path.to_path_buf(), element, false, &mut listed),
+    }
+    listed.sort_by(|a, b| a.path.cmp(&b.path));
+    Some(listed)
+}
+
+fn collect(path: PathBuf, element: &ChecksumElement, recursive: bool, listed: &mut Vec<Listed>) {
+    let (kind, size, synced) = match element {
+        ChecksumElement::File(entry) => (Kind::File, entry.size, entry.synced),
+        ChecksumElement::Symlink(target) => (Kind::Symlink(target.clone()), None, None),
+        ChecksumElement::Directory(entries) => {
+            if recursive {
+                for (name, element) in entries {
+                    collect(path.join(&**name), element, recursive, listed);
+                }
+            }
+            (Kind::Directory, Some(size_of(element)), None)
+        }
+    };
+    listed.push(Listed {
+        path,
+        kind,
+        size,
+        synced,
+        remote: None,
+    });
+}
+
+fn size_of(element: &ChecksumElement) -> u64 {
+    match element {
+        ChecksumElement::File(entry) => entry.size.unwrap_or_default(),
+        ChecksumElement::Symlink(_) => 0,
+        ChecksumElement::Directory(entries) => entries.values().map(size_of).sum(),
+    }
+}
+
+/// Compares `listed` with `live`, a listing of the same directories on the
+/// remote, and adds what only the remote has unless `ignored`. Links aren't
+/// compared, not every remote lists them
+pub fn compare_live(
+    listed: &mut Vec<Listed>,
+    live: &HashMap<PathBuf, RemoteEntry>,
+    ignored: impl Fn(&Path) -> bool,
+) {
+    for entry in listed.iter_mut() {
+        entry.remote = match (&entry.kind, live.get(&entry.path)) {
+            (Kind::Symlink(_), _) => None,
+            (_, None) => Some(Remote::Missing),
+            (Kind::Directory, Some(remote)) if remote.is_dir => Some(Remote::Present),
+            (Kind::File, Some(remote))
+                if !remote.is_dir && entry.size.is_some_and(|size| size != remote.size) =>
+            {
+                Some(Remote::Resized(remote.size))
+            }
+            (Kind::File, Some(remote)) if !remote.is_dir => Some(Remote::Present),
+            // a file where a directory was synced or the other way around
+            (_, Some(_)) => Some(Remote::Missing),
+        };
+    }
+    let mut untracked: Vec<_> = live
+        .values()
+        .filter(|remote| !listed.iter().any(|entry| entry.path == remote.path))
+        .filter(|remote| !ignored(&remote.path))
+        .map(|remote| Listed {
+            path: remote.path.clone(),
+            kind: if remote.is_dir {
+                Kind::Directory
+            } else {
+                Kind::File
+            },
+            size: (!remote.is_dir).then_some(remote.size),
+            synced: None,
+            remote: Some(Remote::Untracked),
+        })
+        .collect();
+    listed.append(&mut untracked);
+    listed.sort_by(|a, b| a.path.cmp(&b.path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum_tree::FileEntry;
+
+    fn tree() -> ChecksumTree {
+        let mut tree = ChecksumTree::default();
+        for (path, size) in [("./a.txt", 3), ("./dir/b.txt", 4), ("./dir/sub/c.txt", 5)] {
+            let entry = FileEntry {
+                checksum: "x".to_string(),
+                size: Some(size),
+                synced: Some(7),
+                ..Default::default()
+            };
+            tree.insert_at(Path::new(path), ChecksumElement::File(entry));
+        }
+        tree.insert_at(
+            Path::new("./link"),
+            ChecksumElement::Symlink("a.txt".into()),
+        );
+        tree
+    }
+
+    fn paths(listed: &[Listed]) -> Vec<&str> {
+        listed
+            .iter()
+            .map(|entry| entry.path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lists_a_directory_or_everything_below_it() {
+        let tree = tree();
+        let listed = list(&tree, Path::new("."), false).unwrap();
+        assert_eq!(paths(&listed), ["./a.txt", "./dir", "./link"]);
+        assert_eq!(listed[1].kind, Kind::Directory);
+        assert_eq!(listed[1].size, Some(9));
+        assert_eq!(listed[2].kind, Kind::Symlink("a.txt".into()));
+
+        let listed = list(&tree, Path::new("./dir"), true).unwrap();
+        assert_eq!(
+            paths(&listed),
+            ["./dir/b.txt", "./dir/sub", "./dir/sub/c.txt"]
+        );
+        assert_eq!(
+            paths(&list(&tree, Path::new("./a.txt"), true).unwrap()),
+            ["./a.txt"]
+        );
+        assert!(list(&tree, Path::new("./nope"), false).is_none());
+    }
+
+    #[test]
+    fn live_listing_shows_what_differs() {
+        let remote = |path: &str, is_dir, size| {
+            (
+                PathBuf::from(path),
+                RemoteEntry {
+                    path: path.into(),
+                    is_dir,
+                    size,
+                    modified: None,
+                },
+            )
+        };
+        let live = HashMap::from([
+            remote("./a.txt", false, 30),
+            remote("./extra.txt", false, 1),
+            remote("./.syncbox.json.gz", false, 1),
+        ]);
+        let mut listed = list(&tree(), Path::new("."), false).unwrap();
+        compare_live(&mut listed, &live, |path| {
+            path.ends_with(".syncbox.json.gz")
+        });
+        let remotes: Vec<_> = listed
+            .iter()
+            .map(|entry| (entry.path.to_str().unwrap(), entry.remote))
+            .collect();
+        assert_eq!(
+            remotes,
+            [
+                ("./a.txt", Some(Remote::Resized(30))),
+                ("./dir", Some(Remote::Missing)),
+                ("./extra.txt", Some(Remote::Untracked)),
+                ("./link", None),
+            ]
+        );
+    }
+}
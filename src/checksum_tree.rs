@@ -1,22 +1,144 @@
+use crate::{
+    compression::{ChecksumCompression, ObjectCompression},
+    hash,
+};
+use digest::Digesting;
+use members::MemberWriter;
+use recover::PartialJson;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
+    fmt,
+    fs::Metadata,
+    io::{self, BufReader, BufWriter, Read, Write},
     ops::{Deref, DerefMut},
     path::Path,
+    time::UNIX_EPOCH,
 };
 
+mod diff;
+mod digest;
+mod intern;
+mod iter;
+mod members;
+mod migrate;
+mod recover;
+mod validate;
+
+pub use diff::TreeDiff;
+pub use intern::{intern, Name};
+pub use iter::Files;
+pub use migrate::SCHEMA;
+pub use validate::{Problem, Validation};
+
+/// Start of the comment carrying the SHA-256 of the serialized tree, in the
+/// gzip header or a skippable zstd frame
+const DIGEST_COMMENT_PREFIX: &str = "syncbox-sha256:";
+
+/// Why a checksum file could not be loaded
+#[derive(Debug)]
+pub enum ChecksumFileError {
+    /// The contents were damaged after being written
+    Corrupted(String),
+    /// The contents are intact but not in a format this version understands
+    UnknownFormat(String),
+    /// Written without a digest, so it can't tell which of the above happened
+    Unreadable(String),
+}
+
+impl fmt::Display for ChecksumFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupted(reason) => write!(f, "checksum file is corrupted: {reason}"),
+            Self::UnknownFormat(reason) => write!(
+                f,
+                "checksum file is intact but its format is not understood, it was probably written by another version of syncbox: {reason}"
+            ),
+            Self::Unreadable(reason) => write!(f, "checksum file could not be read: {reason}"),
+        }
+    }
+}
+
+impl Error for ChecksumFileError {}
+
+/// The entries of a directory by name, sorted so the same tree is always
+/// written the same way
+pub type Entries = BTreeMap<Name, ChecksumElement>;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ChecksumElement {
-    #[serde(alias = "d")]
-    Directory(HashMap<String, ChecksumElement>),
-    #[serde(alias = "f")]
-    File(String),
+    #[serde(deserialize_with = "intern::deserialize_entries")]
+    Directory(Entries),
+    File(FileEntry),
+    /// A symbolic link with its target, synced with `--links preserve`
+    Symlink(String),
+}
+
+/// A synced file. Its size, modification time and permissions are recorded
+/// too when known, so the remote can be checked against them without
+/// stat-ing every file again
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileEntry {
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Permission bits, only recorded on unix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// When the file last reached the remote, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced: Option<i64>,
+    /// Of files with a metadata checksum, see `hash::quick_hash`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quick_hash: Option<String>,
+}
+
+impl FileEntry {
+    /// `checksum` with the size, modification time and permissions of the file
+    pub fn new(checksum: String, metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = Some(std::os::unix::fs::MetadataExt::mode(metadata) & 0o7777);
+        #[cfg(not(unix))]
+        let mode = None;
+        Self {
+            checksum,
+            size: Some(metadata.len()),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .and_then(|since| since.as_secs().try_into().ok()),
+            mode,
+            synced: None,
+            quick_hash: None,
+        }
+    }
+
+    /// Whether this is `previous` only touched, its metadata checksum with
+    /// other times but the same quick hash
+    pub fn is_touched(&self, previous: &FileEntry) -> bool {
+        previous.quick_hash.is_some()
+            && previous.quick_hash == self.quick_hash
+            && hash::only_times_differ(&previous.checksum, &self.checksum)
+    }
+}
+
+impl From<String> for FileEntry {
+    fn from(checksum: String) -> Self {
+        Self {
+            checksum,
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for ChecksumElement {
     fn default() -> Self {
-        Self::Directory(HashMap::default())
+        Self::Directory(Entries::default())
     }
 }
 
@@ -24,17 +146,48 @@ impl Default for ChecksumElement {
 pub struct ChecksumTree {
     #[serde(default)]
     version: String,
+    /// Layout of the file, see `SCHEMA`
+    #[serde(default)]
+    schema: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<ObjectCompression>,
     root: Option<ChecksumElement>,
+    #[serde(skip)]
+    recovered: bool,
 }
 
 impl ChecksumTree {
     fn new() -> Self {
+        Self::with_version(env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Empty tree stamped with `version` instead of the running one, so tests
+    /// and fuzz targets build the same tree on every release
+    pub fn with_version(version: &str) -> Self {
         Self {
-            version: env!("CARGO_PKG_VERSION").into(),
+            version: version.into(),
+            schema: SCHEMA,
+            compression: None,
             root: Some(ChecksumElement::default()),
+            recovered: false,
         }
     }
 
+    /// Whether this was pieced together from a checksum file that was cut off,
+    /// files missing from it look new and get uploaded again
+    pub fn is_recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// How the synced files are compressed on the remote
+    pub fn compression(&self) -> Option<ObjectCompression> {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, compression: Option<ObjectCompression>) {
+        self.compression = compression;
+    }
+
     pub fn get_root(&mut self) -> &mut Option<ChecksumElement> {
         &mut self.root
     }
@@ -43,6 +196,61 @@ impl ChecksumTree {
         &self.version
     }
 
+    pub fn get_at_mut(&mut self, path: &Path) -> Option<&mut ChecksumElement> {
+        let mut current = self.root.as_mut()?;
+        for component in path {
+            match current {
+                ChecksumElement::Directory(dir) => {
+                    current = dir.get_mut(component.to_string_lossy().as_ref())?
+                }
+                ChecksumElement::File(_) | ChecksumElement::Symlink(_) => return None,
+            }
+        }
+        Some(current)
+    }
+
+    pub fn get_at(&self, path: &Path) -> Option<&ChecksumElement> {
+        let mut current = self.root.as_ref()?;
+        for component in path {
+            match current {
+                ChecksumElement::Directory(dir) => {
+                    current = dir.get(component.to_string_lossy().as_ref())?
+                }
+                ChecksumElement::File(_) | ChecksumElement::Symlink(_) => return None,
+            }
+        }
+        Some(current)
+    }
+
+    /// Whether there's a file, link or directory at `path`
+    pub fn contains(&self, path: &Path) -> bool {
+        self.get_at(path).is_some()
+    }
+
+    /// Inserts the element, creating missing directories along the way
+    pub fn insert_at(&mut self, path: &Path, element: ChecksumElement) {
+        let components: Vec<_> = path
+            .iter()
+            .map(|c| c.to_string_lossy().to_string())
+            .collect();
+        let Some((filename, directories)) = components.split_last() else {
+            return;
+        };
+        let mut current = self.root.get_or_insert_with(Default::default);
+        for component in directories {
+            let ChecksumElement::Directory(dir) = current else {
+                unreachable!()
+            };
+            current = dir.entry(intern(component)).or_default();
+            if !matches!(current, ChecksumElement::Directory(_)) {
+                *current = ChecksumElement::default();
+            }
+        }
+        if let ChecksumElement::Directory(dir) = current {
+            dir.insert(intern(filename), element);
+        }
+    }
+
     /// Used for when there was an error while uploading files
     pub fn remove_at(&mut self, path: &Path) {
         if let Some(ChecksumElement::Directory(root_dir)) = self.root.as_mut() {
@@ -55,12 +263,14 @@ impl ChecksumTree {
             for (i, component) in components.iter().enumerate() {
                 // Check if we are at the last component (file or empty directory)
                 if i == components.len() - 1 {
-                    current_dir.remove(component);
+                    current_dir.remove(component.as_str());
                     return;
                 }
 
                 // Navigate to the next directory
-                if let Some(ChecksumElement::Directory(next_dir)) = current_dir.get_mut(component) {
+                if let Some(ChecksumElement::Directory(next_dir)) =
+                    current_dir.get_mut(component.as_str())
+                {
                     current_dir = next_dir;
                 } else {
                     // Path does not exist, nothing to remove
@@ -70,15 +280,142 @@ impl ChecksumTree {
         }
     }
 
-    pub fn to_gzip(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
-        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
-        serde_json::to_writer(&mut encoder, self).unwrap();
-        Ok(encoder.finish()?)
+    /// Compressed JSON, the comment before the first member holds the SHA-256
+    /// of the JSON and every `MEMBER_SIZE` bytes of it end with their own
+    /// checksum
+    pub fn to_compressed(
+        &self,
+        compression: ChecksumCompression,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        self.write_compressed(Vec::new(), compression)
+    }
+
+    /// Streams the compressed JSON into `writer`. The digest comes first, so
+    /// the tree is serialized twice rather than held in memory as JSON
+    pub fn write_compressed<W: Write>(
+        &self,
+        writer: W,
+        compression: ChecksumCompression,
+    ) -> Result<W, Box<dyn Error + Send + Sync + 'static>> {
+        self.write_members(writer, compression, members::MEMBER_SIZE)
+    }
+
+    fn write_members<W: Write>(
+        &self,
+        writer: W,
+        compression: ChecksumCompression,
+        member_size: usize,
+    ) -> Result<W, Box<dyn Error + Send + Sync + 'static>> {
+        let mut digesting = BufWriter::new(Digesting::new(io::sink()));
+        serde_json::to_writer(&mut digesting, self)?;
+        let digest = digesting.into_inner().map_err(|e| e.into_error())?.digest();
+        let comment = format!("{DIGEST_COMMENT_PREFIX}{digest}");
+        let mut encoder = BufWriter::new(MemberWriter::new(
+            writer,
+            compression,
+            &comment,
+            member_size,
+        )?);
+        serde_json::to_writer(&mut encoder, self)?;
+        Ok(encoder.into_inner().map_err(|e| e.into_error())?.finish()?)
+    }
+
+    /// Reads gzip and zstd files alike, fails with a `ChecksumFileError`. The
+    /// JSON is parsed as it's decompressed and digested, without holding all
+    /// of it in memory
+    pub fn from_compressed(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        intern::scoped(|| Self::read_compressed(bytes))
+    }
+
+    fn read_compressed(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let expected_digest = members::comment(bytes)
+            .and_then(|comment| String::from_utf8(comment).ok())
+            .and_then(|comment| {
+                comment
+                    .strip_prefix(DIGEST_COMMENT_PREFIX)
+                    .map(str::to_string)
+            });
+        let decoder =
+            members::decoder(bytes).map_err(|e| ChecksumFileError::Corrupted(e.to_string()))?;
+        let mut reader = Digesting::new(decoder);
+        let parsed = serde_json::from_reader::<_, Self>(BufReader::new(&mut reader));
+        // whatever follows counts towards the digest too
+        let drained = io::copy(&mut reader, &mut io::sink());
+        if let Some(e) = match (&parsed, drained) {
+            (Err(e), _) if e.is_io() => Some(e.to_string()),
+            (_, Err(e)) => Some(e.to_string()),
+            _ => None,
+        } {
+            // usually left behind by an interrupted upload, which ends the
+            // stream early, the members before the cut are kept. Anything
+            // else damaged is refused rather than half read, files missing
+            // from the tree would be uploaded or deleted again
+            let json = members::verified(bytes)
+                .map_err(|reason| ChecksumFileError::Corrupted(format!("{e}: {reason}")))?;
+            return Self::from_partial_json(&json)
+                .ok_or_else(|| ChecksumFileError::Corrupted(e).into());
+        }
+        let Some(expected_digest) = expected_digest else {
+            // written by an older version
+            return Ok(Self::checked(
+                Self::current(parsed, bytes),
+                ChecksumFileError::Unreadable,
+            )?);
+        };
+        let digest = reader.digest();
+        if digest != expected_digest {
+            return Err(ChecksumFileError::Corrupted(format!(
+                "SHA-256 of the contents is {digest}, expected {expected_digest}"
+            ))
+            .into());
+        }
+        Ok(Self::checked(
+            Self::current(parsed, bytes),
+            ChecksumFileError::UnknownFormat,
+        )?)
+    }
+
+    /// `parsed` when it's in the current schema, otherwise the JSON in
+    /// `bytes` read again to be migrated
+    fn current(parsed: serde_json::Result<Self>, bytes: &[u8]) -> serde_json::Result<Self> {
+        match parsed {
+            Ok(tree) if tree.schema == SCHEMA => Ok(tree),
+            _ => {
+                let mut json = Vec::new();
+                members::decoder(bytes)
+                    .and_then(|mut decoder| decoder.read_to_end(&mut json))
+                    .map_err(serde_json::Error::io)?;
+                Self::migrated(&json)
+            }
+        }
     }
 
-    pub fn from_gzip(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
-        let mut decoder = flate2::read::GzDecoder::new(bytes);
-        Ok(serde_json::from_reader(&mut decoder)?)
+    /// A root that isn't a directory can't be reconciled against, it's
+    /// rejected like any other JSON syncbox wouldn't write
+    fn checked(
+        parsed: serde_json::Result<Self>,
+        error: fn(String) -> ChecksumFileError,
+    ) -> Result<Self, ChecksumFileError> {
+        let tree = parsed.map_err(|e| error(e.to_string()))?;
+        if matches!(
+            tree.root,
+            Some(ChecksumElement::File(_) | ChecksumElement::Symlink(_))
+        ) {
+            return Err(error("the root is a file".to_string()));
+        }
+        Ok(tree)
+    }
+
+    fn from_partial_json(json: &[u8]) -> Option<Self> {
+        let mut tree = PartialJson::new(json).tree()?;
+        if matches!(
+            tree.root,
+            Some(ChecksumElement::File(_) | ChecksumElement::Symlink(_))
+        ) {
+            return None;
+        }
+        tree.schema = SCHEMA;
+        Some(tree)
     }
 }
 
@@ -88,8 +425,14 @@ impl Default for ChecksumTree {
     }
 }
 
-impl From<HashMap<String, String>> for ChecksumTree {
-    fn from(map: HashMap<String, String>) -> Self {
+impl<E: Into<FileEntry>> From<HashMap<String, E>> for ChecksumTree {
+    fn from(map: HashMap<String, E>) -> Self {
+        intern::scoped(|| Self::from_files(map))
+    }
+}
+
+impl ChecksumTree {
+    fn from_files<E: Into<FileEntry>>(map: HashMap<String, E>) -> Self {
         let root_map = Default::default();
         let mut stack: Vec<ChecksumElement> = vec![root_map];
         for (path_str, checksum) in map {
@@ -115,8 +458,8 @@ impl From<HashMap<String, String>> for ChecksumTree {
             let map = match map {
                 ChecksumElement::Directory(mut dir) => {
                     dir.insert(
-                        path.file_name().unwrap().to_string_lossy().to_string(),
-                        ChecksumElement::File(checksum),
+                        intern(&path.file_name().unwrap().to_string_lossy()),
+                        ChecksumElement::File(checksum.into()),
                     );
                     dir
                 }
@@ -125,7 +468,7 @@ impl From<HashMap<String, String>> for ChecksumTree {
             stack.push(ChecksumElement::Directory(map));
             // pop back into main root map
             for component in path.iter().rev().skip(1) {
-                let component_str: String = component.to_string_lossy().to_string();
+                let component_str = intern(&component.to_string_lossy());
                 let dir = stack.pop().unwrap();
                 let parent = stack.pop().unwrap();
                 let parent = match parent {
@@ -148,6 +491,20 @@ impl From<HashMap<String, String>> for ChecksumTree {
     }
 }
 
+/// Builds the tree with `insert_at`, a later path replaces an earlier one
+/// in its way, whatever the order of the iterator otherwise
+impl<P: AsRef<Path>, E: Into<FileEntry>> FromIterator<(P, E)> for ChecksumTree {
+    fn from_iter<I: IntoIterator<Item = (P, E)>>(files: I) -> Self {
+        intern::scoped(|| {
+            let mut tree = Self::new();
+            for (path, entry) in files {
+                tree.insert_at(path.as_ref(), ChecksumElement::File(entry.into()));
+            }
+            tree
+        })
+    }
+}
+
 impl Deref for ChecksumTree {
     type Target = Option<ChecksumElement>;
     fn deref(&self) -> &Self::Target {
@@ -161,15 +518,440 @@ impl DerefMut for ChecksumTree {
     }
 }
 
+/// Trees and entries for the tests of other modules
 #[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
 
+    /// Files at the paths with the checksums
+    pub(crate) fn tree(entries: &[(&str, &str)]) -> ChecksumTree {
+        tree_of(
+            entries
+                .iter()
+                .map(|(path, checksum)| (*path, file(checksum))),
+        )
+    }
+
+    /// Files at the paths with the entries
+    pub(crate) fn tree_of<'a>(
+        entries: impl IntoIterator<Item = (&'a str, FileEntry)>,
+    ) -> ChecksumTree {
+        entries
+            .into_iter()
+            .map(|(path, entry)| (path.to_string(), entry))
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    /// An entry with only the checksum, the rest is set with `..file(checksum)`
+    pub(crate) fn file(checksum: &str) -> FileEntry {
+        FileEntry::from(checksum.to_string())
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compression::ChecksumFormat;
+    use proptest::prelude::*;
+    use std::path::PathBuf;
+
+    fn gzip(comment: Option<&str>, json: &[u8]) -> Vec<u8> {
+        let mut builder = flate2::GzBuilder::new();
+        if let Some(comment) = comment {
+            builder = builder.comment(comment);
+        }
+        let mut encoder = builder.write(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, json).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A file cut off just after the member holding the first `at` bytes of
+    /// `json`, like one left behind by an interrupted upload
+    fn cut_off(json: &[u8], at: usize) -> Vec<u8> {
+        let mut bytes = gzip(None, &json[..at]);
+        bytes.extend_from_slice(&gzip(None, &json[at..])[..12]);
+        bytes
+    }
+
+    #[test]
+    fn gzip_round_trip_is_verified() {
+        let tree = ChecksumTree::from(HashMap::from([("./a.txt".to_string(), "hash".to_string())]));
+        let bytes = tree.to_compressed(Default::default()).unwrap();
+        let read = ChecksumTree::from_compressed(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_string(&read).unwrap(),
+            serde_json::to_string(&tree).unwrap()
+        );
+    }
+
+    #[test]
+    fn the_same_tree_is_written_the_same_way() {
+        let paths = ["./b.txt", "./a/c.txt", "./a/b.txt", "./c/a.txt", "./a.txt"];
+        let tree = |paths: &[&str]| -> ChecksumTree {
+            paths.iter().map(|path| (*path, path.to_string())).collect()
+        };
+        let mut reversed = paths;
+        reversed.reverse();
+        assert_eq!(
+            tree(&paths).to_compressed(Default::default()).unwrap(),
+            tree(&reversed).to_compressed(Default::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_keep_their_metadata() {
+        let entry = FileEntry {
+            checksum: "hash".to_string(),
+            size: Some(3),
+            mtime: Some(1_700_000_000),
+            mode: Some(0o644),
+            synced: Some(1_700_000_100),
+            quick_hash: None,
+        };
+        let tree: ChecksumTree = [
+            ("./a.txt", entry.clone()),
+            ("./b.txt", "old".to_string().into()),
+        ]
+        .into_iter()
+        .collect();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.contains(r#""b.txt":{"File":{"checksum":"old"}}"#));
+        assert!(json.contains(
+            r#""a.txt":{"File":{"checksum":"hash","size":3,"mtime":1700000000,"mode":420,"synced":1700000100}}"#
+        ));
+
+        let read = ChecksumTree::from_compressed(&tree.to_compressed(Default::default()).unwrap())
+            .unwrap();
+        assert!(matches!(
+            read.get_at(Path::new("./a.txt")),
+            Some(ChecksumElement::File(read)) if *read == entry
+        ));
+        // cut inside the entry, which is dropped rather than read without its mode
+        let cut = json.find("420").unwrap() + 2;
+        let recovered = ChecksumTree::from_compressed(&cut_off(json.as_bytes(), cut)).unwrap();
+        assert!(recovered.get_at(Path::new("./a.txt")).is_none());
+        let cut = json.find("1700000100").unwrap() + 11;
+        let recovered = ChecksumTree::from_compressed(&cut_off(json.as_bytes(), cut)).unwrap();
+        assert!(matches!(
+            recovered.get_at(Path::new("./a.txt")),
+            Some(ChecksumElement::File(read)) if *read == entry
+        ));
+    }
+
+    #[test]
+    fn digests_of_trees_with_one_hash_get_named() {
+        let digest = "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85";
+        let json = format!(
+            r#"{{"version":"0.5.4","hash":"blake3","root":{{"Directory":{{".":{{"Directory":{{"a.txt":{{"File":"{digest}"}},"big.iso":{{"File":"s9_c1_m2"}}}}}}}}}}}}"#
+        );
+        let named = format!("blake3:{digest}");
+        let checksum = |tree: &ChecksumTree, path: &str| match tree.get_at(Path::new(path)) {
+            Some(ChecksumElement::File(entry)) => entry.checksum.clone(),
+            other => panic!("{path} is {other:?}"),
+        };
+
+        let bytes = gzip(None, json.as_bytes());
+        let tree = ChecksumTree::from_compressed(&bytes).unwrap();
+        assert_eq!(checksum(&tree, "./a.txt"), named);
+        assert_eq!(checksum(&tree, "./big.iso"), "s9_c1_m2");
+        assert!(!serde_json::to_string(&tree).unwrap().contains("\"hash\""));
+
+        // written as a single member, so cut off can't be told from damaged
+        assert!(ChecksumTree::from_compressed(&bytes[..bytes.len() - 8]).is_err());
+    }
+
+    #[test]
+    fn older_schemas_are_migrated() {
+        let json = br#"{"version":"0.5.4","hash":"blake3","root":{"d":{".":{"d":{
+            "a.txt":{"f":"abc"},
+            "b.txt":{"File":{"checksum":"xxh3:def","size":3}},
+            "link":{"l":"a.txt"}
+        }}}}}"#;
+        let tree = ChecksumTree::from_compressed(&gzip(None, json)).unwrap();
+        assert!(matches!(
+            tree.get_at(Path::new("./a.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "blake3:abc"
+        ));
+        assert!(matches!(
+            tree.get_at(Path::new("./b.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "xxh3:def" && entry.size == Some(3)
+        ));
+        assert!(matches!(
+            tree.get_at(Path::new("./link")),
+            Some(ChecksumElement::Symlink(target)) if target == "a.txt"
+        ));
+        // written back in the current schema, which is read without migrating
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.starts_with(r#"{"version":"0.5.4","schema":1,"root":{"Directory":"#));
+        assert!(json.contains(r#""a.txt":{"File":{"checksum":"blake3:abc"}}"#));
+        let read: ChecksumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.schema, SCHEMA);
+
+        let newer = br#"{"version":"9.0.0","schema":99,"root":{"Directory":{}}}"#;
+        let digest = sha256::digest(newer.as_slice());
+        assert!(matches!(
+            *ChecksumTree::from_compressed(&gzip(
+                Some(&format!("{DIGEST_COMMENT_PREFIX}{digest}")),
+                newer
+            ))
+            .unwrap_err()
+            .downcast::<ChecksumFileError>()
+            .unwrap(),
+            ChecksumFileError::UnknownFormat(_)
+        ));
+        let bytes = gzip(None, newer);
+        assert!(ChecksumTree::from_compressed(&bytes[..bytes.len() - 8]).is_err());
+    }
+
+    #[test]
+    fn recovers_complete_entries_of_truncated_files() {
+        let tree = ChecksumTree::from(HashMap::from([
+            ("./a.txt".to_string(), "hash-a".to_string()),
+            ("./dir/b.txt".to_string(), "hash-b".to_string()),
+            ("./dir/c.txt".to_string(), "hash-c".to_string()),
+        ]));
+        let json = serde_json::to_vec(&tree).unwrap();
+        // cut inside the checksum of whichever file comes last
+        let cut = json
+            .windows(5)
+            .rposition(|window| window == b"hash-")
+            .unwrap()
+            + 5;
+        let bytes = tree.to_compressed(Default::default()).unwrap();
+
+        let recovered = ChecksumTree::from_compressed(&cut_off(&json, cut)).unwrap();
+        assert!(recovered.is_recovered());
+        assert!(!ChecksumTree::from_compressed(&bytes)
+            .unwrap()
+            .is_recovered());
+        let files = ["./a.txt", "./dir/b.txt", "./dir/c.txt"]
+            .into_iter()
+            .filter(|path| {
+                matches!(
+                    recovered.get_at(Path::new(path)),
+                    Some(ChecksumElement::File(entry)) if entry.checksum.starts_with("hash-")
+                )
+            })
+            .count();
+        assert_eq!(files, 2);
+    }
+
+    #[test]
+    fn damaged_files_are_refused_rather_than_read_in_part() {
+        let tree = ChecksumTree::from(HashMap::from_iter(
+            (0..20).map(|i| (format!("./{i}.txt"), format!("hash-{i}"))),
+        ));
+        for (format, magic) in [
+            (ChecksumFormat::Gzip, &[0x1f, 0x8b, 0x08][..]),
+            (ChecksumFormat::Zstd, &[0x28, 0xb5, 0x2f, 0xfd][..]),
+        ] {
+            let compression = ChecksumCompression {
+                format,
+                level: None,
+            };
+            let bytes = tree.write_members(Vec::new(), compression, 32).unwrap();
+            let members: Vec<_> = (0..bytes.len())
+                .filter(|&start| bytes[start..].starts_with(magic))
+                .collect();
+            assert!(members.len() > 3, "{format}");
+            let read = ChecksumTree::from_compressed(&bytes).unwrap();
+            assert!(!read.is_recovered(), "{format}");
+            assert_eq!(read.len(), tree.len(), "{format}");
+
+            let cut = ChecksumTree::from_compressed(&bytes[..members[3] + 5]).unwrap();
+            assert!(cut.is_recovered(), "{format}");
+            assert!(cut.len() < tree.len(), "{format}");
+
+            let mut damaged = bytes.clone();
+            damaged[(members[1] + members[2]) / 2] ^= 0x10;
+            assert!(
+                matches!(
+                    *ChecksumTree::from_compressed(&damaged)
+                        .unwrap_err()
+                        .downcast::<ChecksumFileError>()
+                        .unwrap(),
+                    ChecksumFileError::Corrupted(_)
+                ),
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn zstd_files_are_read_like_gzip_ones() {
+        let tree = ChecksumTree::from(HashMap::from_iter(
+            (0..1000).map(|i| (format!("./dir/{i}.txt"), format!("hash-{i}"))),
+        ));
+        let gzip = tree.to_compressed(Default::default()).unwrap();
+        let zstd = tree
+            .to_compressed(ChecksumCompression {
+                format: ChecksumFormat::Zstd,
+                level: Some(19),
+            })
+            .unwrap();
+        assert!(zstd.len() < gzip.len());
+        let comment = members::comment(&zstd).unwrap();
+        assert!(comment.starts_with(DIGEST_COMMENT_PREFIX.as_bytes()));
+        let read = ChecksumTree::from_compressed(&zstd).unwrap();
+        assert!(tree.diff(&read).is_empty());
+        assert!(ChecksumTree::validate(&zstd).problems.is_empty());
+    }
+
+    #[test]
+    fn validation_finds_and_repairs_problems() {
+        let tree = ChecksumTree::from(HashMap::from([("./a.txt".to_string(), "hash".to_string())]));
+        let validation = ChecksumTree::validate(&tree.to_compressed(Default::default()).unwrap());
+        assert!(validation.problems.is_empty());
+        assert!(validation.repaired.is_some());
+
+        let json = br#"{"version":"0.1.0","root":{"Directory":{
+            ".":{"Directory":{
+                "a.txt":{"File":"old"},
+                "a.txt":{"File":"new"},
+                "..":{"File":"hash"},
+                "b.txt":{"File":"md5:hash"},
+                "c.txt":{"File":"blake3:hash"}
+            }},
+            "elsewhere":{"File":"hash"}
+        }}}"#;
+        let validation = ChecksumTree::validate(&gzip(None, json));
+        for problem in [
+            Problem::DuplicateName("./a.txt".into()),
+            Problem::InvalidName("./..".into()),
+            Problem::InvalidChecksum("./b.txt".into()),
+            Problem::OutsideRoot("elsewhere".into()),
+        ] {
+            assert!(validation.problems.contains(&problem), "{problem:?}");
+        }
+        assert_eq!(validation.problems.len(), 4);
+        let repaired = validation.repaired.unwrap();
+        assert_eq!(
+            files(&repaired),
+            vec![
+                ("/./a.txt".to_string(), "new".to_string()),
+                ("/./c.txt".to_string(), "blake3:hash".to_string())
+            ]
+        );
+
+        // a digest that doesn't match still leaves the entries to repair
+        let digest = sha256::digest("something else");
+        let json = br#"{"version":"9.0.0","root":{"Directory":{".":{"Directory":{}}}}}"#;
+        let validation = ChecksumTree::validate(&gzip(
+            Some(&format!("{DIGEST_COMMENT_PREFIX}{digest}")),
+            json,
+        ));
+        assert!(matches!(
+            validation.problems.as_slice(),
+            [Problem::Unreadable(_), Problem::Version(version)] if version == "9.0.0"
+        ));
+        assert_eq!(
+            validation.repaired.unwrap().get_version(),
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let validation = ChecksumTree::validate(b"not gzip");
+        assert!(validation.repaired.is_none());
+    }
+
+    #[test]
+    fn tells_corruption_from_format_changes() {
+        let error = |bytes: &[u8]| {
+            ChecksumTree::from_compressed(bytes)
+                .unwrap_err()
+                .downcast::<ChecksumFileError>()
+                .unwrap()
+        };
+        let json = br#"{"version":"9.0.0","root":{"Socket":"x"}}"#;
+        let digest = sha256::digest(json.as_slice());
+        let comment = format!("{DIGEST_COMMENT_PREFIX}{digest}");
+        assert!(matches!(
+            *error(&gzip(Some(&comment), json)),
+            ChecksumFileError::UnknownFormat(_)
+        ));
+        assert!(matches!(
+            *error(&gzip(
+                Some(&comment),
+                br#"{"version":"9.0.1","root":{"Socket":"x"}}"#
+            )),
+            ChecksumFileError::Corrupted(_)
+        ));
+        assert!(matches!(
+            *error(&gzip(None, json)),
+            ChecksumFileError::Unreadable(_)
+        ));
+        assert!(matches!(
+            *error(b"not gzip"),
+            ChecksumFileError::Corrupted(_)
+        ));
+        let json = br#"{"version":"0.5.4","root":{"File":"x"}}"#;
+        let digest = sha256::digest(json.as_slice());
+        assert!(matches!(
+            *error(&gzip(
+                Some(&format!("{DIGEST_COMMENT_PREFIX}{digest}")),
+                json
+            )),
+            ChecksumFileError::UnknownFormat(_)
+        ));
+    }
+
+    /// `(path, checksum)` of every file, sorted
+    fn files(tree: &ChecksumTree) -> Vec<(String, String)> {
+        let mut files = vec![];
+        let mut stack = vec![(String::new(), tree.root.as_ref().unwrap())];
+        while let Some((path, element)) = stack.pop() {
+            match element {
+                ChecksumElement::Directory(dir) => stack.extend(
+                    dir.iter()
+                        .map(|(name, element)| (format!("{path}/{name}"), element)),
+                ),
+                ChecksumElement::File(entry) => files.push((path, entry.checksum.clone())),
+                ChecksumElement::Symlink(_) => unreachable!("generated trees have no links"),
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn any_tree() -> impl Strategy<Value = ChecksumTree> {
+        prop::collection::vec(("[a-c]{1,2}(/[a-c]{1,2}){0,2}", "[0-9a-f]{8}"), 0..16)
+            .prop_map(|files| files.into_iter().collect())
+    }
+
+    proptest! {
+        #[test]
+        fn gzip_round_trip_keeps_any_tree(tree in any_tree()) {
+            let read = ChecksumTree::from_compressed(&tree.to_compressed(Default::default()).unwrap()).unwrap();
+            prop_assert_eq!(files(&read), files(&tree));
+            prop_assert!(!read.is_recovered());
+        }
+
+        #[test]
+        fn truncated_files_keep_only_complete_entries(
+            tree in any_tree(),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let bytes = tree.write_members(Vec::new(), Default::default(), 64).unwrap();
+            if let Ok(read) = ChecksumTree::from_compressed(&bytes[..cut.index(bytes.len())]) {
+                prop_assert!(read.is_recovered());
+                let complete = files(&tree);
+                for file in files(&read) {
+                    prop_assert!(complete.contains(&file), "{:?} was not in the tree", file);
+                }
+            }
+        }
+
+        #[test]
+        fn reading_any_bytes_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = ChecksumTree::from_compressed(&bytes);
+            let _ = ChecksumTree::from_compressed(&gzip(None, &bytes));
+        }
+    }
 
     #[test]
     fn remove_at() {
-        let mut checksum: ChecksumTree = serde_json::from_str(
-            r#"{
+        let mut checksum = ChecksumTree::migrated(
+            br#"{
            "version": "0.3.0",
            "root": {
              "Directory": {
@@ -195,14 +977,143 @@ mod tests {
         checksum.remove_at(Path::new("./DSC05953.ARW"));
         assert_eq!(
             serde_json::to_string(&checksum).unwrap(),
-            r#"{"version":"0.3.0","root":{"Directory":{".":{"Directory":{"DSC05947.ARW":{"File":"a4849b4f83f996ef9ce68b9f8561db4a991ab5f9dce3c52a45267c8e274bb73a"}}}}}}"#
+            r#"{"version":"0.3.0","schema":1,"root":{"Directory":{".":{"Directory":{"DSC05947.ARW":{"File":{"checksum":"a4849b4f83f996ef9ce68b9f8561db4a991ab5f9dce3c52a45267c8e274bb73a"}}}}}}}"#
+        );
+    }
+
+    #[test]
+    fn iterates_over_files() {
+        let mut tree: ChecksumTree = [
+            (
+                "./a.txt",
+                FileEntry {
+                    size: Some(3),
+                    ..FileEntry::from("a".to_string())
+                },
+            ),
+            (
+                "./dir/b.txt",
+                FileEntry {
+                    size: Some(4),
+                    ..FileEntry::from("b".to_string())
+                },
+            ),
+            ("./dir/c.txt", "c".to_string().into()),
+        ]
+        .into_iter()
+        .collect();
+        tree.insert_at(Path::new("./empty"), ChecksumElement::default());
+        tree.insert_at(
+            Path::new("./link"),
+            ChecksumElement::Symlink("a.txt".into()),
+        );
+
+        let files: Vec<_> = tree
+            .iter()
+            .map(|(path, entry)| (path, entry.checksum.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (PathBuf::from("./a.txt"), "a"),
+                (PathBuf::from("./dir/b.txt"), "b"),
+                (PathBuf::from("./dir/c.txt"), "c"),
+            ]
+        );
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.total_size(), 7);
+        assert!(!tree.is_empty());
+        assert!(ChecksumTree::default().is_empty());
+    }
+
+    #[test]
+    fn diffs_files() {
+        let sized = |checksum: &str, size| FileEntry {
+            size: Some(size),
+            ..FileEntry::from(checksum.to_string())
+        };
+        let before: ChecksumTree = [
+            ("./kept.txt", sized("a", 1)),
+            ("./changed.txt", sized("b", 2)),
+            ("./removed/c.txt", sized("c", 4)),
+            ("./linked.txt", sized("d", 8)),
+        ]
+        .into_iter()
+        .collect();
+        let mut after: ChecksumTree = [
+            ("./kept.txt", sized("a", 1)),
+            ("./changed.txt", sized("e", 16)),
+            ("./added.txt", sized("f", 32)),
+        ]
+        .into_iter()
+        .collect();
+        after.insert_at(
+            Path::new("./linked.txt"),
+            ChecksumElement::Symlink("kept.txt".into()),
+        );
+
+        assert_eq!(
+            before.diff(&after),
+            TreeDiff {
+                added: vec!["./added.txt".into()],
+                modified: vec!["./changed.txt".into()],
+                removed: vec!["./linked.txt".into(), "./removed/c.txt".into()],
+                added_bytes: 32,
+                modified_bytes: 16,
+                removed_bytes: 12,
+            }
+        );
+        assert_eq!(after.diff(&before).added.len(), 2);
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn names_are_shared_between_directories() {
+        let tree: ChecksumTree = [("./a/index.html", "1"), ("./b/index.html", "2")]
+            .map(|(path, checksum)| (path, checksum.to_string()))
+            .into_iter()
+            .collect();
+        let read = ChecksumTree::from_compressed(&tree.to_compressed(Default::default()).unwrap())
+            .unwrap();
+        for tree in [&tree, &read] {
+            let name = |dir: &str| match tree.get_at(Path::new(dir)) {
+                Some(ChecksumElement::Directory(entries)) => entries.keys().next().unwrap().clone(),
+                other => panic!("{dir} is {other:?}"),
+            };
+            let shared = name("./a");
+            assert!(std::sync::Arc::ptr_eq(&shared, &name("./b")));
+            // only the two directories and this test hold the name
+            assert_eq!(std::sync::Arc::strong_count(&shared), 3);
+        }
+    }
+
+    #[test]
+    fn insert_at_and_get_at() {
+        let mut checksum = ChecksumTree::default();
+        checksum.insert_at(
+            Path::new("./dir/nested/file.txt"),
+            ChecksumElement::File("hash".to_string().into()),
         );
+        assert!(matches!(
+            checksum.get_at(Path::new("./dir/nested/file.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "hash"
+        ));
+        assert!(matches!(
+            checksum.get_at(Path::new("./dir/nested")),
+            Some(ChecksumElement::Directory(_))
+        ));
+        assert!(checksum.get_at(Path::new("./dir/other.txt")).is_none());
+        assert!(checksum.contains(Path::new("./dir")));
+        assert!(!checksum.contains(Path::new("./dir/other.txt")));
+        assert!(checksum
+            .get_at(Path::new("./dir/nested/file.txt/below"))
+            .is_none());
     }
 
     #[test]
     fn remove_at_similar() {
-        let mut checksum: ChecksumTree = serde_json::from_str(
-            r#"{
+        let mut checksum = ChecksumTree::migrated(
+            br#"{
            "version": "0.3.0",
            "root": {
              "Directory": {
@@ -228,7 +1139,7 @@ mod tests {
         checksum.remove_at(Path::new("dirrr/DSC05953.ARW"));
         assert_eq!(
             serde_json::to_string(&checksum).unwrap(),
-            r#"{"version":"0.3.0","root":{"Directory":{"dirrr":{"Directory":{"DSC05947.ARW":{"File":"a4849b4f83f996ef9ce68b9f8561db4a991ab5f9dce3c52a45267c8e274bb73a"}}}}}}"#
+            r#"{"version":"0.3.0","schema":1,"root":{"Directory":{"dirrr":{"Directory":{"DSC05947.ARW":{"File":{"checksum":"a4849b4f83f996ef9ce68b9f8561db4a991ab5f9dce3c52a45267c8e274bb73a"}}}}}}}"#
         );
     }
 }
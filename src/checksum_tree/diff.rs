@@ -0,0 +1,54 @@
+use super::{ChecksumElement, ChecksumTree};
+use std::path::PathBuf;
+
+/// The files that differ between two trees, see `ChecksumTree::diff`. Paths
+/// are sorted, bytes are the recorded sizes
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TreeDiff {
+    /// Files only in the other tree
+    pub added: Vec<PathBuf>,
+    /// Files in both trees with different contents
+    pub modified: Vec<PathBuf>,
+    /// Files only in this tree
+    pub removed: Vec<PathBuf>,
+    pub added_bytes: u64,
+    /// Sizes in the other tree
+    pub modified_bytes: u64,
+    pub removed_bytes: u64,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl ChecksumTree {
+    /// How the files changed from this tree to `other`. Unlike the
+    /// `Reconciler` it doesn't plan anything, so any two trees can be
+    /// compared, like two snapshots. Directories and links are left out
+    pub fn diff(&self, other: &ChecksumTree) -> TreeDiff {
+        let mut diff = TreeDiff::default();
+        for (path, entry) in self {
+            match other.get_at(&path) {
+                Some(ChecksumElement::File(other)) => {
+                    if other.checksum != entry.checksum {
+                        diff.modified_bytes += other.size.unwrap_or_default();
+                        diff.modified.push(path);
+                    }
+                }
+                _ => {
+                    diff.removed_bytes += entry.size.unwrap_or_default();
+                    diff.removed.push(path);
+                }
+            }
+        }
+        for (path, entry) in other {
+            if !matches!(self.get_at(&path), Some(ChecksumElement::File(_))) {
+                diff.added_bytes += entry.size.unwrap_or_default();
+                diff.added.push(path);
+            }
+        }
+        diff
+    }
+}
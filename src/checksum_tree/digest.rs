@@ -0,0 +1,43 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+/// Passes reads or writes through to `inner`, keeping the SHA-256 of the
+/// bytes that went by, so a tree can be digested as it's streamed
+pub(super) struct Digesting<T> {
+    inner: T,
+    hasher: Sha256,
+}
+
+impl<T> Digesting<T> {
+    pub(super) fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Hex digest of everything read or written so far
+    pub(super) fn digest(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for Digesting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Digesting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
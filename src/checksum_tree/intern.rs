@@ -0,0 +1,77 @@
+use super::{ChecksumElement, Entries};
+use serde::de::{Deserializer, MapAccess, Visitor};
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, fmt, sync::Arc};
+
+/// Name of an entry in a directory. Names that occur in many directories,
+/// like `index.html` or `2024`, share one allocation between all of them
+pub type Name = Arc<str>;
+
+thread_local! {
+    /// Names handed out while a tree is read or built on this thread, names
+    /// of a tree with millions of files repeat far more often than not
+    static NAMES: RefCell<Option<HashSet<Name>>> = const { RefCell::new(None) };
+}
+
+/// Runs `build` with the names it interns shared, they're forgotten once it
+/// returns, so only the trees built keep them
+pub fn scoped<T>(build: impl FnOnce() -> T) -> T {
+    let outermost = NAMES.with_borrow_mut(|names| {
+        let outermost = names.is_none();
+        names.get_or_insert_with(HashSet::new);
+        outermost
+    });
+    let _forget = Forget(outermost);
+    build()
+}
+
+/// Forgets the names when the outermost scope ends, panicking or not
+struct Forget(bool);
+
+impl Drop for Forget {
+    fn drop(&mut self) {
+        if self.0 {
+            NAMES.with_borrow_mut(|names| *names = None);
+        }
+    }
+}
+
+/// The shared allocation of `name` within `scoped`, a new one outside it
+pub fn intern(name: &str) -> Name {
+    NAMES.with_borrow_mut(|names| {
+        let Some(names) = names else {
+            return name.into();
+        };
+        if let Some(interned) = names.get(name) {
+            return interned.clone();
+        }
+        let interned: Name = name.into();
+        names.insert(interned.clone());
+        interned
+    })
+}
+
+/// Reads the entries of a directory with interned names
+pub(super) fn deserialize_entries<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Entries, D::Error> {
+    deserializer.deserialize_map(EntriesVisitor)
+}
+
+struct EntriesVisitor;
+
+impl<'de> Visitor<'de> for EntriesVisitor {
+    type Value = Entries;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "directory entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+        let mut entries = Entries::default();
+        while let Some(name) = map.next_key::<Cow<str>>()? {
+            let element = map.next_value::<ChecksumElement>()?;
+            entries.insert(intern(&name), element);
+        }
+        Ok(entries)
+    }
+}
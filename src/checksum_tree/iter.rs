@@ -0,0 +1,64 @@
+use super::{ChecksumElement, ChecksumTree, FileEntry};
+use std::path::PathBuf;
+
+/// The files of a tree with their paths, see `ChecksumTree::iter`
+pub struct Files<'a> {
+    stack: Vec<(PathBuf, &'a ChecksumElement)>,
+}
+
+impl<'a> Iterator for Files<'a> {
+    type Item = (PathBuf, &'a FileEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, element)) = self.stack.pop() {
+            match element {
+                ChecksumElement::Directory(entries) => self.stack.extend(
+                    entries
+                        .iter()
+                        .rev()
+                        .map(|(name, element)| (path.join(&**name), element)),
+                ),
+                ChecksumElement::File(entry) => return Some((path, entry)),
+                ChecksumElement::Symlink(_) => {}
+            }
+        }
+        None
+    }
+}
+
+impl ChecksumTree {
+    /// Every file with its path, like `./dir/file.txt`, sorted by path.
+    /// Directories and links are left out
+    pub fn iter(&self) -> Files<'_> {
+        Files {
+            stack: self
+                .root
+                .iter()
+                .map(|root| (PathBuf::new(), root))
+                .collect(),
+        }
+    }
+
+    /// Number of files
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Sum of the recorded sizes, files recorded without one count as empty
+    pub fn total_size(&self) -> u64 {
+        self.iter().filter_map(|(_, entry)| entry.size).sum()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChecksumTree {
+    type Item = (PathBuf, &'a FileEntry);
+    type IntoIter = Files<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,197 @@
+use crate::compression::{ChecksumCompression, ChecksumFormat};
+use flate2::write::GzEncoder;
+use std::io::{self, Read, Write};
+
+/// Bytes of JSON per member, a gzip member or a zstd frame. Every member ends
+/// with a checksum of its part, so when a file is cut off the members before
+/// the cut are known to be intact, and one damaged in the middle is told apart
+/// from a cut off one
+pub(super) const MEMBER_SIZE: usize = 1 << 20;
+
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Start of a zstd frame decoders skip, it carries the comment of zstd files
+/// like the header of the first member does in gzip files
+const SKIPPABLE_MAGIC: [u8; 4] = [0x50, 0x2a, 0x4d, 0x18];
+
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(writer: W, compression: ChecksumCompression) -> io::Result<Self> {
+        Ok(match compression.format {
+            ChecksumFormat::Gzip => Self::Gzip(GzEncoder::new(writer, compression.gzip_level())),
+            ChecksumFormat::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, compression.zstd_level())?;
+                encoder.include_checksum(true)?;
+                Self::Zstd(encoder)
+            }
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::Gzip(encoder) => encoder,
+            Self::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compressed stream of members of at most `size` bytes of input each,
+/// decoders of a single stream read all of them
+pub(super) struct MemberWriter<W: Write> {
+    encoder: Option<Encoder<W>>,
+    compression: ChecksumCompression,
+    size: usize,
+    written: usize,
+}
+
+impl<W: Write> MemberWriter<W> {
+    /// `comment` goes before the first member
+    pub(super) fn new(
+        mut writer: W,
+        compression: ChecksumCompression,
+        comment: &str,
+        size: usize,
+    ) -> io::Result<Self> {
+        let encoder = match compression.format {
+            ChecksumFormat::Gzip => Encoder::Gzip(
+                flate2::GzBuilder::new()
+                    .comment(comment)
+                    .write(writer, compression.gzip_level()),
+            ),
+            ChecksumFormat::Zstd => {
+                writer.write_all(&SKIPPABLE_MAGIC)?;
+                writer.write_all(&(comment.len() as u32).to_le_bytes())?;
+                writer.write_all(comment.as_bytes())?;
+                Encoder::new(writer, compression)?
+            }
+        };
+        Ok(Self {
+            encoder: Some(encoder),
+            compression,
+            size,
+            written: 0,
+        })
+    }
+
+    fn encoder(&mut self) -> io::Result<&mut Encoder<W>> {
+        self.encoder.as_mut().ok_or_else(unfinished)
+    }
+
+    pub(super) fn finish(self) -> io::Result<W> {
+        match self.encoder {
+            Some(encoder) => encoder.finish(),
+            None => Err(unfinished()),
+        }
+    }
+}
+
+impl<W: Write> Write for MemberWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written == self.size {
+            if let Some(encoder) = self.encoder.take() {
+                self.encoder = Some(Encoder::new(encoder.finish()?, self.compression)?);
+            }
+            self.written = 0;
+        }
+        let room = self.size - self.written;
+        let written = self
+            .encoder()?
+            .writer()
+            .write(&buf[..buf.len().min(room)])?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder()?.writer().flush()
+    }
+}
+
+fn unfinished() -> io::Error {
+    io::Error::other("the previous member failed to finish")
+}
+
+/// The members of a zstd file after its comment, `None` for gzip files
+fn zstd_members(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        return Some(bytes);
+    }
+    let len = bytes.strip_prefix(&SKIPPABLE_MAGIC)?.get(..4)?;
+    let len = u32::from_le_bytes(len.try_into().ok()?) as usize;
+    Some(bytes.get(8 + len..).unwrap_or_default())
+}
+
+/// The comment written before the first member
+pub(super) fn comment(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.starts_with(&SKIPPABLE_MAGIC) {
+        let members = zstd_members(bytes)?;
+        return bytes
+            .get(8..bytes.len() - members.len())
+            .map(<[u8]>::to_vec);
+    }
+    flate2::read::GzDecoder::new(bytes)
+        .header()?
+        .comment()
+        .map(<[u8]>::to_vec)
+}
+
+/// Decompresses all members of a gzip or zstd file
+pub(super) fn decoder(bytes: &[u8]) -> io::Result<Box<dyn Read + '_>> {
+    Ok(match zstd_members(bytes) {
+        Some(_) => Box::new(zstd::Decoder::with_buffer(bytes)?),
+        None => Box::new(flate2::read::MultiGzDecoder::new(bytes)),
+    })
+}
+
+/// The JSON of the first member of `bytes` and what follows it
+fn member(bytes: &[u8], zstd: bool) -> io::Result<(Vec<u8>, &[u8])> {
+    let mut json = Vec::new();
+    if zstd {
+        let mut decoder = zstd::Decoder::with_buffer(bytes)?.single_frame();
+        decoder.read_to_end(&mut json)?;
+        Ok((json, decoder.finish()))
+    } else {
+        let mut decoder = flate2::bufread::GzDecoder::new(bytes);
+        decoder.read_to_end(&mut json)?;
+        Ok((json, decoder.into_inner()))
+    }
+}
+
+/// The JSON of the members up to the first one that doesn't decode or match
+/// its checksum. Fails when an intact member follows it, the file was damaged
+/// in the middle rather than cut off
+pub(super) fn verified(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (mut rest, zstd, magic) = match zstd_members(bytes) {
+        Some(members) => (members, true, &ZSTD_MAGIC[..]),
+        None => (bytes, false, &GZIP_MAGIC[..]),
+    };
+    let mut json = Vec::new();
+    while !rest.is_empty() {
+        match member(rest, zstd) {
+            Ok((mut member, after)) => {
+                json.append(&mut member);
+                rest = after;
+            }
+            Err(e) => {
+                if (1..rest.len()).any(|start| {
+                    rest[start..].starts_with(magic) && member(&rest[start..], zstd).is_ok()
+                }) {
+                    return Err(format!("{e}, and intact data follows"));
+                }
+                break;
+            }
+        }
+    }
+    Ok(json)
+}
@@ -0,0 +1,83 @@
+use super::ChecksumTree;
+use crate::hash::HashAlgorithm;
+use serde::de::Error as _;
+use serde_json::{Map, Value};
+
+/// Layout of the checksum files this version writes, recorded in them as
+/// `schema`. A change to the layout bumps it and adds the step upgrading the
+/// previous layout to `STEPS`, so older files keep being read without `--force`
+pub const SCHEMA: u32 = 1;
+
+/// Upgrades the JSON of a tree by one schema
+type Step = fn(&mut Map<String, Value>) -> serde_json::Result<()>;
+
+/// `STEPS[n]` upgrades a tree of schema `n` to schema `n + 1`
+const STEPS: [Step; SCHEMA as usize] = [full_form];
+
+impl ChecksumTree {
+    /// Reads the JSON of a tree of any schema up to `SCHEMA`. Trees of older
+    /// schemas are upgraded as a whole in memory, unlike current ones, which
+    /// are parsed as they're streamed
+    pub(super) fn migrated(json: &[u8]) -> serde_json::Result<Self> {
+        let mut value: Value = serde_json::from_slice(json)?;
+        let Value::Object(tree) = &mut value else {
+            return Err(serde_json::Error::custom("expected a checksum tree"));
+        };
+        let schema = match tree.get("schema") {
+            None => 0,
+            Some(schema) => schema
+                .as_u64()
+                .and_then(|schema| u32::try_from(schema).ok())
+                .ok_or_else(|| serde_json::Error::custom(format!("invalid schema {schema}")))?,
+        };
+        if schema > SCHEMA {
+            return Err(serde_json::Error::custom(format!(
+                "schema {schema} is newer than schema {SCHEMA} this version reads"
+            )));
+        }
+        for step in &STEPS[schema as usize..] {
+            step(tree)?;
+        }
+        tree.insert("schema".to_string(), SCHEMA.into());
+        serde_json::from_value(value)
+    }
+}
+
+/// Schema 0 to 1: elements spelled out instead of `d`, `f` and `l`, files as
+/// objects instead of bare checksums, and every digest naming its algorithm
+/// instead of one `hash` for the whole tree
+fn full_form(tree: &mut Map<String, Value>) -> serde_json::Result<()> {
+    let hash = match tree.remove("hash") {
+        Some(hash) => serde_json::from_value(hash)?,
+        None => HashAlgorithm::default(),
+    };
+    let mut stack: Vec<_> = tree.get_mut("root").into_iter().collect();
+    while let Some(element) = stack.pop() {
+        let Value::Object(element) = element else {
+            continue;
+        };
+        for (short, long) in [("d", "Directory"), ("f", "File"), ("l", "Symlink")] {
+            if let Some(value) = element.remove(short) {
+                element.insert(long.to_string(), value);
+            }
+        }
+        if let Some(file) = element.get_mut("File") {
+            if let Value::String(checksum) = file {
+                *file = Value::Object(Map::from_iter([(
+                    "checksum".to_string(),
+                    Value::String(std::mem::take(checksum)),
+                )]));
+            }
+            if let Some(Value::String(checksum)) = file.get_mut("checksum") {
+                // unnamed digests are otherwise taken for SHA-256
+                if HashAlgorithm::of(checksum).is_sha256() {
+                    *checksum = hash.tag(checksum);
+                }
+            }
+        }
+        if let Some(Value::Object(entries)) = element.get_mut("Directory") {
+            stack.extend(entries.values_mut());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use super::{intern, ChecksumElement, ChecksumTree, Entries, FileEntry, SCHEMA};
+
+/// Reads as much of a checksum tree as possible from JSON that was cut off,
+/// keeping only entries that were written completely
+pub(super) struct PartialJson<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PartialJson<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// The tree as far as it could be read, `None` when not even the root
+    /// could be read
+    pub(super) fn tree(&mut self) -> Option<ChecksumTree> {
+        if !self.eat(b'{') {
+            return None;
+        }
+        let mut tree = ChecksumTree {
+            version: String::new(),
+            root: None,
+            recovered: true,
+            ..Default::default()
+        };
+        while let Some(key) = self.string() {
+            if !self.eat(b':') {
+                break;
+            }
+            match key.as_str() {
+                "version" => match self.string() {
+                    Some(value) => tree.version = value,
+                    None => break,
+                },
+                // a layout this version doesn't know can't be read even in part
+                "schema" => match self.number() {
+                    Some(schema) if (0..=i64::from(SCHEMA)).contains(&schema) => {}
+                    Some(_) => return None,
+                    None => break,
+                },
+                "compression" => match self.string() {
+                    Some(value) => tree.compression = value.parse().ok(),
+                    None => break,
+                },
+                "root" => tree.root = self.element(),
+                _ => break,
+            }
+            if !self.eat(b',') {
+                break;
+            }
+        }
+        tree.root.as_ref()?;
+        Some(tree)
+    }
+
+    /// A directory keeps the entries read before the input ended, a file is
+    /// only returned when its checksum is complete
+    fn element(&mut self) -> Option<ChecksumElement> {
+        if !self.eat(b'{') {
+            return None;
+        }
+        let kind = self.string()?;
+        if !self.eat(b':') {
+            return None;
+        }
+        let element = match kind.as_str() {
+            "Directory" | "d" => ChecksumElement::Directory(self.directory()),
+            "File" | "f" => ChecksumElement::File(self.file()?),
+            "Symlink" | "l" => ChecksumElement::Symlink(self.string()?),
+            _ => return None,
+        };
+        self.eat(b'}');
+        Some(element)
+    }
+
+    fn directory(&mut self) -> Entries {
+        let mut entries = Entries::new();
+        if !self.eat(b'{') || self.eat(b'}') {
+            return entries;
+        }
+        while let Some(name) = self.string() {
+            if !self.eat(b':') {
+                break;
+            }
+            let Some(element) = self.element() else {
+                break;
+            };
+            entries.insert(intern(&name), element);
+            if !self.eat(b',') {
+                self.eat(b'}');
+                break;
+            }
+        }
+        entries
+    }
+
+    /// A plain checksum, or an entry with metadata when it's complete
+    fn file(&mut self) -> Option<FileEntry> {
+        if !self.eat(b'{') {
+            return self.string().map(FileEntry::from);
+        }
+        let mut entry = FileEntry::default();
+        let mut checksum = None;
+        loop {
+            let key = self.string()?;
+            if !self.eat(b':') {
+                return None;
+            }
+            match key.as_str() {
+                "checksum" => checksum = Some(self.string()?),
+                "size" => entry.size = Some(self.number()?.try_into().ok()?),
+                "mtime" => entry.mtime = Some(self.number()?),
+                "mode" => entry.mode = Some(self.number()?.try_into().ok()?),
+                "synced" => entry.synced = Some(self.number()?),
+                "quick_hash" => entry.quick_hash = Some(self.string()?),
+                _ => return None,
+            }
+            if !self.eat(b',') {
+                break;
+            }
+        }
+        if !self.eat(b'}') {
+            return None;
+        }
+        entry.checksum = checksum?;
+        Some(entry)
+    }
+
+    /// An integer followed by something else, a number the input ends in may
+    /// have been cut short
+    fn number(&mut self) -> Option<i64> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        if self.pos == self.bytes.len() {
+            return None;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// A string with its closing quote, `None` when the input ends before it
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let start = self.pos - 1;
+        let mut escaped = false;
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'\\' if !escaped => escaped = true,
+                b'"' if !escaped => {
+                    return serde_json::from_slice(&self.bytes[start..self.pos]).ok()
+                }
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    /// Consumes `byte` after any whitespace
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+}
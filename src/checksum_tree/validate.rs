@@ -0,0 +1,242 @@
+use super::{members, ChecksumElement, ChecksumFileError, ChecksumTree, Entries};
+use crate::hash::HashAlgorithm;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use std::{
+    collections::HashSet,
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Something wrong with a checksum file, found by `ChecksumTree::validate`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The file can't be loaded as it is, for the given reason
+    Unreadable(String),
+    /// The upload writing it was interrupted, entries after the cut are lost
+    CutOff,
+    /// Written by a newer version of syncbox, or with no version at all
+    Version(String),
+    /// A directory lists the same name twice, only the last entry is used
+    DuplicateName(PathBuf),
+    /// A name that can't be part of a path, like `..` or one with a slash
+    InvalidName(PathBuf),
+    /// An entry next to the synced directory `.` instead of in it
+    OutsideRoot(PathBuf),
+    /// A checksum that's empty or names an unknown algorithm
+    InvalidChecksum(PathBuf),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(reason) => write!(f, "{reason}"),
+            Self::CutOff => write!(f, "the file was cut off, entries after the cut are lost"),
+            Self::Version(version) if version.is_empty() => write!(f, "no version recorded"),
+            Self::Version(version) => write!(f, "written by a newer syncbox {version}"),
+            Self::DuplicateName(path) => write!(f, "{path:?} is listed more than once"),
+            Self::InvalidName(path) => write!(f, "{path:?} is not a valid path"),
+            Self::OutsideRoot(path) => write!(f, "{path:?} is outside of the synced directory"),
+            Self::InvalidChecksum(path) => write!(f, "{path:?} has an invalid checksum"),
+        }
+    }
+}
+
+/// What `ChecksumTree::validate` found
+#[derive(Debug)]
+pub struct Validation {
+    pub problems: Vec<Problem>,
+    /// The tree without the entries problems were found in, stamped with the
+    /// running version. `None` when nothing could be read at all
+    pub repaired: Option<ChecksumTree>,
+}
+
+impl ChecksumTree {
+    /// Checks a compressed checksum file as read from the remote. Entries the
+    /// problems were found in are left out of the repaired tree, so the next
+    /// sync uploads them again
+    pub fn validate(bytes: &[u8]) -> Validation {
+        let mut problems = vec![];
+        let mut json = Vec::new();
+        let decoded = members::decoder(bytes)
+            .and_then(|mut decoder| decoder.read_to_end(&mut json))
+            .is_ok();
+        let tree = match Self::from_compressed(bytes) {
+            Ok(tree) => {
+                if tree.is_recovered() {
+                    problems.push(Problem::CutOff);
+                }
+                Some(tree)
+            }
+            Err(e) => {
+                problems.push(Problem::Unreadable(e.to_string()));
+                // a digest that doesn't match doesn't mean all of it is lost
+                Self::checked(Self::migrated(&json), ChecksumFileError::Corrupted).ok()
+            }
+        };
+        if decoded {
+            let mut duplicates = vec![];
+            let mut deserializer = serde_json::Deserializer::from_slice(&json);
+            // invalid JSON was reported above
+            TreeNames(&mut duplicates)
+                .deserialize(&mut deserializer)
+                .ok();
+            problems.extend(duplicates.into_iter().map(Problem::DuplicateName));
+        }
+        let repaired = tree.map(|mut tree| {
+            let current = env!("CARGO_PKG_VERSION");
+            if tree.version.is_empty() || current < tree.version.as_str() {
+                problems.push(Problem::Version(tree.version.clone()));
+            }
+            tree.version = current.to_string();
+            tree.recovered = false;
+            if let Some(ChecksumElement::Directory(entries)) = &mut tree.root {
+                entries.retain(|name, _| {
+                    let inside = &**name == ".";
+                    if !inside {
+                        problems.push(Problem::OutsideRoot(PathBuf::from(&**name)));
+                    }
+                    inside
+                });
+                if let Some(ChecksumElement::Directory(entries)) = entries.get_mut(".") {
+                    check_entries(entries, Path::new("."), &mut problems);
+                }
+            }
+            tree
+        });
+        Validation { problems, repaired }
+    }
+}
+
+/// Removes the entries below `path` the reconciler can't use
+fn check_entries(entries: &mut Entries, path: &Path, problems: &mut Vec<Problem>) {
+    entries.retain(|name, element| {
+        let path = path.join(&**name);
+        if name.is_empty() || &**name == "." || &**name == ".." || name.contains(['/', '\\', '\0'])
+        {
+            problems.push(Problem::InvalidName(path));
+            return false;
+        }
+        match element {
+            ChecksumElement::Directory(entries) => check_entries(entries, &path, problems),
+            ChecksumElement::File(entry) => {
+                let known = match entry.checksum.split_once(':') {
+                    Some((algorithm, _)) => algorithm.parse::<HashAlgorithm>().is_ok(),
+                    None => !entry.checksum.is_empty(),
+                };
+                if !known {
+                    problems.push(Problem::InvalidChecksum(path));
+                    return false;
+                }
+            }
+            ChecksumElement::Symlink(_) => {}
+        }
+        true
+    });
+}
+
+/// Walks the JSON of a tree collecting names a directory lists more than
+/// once, which parsing it into maps silently drops
+struct TreeNames<'a>(&'a mut Vec<PathBuf>);
+
+impl<'de> DeserializeSeed<'de> for TreeNames<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TreeNames<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a checksum tree")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "root" {
+                map.next_value_seed(ElementNames {
+                    path: PathBuf::new(),
+                    duplicates: &mut *self.0,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An element at `path`, only directories are looked into
+struct ElementNames<'a> {
+    path: PathBuf,
+    duplicates: &'a mut Vec<PathBuf>,
+}
+
+impl<'de> DeserializeSeed<'de> for ElementNames<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ElementNames<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a directory, file or link")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(kind) = map.next_key::<String>()? {
+            if !matches!(kind.as_str(), "Directory" | "d") {
+                map.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            map.next_value_seed(DirectoryNames {
+                path: self.path.clone(),
+                duplicates: &mut *self.duplicates,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+struct DirectoryNames<'a> {
+    path: PathBuf,
+    duplicates: &'a mut Vec<PathBuf>,
+}
+
+impl<'de> DeserializeSeed<'de> for DirectoryNames<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DirectoryNames<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "directory entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut seen = HashSet::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let path = self.path.join(&name);
+            if !seen.insert(name) {
+                self.duplicates.push(path.clone());
+            }
+            map.next_value_seed(ElementNames {
+                path,
+                duplicates: &mut *self.duplicates,
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use std::{fmt, str::FromStr};
+
+/// Mode bits given to uploaded files and created directories, instead of
+/// whatever the server defaults to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChmodPolicy {
+    pub files: Option<u32>,
+    pub dirs: Option<u32>,
+}
+
+impl FromStr for ChmodPolicy {
+    type Err = String;
+
+    /// Parses `files=644,dirs=755`, either part may be left out
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (target, mode) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected files=MODE or dirs=MODE, got {part:?}"))?;
+            let mode = u32::from_str_radix(mode, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| format!("invalid octal mode {mode:?}"))?;
+            match target {
+                "files" => policy.files = Some(mode),
+                "dirs" => policy.dirs = Some(mode),
+                _ => return Err(format!("unknown target {target:?}, expected files or dirs")),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for ChmodPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [("files", self.files), ("dirs", self.dirs)]
+            .into_iter()
+            .filter_map(|(target, mode)| Some(format!("{target}={:o}", mode?)))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_targets() {
+        let policy = "files=644,dirs=0755".parse::<ChmodPolicy>().unwrap();
+        assert_eq!(policy.files, Some(0o644));
+        assert_eq!(policy.dirs, Some(0o755));
+        assert_eq!(policy.to_string(), "files=644,dirs=755");
+    }
+
+    #[test]
+    fn parts_are_optional() {
+        let policy = "dirs=750".parse::<ChmodPolicy>().unwrap();
+        assert_eq!(policy.files, None);
+        assert_eq!(policy.to_string(), "dirs=750");
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        for policy in ["files=999", "files", "other=644", "files=17777"] {
+            assert!(policy.parse::<ChmodPolicy>().is_err(), "{policy}");
+        }
+    }
+}
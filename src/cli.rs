@@ -0,0 +1,889 @@
+use crate::{
+    chmod::ChmodPolicy,
+    collision::CollisionPolicy,
+    compression::{ChecksumCompression, ChecksumFormat, ObjectCompression},
+    conflict::ConflictPolicy,
+    daemon::{self, Schedule},
+    deadline::RunTimeout,
+    drift::DriftPolicy,
+    guard::DEFAULT_MAX_FILES,
+    hash::{HashAlgorithm, MetadataChangePolicy},
+    i18n::Lang,
+    links::LinkPolicy,
+    lock,
+    prefix::PrefixTemplate,
+    shard::Shard,
+    snapshots::RetentionPolicy,
+};
+use clap::{
+    builder::{styling::AnsiColor, Styles},
+    ColorChoice, Parser, Subcommand,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::level_filters::LevelFilter;
+
+const DEFAULT_FILE_SIZE_THRESHOLD: u64 = 1;
+
+fn get_styles() -> Styles {
+    Styles::styled()
+        .header(AnsiColor::Yellow.on_default())
+        .usage(AnsiColor::Green.on_default())
+        .literal(AnsiColor::Green.on_default())
+        .placeholder(AnsiColor::Green.on_default())
+}
+
+/// Fast sync with remote filesystem
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, styles = get_styles(), args_override_self = true)]
+pub struct Args {
+    #[arg(
+        long,
+        help = "Start from the options of this profile in syncbox.toml, options given on the command line win",
+        env = "SYNCBOX_PROFILE"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        help = "Name of the checksum file",
+        default_value = "./.syncbox.json.gz",
+        env = "SYNCBOX_CHECKSUM_FILE"
+    )]
+    pub checksum_file: String,
+
+    #[arg(
+        long,
+        help = "Compression of the checksum file, gzip or zstd. Either is read back",
+        default_value = "gzip",
+        env = "SYNCBOX_CHECKSUM_COMPRESSION"
+    )]
+    pub checksum_compression: ChecksumFormat,
+
+    #[arg(
+        long,
+        help = "Compression level of the checksum file, 1 to 9 for gzip and 1 to 22 for zstd [default: 6 for gzip, 3 for zstd]",
+        value_parser = clap::value_parser!(u32).range(1..=22),
+        env = "SYNCBOX_CHECKSUM_COMPRESSION_LEVEL"
+    )]
+    pub checksum_compression_level: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Sync below this remote path, may use {git_branch}, {git_sha}, {date} and {hostname}, e.g. previews/{git_branch}",
+        visible_alias = "remote-prefix",
+        env = "SYNCBOX_PREFIX_TEMPLATE"
+    )]
+    pub prefix_template: Option<PrefixTemplate>,
+
+    #[arg(
+        long,
+        help = "Stop starting new actions in time to upload the checksum file before the run has taken this long, e.g. 50m",
+        env = "SYNCBOX_RUN_TIMEOUT"
+    )]
+    pub run_timeout: Option<RunTimeout>,
+
+    #[arg(
+        long,
+        help = "Will skip execution and only creates the checksum file",
+        default_value_t = false
+    )]
+    pub checksum_only: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Will upload checksum file every N files",
+        default_value_t = 0,
+        env = "SYNCBOX_INTERMITTENT_CHECKSUM_UPLOAD"
+    )]
+    pub intermittent_checksum_upload: usize,
+
+    #[arg(
+        long,
+        help = "Keep the state of the remote in a local SQLite database, committed after every file",
+        env = "SYNCBOX_STATE_DB"
+    )]
+    pub state_db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[arg(
+        long,
+        help = "Ignore corrupted checksum file and override",
+        default_value_t = false
+    )]
+    pub force: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Concurrency limit",
+        default_value_t = 1,
+        env = "SYNCBOX_CONCURRENCY"
+    )]
+    pub concurrency: usize,
+
+    #[arg(
+        long,
+        help = "Number of files checksummed at once, defaults to the number of CPUs",
+        env = "SYNCBOX_SCAN_THREADS"
+    )]
+    pub scan_threads: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Scan and execute one file at a time in a stable order and print plain lines instead of progress bars, for reproducible runs",
+        default_value_t = false,
+        env = "SYNCBOX_DETERMINISTIC"
+    )]
+    pub deterministic: bool,
+
+    #[arg(
+        long,
+        help = "Print the plan and the results as aligned tables instead of a line per action",
+        default_value_t = false,
+        env = "SYNCBOX_TABLE"
+    )]
+    pub table: bool,
+
+    #[arg(
+        long,
+        help = "When to use colors, auto leaves them out when the output isn't a terminal",
+        default_value_t = ColorChoice::Auto,
+        env = "SYNCBOX_COLOR"
+    )]
+    pub color: ColorChoice,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Read the real remote but only pretend to change it or the local files, printing what would be done",
+        default_value_t = false,
+        env = "SYNCBOX_DRY_RUN"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Show a desktop notification when the sync finishes or fails",
+        default_value_t = false,
+        env = "SYNCBOX_NOTIFY"
+    )]
+    pub notify: bool,
+
+    #[arg(
+        long,
+        help = "Exit with 9 when there was nothing to do, so scripts can tell whether anything changed",
+        default_value_t = false,
+        env = "SYNCBOX_DETAILED_EXIT_CODES"
+    )]
+    pub detailed_exit_codes: bool,
+
+    #[arg(
+        long,
+        help = "Language of the output, en or cs, detected from LC_ALL, LC_MESSAGES or LANG by default",
+        env = "SYNCBOX_LANG"
+    )]
+    pub lang: Option<Lang>,
+
+    #[arg(
+        long,
+        help = "Files of size below this threshold (in MBs) will be read and digested (see --hash), the others will use metadata as the checksum",
+        default_value_t = DEFAULT_FILE_SIZE_THRESHOLD,
+        env = "SYNCBOX_FILE_THRESHOLD"
+    )]
+    pub file_size_threshold: u64,
+
+    #[arg(
+        long,
+        help = "Digest for files below the size threshold: sha256, blake3 (faster, uses all cores), xxh3 (fastest, not cryptographic) or metadata (size and times only)",
+        default_value_t = HashAlgorithm::Sha256,
+        env = "SYNCBOX_HASH"
+    )]
+    pub hash: HashAlgorithm,
+
+    #[arg(
+        long,
+        help = "What to do with a file above the size threshold whose times changed but not its size: upload (again) or quick-hash (compare its first and last megabyte before uploading, e.g. after touch or git checkout)",
+        default_value_t = MetadataChangePolicy::Upload,
+        env = "SYNCBOX_METADATA_CHANGES"
+    )]
+    pub metadata_changes: MetadataChangePolicy,
+
+    #[arg(
+        long,
+        help = "Read every file again instead of reusing the checksums of files whose size and modification time didn't change",
+        default_value_t = false,
+        env = "SYNCBOX_NO_SCAN_CACHE"
+    )]
+    pub no_scan_cache: bool,
+
+    #[arg(
+        long,
+        help = "What to do with symbolic links: follow (uploads what they point to), preserve (recreates them on SFTP and local destinations) or skip",
+        default_value_t = LinkPolicy::Skip,
+        env = "SYNCBOX_LINKS"
+    )]
+    pub links: LinkPolicy,
+
+    #[arg(short, long, default_value_t = false)]
+    pub skip_removal: bool,
+
+    #[arg(
+        long,
+        help = "Print what the plan amounts to, by kind of action and by directory, before executing it",
+        default_value_t = false,
+        env = "SYNCBOX_SUMMARY"
+    )]
+    pub summary: bool,
+
+    #[arg(
+        long,
+        help = "Remove files from the remote before uploading any, for destinations short on space",
+        default_value_t = false,
+        env = "SYNCBOX_DELETE_FIRST"
+    )]
+    pub delete_first: bool,
+
+    #[arg(
+        help = "Directory to diff against",
+        default_value = ".",
+        env = "SYNCBOX_DIRECTORY"
+    )]
+    pub directory: String,
+
+    #[arg(
+        long,
+        help = "Ask before syncing more files than this, a mistyped directory argument may point at far more than intended",
+        default_value_t = DEFAULT_MAX_FILES,
+        env = "SYNCBOX_MAX_FILES"
+    )]
+    pub max_files: usize,
+
+    #[arg(
+        long,
+        help = "Ask before removing more files than this from the remote",
+        env = "SYNCBOX_MAX_DELETE"
+    )]
+    pub max_delete: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Sync the filesystem root, a home directory or more than --max-files files without asking",
+        default_value_t = false,
+        env = "SYNCBOX_YES_I_MEAN_IT"
+    )]
+    pub yes_i_mean_it: bool,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Sync only the files matching this glob, may be repeated, remote files not matching are left alone too"
+    )]
+    pub include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Leave out paths matching this glob, may be repeated, unlike .syncboxignore they aren't removed from the remote either"
+    )]
+    pub exclude: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "DIRECTORY",
+        help = "Sync only what's below this directory, e.g. public/assets, may be repeated, the rest of the remote is left alone"
+    )]
+    pub only: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Compare paths ignoring letter case, for case-insensitive destinations",
+        default_value_t = false,
+        env = "SYNCBOX_CASE_INSENSITIVE"
+    )]
+    pub case_insensitive: bool,
+
+    #[arg(
+        long,
+        help = "What to do when the remote has a directory where a file goes or vice versa: fail, replace or rename (moves it aside)",
+        default_value_t = CollisionPolicy::Fail,
+        env = "SYNCBOX_ON_COLLISION"
+    )]
+    pub on_collision: CollisionPolicy,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        help = "List the remote and compare it with the checksum file, for files changed or deleted behind syncbox's back: reupload (uploads them again), adopt (keeps remote changes to files unchanged locally) or error",
+        conflicts_with = "compress",
+        env = "SYNCBOX_DRIFT"
+    )]
+    pub drift: Option<DriftPolicy>,
+
+    #[arg(
+        long,
+        help = "How bisync and pull settle files that differ locally and on the remote: skip, newer-wins, local-wins, remote-wins, keep-both (keeps the local version beside the remote one) or ask [default: skip for bisync, remote-wins for pull]",
+        env = "SYNCBOX_ON_CONFLICT"
+    )]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    #[arg(
+        long,
+        help = "Upload into a temporary file and rename it into place once complete",
+        default_value_t = false,
+        env = "SYNCBOX_ATOMIC_UPLOADS"
+    )]
+    pub atomic_uploads: bool,
+
+    #[arg(
+        long,
+        help = "Copy files on the remote when a file with the same checksum is already there, instead of uploading them (S3)",
+        default_value_t = false,
+        env = "SYNCBOX_SERVER_SIDE_COPY"
+    )]
+    pub server_side_copy: bool,
+
+    #[arg(
+        long,
+        help = "Compress file contents before uploading them, the stored files get a suffix like .gz",
+        env = "SYNCBOX_COMPRESS"
+    )]
+    pub compress: Option<ObjectCompression>,
+
+    #[arg(
+        long,
+        help = "Download files into this local directory before removing them from the remote",
+        env = "SYNCBOX_ARCHIVE_REMOVED"
+    )]
+    pub archive_removed: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Maximum number of requests per second across all connections [default: 3500 for S3, unlimited otherwise]",
+        env = "SYNCBOX_MAX_RPS"
+    )]
+    pub max_rps: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Set the modification time of uploaded files to the one of the local file",
+        default_value_t = false,
+        env = "SYNCBOX_PRESERVE_MTIME"
+    )]
+    pub preserve_mtime: bool,
+
+    #[arg(
+        long,
+        help = "Seconds the remote clock may differ from the local one before warning, checked when preserving modification times",
+        default_value_t = 2.0,
+        env = "SYNCBOX_MTIME_TOLERANCE"
+    )]
+    pub mtime_tolerance: f64,
+
+    #[arg(
+        long,
+        help = "Only execute the i-th of N deterministic slices of the plan, e.g. 1/4, merging into the remote checksum",
+        env = "SYNCBOX_SHARD"
+    )]
+    pub shard: Option<Shard>,
+
+    #[arg(
+        long,
+        value_parser = lock::parse_ttl,
+        default_value = "10m",
+        help = "How long the lock on the remote outlives a run that was killed, it's refreshed while the run goes on",
+        env = "SYNCBOX_LOCK_TTL"
+    )]
+    pub lock_ttl: Duration,
+
+    #[arg(
+        long,
+        help = "Change the remote without taking its lock, for remotes nothing else syncs to",
+        default_value_t = false,
+        env = "SYNCBOX_NO_LOCK"
+    )]
+    pub no_lock: bool,
+
+    #[arg(
+        long,
+        help = "Take the lock on the remote even when another run holds it, for a run that's known to be gone",
+        default_value_t = false,
+        conflicts_with = "no_lock"
+    )]
+    pub break_lock: bool,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        help = "Shell command asked about every planned action before executing, it reads lines like `remove ./a.txt` and answers each with keep, drop, defer or conflict",
+        env = "SYNCBOX_FILTER_CMD"
+    )]
+    pub filter_cmd: Option<String>,
+
+    #[arg(
+        long,
+        help = "Replicate file mode bits on SFTP and local destinations",
+        default_value_t = false,
+        env = "SYNCBOX_PRESERVE_PERMISSIONS"
+    )]
+    pub preserve_permissions: bool,
+
+    #[arg(
+        long,
+        help = "Together with --preserve-permissions also replicate uid/gid (needs root on the destination)",
+        default_value_t = false,
+        env = "SYNCBOX_PRESERVE_OWNER"
+    )]
+    pub preserve_owner: bool,
+
+    #[arg(
+        long,
+        help = "Mode bits for uploaded files and created directories, e.g. files=644,dirs=755 (SFTP, FTP and local destinations)",
+        default_value_t = ChmodPolicy::default(),
+        hide_default_value = true,
+        conflicts_with = "preserve_permissions",
+        env = "SYNCBOX_CHMOD"
+    )]
+    pub chmod: ChmodPolicy,
+
+    #[arg(
+        long,
+        help = "Check the size of every uploaded file on the remote before marking it as synced",
+        default_value_t = false,
+        env = "SYNCBOX_VERIFY_UPLOADS"
+    )]
+    pub verify_uploads: bool,
+
+    #[arg(
+        long,
+        help = "Stream progress events as JSON lines over a Unix socket at this path and accept pause, resume, status and cancel commands",
+        env = "SYNCBOX_CONTROL_SOCKET"
+    )]
+    pub control_socket: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Log at this level: error, warn, info, debug or trace. The terminal shows warnings only when there's a --log-file [default: warn, info in the log file]",
+        env = "SYNCBOX_LOG_LEVEL"
+    )]
+    pub log_level: Option<LevelFilter>,
+
+    #[arg(
+        long,
+        help = "Append a log of every action, with timestamps and the messages of the transports, to this file",
+        env = "SYNCBOX_LOG_FILE"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Keep a timestamped copy of the checksum file on the remote after every successful run",
+        default_value_t = false,
+        env = "SYNCBOX_SNAPSHOTS"
+    )]
+    pub snapshots: bool,
+
+    #[arg(
+        long,
+        help = "Together with --snapshots keep only the N most recent snapshots",
+        env = "SYNCBOX_KEEP_LAST"
+    )]
+    pub keep_last: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Together with --snapshots keep the newest snapshot of each of the last N days",
+        env = "SYNCBOX_KEEP_DAILY"
+    )]
+    pub keep_daily: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Together with --snapshots keep the newest snapshot of each of the last N weeks",
+        env = "SYNCBOX_KEEP_WEEKLY"
+    )]
+    pub keep_weekly: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Together with --snapshots keep the newest snapshot of each of the last N months",
+        env = "SYNCBOX_KEEP_MONTHLY"
+    )]
+    pub keep_monthly: Option<usize>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Sync(TransportType),
+    /// Ask for a transport and its credentials, test the connection and
+    /// write a profile to syncbox.toml, the credentials to .env.syncbox and a
+    /// starter .syncboxignore
+    Init {
+        #[arg(
+            long,
+            help = "Replace an existing .env.syncbox and .syncboxignore",
+            default_value_t = false
+        )]
+        force: bool,
+    },
+    /// Print statistics about the files in the directory
+    Stats {
+        #[arg(
+            long,
+            help = "Report groups of duplicate files and the bytes they waste",
+            default_value_t = false
+        )]
+        dupes: bool,
+    },
+    /// List checksum snapshots kept on the remote
+    Snapshots {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Inspect the checksum file on the remote
+    Checksum {
+        #[command(subcommand)]
+        command: ChecksumCommand,
+    },
+    /// Show when files last reached the remote
+    #[command(subcommand_precedence_over_arg = true)]
+    Log {
+        #[arg(help = "A synced file, or a directory to list every file below it")]
+        path: PathBuf,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// List what the checksum file on the remote has in a directory, with
+    /// sizes and when each file was synced
+    #[command(subcommand_precedence_over_arg = true)]
+    Ls {
+        #[arg(help = "A synced directory or file, the synced directory when left out")]
+        path: Option<PathBuf>,
+        #[arg(
+            long,
+            short,
+            help = "List everything below the directory",
+            default_value_t = false
+        )]
+        recursive: bool,
+        #[arg(
+            long,
+            help = "List the remote as well to show what's missing, differs or isn't in the checksum file",
+            default_value_t = false
+        )]
+        live: bool,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Remove what's on the remote but not in the checksum file, like files
+    /// left behind by crashed runs or uploaded by hand
+    Prune {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Take over a remote that was deployed without syncbox: files already
+    /// there with the same size are marked as synced, only the rest is uploaded
+    Adopt {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Show what a sync would do against the checksum file on the remote,
+    /// without changing anything
+    Plan {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Compare the files on the remote with the checksum file and report
+    /// the ones missing, differing or not in it
+    Verify {
+        #[arg(
+            long,
+            help = "Download the files to compare their contents, not only their sizes",
+            default_value_t = false
+        )]
+        hashes: bool,
+        #[arg(
+            long,
+            help = "Upload missing and differing files again, files changed locally since are left for the next sync",
+            default_value_t = false
+        )]
+        repair: bool,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Make the local directory match the checksum file on the remote:
+    /// download what is missing or differs and remove what the remote doesn't
+    /// have, e.g. to rebuild a machine from its backup
+    Pull {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Download the files in the checksum file on the remote, or those below
+    /// a path, into another directory and check them against their checksums
+    #[command(subcommand_precedence_over_arg = true)]
+    Restore {
+        #[arg(help = "A synced file or directory, everything when left out")]
+        path: Option<PathBuf>,
+        #[arg(long, short, help = "Directory to restore into, created if missing")]
+        target: PathBuf,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Sync both ways: upload what changed locally and download what changed
+    /// on the remote since the last two-way sync, files changed on both sides
+    /// are reported as conflicts and left alone
+    Bisync {
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Sync on a schedule in a long running process. Syncs never overlap,
+    /// runs due while one is still going are skipped
+    Daemon {
+        #[arg(
+            long,
+            help = "An interval like 6h or 30m, or a cron expression like \"0 3 * * *\" in local time"
+        )]
+        every: Schedule,
+        #[arg(
+            long,
+            value_parser = daemon::parse_jitter,
+            help = "Delay every run by a random time up to this, e.g. 5m, so machines sharing a schedule don't sync at once"
+        )]
+        jitter: Option<Duration>,
+        #[arg(
+            long,
+            help = "Keep the state of the daemon and the result of the last sync in this JSON file"
+        )]
+        status_file: Option<PathBuf>,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+    /// Watch the given files and upload each one as soon as it is saved
+    #[command(subcommand_precedence_over_arg = true)]
+    PushOnSave {
+        #[arg(required = true, help = "Files to watch, inside the synced directory")]
+        paths: Vec<PathBuf>,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ChecksumCommand {
+    /// Report damage, unknown versions, duplicate names and entries syncbox
+    /// can't use in the checksum file
+    Validate {
+        #[arg(
+            long,
+            help = "Replace the checksum file with a copy leaving out the entries problems were found in, the original is kept with a .broken suffix",
+            default_value_t = false
+        )]
+        repair: bool,
+        #[command(subcommand)]
+        transport: TransportType,
+    },
+}
+
+#[derive(Clone, Debug, Parser)]
+pub enum TransportType {
+    Ftp {
+        #[arg(long, env = "FTP_HOST")]
+        ftp_host: String,
+        #[arg(long, env = "FTP_USER")]
+        ftp_user: String,
+        #[arg(long, env = "FTP_PASS")]
+        ftp_pass: String,
+        #[arg(long, default_value = ".", env = "FTP_DIR")]
+        ftp_dir: String,
+        #[arg(long, default_value_t = false, env = "FTP_USE_TLS")]
+        use_tls: bool,
+        #[arg(
+            long,
+            help = "Compress transfers with MODE Z when the server supports it",
+            default_value_t = false,
+            env = "FTP_COMPRESSION"
+        )]
+        compression: bool,
+    },
+    Sftp {
+        #[arg(long, env = "SFTP_HOST")]
+        host: String,
+        #[arg(long, env = "SFTP_USER")]
+        user: String,
+        /// Leave empty to authenticate with ssh keys or the agent
+        #[arg(long, default_value = "", env = "SFTP_PASS")]
+        pass: String,
+        #[arg(long, default_value = ".", env = "SFTP_DIR")]
+        dir: String,
+        #[arg(
+            long,
+            help = "Reach the host through this SSH jump host, e.g. user@bastion:2222, comma separate several hops",
+            env = "SFTP_PROXY_JUMP"
+        )]
+        proxy_jump: Option<String>,
+    },
+    Local {
+        #[arg(long, short)]
+        destination: String,
+        #[arg(
+            long,
+            help = "Move removed files to the trash instead of deleting them",
+            default_value_t = false
+        )]
+        use_trash: bool,
+    },
+    S3 {
+        #[arg(long, env = "S3_BUCKET")]
+        bucket: String,
+        #[arg(long, env = "S3_REGION")]
+        region: String,
+        #[arg(long, env = "S3_ACCESS_KEY")]
+        access_key: String,
+        #[arg(long, env = "S3_SECRET_KEY")]
+        secret_key: String,
+        #[arg(long, default_value = "STANDARD", env = "S3_STORAGE_CLASS")]
+        storage_class: String,
+        #[arg(long, default_value = ".", env = "S3_DIRECTORY")]
+        directory: String,
+        #[arg(
+            long,
+            help = "URL of an S3 compatible service like MinIO, instead of AWS",
+            env = "S3_ENDPOINT"
+        )]
+        endpoint: Option<String>,
+    },
+    Dry,
+}
+
+impl TransportType {
+    /// Request rate the backend is known to cope with
+    pub fn default_max_rps(&self) -> Option<f64> {
+        match self {
+            // S3 starts throttling writes to a single prefix above this
+            TransportType::S3 { .. } => Some(3500.0),
+            _ => None,
+        }
+    }
+}
+
+impl TransportType {
+    /// Moves the remote base path down by `prefix`
+    pub fn push_prefix(&mut self, prefix: &str) {
+        let dir = match self {
+            TransportType::Ftp { ftp_dir, .. } => ftp_dir,
+            TransportType::Sftp { dir, .. } => dir,
+            TransportType::Local { destination, .. } => destination,
+            TransportType::S3 { directory, .. } => directory,
+            TransportType::Dry => return,
+        };
+        *dir = format!("{}/{prefix}", dir.trim_end_matches('/'));
+    }
+
+    /// Names the destination in the state database, so syncing the same
+    /// directory to several remotes keeps a separate state for each
+    pub fn remote_id(&self) -> String {
+        match self {
+            TransportType::Ftp {
+                ftp_host,
+                ftp_user,
+                ftp_dir,
+                ..
+            } => format!("ftp://{ftp_user}@{ftp_host}/{ftp_dir}"),
+            TransportType::Sftp {
+                host, user, dir, ..
+            } => format!("sftp://{user}@{host}/{dir}"),
+            TransportType::Local { destination, .. } => {
+                let destination = Path::new(destination);
+                let destination = destination
+                    .canonicalize()
+                    .unwrap_or_else(|_| destination.to_path_buf());
+                format!("file://{}", destination.display())
+            }
+            TransportType::S3 {
+                bucket,
+                directory,
+                endpoint,
+                ..
+            } => match endpoint {
+                Some(endpoint) => {
+                    format!("{}/{bucket}/{directory}", endpoint.trim_end_matches('/'))
+                }
+                None => format!("s3://{bucket}/{directory}"),
+            },
+            TransportType::Dry => "dry".to_string(),
+        }
+    }
+}
+
+impl Args {
+    pub fn checksum_compression(&self) -> ChecksumCompression {
+        ChecksumCompression {
+            format: self.checksum_compression,
+            level: self.checksum_compression_level,
+        }
+    }
+
+    pub fn transport(&self) -> Option<&TransportType> {
+        match &self.command {
+            Command::Sync(transport) => Some(transport),
+            Command::Snapshots { transport } => Some(transport),
+            Command::Checksum {
+                command: ChecksumCommand::Validate { transport, .. },
+            } => Some(transport),
+            Command::Log { transport, .. } => Some(transport),
+            Command::Ls { transport, .. } => Some(transport),
+            Command::Prune { transport, .. } => Some(transport),
+            Command::Adopt { transport } => Some(transport),
+            Command::Plan { transport } => Some(transport),
+            Command::Verify { transport, .. } => Some(transport),
+            Command::Pull { transport } => Some(transport),
+            Command::Restore { transport, .. } => Some(transport),
+            Command::Bisync { transport } => Some(transport),
+            Command::Daemon { transport, .. } => Some(transport),
+            Command::PushOnSave { transport, .. } => Some(transport),
+            Command::Init { .. } | Command::Stats { .. } => None,
+        }
+    }
+
+    pub fn transport_mut(&mut self) -> Option<&mut TransportType> {
+        match &mut self.command {
+            Command::Sync(transport) => Some(transport),
+            Command::Snapshots { transport } => Some(transport),
+            Command::Checksum {
+                command: ChecksumCommand::Validate { transport, .. },
+            } => Some(transport),
+            Command::Log { transport, .. } => Some(transport),
+            Command::Ls { transport, .. } => Some(transport),
+            Command::Prune { transport, .. } => Some(transport),
+            Command::Adopt { transport } => Some(transport),
+            Command::Plan { transport } => Some(transport),
+            Command::Verify { transport, .. } => Some(transport),
+            Command::Pull { transport } => Some(transport),
+            Command::Restore { transport, .. } => Some(transport),
+            Command::Bisync { transport } => Some(transport),
+            Command::Daemon { transport, .. } => Some(transport),
+            Command::PushOnSave { transport, .. } => Some(transport),
+            Command::Init { .. } | Command::Stats { .. } => None,
+        }
+    }
+
+    /// Whether the remote is left alone, with `--dry-run` or the `dry` transport
+    pub fn is_dry(&self) -> bool {
+        self.dry_run || matches!(self.transport(), Some(TransportType::Dry))
+    }
+
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last: self.keep_last,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+        }
+    }
+}
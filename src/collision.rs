@@ -0,0 +1,242 @@
+use crate::transport::{is_not_found, Transport};
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// What to do when the remote has a directory where a file should go, or the other way around
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Report the collision and leave the remote untouched
+    #[default]
+    Fail,
+    /// Remove whatever is in the way
+    Replace,
+    /// Move whatever is in the way aside, see `conflict_path`
+    Rename,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "replace" => Ok(Self::Replace),
+            "rename" => Ok(Self::Rename),
+            _ => Err(format!(
+                "unknown collision policy {s:?}, expected fail, replace or rename"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fail => write!(f, "fail"),
+            Self::Replace => write!(f, "replace"),
+            Self::Rename => write!(f, "rename"),
+        }
+    }
+}
+
+/// Where `Rename` moves a colliding entry, e.g. `dir/name.syncbox-conflict-1700000000`
+pub fn conflict_path(path: &Path, at: SystemTime) -> PathBuf {
+    let secs = at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{file_name}.syncbox-conflict-{secs}"))
+}
+
+/// Called after creating `path` failed, checks whether something of the other kind
+/// is in the way and clears it according to `policy`.
+/// Returns whether the operation should be retried.
+pub async fn resolve_collision(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    want_dir: bool,
+    policy: CollisionPolicy,
+) -> Result<bool, Box<dyn Error + Send + Sync + 'static>> {
+    let Some(entry) = transport.stat(path).await.unwrap_or(None) else {
+        return Ok(false);
+    };
+    if entry.is_dir == want_dir {
+        return Ok(false);
+    }
+    let existing = if entry.is_dir { "directory" } else { "file" };
+    match policy {
+        CollisionPolicy::Fail => {
+            return Err(format!(
+                "{path:?} already exists on the remote as a {existing} (see --on-collision)"
+            )
+            .into())
+        }
+        CollisionPolicy::Replace if entry.is_dir => remove_all(transport, path).await?,
+        CollisionPolicy::Replace => transport.remove(path).await?,
+        CollisionPolicy::Rename => {
+            transport
+                .rename(path, &conflict_path(path, SystemTime::now()))
+                .await?
+        }
+    }
+    // removing the last entry of a directory may have removed its parents as well
+    if let Some(parent) = path.parent() {
+        let mut ancestors = parent.ancestors().collect::<Vec<_>>();
+        ancestors.reverse();
+        for ancestor in ancestors
+            .into_iter()
+            .filter(|ancestor| !matches!(ancestor.to_str(), Some("" | ".")))
+        {
+            transport.mkdir(ancestor).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// Removes a remote directory with everything inside
+async fn remove_all(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let mut dirs = vec![path.to_path_buf()];
+    let mut to_list = vec![path.to_path_buf()];
+    while let Some(dir) = to_list.pop() {
+        for entry in transport.list(&dir).await? {
+            if entry.is_dir {
+                dirs.push(entry.path.clone());
+                to_list.push(entry.path);
+            } else {
+                transport.remove(&entry.path).await?;
+            }
+        }
+    }
+    // deepest first, some transports clean up empty parents on their own
+    for dir in dirs.iter().rev() {
+        match transport.remove_dir(dir).await {
+            Err(error) if !is_not_found(error.as_ref()) => return Err(error),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+    use std::{io::Cursor, time::Duration};
+
+    async fn setup() -> Box<dyn Transport + Send + Sync> {
+        let mut transport: Box<dyn Transport + Send + Sync> = Box::new(MemoryTransport::new());
+        transport.mkdir(Path::new("./dir")).await.unwrap();
+        transport.mkdir(Path::new("./dir/inner")).await.unwrap();
+        for file in ["./dir/a", "./dir/inner/b", "./file"] {
+            transport
+                .write(Path::new(file), Box::new(Cursor::new(vec![1])), 1)
+                .await
+                .unwrap();
+        }
+        transport
+    }
+
+    #[test]
+    fn parses_policies() {
+        for policy in ["fail", "replace", "rename"] {
+            assert_eq!(
+                policy
+                    .parse::<CollisionPolicy>()
+                    .unwrap()
+                    .to_string()
+                    .as_str(),
+                policy
+            );
+        }
+        assert!("overwrite".parse::<CollisionPolicy>().is_err());
+    }
+
+    #[test]
+    fn conflict_path_is_sibling() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(42);
+        assert_eq!(
+            conflict_path(Path::new("./dir/name"), at),
+            PathBuf::from("./dir/name.syncbox-conflict-42")
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_to_resolve() {
+        let mut transport = setup().await;
+        for (path, want_dir) in [("./missing", false), ("./dir", true), ("./file", false)] {
+            let retry = resolve_collision(
+                &mut transport,
+                Path::new(path),
+                want_dir,
+                CollisionPolicy::Replace,
+            )
+            .await
+            .unwrap();
+            assert!(!retry);
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_reports_collision() {
+        let mut transport = setup().await;
+        let error = resolve_collision(
+            &mut transport,
+            Path::new("./dir"),
+            false,
+            Default::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("as a directory"));
+        assert!(transport
+            .stat(Path::new("./dir/a"))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn replace_removes_directory() {
+        let mut transport = setup().await;
+        let retry = resolve_collision(
+            &mut transport,
+            Path::new("./dir"),
+            false,
+            CollisionPolicy::Replace,
+        )
+        .await
+        .unwrap();
+        assert!(retry);
+        assert!(transport.stat(Path::new("./dir")).await.unwrap().is_none());
+        assert!(transport.stat(Path::new("./file")).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn rename_moves_file_aside() {
+        let mut transport = setup().await;
+        let retry = resolve_collision(
+            &mut transport,
+            Path::new("./file"),
+            true,
+            CollisionPolicy::Rename,
+        )
+        .await
+        .unwrap();
+        assert!(retry);
+        assert!(transport.stat(Path::new("./file")).await.unwrap().is_none());
+        let root = transport.list(Path::new(".")).await.unwrap();
+        assert!(root.iter().any(|entry| entry
+            .path
+            .to_string_lossy()
+            .starts_with("./file.syncbox-conflict-")));
+    }
+}
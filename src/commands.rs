@@ -0,0 +1,677 @@
+use crate::{
+    browse,
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    cli::Args,
+    config::{Config, CONFIG_FILENAME},
+    connect::{make_transport, take_lock},
+    dedup::{find_duplicates, sharing_size},
+    exit::{self, Failure},
+    hash::HashAlgorithm,
+    init::{self, Answers, ENV_FILENAME, IGNORE_TEMPLATE},
+    prompt::{ask, confirm_removals},
+    prune,
+    report::HumanBytes,
+    run::archive_file,
+    scan::{calculate_checksums, checksum_with, resolve_files, LocalFiles},
+    snapshots::SnapshotIndex,
+    t,
+    table::Table,
+    transport::{throttle::RateLimiter, Transport},
+    upload::{set_metadata, upload_file},
+    verify,
+};
+use clap::Parser;
+use console::style;
+use std::{error::Error, io::IsTerminal, path::Path, sync::Arc};
+use tokio::fs;
+
+/// Asks what `syncbox init` writes and writes it once the connection works
+pub async fn init(args: &Args, force: bool) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if !std::io::stdin().is_terminal() {
+        return Err("init asks questions, run it in a terminal".into());
+    }
+    let config = Config::load(Path::new(CONFIG_FILENAME))?;
+    let profile = ask(&t!("init_profile"), Some("prod"), false)?;
+    if !profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(
+            format!("{profile:?} can't name a profile, use letters, digits, - and _").into(),
+        );
+    }
+    if config.profiles.contains_key(&profile) {
+        return Err(format!("{CONFIG_FILENAME} already has a profile {profile:?}").into());
+    }
+    let transport = ask(&t!("init_transport"), Some("sftp"), false)?;
+    let fields = init::fields(&transport).ok_or_else(|| {
+        format!("unknown transport {transport:?}, expected ftp, sftp, local or s3")
+    })?;
+    let mut values = vec![];
+    for field in fields {
+        let value = ask(
+            &t!("init_option", name = field.name),
+            field.default,
+            field.env.is_some(),
+        )?;
+        values.push((*field, value));
+    }
+    let answers = Answers {
+        profile,
+        transport,
+        values,
+    };
+
+    println!("{}", t!("init_testing"));
+    let tested = Args::try_parse_from(
+        ["syncbox".to_string()]
+            .into_iter()
+            .chain(answers.transport_arguments()),
+    )?;
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(&tested, &rate_limiter).await?;
+    let pinged = transport.ping().await;
+    transport.close().await?;
+    pinged.map_err(exit::connecting)?;
+
+    let mut toml = std::fs::read_to_string(CONFIG_FILENAME).unwrap_or_default();
+    if !toml.is_empty() {
+        toml.push('\n');
+    }
+    toml.push_str(&answers.profile_toml());
+    let files = [
+        (CONFIG_FILENAME, toml, true),
+        (ENV_FILENAME, answers.env_file(), force),
+        (".syncboxignore", IGNORE_TEMPLATE.to_string(), force),
+    ];
+    for (path, contents, replace) in files {
+        if !replace && Path::new(path).exists() {
+            println!("      {}", t!("init_kept", path = path));
+            continue;
+        }
+        fs::write(path, contents).await?;
+        println!("      {}", t!("init_written", path = path));
+    }
+    println!("{}", t!("init_done", profile = answers.profile));
+    Ok(())
+}
+
+pub async fn print_stats(
+    args: &Args,
+    dupes: bool,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    println!("{} {}", style("[1/2]").dim().bold(), t!("resolving_files"));
+    let LocalFiles { files, .. } = resolve_files(args)?;
+
+    println!(
+        "{} {}",
+        style("[2/2]").dim().bold(),
+        t!("calculating_checksums")
+    );
+    let checksums = calculate_checksums(args, files).await?;
+
+    let files_count = checksums.len();
+    let mut total_size = 0;
+    let mut digested_files = Vec::with_capacity(checksums.len());
+    let mut undigested_files = vec![];
+    for (path, entry) in checksums {
+        let size = entry.size.unwrap_or_default();
+        total_size += size;
+        // files above the threshold only carry metadata as their checksum
+        if HashAlgorithm::of(&entry.checksum) == HashAlgorithm::Metadata {
+            undigested_files.push((path, size));
+        } else {
+            digested_files.push((path, entry.checksum, size));
+        }
+    }
+    println!(
+        "{}",
+        t!(
+            "stats",
+            count = style(files_count).bold(),
+            size = total_size.to_human_size()
+        )
+    );
+
+    if dupes {
+        // a file can only be a copy of one as large, whatever the threshold
+        let candidates = sharing_size(undigested_files);
+        if !candidates.is_empty() {
+            println!(
+                "      {}",
+                t!("hashing_same_size", count = candidates.len())
+            );
+        }
+        for (path, size) in candidates {
+            let checksum = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || {
+                    let metadata = std::fs::metadata(&path)?;
+                    HashAlgorithm::Blake3.checksum(Path::new(&path), &metadata)
+                }
+            })
+            .await?
+            .map_err(|e| format!("Failed checksum of {path:?} with error {e:?}"))?;
+            digested_files.push((path, checksum, size));
+        }
+        let groups = find_duplicates(digested_files);
+        if groups.is_empty() {
+            println!("      {}", t!("no_duplicates"));
+            return Ok(());
+        }
+        println!(
+            "{}",
+            t!(
+                "duplicates",
+                count = style(groups.len()).bold(),
+                size = groups
+                    .iter()
+                    .map(|group| group.wasted_bytes())
+                    .sum::<u64>()
+                    .to_human_size()
+            )
+        );
+        for group in groups {
+            println!(
+                "  {}",
+                t!(
+                    "duplicate_group",
+                    count = group.paths.len(),
+                    size = group.size.to_human_size(),
+                    wasted = style(group.wasted_bytes().to_human_size()).bold()
+                )
+            );
+            for path in group.paths {
+                println!("      {path}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn list_snapshots(args: &Args) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    let mut index = read_snapshot_index(&mut transport, Path::new(&args.checksum_file)).await;
+    transport.close().await?;
+
+    if index.snapshots.is_empty() {
+        println!("      {}", t!("no_snapshots"));
+        return Ok(());
+    }
+    index.snapshots.sort_by_key(|snapshot| snapshot.created);
+    for snapshot in index.snapshots {
+        println!(
+            "📸 {}  {}",
+            style(snapshot.created.format("%Y-%m-%d %H:%M:%S UTC")).bold(),
+            snapshot.path.display()
+        );
+    }
+    Ok(())
+}
+
+pub async fn validate_checksum_file(
+    args: &Args,
+    repair: bool,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    // a sync running meanwhile would overwrite the repaired file, or have it overwritten
+    let _lock = if repair && !args.is_dry() {
+        take_lock(args, &mut *transport, &rate_limiter, None).await?
+    } else {
+        None
+    };
+    let checksum_file = Path::new(&args.checksum_file);
+    let bytes = transport.read(checksum_file).await.map_err(|e| {
+        Failure::ChecksumFile.error(format!("Could not read {}: {e}", args.checksum_file))
+    })?;
+    let validation = ChecksumTree::validate(&bytes);
+
+    if validation.problems.is_empty() {
+        println!("{}", t!("checksum_valid", path = args.checksum_file));
+        return transport.close().await;
+    }
+    println!(
+        "{}",
+        t!(
+            "checksum_problems",
+            path = args.checksum_file,
+            count = validation.problems.len()
+        )
+    );
+    for problem in &validation.problems {
+        println!("      {}", style(problem).yellow());
+    }
+    if !repair {
+        transport.close().await?;
+        return Err(Failure::ChecksumFile.error(format!(
+            "{} is not valid, rerun with --repair to fix it",
+            args.checksum_file
+        )));
+    }
+    let Some(repaired) = validation.repaired else {
+        transport.close().await?;
+        return Err(Failure::ChecksumFile.error(
+            "nothing could be read to repair, a sync with --force uploads everything again",
+        ));
+    };
+    let mut broken = checksum_file.as_os_str().to_owned();
+    broken.push(".broken");
+    let size = bytes.len() as u64;
+    transport
+        .write(
+            Path::new(&broken),
+            Box::new(std::io::Cursor::new(bytes)),
+            size,
+        )
+        .await?;
+    transport
+        .write_last_checksum(checksum_file, &repaired, args.checksum_compression())
+        .await?;
+    println!(
+        "{}",
+        t!(
+            "checksum_repaired",
+            path = args.checksum_file,
+            broken = Path::new(&broken).display()
+        )
+    );
+    transport.close().await
+}
+
+/// Compares the remote with the checksum file, see `syncbox verify`
+pub async fn verify_remote(
+    args: &Args,
+    hashes: bool,
+    repair: bool,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if args.compress.is_some() {
+        return Err("verify compares file sizes, which compressed files don't keep, run it without --compress".into());
+    }
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    // repairing uploads files and may write the checksum file
+    let _lock = if repair && !args.is_dry() {
+        take_lock(args, &mut *transport, &rate_limiter, None).await?
+    } else {
+        None
+    };
+    let checksum_file = Path::new(&args.checksum_file);
+    let mut checksum_tree = match transport.read_last_checksum(checksum_file).await {
+        Ok(tree) => tree,
+        Err(e) => {
+            transport.close().await?;
+            return Err(Failure::ChecksumFile.error(e));
+        }
+    };
+
+    println!("{}", t!("listing_remote"));
+    let listing = verify::list_all(&mut *transport, Path::new("."))
+        .await
+        .map_err(|e| format!("Listing the remote failed: {e}"))?;
+    let mut report = verify::compare(&checksum_tree, &listing, checksum_file);
+    if hashes {
+        println!("{}", t!("verify_hashing"));
+        verify::compare_hashes(&mut *transport, &checksum_tree, &mut report).await?;
+    }
+    if report.is_clean() {
+        println!(
+            "{}",
+            t!(
+                "verify_clean",
+                path = args.checksum_file,
+                count = checksum_tree.iter().count()
+            )
+        );
+        return transport.close().await;
+    }
+    for path in &report.missing {
+        println!("      {}", t!("verify_missing", path = format!("{path:?}")));
+    }
+    for path in &report.mismatched {
+        println!(
+            "      {}",
+            t!("verify_mismatched", path = format!("{path:?}"))
+        );
+    }
+    for path in &report.extra {
+        println!("      {}", t!("verify_extra", path = format!("{path:?}")));
+    }
+    // extra files get in nobody's way, the next sync can still overwrite them
+    if report.missing.is_empty() && report.mismatched.is_empty() {
+        return transport.close().await;
+    }
+    if !repair {
+        transport.close().await?;
+        return Err(format!(
+            "the remote doesn't match {}, rerun with --repair to upload the files again",
+            args.checksum_file
+        )
+        .into());
+    }
+
+    let mut forgotten = false;
+    for path in report.missing.iter().chain(&report.mismatched) {
+        let Some(ChecksumElement::File(entry)) = checksum_tree.get_at(path) else {
+            continue;
+        };
+        let unchanged = match fs::metadata(path).await {
+            Ok(metadata) => {
+                checksum_with(
+                    path,
+                    &metadata,
+                    HashAlgorithm::of(&entry.checksum),
+                    args.preserve_permissions,
+                    args.preserve_owner,
+                )? == entry.checksum
+            }
+            Err(_) => false,
+        };
+        if unchanged {
+            // the whole directory may be gone
+            let mut parents = path.ancestors().skip(1).collect::<Vec<_>>();
+            parents.retain(|parent| !matches!(parent.to_str(), Some("" | ".")));
+            for parent in parents.into_iter().rev() {
+                transport.mkdir(parent).await?;
+            }
+            let pb = Arc::new(indicatif::ProgressBar::hidden());
+            upload_file(&mut transport, path, &pb, args.atomic_uploads, None).await?;
+            set_metadata(&mut transport, path, args).await?;
+            println!(
+                "      {}",
+                t!("verify_reuploaded", path = format!("{path:?}"))
+            );
+        } else {
+            checksum_tree.remove_at(path);
+            forgotten = true;
+            println!(
+                "      {}",
+                t!("verify_forgotten", path = format!("{path:?}"))
+            );
+        }
+    }
+    if forgotten {
+        transport
+            .write_last_checksum(checksum_file, &checksum_tree, args.checksum_compression())
+            .await?;
+    }
+    transport.close().await
+}
+
+/// Prints when the file at `path`, or each file below it, last reached the
+/// remote, most recent first
+pub async fn print_log(
+    args: &Args,
+    path: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    let checksum_tree = transport
+        .read_last_checksum(Path::new(&args.checksum_file))
+        .await;
+    transport.close().await?;
+    let checksum_tree = checksum_tree?;
+
+    let path = Path::new(".").join(path.strip_prefix(".").unwrap_or(path));
+    if !checksum_tree.contains(&path) {
+        return Err(format!("{path:?} is not in {}", args.checksum_file).into());
+    }
+    let mut files: Vec<_> = checksum_tree
+        .iter()
+        .filter(|(file, _)| file.starts_with(&path))
+        .map(|(file, entry)| (entry.synced, file))
+        .collect();
+    if files.is_empty() {
+        println!("      {}", t!("log_empty", path = format!("{path:?}")));
+    }
+    files.sort_by(|a, b| b.cmp(a));
+    for (synced, path) in files {
+        let synced = synced.and_then(|synced| chrono::DateTime::from_timestamp(synced, 0));
+        match synced {
+            Some(synced) => println!(
+                "🕒 {}  {}",
+                style(synced.format("%Y-%m-%d %H:%M:%S UTC")).bold(),
+                path.display()
+            ),
+            None => println!("🕒 {}  {}", style(t!("log_unknown")).dim(), path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// Removes what the checksum file doesn't have from the remote, see
+/// `syncbox prune`
+pub async fn prune_remote(args: &Args) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    // a sync running meanwhile may be uploading what looks like orphans
+    let _lock = if args.dry_run {
+        None
+    } else {
+        take_lock(args, &mut *transport, &rate_limiter, None).await?
+    };
+    let checksum_file = Path::new(&args.checksum_file);
+    // without a checksum file everything would be an orphan
+    let checksum_tree = match transport.read(checksum_file).await {
+        Ok(bytes) => ChecksumTree::from_compressed(&bytes),
+        Err(e) => Err(format!("Could not read {}: {e}", args.checksum_file).into()),
+    };
+    let checksum_tree = match checksum_tree {
+        Ok(tree) if tree.is_recovered() => Err(format!(
+            "{} was cut off, run a sync first so it lists every file again",
+            args.checksum_file
+        )
+        .into()),
+        result => result,
+    };
+    let checksum_tree = match checksum_tree {
+        Ok(tree) => tree,
+        Err(e) => {
+            transport.close().await?;
+            return Err(Failure::ChecksumFile.error(e));
+        }
+    };
+
+    println!("{}", t!("listing_remote"));
+    let listing = verify::list_all(&mut *transport, Path::new("."))
+        .await
+        .map_err(|e| format!("Listing the remote failed: {e}"))?;
+    let orphans = prune::orphans(&checksum_tree, &listing, checksum_file);
+    if orphans.is_empty() {
+        println!("{}", t!("prune_nothing", path = args.checksum_file));
+        return transport.close().await;
+    }
+    println!(
+        "{}",
+        t!(
+            "prune_orphans",
+            count = orphans.files.len(),
+            path = args.checksum_file
+        )
+    );
+    for path in orphans.files.iter().chain(&orphans.directories) {
+        println!("      {}", path.display());
+    }
+    if args.dry_run {
+        return transport.close().await;
+    }
+    if let Some(max_delete) = args.max_delete {
+        if let Err(e) = confirm_removals(args, orphans.files.len(), max_delete) {
+            transport.close().await?;
+            return Err(e);
+        }
+    }
+
+    let mut failed = 0;
+    let total = orphans.files.len() + orphans.directories.len();
+    let directories = orphans.directories.iter().map(|path| (path, true));
+    for (i, (path, directory)) in orphans
+        .files
+        .iter()
+        .map(|path| (path, false))
+        .chain(directories)
+        .enumerate()
+    {
+        let n = std::time::Instant::now();
+        let removed = match (directory, &args.archive_removed) {
+            (true, _) => transport.remove_dir(path).await,
+            (false, Some(archive_dir)) => {
+                match archive_file(&mut transport, path, archive_dir).await {
+                    Ok(()) => transport.remove(path).await,
+                    Err(error) => Err(format!("archiving failed, not removing: {error}").into()),
+                }
+            }
+            (false, None) => transport.remove(path).await,
+        };
+        let (index, path, seconds) = (
+            i + 1,
+            format!("{path:?}"),
+            format!("{:.2}", n.elapsed().as_secs_f64()),
+        );
+        match removed {
+            Ok(()) if directory => println!(
+                "{}",
+                t!(
+                    "removed_directory",
+                    index = index,
+                    total = total,
+                    path = path,
+                    seconds = seconds
+                )
+            ),
+            Ok(()) => println!(
+                "{}",
+                t!(
+                    "removed",
+                    index = index,
+                    total = total,
+                    path = path,
+                    seconds = seconds
+                )
+            ),
+            Err(error) => {
+                eprintln!("{}", t!("remove_failed", path = path, error = error));
+                failed += 1;
+            }
+        }
+    }
+    transport.close().await?;
+    if failed > 0 {
+        return Err(format!("{failed} of {total} could not be removed").into());
+    }
+    Ok(())
+}
+
+/// Prints what the checksum file has at `path`, see `syncbox ls`
+pub async fn print_listing(
+    args: &Args,
+    path: &Path,
+    recursive: bool,
+    live: bool,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let rate_limiter = Arc::new(RateLimiter::new(args.max_rps));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    let checksum_file = Path::new(&args.checksum_file);
+    let checksum_tree = match transport.read_last_checksum(checksum_file).await {
+        Ok(tree) => tree,
+        Err(e) => {
+            transport.close().await?;
+            return Err(Failure::ChecksumFile.error(e));
+        }
+    };
+    let path = Path::new(".").join(path.strip_prefix(".").unwrap_or(path));
+    let Some(mut listed) = browse::list(&checksum_tree, &path, recursive) else {
+        transport.close().await?;
+        return Err(format!("{path:?} is not in {}", args.checksum_file).into());
+    };
+    if live {
+        let is_dir = listed.first().is_some_and(|entry| entry.path != path);
+        let listing = match (is_dir, recursive) {
+            (true, true) => verify::list_all(&mut *transport, &path).await,
+            (true, false) => transport.list(&path).await.map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.path.clone(), entry))
+                    .collect()
+            }),
+            (false, _) => transport.stat(&path).await.map(|entry| {
+                entry
+                    .into_iter()
+                    .map(|entry| (path.clone(), entry))
+                    .collect()
+            }),
+        };
+        let listing = listing.map_err(|e| format!("Listing the remote failed: {e}"))?;
+        browse::compare_live(&mut listed, &listing, |path| {
+            verify::is_syncbox_file(path, checksum_file)
+        });
+    }
+    transport.close().await?;
+
+    if listed.is_empty() {
+        println!("{}", t!("log_empty", path = format!("{path:?}")));
+        return Ok(());
+    }
+    let mut header = vec![t!("column_path"), t!("column_size"), t!("column_synced")];
+    if live {
+        header.push(t!("column_remote"));
+    }
+    let header: Vec<_> = header.iter().map(String::as_str).collect();
+    let mut table = Table::new(&header).align_right(1);
+    for entry in listed {
+        let name = match &entry.kind {
+            browse::Kind::File => entry.path.display().to_string(),
+            browse::Kind::Directory => style(format!("{}/", entry.path.display()))
+                .blue()
+                .bold()
+                .to_string(),
+            browse::Kind::Symlink(target) => {
+                format!("{} -> {target}", style(entry.path.display()).cyan())
+            }
+        };
+        let size = entry
+            .size
+            .map(|size| size.to_human_size())
+            .unwrap_or_default();
+        let synced = entry
+            .synced
+            .and_then(|synced| chrono::DateTime::from_timestamp(synced, 0))
+            .map(|synced| synced.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default();
+        let mut row = vec![name, size, synced];
+        if live {
+            row.push(match entry.remote {
+                Some(browse::Remote::Present) => style(t!("remote_present")).green().to_string(),
+                Some(browse::Remote::Missing) => style(t!("remote_missing")).red().to_string(),
+                Some(browse::Remote::Resized(size)) => {
+                    style(t!("remote_resized", size = size.to_human_size()))
+                        .yellow()
+                        .to_string()
+                }
+                Some(browse::Remote::Untracked) => {
+                    style(t!("remote_untracked")).yellow().to_string()
+                }
+                None => String::new(),
+            });
+        }
+        table.push(row);
+    }
+    print!("{table}");
+    Ok(())
+}
+
+/// A missing or unreadable index means no snapshots were taken yet
+pub async fn read_snapshot_index(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    checksum_file: &Path,
+) -> SnapshotIndex {
+    match transport
+        .read(&SnapshotIndex::index_path(checksum_file))
+        .await
+    {
+        Ok(bytes) => SnapshotIndex::from_json(&bytes).unwrap_or_default(),
+        Err(_) => SnapshotIndex::default(),
+    }
+}
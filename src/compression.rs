@@ -0,0 +1,281 @@
+use flate2::{Compress, Compression, FlushCompress, Status};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+const INPUT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Compresses everything read through it into a zlib stream
+pub struct ZlibEncoder<R> {
+    reader: R,
+    compress: Compress,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_position: usize,
+    finished: bool,
+}
+
+impl<R> ZlibEncoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            compress: Compress::new(Compression::default(), true),
+            input: vec![0; INPUT_CHUNK_SIZE],
+            output: Vec::with_capacity(INPUT_CHUNK_SIZE),
+            output_position: 0,
+            finished: false,
+        }
+    }
+
+    /// Number of uncompressed bytes consumed so far
+    pub fn total_in(&self) -> u64 {
+        self.compress.total_in()
+    }
+
+    fn compress_chunk(&mut self, len: usize) -> io::Result<()> {
+        let flush = if len == 0 {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::None
+        };
+        self.output.clear();
+        self.output_position = 0;
+        let mut consumed = 0;
+        loop {
+            self.output.reserve(len.max(1024));
+            let total_in = self.compress.total_in();
+            let status = self
+                .compress
+                .compress_vec(&self.input[consumed..len], &mut self.output, flush)
+                .map_err(io::Error::other)?;
+            consumed += (self.compress.total_in() - total_in) as usize;
+            match flush {
+                FlushCompress::Finish if status == Status::StreamEnd => {
+                    self.finished = true;
+                    return Ok(());
+                }
+                FlushCompress::Finish => continue,
+                _ if consumed == len => return Ok(()),
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl<R> AsyncRead for ZlibEncoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.output_position < this.output.len() {
+                let pending = &this.output[this.output_position..];
+                let len = pending.len().min(buf.remaining());
+                buf.put_slice(&pending[..len]);
+                this.output_position += len;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+            let mut input = ReadBuf::new(&mut this.input);
+            ready!(Pin::new(&mut this.reader).poll_read(cx, &mut input))?;
+            let len = input.filled().len();
+            this.compress_chunk(len)?;
+        }
+    }
+}
+
+/// Compression applied to file contents before they're stored on the remote
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectCompression {
+    Gzip,
+}
+
+impl ObjectCompression {
+    /// Appended to the name of every stored file
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Gzip => ".gz",
+        }
+    }
+
+    pub fn remote_path(&self, path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(self.suffix());
+        PathBuf::from(name)
+    }
+
+    /// Compresses `source` into `target`, returns the compressed size
+    pub fn compress_file(&self, source: &Path, target: &Path) -> io::Result<u64> {
+        match self {
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(fs::File::create(target)?, Compression::best());
+                io::copy(&mut fs::File::open(source)?, &mut encoder)?;
+                encoder.finish()?.metadata().map(|metadata| metadata.len())
+            }
+        }
+    }
+
+    /// Decompresses the stored file `source` into `target`, returns the
+    /// decompressed size
+    pub fn decompress_file(&self, source: &Path, target: &Path) -> io::Result<u64> {
+        match self {
+            Self::Gzip => {
+                let mut decoder = flate2::read::GzDecoder::new(fs::File::open(source)?);
+                io::copy(&mut decoder, &mut fs::File::create(target)?)
+            }
+        }
+    }
+}
+
+impl FromStr for ObjectCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" => Err("zstd is not available in this build, use gzip".to_string()),
+            _ => Err(format!("unknown compression {s:?}, expected gzip")),
+        }
+    }
+}
+
+impl fmt::Display for ObjectCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gzip => write!(f, "gzip"),
+        }
+    }
+}
+
+/// Format of the checksum file. Files in either format are read, whichever
+/// the next one is written in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumFormat {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for ChecksumFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            _ => Err(format!("unknown compression {s:?}, expected gzip or zstd")),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gzip => write!(f, "gzip"),
+            Self::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// How the checksum file is compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChecksumCompression {
+    pub format: ChecksumFormat,
+    /// From 1 to 22, the default of the format when `None`. Gzip stops at 9,
+    /// higher levels compress like 9
+    pub level: Option<u32>,
+}
+
+impl ChecksumCompression {
+    pub fn gzip_level(&self) -> Compression {
+        self.level
+            .map_or_else(Compression::default, |level| Compression::new(level.min(9)))
+    }
+
+    pub fn zstd_level(&self) -> i32 {
+        self.level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| {
+            level.min(22) as i32
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut encoder = ZlibEncoder::new(Cursor::new(data.clone()));
+        let mut compressed = vec![];
+        encoder.read_to_end(&mut compressed).await.unwrap();
+        assert_eq!(encoder.total_in(), data.len() as u64);
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = vec![];
+        flate2::read::ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[tokio::test]
+    async fn empty_input() {
+        let mut compressed = vec![];
+        ZlibEncoder::new(Cursor::new(vec![]))
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        let mut decompressed = vec![];
+        flate2::read::ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.is_empty());
+    }
+
+    #[test]
+    fn gzip_files() {
+        let dir = std::env::temp_dir().join(format!("syncbox-gzip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = "all work and no play makes jack a dull boy\n".repeat(1000);
+        fs::write(dir.join("a.txt"), &data).unwrap();
+
+        let compression = "gzip".parse::<ObjectCompression>().unwrap();
+        let target = compression.remote_path(&dir.join("a.txt"));
+        assert_eq!(target, dir.join("a.txt.gz"));
+        let size = compression
+            .compress_file(&dir.join("a.txt"), &target)
+            .unwrap();
+        assert!(size < data.len() as u64 / 10);
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(&target).unwrap())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let restored = dir.join("restored.txt");
+        let size = compression.decompress_file(&target, &restored).unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(fs::read_to_string(&restored).unwrap(), data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
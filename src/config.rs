@@ -0,0 +1,262 @@
+use crate::{hooks::Hook, invalidate::Invalidation, script::Script};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, io, path::Path};
+
+/// Looked up in the synced directory, never synced itself
+pub const CONFIG_FILENAME: &str = "syncbox.toml";
+
+/// Settings that don't fit on the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// CDN caches purged of the changed paths after every sync
+    #[serde(default)]
+    pub invalidate: Vec<Invalidation>,
+    /// Commands run and URLs posted to before and after a sync
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    /// Options picked with `--profile`
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Rhai code computing options and deciding on planned actions, see
+    /// `Script`
+    #[serde(default)]
+    pub script: Option<String>,
+}
+
+/// Command line options kept under a name, like
+///
+/// ```toml
+/// [profiles.prod]
+/// concurrency = 8
+/// exclude = ["*.map"]
+///
+/// [profiles.prod.transport]
+/// type = "sftp"
+/// host = "example.com"
+/// pass_env = "PROD_SFTP_PASS"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Profile {
+    /// The transport with its options, for when the command line names none
+    #[serde(default)]
+    pub transport: Option<toml::Table>,
+    /// Long options by name, like `max_files = 10` for `--max-files=10`
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+impl Profile {
+    /// The options as command line arguments
+    pub fn arguments(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync + 'static>> {
+        arguments(&self.options)
+    }
+
+    /// The transport subcommand and its options as command line arguments,
+    /// none when the profile has no transport
+    pub fn transport_arguments(
+        &self,
+    ) -> Result<Option<Vec<String>>, Box<dyn Error + Send + Sync + 'static>> {
+        let Some(transport) = &self.transport else {
+            return Ok(None);
+        };
+        let Some(toml::Value::String(name)) = transport.get("type") else {
+            return Err("the transport of a profile needs a type, like type = \"sftp\"".into());
+        };
+        let mut options = transport.clone();
+        options.remove("type");
+        Ok(Some([vec![name.clone()], arguments(&options)?].concat()))
+    }
+}
+
+/// `key = value` as `--key=value`, a list repeats the option and `true`
+/// passes the flag alone. Keys ending in `_env` take the value of that
+/// environment variable, so `pass_env = "PROD_PASS"` is `--pass=$PROD_PASS`
+fn arguments(options: &toml::Table) -> Result<Vec<String>, Box<dyn Error + Send + Sync + 'static>> {
+    let mut arguments = vec![];
+    for (key, value) in options {
+        let (key, value) = match key.strip_suffix("_env") {
+            Some(key) => {
+                let toml::Value::String(variable) = value else {
+                    return Err(format!("{key}_env names an environment variable").into());
+                };
+                let value = std::env::var(variable)
+                    .map_err(|_| format!("{variable}, {key}_env of the profile, is not set"))?;
+                (key, toml::Value::String(value))
+            }
+            None => (key.as_str(), value.clone()),
+        };
+        let option = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => arguments.push(option.clone()),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(value) => arguments.push(format!("{option}={value}")),
+                toml::Value::Integer(_) | toml::Value::Float(_) => {
+                    arguments.push(format!("{option}={value}"))
+                }
+                _ => return Err(format!("{key} can't be given on the command line").into()),
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+impl Config {
+    /// The options `options(profile)` of the script returns as command line
+    /// arguments, with the git variables of `directory`
+    pub fn computed_arguments(
+        &self,
+        directory: &Path,
+        profile: Option<&str>,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync + 'static>> {
+        let Some(source) = &self.script else {
+            return Ok(vec![]);
+        };
+        arguments(&Script::compile(source, directory)?.options(profile)?)
+    }
+
+    /// Reads `path`, a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                let config: Self =
+                    toml::from_str(&contents).map_err(|e| format!("{path:?}: {e}"))?;
+                for hook in &config.hooks {
+                    hook.check().map_err(|e| format!("{path:?}: {e}"))?;
+                }
+                if let Some(source) = &config.script {
+                    Script::check(source).map_err(|e| format!("{path:?}: {e}"))?;
+                }
+                Ok(config)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("cannot read {path:?}: {e}").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_invalidations() {
+        let config: Config = toml::from_str(
+            r#"
+            [[invalidate]]
+            cdn = "cloudfront"
+            distribution_id = "E2QWRUHAPOMQZL"
+
+            [[invalidate]]
+            cdn = "cloudflare"
+            zone_id = "023e105f4ecef8ad9ca31a8372d0c353"
+            base_url = "https://example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.invalidate.len(), 2);
+        assert!(matches!(
+            &config.invalidate[1],
+            Invalidation::Cloudflare { api_token_env, .. } if api_token_env == "CLOUDFLARE_API_TOKEN"
+        ));
+
+        let typo = toml::from_str::<Config>(
+            r#"
+            [[invalidate]]
+            cdn = "fastly"
+            service = "SU1Z0isxPaozGVKXdv0eY"
+            "#,
+        );
+        assert!(typo.is_err());
+        assert!(Config::load(Path::new("/nonexistent/syncbox.toml"))
+            .unwrap()
+            .invalidate
+            .is_empty());
+    }
+
+    #[test]
+    fn profiles_become_command_line_arguments() {
+        std::env::set_var("SYNCBOX_TEST_PROFILE_PASS", "secret");
+        let config: Config = toml::from_str(
+            r#"
+            [profiles.prod]
+            concurrency = 8
+            exclude = ["*.map", "tmp/"]
+            preserve_mtime = true
+            summary = false
+
+            [profiles.prod.transport]
+            type = "sftp"
+            host = "example.com"
+            pass_env = "SYNCBOX_TEST_PROFILE_PASS"
+
+            [profiles.local]
+            max_files = 10
+            "#,
+        )
+        .unwrap();
+        let prod = &config.profiles["prod"];
+        assert_eq!(
+            prod.arguments().unwrap(),
+            [
+                "--concurrency=8",
+                "--exclude=*.map",
+                "--exclude=tmp/",
+                "--preserve-mtime"
+            ]
+        );
+        assert_eq!(
+            prod.transport_arguments().unwrap().unwrap(),
+            ["sftp", "--host=example.com", "--pass=secret"]
+        );
+        assert!(config.profiles["local"]
+            .transport_arguments()
+            .unwrap()
+            .is_none());
+
+        let unset: Config = toml::from_str(
+            r#"
+            [profiles.prod.transport]
+            type = "ftp"
+            ftp_pass_env = "SYNCBOX_TEST_PROFILE_UNSET"
+            "#,
+        )
+        .unwrap();
+        assert!(unset.profiles["prod"].transport_arguments().is_err());
+    }
+
+    #[test]
+    fn the_script_computes_options() {
+        let config: Config = toml::from_str(
+            r#"
+            script = """
+            fn options(profile) {
+                #{ exclude: ["drafts/"], prefix_template: "previews/" + profile, deterministic: true }
+            }
+            """
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .computed_arguments(&std::env::temp_dir(), Some("staging"))
+                .unwrap(),
+            [
+                "--deterministic",
+                "--exclude=drafts/",
+                "--prefix-template=previews/staging"
+            ]
+        );
+
+        let failing: Config =
+            toml::from_str(r#"script = "fn options(profile) { 1 / 0 }""#).unwrap();
+        assert!(failing
+            .computed_arguments(&std::env::temp_dir(), None)
+            .is_err());
+    }
+}
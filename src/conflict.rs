@@ -0,0 +1,266 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    collision::conflict_path,
+    reconciler::Action,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// How to settle a file that changed both locally and on the remote, see
+/// `Reconciler::merge`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Report the conflict and leave both sides as they are
+    #[default]
+    Skip,
+    /// The side modified last wins, by the recorded modification times
+    NewerWins,
+    LocalWins,
+    RemoteWins,
+    /// The remote version takes the path, the local one is kept beside it,
+    /// see `conflict_path`
+    KeepBoth,
+    /// Ask on the terminal for each conflict, decides nothing by itself
+    Ask,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "newer-wins" => Ok(Self::NewerWins),
+            "local-wins" => Ok(Self::LocalWins),
+            "remote-wins" => Ok(Self::RemoteWins),
+            "keep-both" => Ok(Self::KeepBoth),
+            "ask" => Ok(Self::Ask),
+            _ => Err(format!(
+                "unknown conflict policy {s:?}, expected skip, newer-wins, local-wins, remote-wins, keep-both or ask"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Skip => write!(f, "skip"),
+            Self::NewerWins => write!(f, "newer-wins"),
+            Self::LocalWins => write!(f, "local-wins"),
+            Self::RemoteWins => write!(f, "remote-wins"),
+            Self::KeepBoth => write!(f, "keep-both"),
+            Self::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+/// A path with different contents on each side, `None` where it's gone
+pub struct Conflict<'a> {
+    pub path: &'a Path,
+    pub local: Option<&'a ChecksumElement>,
+    pub remote: Option<&'a ChecksumElement>,
+}
+
+/// How a conflict was settled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Left as it is on both sides
+    Skip,
+    /// The local version is uploaded
+    Local,
+    /// The remote version is downloaded
+    Remote,
+    /// The local version is moved to this path and uploaded there, the
+    /// remote one is downloaded
+    KeepBoth(PathBuf),
+}
+
+impl ConflictPolicy {
+    /// What the policy settles `conflict` with, `Skip` when it can't tell,
+    /// like `NewerWins` without modification times or `Ask`
+    pub fn resolve(&self, conflict: &Conflict, now: SystemTime) -> Resolution {
+        match self {
+            Self::Skip | Self::Ask => Resolution::Skip,
+            Self::LocalWins => Resolution::Local,
+            Self::RemoteWins => Resolution::Remote,
+            Self::KeepBoth => keep_both(conflict, now),
+            Self::NewerWins => {
+                let mtime = |element: Option<&ChecksumElement>| match element {
+                    Some(ChecksumElement::File(entry)) => entry.mtime,
+                    _ => None,
+                };
+                match (mtime(conflict.local), mtime(conflict.remote)) {
+                    (Some(local), Some(remote)) if local > remote => Resolution::Local,
+                    (Some(local), Some(remote)) if remote > local => Resolution::Remote,
+                    // modified on one side, removed on the other
+                    (Some(_), None) if conflict.remote.is_none() => Resolution::Local,
+                    (None, Some(_)) if conflict.local.is_none() => Resolution::Remote,
+                    _ => Resolution::Skip,
+                }
+            }
+        }
+    }
+}
+
+/// Both versions when there are two, otherwise the one that's left
+fn keep_both(conflict: &Conflict, now: SystemTime) -> Resolution {
+    match (conflict.local, conflict.remote) {
+        (Some(_), Some(_)) => Resolution::KeepBoth(conflict_path(conflict.path, now)),
+        (Some(_), None) => Resolution::Local,
+        _ => Resolution::Remote,
+    }
+}
+
+/// Settles the downloads of `Reconciler::pull` replacing a local file or link
+/// with other contents. `Local` drops the download, `Skip` turns it into a
+/// `Conflict`. Returns the actions and where local files are to be moved
+/// before downloading, nothing is uploaded
+pub fn settle_pull(
+    actions: Vec<Action>,
+    local: &ChecksumTree,
+    remote: &ChecksumTree,
+    mut resolve: impl FnMut(&Conflict) -> Resolution,
+) -> (Vec<Action>, Vec<(PathBuf, PathBuf)>) {
+    let mut settled = Vec::with_capacity(actions.len());
+    let mut moved = vec![];
+    for action in actions {
+        let Action::Get(path) = &action else {
+            settled.push(action);
+            continue;
+        };
+        let local_element = match local.get_at(path) {
+            Some(element @ (ChecksumElement::File(_) | ChecksumElement::Symlink(_))) => element,
+            _ => {
+                settled.push(action);
+                continue;
+            }
+        };
+        let conflict = Conflict {
+            path,
+            local: Some(local_element),
+            remote: remote.get_at(path),
+        };
+        match resolve(&conflict) {
+            Resolution::Skip => settled.push(Action::Conflict(path.clone())),
+            Resolution::Local => {}
+            Resolution::Remote => settled.push(action),
+            Resolution::KeepBoth(copy) => {
+                moved.push((path.clone(), copy));
+                settled.push(action);
+            }
+        }
+    }
+    (settled, moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum_tree::{fixtures::file, FileEntry};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_policies() {
+        for policy in [
+            "skip",
+            "newer-wins",
+            "local-wins",
+            "remote-wins",
+            "keep-both",
+            "ask",
+        ] {
+            assert_eq!(
+                policy.parse::<ConflictPolicy>().unwrap().to_string(),
+                policy
+            );
+        }
+        assert!("mine".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn newer_wins_by_modification_time() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(42);
+        let modified = |checksum, mtime| {
+            ChecksumElement::File(FileEntry {
+                mtime,
+                ..file(checksum)
+            })
+        };
+        let (old, new, unknown) = (
+            modified("a", Some(1)),
+            modified("b", Some(2)),
+            modified("c", None),
+        );
+        let resolve = |local, remote| {
+            ConflictPolicy::NewerWins.resolve(
+                &Conflict {
+                    path: Path::new("./a.txt"),
+                    local,
+                    remote,
+                },
+                now,
+            )
+        };
+        assert_eq!(resolve(Some(&new), Some(&old)), Resolution::Local);
+        assert_eq!(resolve(Some(&old), Some(&new)), Resolution::Remote);
+        assert_eq!(resolve(None, Some(&new)), Resolution::Remote);
+        assert_eq!(resolve(Some(&old), Some(&unknown)), Resolution::Skip);
+        assert_eq!(resolve(Some(&old), Some(&old)), Resolution::Skip);
+
+        assert_eq!(
+            ConflictPolicy::KeepBoth.resolve(
+                &Conflict {
+                    path: Path::new("./a.txt"),
+                    local: Some(&old),
+                    remote: Some(&new),
+                },
+                now
+            ),
+            Resolution::KeepBoth("./a.txt.syncbox-conflict-42".into())
+        );
+    }
+
+    #[test]
+    fn pulled_conflicts_are_settled() {
+        let local: ChecksumTree = [("./changed.txt", "local"), ("./kept.txt", "local")]
+            .into_iter()
+            .map(|(path, checksum)| (path, checksum.to_string()))
+            .collect();
+        let remote: ChecksumTree = [
+            ("./changed.txt", "remote"),
+            ("./kept.txt", "remote"),
+            ("./new.txt", "new"),
+        ]
+        .into_iter()
+        .map(|(path, checksum)| (path, checksum.to_string()))
+        .collect();
+        let actions = vec![
+            Action::Get("./changed.txt".into()),
+            Action::Get("./kept.txt".into()),
+            Action::Get("./new.txt".into()),
+        ];
+        let (settled, moved) = settle_pull(actions, &local, &remote, |conflict| {
+            if conflict.path == Path::new("./kept.txt") {
+                Resolution::Local
+            } else {
+                Resolution::KeepBoth(conflict.path.with_extension("copy"))
+            }
+        });
+        assert_eq!(
+            settled,
+            vec![
+                Action::Get("./changed.txt".into()),
+                Action::Get("./new.txt".into())
+            ]
+        );
+        assert_eq!(
+            moved,
+            vec![("./changed.txt".into(), "./changed.copy".into())]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    cli::{Args, TransportType},
+    exit,
+    lock::{self, HeldLock, Lock},
+    shard::Shard,
+    t,
+    transport::{
+        dry::{DryRun, DryTransport},
+        ftp::Ftp,
+        local::LocalFilesystem,
+        pool::TransportPool,
+        s3::AwsS3,
+        sftp::SFtp,
+        throttle::{RateLimiter, Throttled},
+        Transport,
+    },
+};
+use std::{error::Error, sync::Arc};
+
+/// Takes the lock on the remote for the rest of the run, unless `--no-lock`.
+/// A shard takes one of its own and only locks the checksum file to write it
+pub async fn take_lock(
+    args: &Args,
+    transport: &mut (dyn Transport + Send + Sync),
+    rate_limiter: &Arc<RateLimiter>,
+    shard: Option<Shard>,
+) -> Result<Option<HeldLock>, Box<dyn Error + Send + Sync + 'static>> {
+    if args.no_lock {
+        return Ok(None);
+    }
+    let path = lock::path(&args.checksum_file, shard.map(|shard| shard.index()));
+    let taken = Lock::new(args.lock_ttl);
+    let replaced = lock::acquire(transport, &path, &taken, args.break_lock).await?;
+    if let Some(replaced) = replaced {
+        println!(
+            "      {}",
+            t!(
+                "lock_replaced",
+                host = replaced.host,
+                pid = replaced.pid,
+                time = replaced
+                    .refreshed
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+            )
+        );
+    }
+    // refreshing and removing it mustn't wait for a connection busy with uploads
+    let pool = transport_pool(args, rate_limiter, 1);
+    Ok(Some(HeldLock::keep(pool, path, taken)))
+}
+
+/// Opens up to `size` connections to the remote as they're needed
+pub fn transport_pool(
+    args: &Args,
+    rate_limiter: &Arc<RateLimiter>,
+    size: usize,
+) -> Arc<TransportPool> {
+    let args = args.clone();
+    let rate_limiter = Arc::clone(rate_limiter);
+    TransportPool::new(
+        size,
+        Box::new(move || {
+            let args = args.clone();
+            let rate_limiter = Arc::clone(&rate_limiter);
+            Box::pin(async move { make_transport(&args, &rate_limiter).await })
+        }),
+    )
+}
+
+pub async fn make_transport(
+    args: &Args,
+    rate_limiter: &Arc<RateLimiter>,
+) -> Result<Box<dyn Transport + Send + Sync>, Box<dyn Error + Send + Sync + 'static>> {
+    let transport: Box<dyn Transport + Send + Sync> =
+        match args.transport().ok_or("no transport given")? {
+            TransportType::Ftp {
+                ftp_host,
+                ftp_user,
+                ftp_pass,
+                ftp_dir,
+                use_tls,
+                compression,
+            } => Box::new(
+                Ftp::new(ftp_host, ftp_user, ftp_pass, ftp_dir)
+                    .compression(*compression)
+                    .connect(*use_tls)
+                    .await
+                    .map_err(exit::connecting)?,
+            ),
+            TransportType::Sftp {
+                host,
+                user,
+                pass,
+                dir,
+                proxy_jump,
+            } => Box::new(
+                SFtp::new(host, user, pass, dir, proxy_jump.clone())
+                    .await
+                    .map_err(exit::connecting)?,
+            ),
+            TransportType::Local {
+                destination,
+                use_trash,
+            } => Box::new(LocalFilesystem::new(destination).use_trash(*use_trash)),
+            TransportType::S3 {
+                bucket,
+                region,
+                access_key,
+                secret_key,
+                storage_class,
+                directory,
+                endpoint,
+            } => Box::new(AwsS3::new(
+                bucket,
+                region,
+                access_key,
+                secret_key,
+                storage_class,
+                directory.into(),
+                endpoint.clone(),
+            )?),
+            TransportType::Dry => Box::new(DryTransport),
+        };
+    let transport: Box<dyn Transport + Send + Sync> = if args.dry_run {
+        Box::new(DryRun::new(transport))
+    } else {
+        transport
+    };
+    Ok(Box::new(Throttled::new(
+        transport,
+        Arc::clone(rate_limiter),
+    )))
+}
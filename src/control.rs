@@ -0,0 +1,336 @@
+use serde::Serialize;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::{broadcast, watch};
+
+/// Sent to every connected client as one JSON object per line
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started { files: usize, bytes: u64 },
+    FileStarted { path: PathBuf, size: u64 },
+    FileFinished { path: PathBuf, bytes: u64 },
+    FileFailed { path: PathBuf, error: String },
+    Removed { path: PathBuf },
+    Paused,
+    Resumed,
+    Cancelled,
+    Status(Status),
+    Error { message: String },
+    Finished { bytes: u64, errors: bool },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub state: RunState,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Commands accepted from clients, one per line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Status,
+    Cancel,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "pause" => Ok(Self::Pause),
+            "resume" => Ok(Self::Resume),
+            "status" => Ok(Self::Status),
+            "cancel" => Ok(Self::Cancel),
+            other => Err(format!(
+                "unknown command {other:?}, expected pause, resume, status or cancel"
+            )),
+        }
+    }
+}
+
+/// Shared between the sync and the control socket, publishes events and
+/// lets clients pause or cancel the run
+pub struct Controller {
+    events: broadcast::Sender<Event>,
+    paused: watch::Sender<bool>,
+    cancelled: AtomicBool,
+    status: Mutex<Status>,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            events: broadcast::channel(1024).0,
+            paused: watch::channel(false).0,
+            cancelled: AtomicBool::new(false),
+            status: Mutex::new(Status::default()),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        {
+            let mut status = self.status.lock().unwrap();
+            match &event {
+                Event::Started { files, bytes } => {
+                    status.files_total = *files;
+                    status.bytes_total = *bytes;
+                }
+                Event::FileFinished { bytes, .. } => {
+                    status.files_done += 1;
+                    status.bytes_done += bytes;
+                }
+                Event::FileFailed { .. } | Event::Removed { .. } => status.files_done += 1,
+                _ => {}
+            }
+        }
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub fn status(&self) -> Status {
+        let mut status = self.status.lock().unwrap().clone();
+        status.state = if self.is_cancelled() {
+            RunState::Cancelled
+        } else if *self.paused.borrow() {
+            RunState::Paused
+        } else {
+            RunState::Running
+        };
+        status
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(SeqCst)
+    }
+
+    /// Executes the command and returns the reply for the client that sent it
+    pub fn handle(&self, command: Command) -> Event {
+        match command {
+            Command::Pause => {
+                self.paused.send_replace(true);
+                self.emit(Event::Paused);
+            }
+            Command::Resume => {
+                self.paused.send_replace(false);
+                self.emit(Event::Resumed);
+            }
+            Command::Cancel => {
+                self.cancelled.store(true, SeqCst);
+                // wake up everyone waiting on a pause so they can bail out
+                self.paused.send_replace(false);
+                self.emit(Event::Cancelled);
+            }
+            Command::Status => {}
+        }
+        Event::Status(self.status())
+    }
+
+    /// Blocks while the run is paused, call before starting each action
+    pub async fn wait_while_paused(&self) {
+        let mut paused = self.paused.subscribe();
+        while *paused.borrow_and_update() && !self.is_cancelled() {
+            if paused.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Listening control socket, removed from the filesystem on drop
+pub struct ControlSocket {
+    path: PathBuf,
+    accept: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.accept.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Starts accepting clients on a Unix socket at `path`
+#[cfg(unix)]
+pub fn serve(
+    path: &Path,
+    controller: Arc<Controller>,
+) -> Result<ControlSocket, Box<dyn Error + Send + Sync + 'static>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // a socket left behind by a crashed run would make bind fail, anything else is kept
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{path:?} exists and is not a socket").into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let accept = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_client(stream, Arc::clone(&controller)));
+                }
+                Err(error) => {
+                    tracing::warn!("control socket stopped accepting clients: {error}");
+                    break;
+                }
+            }
+        }
+    });
+    Ok(ControlSocket {
+        path: path.to_path_buf(),
+        accept,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn serve(
+    _path: &Path,
+    _controller: Arc<Controller>,
+) -> Result<ControlSocket, Box<dyn Error + Send + Sync + 'static>> {
+    Err("the control socket is only supported on unix".into())
+}
+
+#[cfg(unix)]
+async fn handle_client(stream: tokio::net::UnixStream, controller: Arc<Controller>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = controller.subscribe();
+    loop {
+        let event = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => match line.parse::<Command>() {
+                    Ok(command) => controller.handle(command),
+                    Err(message) => Event::Error { message },
+                },
+                _ => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => event,
+                // a slow client misses some events rather than holding up the sync
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        let mut line = serde_json::to_string(&event).unwrap();
+        line.push('\n');
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!("pause".parse(), Ok(Command::Pause));
+        assert_eq!(" Resume\n".parse(), Ok(Command::Resume));
+        assert!("stop".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn events_are_tagged() {
+        let event = Event::FileFinished {
+            path: "./a".into(),
+            bytes: 3,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"file_finished","path":"./a","bytes":3}"#
+        );
+    }
+
+    #[test]
+    fn status_counts_events() {
+        let controller = Controller::new();
+        controller.emit(Event::Started { files: 2, bytes: 5 });
+        controller.emit(Event::FileFinished {
+            path: "./a".into(),
+            bytes: 5,
+        });
+        controller.handle(Command::Pause);
+        let status = controller.status();
+        assert_eq!(status.files_done, 1);
+        assert_eq!(status.bytes_done, 5);
+        assert_eq!(status.state, RunState::Paused);
+    }
+
+    #[tokio::test]
+    async fn pause_blocks_until_resumed_or_cancelled() {
+        let controller = Arc::new(Controller::new());
+        controller.handle(Command::Pause);
+        let waiting = {
+            let controller = Arc::clone(&controller);
+            tokio::spawn(async move { controller.wait_while_paused().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        controller.handle(Command::Cancel);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(controller.is_cancelled());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn socket_answers_commands() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let path = std::env::temp_dir().join(format!("syncbox-{}.sock", std::process::id()));
+        let controller = Arc::new(Controller::new());
+        let socket = serve(&path, Arc::clone(&controller)).unwrap();
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"status\n").await.unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains(r#""event":"status""#));
+        assert!(reply.contains(r#""state":"running""#));
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{
+    cli::{Args, Command, TransportType},
+    config::Config,
+    control::{self, Controller},
+    deadline::parse_duration,
+    run::{log_outcome, notify_outcome, run_hooked, Outcome},
+    t,
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use serde::Serialize;
+use std::{error::Error, path::Path, str::FromStr, sync::Arc, time::Duration};
+
+/// How far ahead a cron expression is searched for a matching minute, a
+/// day like the 30th of February never comes
+const MAX_CRON_SEARCH_DAYS: i64 = 5 * 366;
+
+/// When `syncbox daemon` syncs: at an interval like `6h`, or at the minutes
+/// a cron expression like `0 3 * * *` matches in local time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The first run after starting at `now`, intervals start right away
+    pub fn first<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Schedule::Every(_) => Some(now.clone()),
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// The run following one started at `time`, none when the expression
+    /// never matches again
+    pub fn next_after<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Schedule::Every(interval) => Some(time.clone() + TimeDelta::from_std(*interval).ok()?),
+            Schedule::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    /// Five fields separated by spaces are a cron expression, anything else
+    /// an interval
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.split_whitespace().count() == 5 {
+            return s.parse().map(Schedule::Cron);
+        }
+        let interval = parse_duration(s)
+            .map_err(|e| format!("{e}, or give a cron expression like \"0 3 * * *\""))?;
+        if interval.is_zero() {
+            return Err("the interval between syncs must be longer than zero".to_string());
+        }
+        Ok(Schedule::Every(interval))
+    }
+}
+
+/// Minute, hour, day of month, month and day of week, each `*`, a number,
+/// a range like `1-5` or a list of them, optionally stepped like `*/15`.
+/// Sunday is 0 or 7
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Like cron, when both days are restricted either one matching is enough
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// The first matching minute after `time`. Minutes skipped by a change
+    /// to daylight saving time don't match
+    pub fn next_after<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let local = time.naive_local();
+        let mut candidate = local.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let limit = candidate + TimeDelta::days(MAX_CRON_SEARCH_DAYS);
+        while candidate < limit {
+            if !has(self.months, candidate.month()) {
+                let (year, month) = match candidate.month() {
+                    12 => (candidate.year() + 1, 1),
+                    month => (candidate.year(), month + 1),
+                };
+                candidate = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&candidate) {
+                candidate = (candidate.date() + TimeDelta::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, candidate.hour()) {
+                candidate = candidate.with_minute(0)? + TimeDelta::hours(1);
+            } else if !has(self.minutes, candidate.minute()) {
+                candidate += TimeDelta::minutes(1);
+            } else if let Some(matched) = time.timezone().from_local_datetime(&candidate).earliest()
+            {
+                return Some(matched);
+            } else {
+                candidate += TimeDelta::minutes(1);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, time: &NaiveDateTime) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "{s:?} has {} fields, a cron expression has five",
+                fields.len()
+            ));
+        };
+        let mut weekdays_bits = field(weekdays, 0, 7)?;
+        // Sunday either way
+        if has(weekdays_bits, 7) {
+            weekdays_bits |= 1;
+        }
+        Ok(Self {
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekdays_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// The values a cron field matches, as bits
+fn field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| format!("{s:?} in {field:?} isn't a number from {min} to {max}"))
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step {step:?} in {field:?}")),
+            },
+            None => (part, 1),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (number(from)?, number(to)?),
+            // `5/15` runs from 5 to the end
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if from > to {
+            return Err(format!("{range:?} in {field:?} runs backwards"));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// A duration like `5m` for `--jitter`, `0` for none
+pub fn parse_jitter(s: &str) -> Result<Duration, String> {
+    parse_duration(s).map_err(|e| format!("{e}, the jitter is a duration like 5m"))
+}
+
+/// What `--status-file` holds, rewritten whenever a sync starts or ends so
+/// monitoring can tell a stuck or failing daemon from a healthy one
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    pub pid: u32,
+    pub running: bool,
+    /// Start of the sync in progress
+    pub started: Option<DateTime<Local>>,
+    pub next_run: Option<DateTime<Local>>,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_run: Option<LastRun>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LastRun {
+    pub started: DateTime<Local>,
+    pub finished: DateTime<Local>,
+    /// `ok`, `errors` when some files failed or `failed`
+    pub result: &'static str,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+impl Status {
+    /// Replaces the file at `path` at once, readers never see half of it
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Runs a sync whenever `syncbox daemon` is due until stopped with Ctrl+C.
+/// A failed sync is reported and tried again on schedule
+pub async fn run_daemon(
+    mut args: Args,
+    config: &Config,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let Command::Daemon {
+        every,
+        jitter,
+        status_file,
+        transport,
+    } = args.command.clone()
+    else {
+        unreachable!("not a daemon");
+    };
+    // every run changes into the synced directory again
+    args.directory = std::path::absolute(&args.directory)?
+        .to_string_lossy()
+        .into_owned();
+    if let Some(archive_removed) = args.archive_removed.as_mut() {
+        *archive_removed = std::path::absolute(&archive_removed)?;
+    }
+    let status_file = status_file.map(std::path::absolute).transpose()?;
+    args.command = Command::Sync(transport);
+    if let TransportType::Local { destination, .. } = args.transport_mut().unwrap() {
+        *destination = std::path::absolute(&destination)?
+            .to_string_lossy()
+            .into_owned();
+    }
+
+    let mut status = Status {
+        pid: std::process::id(),
+        ..Default::default()
+    };
+    let mut due = every.first(&chrono::Local::now());
+    let mut stopping = false;
+    loop {
+        let Some(next_run) = due else {
+            return Err("the cron expression never matches, no sync would ever run".into());
+        };
+        let delay = jitter.map_or(Duration::ZERO, |jitter| {
+            jitter.mul_f64(rand::random::<f64>())
+        });
+        let next_run = next_run + chrono::TimeDelta::from_std(delay)?;
+        status.next_run = Some(next_run);
+        if let Some(path) = &status_file {
+            status.write(path)?;
+        }
+        println!(
+            "{}",
+            t!("daemon_next", time = next_run.format("%Y-%m-%d %H:%M:%S"))
+        );
+        let wait = (next_run - chrono::Local::now())
+            .to_std()
+            .unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        let started = chrono::Local::now();
+        status.running = true;
+        status.started = Some(started);
+        status.next_run = None;
+        if let Some(path) = &status_file {
+            status.write(path)?;
+        }
+        let now = std::time::Instant::now();
+        let controller = Arc::new(Controller::new());
+        let run = run_hooked(args.clone(), config, Arc::clone(&controller), now);
+        tokio::pin!(run);
+        let result = loop {
+            tokio::select! {
+                result = &mut run => break result,
+                _ = tokio::signal::ctrl_c() => {
+                    // dropped like a sync stopped with Ctrl+C outside the daemon
+                    if stopping {
+                        break Err("stopped during a sync".into());
+                    }
+                    // what's done is kept in the checksum file, like a cancel
+                    // over the control socket
+                    println!("{}", t!("daemon_stopping"));
+                    controller.handle(control::Command::Cancel);
+                    stopping = true;
+                }
+            }
+        };
+        log_outcome(&result);
+        if args.notify {
+            notify_outcome(&result, now.elapsed()).await;
+        }
+        let (result, bytes, error) = match result {
+            Ok(Some(Outcome { bytes, errors, .. })) => {
+                (if errors { "errors" } else { "ok" }, bytes, None)
+            }
+            Ok(None) => ("ok", 0, None),
+            Err(e) => {
+                eprintln!("{}", t!("daemon_failed", error = e));
+                ("failed", 0, Some(e.to_string()))
+            }
+        };
+        status.runs += 1;
+        if result != "ok" {
+            status.failures += 1;
+        }
+        status.running = false;
+        status.started = None;
+        status.last_run = Some(LastRun {
+            started,
+            finished: chrono::Local::now(),
+            result,
+            bytes,
+            error,
+        });
+
+        if stopping {
+            break;
+        }
+        due = every.next_after(&started);
+        let now = chrono::Local::now();
+        if due.as_ref().is_some_and(|due| *due <= now) {
+            println!("{}", t!("daemon_overran"));
+            due = every.next_after(&now);
+        }
+    }
+
+    status.next_run = None;
+    if let Some(path) = &status_file {
+        status.write(path)?;
+    }
+    println!("{}", t!("daemon_stopped"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn parses_intervals_and_cron_expressions() {
+        assert_eq!(
+            "6h".parse::<Schedule>().unwrap(),
+            Schedule::Every(Duration::from_secs(6 * 3600))
+        );
+        let Schedule::Cron(cron) = "*/15 9-17 * * 1-5".parse::<Schedule>().unwrap() else {
+            panic!("not a cron expression");
+        };
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.weekdays, 0b111110);
+        assert_eq!(field("5/20", 0, 59).unwrap(), 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(field("1,3-4", 0, 7).unwrap(), 0b11010);
+        assert_eq!(parse_jitter("0s"), Ok(Duration::ZERO));
+        for invalid in [
+            "0",
+            "0m",
+            "6x",
+            "* * * *",
+            "60 * * * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+        ] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn finds_the_next_run() {
+        let every = "6h".parse::<Schedule>().unwrap();
+        let now = at("2024-05-10 10:20:30");
+        assert_eq!(every.first(&now), Some(now));
+        assert_eq!(every.next_after(&now), Some(at("2024-05-10 16:20:30")));
+
+        let nightly = "30 3 * * *".parse::<Schedule>().unwrap();
+        assert_eq!(nightly.first(&now), Some(at("2024-05-11 03:30:00")));
+        let weekdays = "0 9 * * 1-5".parse::<Schedule>().unwrap();
+        // a Friday evening
+        assert_eq!(
+            weekdays.next_after(&at("2024-05-10 18:00:00")),
+            Some(at("2024-05-13 09:00:00"))
+        );
+        // the first of the month or any Sunday
+        let either = "0 0 1 * 0".parse::<Schedule>().unwrap();
+        assert_eq!(
+            either.next_after(&at("2024-05-10 00:00:00")),
+            Some(at("2024-05-12 00:00:00"))
+        );
+        let leap_day = "0 12 29 2 *".parse::<Schedule>().unwrap();
+        assert_eq!(leap_day.first(&now), Some(at("2028-02-29 12:00:00")));
+        assert_eq!("0 0 30 2 *".parse::<Schedule>().unwrap().first(&now), None);
+    }
+}
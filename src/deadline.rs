@@ -0,0 +1,206 @@
+use futures::{stream, StreamExt};
+use std::{fmt, str::FromStr, time::Duration};
+use tokio::{
+    task::{JoinError, JoinHandle},
+    time::Instant,
+};
+
+/// Longest part of the run kept back for uploading the checksum file and
+/// closing connections
+const MAX_FLUSH_RESERVE: Duration = Duration::from_secs(60);
+
+/// Wall clock limit for a whole run, e.g. `50m`, `1h30m` or `90s`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunTimeout(Duration);
+
+impl RunTimeout {
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+
+    /// When no more actions are started, leaves a tenth of the run (at most two
+    /// minutes) for actions in flight and flushing the checksum file
+    pub fn stop_scheduling_at(&self, started: Instant) -> Instant {
+        started + self.0 - self.flush_reserve() * 2
+    }
+
+    /// When actions still in flight are abandoned, so the checksum file can
+    /// be uploaded before the limit is reached
+    pub fn abandon_at(&self, started: Instant) -> Instant {
+        started + self.0 - self.flush_reserve()
+    }
+
+    fn flush_reserve(&self) -> Duration {
+        (self.0 / 20).min(MAX_FLUSH_RESERVE)
+    }
+}
+
+impl FromStr for RunTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let duration = parse_duration(s)?;
+        if duration.is_zero() {
+            return Err("run timeout must be longer than zero".to_string());
+        }
+        if Instant::now().checked_add(duration).is_none() {
+            return Err(format!("run timeout {s:?} is too long"));
+        }
+        Ok(Self(duration))
+    }
+}
+
+/// Parses a number of seconds or a sequence of `<number><unit>` parts with
+/// units `h`, `m` and `s`, zero included
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    if s.is_empty() {
+        return Err("missing duration, expected e.g. 50m or 1h30m".to_string());
+    }
+    let too_long = || format!("duration {s:?} is too long");
+    let mut total: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("missing unit after {rest:?}, expected h, m or s"))?;
+        let value = match rest[..digits].parse::<u64>() {
+            Ok(value) => value,
+            Err(e) if *e.kind() == std::num::IntErrorKind::PosOverflow => return Err(too_long()),
+            Err(_) => {
+                return Err(format!(
+                    "invalid duration {s:?}, expected e.g. 50m or 1h30m"
+                ))
+            }
+        };
+        let unit = match rest[digits..].chars().next() {
+            Some('h') => 3600,
+            Some('m') => 60,
+            Some('s') => 1,
+            _ => return Err(format!("invalid unit in {s:?}, expected h, m or s")),
+        };
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(too_long)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Waits for the spawned `actions`, `concurrency` at a time. Once `abandon_at`
+/// passes, those still running are aborted so they don't compete with
+/// flushing the checksum file, and none are returned
+pub async fn run_until(
+    abandon_at: Option<Instant>,
+    concurrency: usize,
+    actions: impl Iterator<Item = JoinHandle<()>>,
+) -> Option<Vec<Result<(), JoinError>>> {
+    let mut started = vec![];
+    let finished = {
+        let actions = stream::iter(actions.inspect(|action| started.push(action.abort_handle())))
+            .buffer_unordered(concurrency)
+            .collect::<Vec<_>>();
+        match abandon_at {
+            Some(abandon_at) => tokio::time::timeout_at(abandon_at, actions).await.ok(),
+            None => Some(actions.await),
+        }
+    };
+    if finished.is_none() {
+        for action in started {
+            action.abort();
+        }
+    }
+    finished
+}
+
+impl fmt::Display for RunTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.as_secs();
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if hours > 0 {
+            write!(f, "{hours}h")?;
+        }
+        if minutes > 0 {
+            write!(f, "{minutes}m")?;
+        }
+        if seconds > 0 || (hours == 0 && minutes == 0) {
+            write!(f, "{seconds}s")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    };
+
+    #[test]
+    fn parses_durations() {
+        let timeout = "1h30m".parse::<RunTimeout>().unwrap();
+        assert_eq!(timeout.duration(), Duration::from_secs(5400));
+        assert_eq!(timeout.to_string(), "1h30m");
+        assert_eq!(
+            "90".parse::<RunTimeout>().unwrap().duration(),
+            Duration::from_secs(90)
+        );
+        assert_eq!("50m".parse::<RunTimeout>().unwrap().to_string(), "50m");
+        for invalid in [
+            "",
+            "0",
+            "0s",
+            "0h0m",
+            "5x",
+            "m",
+            "10m5",
+            "18446744073709551615",
+            "99999999999999999h",
+            "5124095576030431h5124095576030431h",
+        ] {
+            assert!(invalid.parse::<RunTimeout>().is_err(), "{invalid}");
+        }
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn keeps_time_for_flushing() {
+        let started = Instant::now();
+        let timeout = "50m".parse::<RunTimeout>().unwrap();
+        assert_eq!(
+            timeout.abandon_at(started) - started,
+            Duration::from_secs(49 * 60)
+        );
+        assert_eq!(
+            timeout.stop_scheduling_at(started) - started,
+            Duration::from_secs(48 * 60)
+        );
+        let short = "100s".parse::<RunTimeout>().unwrap();
+        assert_eq!(short.abandon_at(started) - started, Duration::from_secs(95));
+    }
+
+    #[tokio::test]
+    async fn abandoned_actions_are_aborted() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let actions = [0, 60].map(|seconds| {
+            let finished = Arc::clone(&finished);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(seconds)).await;
+                finished.fetch_add(1, SeqCst);
+            })
+        });
+        let abandon_at = Instant::now() + Duration::from_millis(50);
+        assert!(run_until(Some(abandon_at), 2, actions.into_iter())
+            .await
+            .is_none());
+        tokio::task::yield_now().await;
+        assert_eq!(finished.load(SeqCst), 1);
+        assert_eq!(Arc::strong_count(&finished), 1);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub checksum: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+impl DuplicateGroup {
+    /// Bytes that would be freed by keeping only one copy of the file
+    pub fn wasted_bytes(&self) -> u64 {
+        self.size * (self.paths.len() as u64).saturating_sub(1)
+    }
+}
+
+/// Groups `(path, checksum, size)` triples by checksum and returns the groups
+/// with more than one path, the ones wasting the most bytes first
+pub fn find_duplicates(
+    files: impl IntoIterator<Item = (String, String, u64)>,
+) -> Vec<DuplicateGroup> {
+    let mut by_checksum: HashMap<String, DuplicateGroup> = HashMap::new();
+    for (path, checksum, size) in files {
+        by_checksum
+            .entry(checksum.clone())
+            .or_insert_with(|| DuplicateGroup {
+                checksum,
+                size,
+                paths: vec![],
+            })
+            .paths
+            .push(path);
+    }
+
+    let mut groups: Vec<_> = by_checksum
+        .into_values()
+        .filter(|group| group.paths.len() > 1)
+        .map(|mut group| {
+            group.paths.sort();
+            group
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.wasted_bytes()
+            .cmp(&a.wasted_bytes())
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    groups
+}
+
+/// The files sharing their size with another one, ordered by path. Of files
+/// whose checksums don't digest their contents, only these can be duplicates
+pub fn sharing_size(files: impl IntoIterator<Item = (String, u64)>) -> Vec<(String, u64)> {
+    let mut by_size: HashMap<u64, Vec<String>> = HashMap::new();
+    for (path, size) in files {
+        by_size.entry(size).or_default().push(path);
+    }
+    let mut shared: Vec<_> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (path, size)))
+        .collect();
+    shared.sort();
+    shared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_duplicates() {
+        let groups = find_duplicates(vec![
+            ("./a.txt".to_string(), "hash1".to_string(), 10),
+            ("./b.txt".to_string(), "hash2".to_string(), 10),
+        ]);
+
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn groups_sorted_by_wasted_bytes() {
+        let groups = find_duplicates(vec![
+            ("./small/b.txt".to_string(), "small".to_string(), 10),
+            ("./big/1.jpg".to_string(), "big".to_string(), 1000),
+            ("./small/a.txt".to_string(), "small".to_string(), 10),
+            ("./small/c.txt".to_string(), "small".to_string(), 10),
+            ("./big/2.jpg".to_string(), "big".to_string(), 1000),
+            ("./unique.txt".to_string(), "unique".to_string(), 5000),
+        ]);
+
+        assert_eq!(
+            groups,
+            vec![
+                DuplicateGroup {
+                    checksum: "big".to_string(),
+                    size: 1000,
+                    paths: vec!["./big/1.jpg".to_string(), "./big/2.jpg".to_string()],
+                },
+                DuplicateGroup {
+                    checksum: "small".to_string(),
+                    size: 10,
+                    paths: vec![
+                        "./small/a.txt".to_string(),
+                        "./small/b.txt".to_string(),
+                        "./small/c.txt".to_string()
+                    ],
+                },
+            ]
+        );
+        assert_eq!(groups[0].wasted_bytes(), 1000);
+        assert_eq!(groups[1].wasted_bytes(), 20);
+    }
+
+    #[test]
+    fn only_sizes_shared_are_candidates() {
+        let shared = sharing_size(vec![
+            ("./b.mov".to_string(), 3_000_000),
+            ("./c.mov".to_string(), 5_000_000),
+            ("./a.mov".to_string(), 3_000_000),
+        ]);
+
+        assert_eq!(
+            shared,
+            vec![
+                ("./a.mov".to_string(), 3_000_000),
+                ("./b.mov".to_string(), 3_000_000),
+            ]
+        );
+    }
+}
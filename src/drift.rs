@@ -0,0 +1,294 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    transport::{RemoteEntry, Transport},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// A file of the checksum file that's no longer on the remote the way
+/// syncbox left it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// Another size, or modified after it was synced
+    Changed(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl Drift {
+    pub fn path(&self) -> &Path {
+        let (Drift::Changed(path) | Drift::Deleted(path)) = self;
+        path
+    }
+}
+
+/// What to do about files changed or deleted on the remote behind syncbox's back
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Upload the local version again, or remove a changed file the local
+    /// directory doesn't have any more
+    #[default]
+    Reupload,
+    /// Keep what the remote has for files that didn't change locally,
+    /// deleted files can only be uploaded again
+    Adopt,
+    /// Stop before anything is executed
+    Error,
+}
+
+impl FromStr for DriftPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reupload" => Ok(Self::Reupload),
+            "adopt" => Ok(Self::Adopt),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "unknown drift policy {s:?}, expected reupload, adopt or error"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DriftPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reupload => write!(f, "reupload"),
+            Self::Adopt => write!(f, "adopt"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The remote entries of the directories `tree` has, by path. Directories
+/// the tree doesn't know are not descended into
+pub async fn list_tracked<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    tree: &ChecksumTree,
+) -> Result<HashMap<PathBuf, RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+    let mut listing = HashMap::new();
+    let mut to_list = vec![PathBuf::from(".")];
+    while let Some(dir) = to_list.pop() {
+        for entry in transport.list(&dir).await? {
+            if entry.is_dir
+                && matches!(
+                    tree.get_at(&entry.path),
+                    Some(ChecksumElement::Directory(_))
+                )
+            {
+                to_list.push(entry.path.clone());
+            }
+            listing.insert(entry.path.clone(), entry);
+        }
+    }
+    Ok(listing)
+}
+
+/// Files of `tree` missing from `listing`, or there with another size than
+/// recorded or modified more than `tolerance` after they were synced, in
+/// the order of their paths
+pub fn detect(
+    tree: &ChecksumTree,
+    listing: &HashMap<PathBuf, RemoteEntry>,
+    tolerance: Duration,
+) -> Vec<Drift> {
+    let mut drifts = tree
+        .iter()
+        .filter_map(|(path, entry)| {
+            let Some(remote) = listing.get(&path).filter(|remote| !remote.is_dir) else {
+                return Some(Drift::Deleted(path));
+            };
+            let resized = entry.size.is_some_and(|size| size != remote.size);
+            let modified_later = entry
+                .synced
+                .and_then(|synced| u64::try_from(synced).ok())
+                .zip(remote.modified)
+                .is_some_and(|(synced, modified)| {
+                    modified > UNIX_EPOCH + Duration::from_secs(synced) + tolerance
+                });
+            (resized || modified_later).then_some(Drift::Changed(path))
+        })
+        .collect::<Vec<_>>();
+    drifts.sort_by(|a, b| a.path().cmp(b.path()));
+    drifts
+}
+
+/// Makes `previous`, the tree of the remote, and `next`, the local one,
+/// reconcile the `drifts` as `policy` says. Deleted files are forgotten,
+/// changed ones no longer match anything. With `DriftPolicy::Adopt`, changed
+/// files the local directory has as they were synced are taken as they are
+/// on the remote instead, recording its size and time so they aren't
+/// reported again. Does nothing with `DriftPolicy::Error`
+pub fn settle(
+    policy: DriftPolicy,
+    drifts: &[Drift],
+    previous: &mut ChecksumTree,
+    next: &mut ChecksumTree,
+    listing: &HashMap<PathBuf, RemoteEntry>,
+) {
+    if policy == DriftPolicy::Error {
+        return;
+    }
+    for drift in drifts {
+        match drift {
+            Drift::Deleted(path) => previous.remove_at(path),
+            Drift::Changed(path) => {
+                let adopted = match (previous.get_at(path), next.get_at(path)) {
+                    (Some(ChecksumElement::File(previous)), Some(ChecksumElement::File(local)))
+                        if policy == DriftPolicy::Adopt && previous.checksum == local.checksum =>
+                    {
+                        let remote = &listing[path];
+                        let mut local = local.clone();
+                        local.size = Some(remote.size);
+                        local.synced = remote
+                            .modified
+                            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                            .and_then(|since| since.as_secs().try_into().ok());
+                        Some(local)
+                    }
+                    _ => None,
+                };
+                match adopted {
+                    Some(entry) => {
+                        previous.insert_at(path, ChecksumElement::File(entry.clone()));
+                        next.insert_at(path, ChecksumElement::File(entry));
+                    }
+                    None => {
+                        if let Some(ChecksumElement::File(entry)) = previous.get_at_mut(path) {
+                            entry.checksum.clear();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum_tree::{
+            fixtures::{file, tree_of},
+            FileEntry,
+        },
+        reconciler::{Action, Reconciler},
+        transport::memory::MemoryTransport,
+    };
+    use std::io::Cursor;
+
+    fn synced(checksum: &str) -> FileEntry {
+        FileEntry {
+            size: Some(3),
+            synced: Some(1),
+            ..file(checksum)
+        }
+    }
+
+    async fn write(transport: &mut MemoryTransport, path: &str, contents: &str) {
+        transport
+            .write(
+                Path::new(path),
+                Box::new(Cursor::new(contents.as_bytes().to_vec())),
+                contents.len() as u64,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_changed_and_deleted_files() {
+        let mut transport = MemoryTransport::new();
+        transport.mkdir(Path::new("./dir")).await.unwrap();
+        write(&mut transport, "./same.txt", "abc").await;
+        write(&mut transport, "./dir/grown.txt", "abcd").await;
+        write(&mut transport, "./untracked.txt", "abc").await;
+
+        let tree = tree_of(
+            ["./same.txt", "./dir/grown.txt", "./dir/gone.txt"].map(|path| (path, synced("a"))),
+        );
+        let listing = list_tracked(&mut transport, &tree).await.unwrap();
+        let forever = Duration::from_secs(u32::MAX.into());
+        assert_eq!(
+            detect(&tree, &listing, forever),
+            vec![
+                Drift::Deleted("./dir/gone.txt".into()),
+                Drift::Changed("./dir/grown.txt".into()),
+            ]
+        );
+        // written long after the tree says they were synced
+        assert!(detect(&tree, &listing, Duration::from_secs(60))
+            .contains(&Drift::Changed("./same.txt".into())));
+    }
+
+    #[test]
+    fn drifted_files_are_settled_by_the_policy() {
+        let remote = |path: &str, size| RemoteEntry {
+            path: path.into(),
+            is_dir: false,
+            size,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(5)),
+        };
+        let listing = HashMap::from([
+            ("./changed.txt".into(), remote("./changed.txt", 9)),
+            ("./edited.txt".into(), remote("./edited.txt", 9)),
+        ]);
+        let drifts = [
+            Drift::Changed("./changed.txt".into()),
+            Drift::Changed("./edited.txt".into()),
+            Drift::Deleted("./gone.txt".into()),
+        ];
+        let previous = tree_of(
+            ["./changed.txt", "./edited.txt", "./gone.txt"].map(|path| (path, synced("a"))),
+        );
+        let mut next = previous.clone();
+        // edited locally as well, the local version wins
+        next.insert_at(
+            Path::new("./edited.txt"),
+            ChecksumElement::File(synced("b")),
+        );
+
+        let (mut reuploaded, mut local) = (previous.clone(), next.clone());
+        settle(
+            DriftPolicy::Reupload,
+            &drifts,
+            &mut reuploaded,
+            &mut local,
+            &listing,
+        );
+        assert_eq!(
+            Reconciler::reconcile(reuploaded, &local).unwrap(),
+            vec![
+                Action::Put("./changed.txt".into()),
+                Action::Put("./edited.txt".into()),
+                Action::Put("./gone.txt".into()),
+            ]
+        );
+
+        let (mut adopted, mut local) = (previous, next);
+        settle(
+            DriftPolicy::Adopt,
+            &drifts,
+            &mut adopted,
+            &mut local,
+            &listing,
+        );
+        assert!(matches!(
+            local.get_at(Path::new("./changed.txt")),
+            Some(ChecksumElement::File(entry)) if entry.size == Some(9) && entry.synced == Some(5)
+        ));
+        assert_eq!(
+            Reconciler::reconcile(adopted, &local).unwrap(),
+            vec![
+                Action::Put("./edited.txt".into()),
+                Action::Put("./gone.txt".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{lock::LockHeld, transport::is_auth_failure};
+use std::{error::Error, fmt};
+
+/// Exit code of a run that found nothing to do, with `--detailed-exit-codes`
+pub const NOTHING_TO_DO: u8 = 9;
+
+/// Why syncbox failed as far as a script calling it cares, each kind exits
+/// with a code of its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Anything not told apart below
+    Other,
+    /// Invalid arguments or a profile that doesn't exist, like clap's errors
+    Usage,
+    /// The run went through, but some actions failed
+    Partial,
+    /// The remote couldn't be reached
+    Connection,
+    /// The remote turned down the credentials
+    Auth,
+    /// Another run holds the lock on the remote
+    Locked,
+    /// The checksum file on the remote is missing or damaged where one is needed
+    ChecksumFile,
+    /// Stopped by `--run-timeout` or the control socket before finishing
+    Interrupted,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Other => 1,
+            Failure::Usage => 2,
+            Failure::Partial => 3,
+            Failure::Connection => 4,
+            Failure::Auth => 5,
+            Failure::Locked => 6,
+            Failure::ChecksumFile => 7,
+            Failure::Interrupted => 8,
+        }
+    }
+
+    /// Marks `error` as this kind of failure, its message stays the same
+    pub fn error(
+        self,
+        error: impl Into<Box<dyn Error + Send + Sync + 'static>>,
+    ) -> Box<dyn Error + Send + Sync + 'static> {
+        Box::new(Classified {
+            failure: self,
+            error: error.into(),
+        })
+    }
+
+    /// The kind of failure `error` was marked as, or is by its type
+    pub fn of(error: &(dyn Error + Send + Sync + 'static)) -> Self {
+        if let Some(classified) = error.downcast_ref::<Classified>() {
+            return classified.failure;
+        }
+        if error.is::<LockHeld>() {
+            return Failure::Locked;
+        }
+        Failure::Other
+    }
+}
+
+/// Marks an error opening or pinging a connection, turned down credentials
+/// apart from the rest. Errors already marked are left alone
+pub fn connecting(
+    error: Box<dyn Error + Send + Sync + 'static>,
+) -> Box<dyn Error + Send + Sync + 'static> {
+    if error.is::<Classified>() {
+        return error;
+    }
+    let failure = if is_auth_failure(error.as_ref()) {
+        Failure::Auth
+    } else {
+        Failure::Connection
+    };
+    failure.error(format!("Connection failed with error: {error}"))
+}
+
+/// An error marked with its kind of failure
+#[derive(Debug)]
+struct Classified {
+    failure: Failure,
+    error: Box<dyn Error + Send + Sync + 'static>,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for Classified {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn errors_keep_their_kind_and_message() {
+        let error = Failure::ChecksumFile.error("could not read .syncbox.json.gz");
+        assert_eq!(Failure::of(error.as_ref()), Failure::ChecksumFile);
+        assert_eq!(error.to_string(), "could not read .syncbox.json.gz");
+        assert_eq!(
+            Failure::of(connecting(error).as_ref()),
+            Failure::ChecksumFile
+        );
+
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        let error = connecting(refused.into());
+        assert_eq!(Failure::of(error.as_ref()), Failure::Connection);
+        assert_eq!(error.to_string(), "Connection failed with error: refused");
+        assert_eq!(
+            Failure::of(&*Box::<dyn Error + Send + Sync>::from("no")),
+            Failure::Other
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+/// More files than this are likely a mistyped directory argument
+pub const DEFAULT_MAX_FILES: usize = 100_000;
+
+/// Why syncing `directory` is likely a mistake, e.g. because the directory
+/// argument was left out in a script and it resolved to `/` or the home
+/// directory. Both paths are expected to be canonical
+pub fn risky_directory(directory: &Path, home: Option<&Path>) -> Option<String> {
+    if directory.parent().is_none() {
+        return Some(format!(
+            "{} is the root of the filesystem",
+            directory.display()
+        ));
+    }
+    let home = home?;
+    if directory == home {
+        Some(format!("{} is your home directory", directory.display()))
+    } else if Some(directory) == home.parent() {
+        Some(format!(
+            "{} holds the home directories of all users",
+            directory.display()
+        ))
+    } else {
+        None
+    }
+}
+
+/// The canonical home directory of the current user
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .and_then(|home| Path::new(&home).canonicalize().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roots_and_homes_are_risky() {
+        let home = Some(Path::new("/home/alice"));
+        assert!(risky_directory(Path::new("/"), home)
+            .unwrap()
+            .contains("root of the filesystem"));
+        assert!(risky_directory(Path::new("/home/alice"), home)
+            .unwrap()
+            .contains("home directory"));
+        assert!(risky_directory(Path::new("/home"), home).is_some());
+        assert!(risky_directory(Path::new("/home/alice/site"), home).is_none());
+        assert!(risky_directory(Path::new("/srv/www"), home).is_none());
+        assert!(risky_directory(Path::new("/srv/www"), None).is_none());
+    }
+}
@@ -0,0 +1,341 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Turns a file into the digest the reconciler compares, a file whose digest
+/// changed is uploaded again
+pub trait Hasher: Send + Sync {
+    fn digest(&self, path: &Path, metadata: &Metadata) -> io::Result<String>;
+}
+
+pub struct Sha256;
+
+impl Hasher for Sha256 {
+    fn digest(&self, path: &Path, _: &Metadata) -> io::Result<String> {
+        sha256::try_digest(path)
+    }
+}
+
+/// Hashes large files on all cores
+pub struct Blake3;
+
+impl Hasher for Blake3 {
+    fn digest(&self, path: &Path, _: &Metadata) -> io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_mmap_rayon(path)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// 128-bit XXH3
+pub struct Xxh3Hasher;
+
+impl Hasher for Xxh3Hasher {
+    fn digest(&self, path: &Path, _: &Metadata) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        let mut hasher = Xxh3::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+        Ok(format!("{:032x}", hasher.digest128()))
+    }
+}
+
+/// Size, creation and modification time instead of the contents, which
+/// doesn't read the file at all
+pub struct MetadataOnly;
+
+impl Hasher for MetadataOnly {
+    fn digest(&self, _: &Path, metadata: &Metadata) -> io::Result<String> {
+        let seconds = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .map_err(io::Error::other)
+        };
+        Ok(format!(
+            "s{}_c{}_m{}",
+            metadata.len(),
+            seconds(metadata.created()?)?,
+            seconds(metadata.modified()?)?
+        ))
+    }
+}
+
+/// Bytes read from each end of a file for its quick hash
+const QUICK_HASH_SPAN: u64 = 1024 * 1024;
+
+/// XXH3 of the first and the last megabyte of a file, recorded along with
+/// metadata checksums to tell a file that was only touched from one that
+/// changed, see `MetadataChangePolicy`. Changes in between go unnoticed
+pub fn quick_hash(path: &Path, metadata: &Metadata) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = Vec::with_capacity(QUICK_HASH_SPAN as usize);
+    (&mut file).take(QUICK_HASH_SPAN).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+    if metadata.len() > QUICK_HASH_SPAN {
+        let tail = metadata
+            .len()
+            .saturating_sub(QUICK_HASH_SPAN)
+            .max(QUICK_HASH_SPAN);
+        file.seek(SeekFrom::Start(tail))?;
+        buffer.clear();
+        file.take(QUICK_HASH_SPAN).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(format!("{:032x}", hasher.digest128()))
+}
+
+/// Whether two metadata checksums differ in their creation and modification
+/// times only, not in size nor permissions
+pub fn only_times_differ(a: &str, b: &str) -> bool {
+    fn without_times(checksum: &str) -> Option<(&str, Vec<&str>)> {
+        if HashAlgorithm::of(checksum) != HashAlgorithm::Metadata {
+            return None;
+        }
+        let mut parts = checksum.split('_');
+        let size = parts.next()?;
+        let times = [parts.next()?, parts.next()?];
+        (times[0].starts_with('c') && times[1].starts_with('m')).then(|| (size, parts.collect()))
+    }
+    a != b && without_times(a).is_some_and(|a| Some(a) == without_times(b))
+}
+
+/// `checksum` without the permissions `--preserve-permissions` adds to it,
+/// so what's left only changes along with the file
+pub fn without_permissions(checksum: &str) -> &str {
+    let Some((contents, permissions)) = checksum.rsplit_once("_p") else {
+        return checksum;
+    };
+    let mode = permissions
+        .split_once("_o")
+        .map_or(permissions, |(mode, _)| mode);
+    if !mode.is_empty() && mode.chars().all(|c| c.is_digit(8)) {
+        contents
+    } else {
+        checksum
+    }
+}
+
+/// What to make of a file above `--file-size-threshold` whose times changed
+/// but not its size, like after `touch` or `git checkout`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MetadataChangePolicy {
+    /// Upload it again
+    #[default]
+    Upload,
+    /// Upload it again unless its quick hash is the same, see `quick_hash`
+    QuickHash,
+}
+
+impl FromStr for MetadataChangePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upload" => Ok(Self::Upload),
+            "quick-hash" => Ok(Self::QuickHash),
+            _ => Err(format!(
+                "unknown metadata change policy {s:?}, expected upload or quick-hash"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MetadataChangePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upload => write!(f, "upload"),
+            Self::QuickHash => write!(f, "quick-hash"),
+        }
+    }
+}
+
+/// Algorithm of a checksum, every checksum names its own since checksums of
+/// different algorithms can't be compared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Several times faster than SHA-256
+    Blake3,
+    /// Not cryptographic but faster still, for destinations nobody tampers
+    /// with
+    Xxh3,
+    /// Used above `--file-size-threshold`
+    Metadata,
+}
+
+impl HashAlgorithm {
+    /// Trees written before checksums named their algorithm are SHA-256
+    pub fn is_sha256(&self) -> bool {
+        *self == Self::Sha256
+    }
+
+    pub fn hasher(&self) -> &'static dyn Hasher {
+        match self {
+            Self::Sha256 => &Sha256,
+            Self::Blake3 => &Blake3,
+            Self::Xxh3 => &Xxh3Hasher,
+            Self::Metadata => &MetadataOnly,
+        }
+    }
+
+    /// Checksum of `path` naming this algorithm
+    pub fn checksum(&self, path: &Path, metadata: &Metadata) -> io::Result<String> {
+        Ok(self.tag(&self.hasher().digest(path, metadata)?))
+    }
+
+    /// Names the algorithm in front of `digest` like `blake3:…`. SHA-256 and
+    /// metadata checksums predate this and stay as they were, so existing
+    /// checksum files keep matching
+    pub fn tag(&self, digest: &str) -> String {
+        match self {
+            Self::Sha256 | Self::Metadata => digest.to_string(),
+            Self::Blake3 | Self::Xxh3 => format!("{self}:{digest}"),
+        }
+    }
+
+    /// Algorithm `checksum` was made with
+    pub fn of(checksum: &str) -> Self {
+        if let Some(algorithm) = checksum
+            .split_once(':')
+            .and_then(|(name, _)| name.parse().ok())
+        {
+            return algorithm;
+        }
+        let mut chars = checksum.chars();
+        if chars.next() == Some('s') && chars.next().is_some_and(|c| c.is_ascii_digit()) {
+            Self::Metadata
+        } else {
+            Self::Sha256
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            "xxh3" => Ok(Self::Xxh3),
+            "metadata" => Ok(Self::Metadata),
+            _ => Err(format!(
+                "unknown hash {s:?}, expected sha256, blake3, xxh3 or metadata"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "sha256"),
+            Self::Blake3 => write!(f, "blake3"),
+            Self::Xxh3 => write!(f, "xxh3"),
+            Self::Metadata => write!(f, "metadata"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xxhash_rust::xxh3::xxh3_128;
+
+    #[test]
+    fn digests_files() {
+        let path = std::env::temp_dir().join(format!("syncbox-hash-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let checksum = |algorithm: HashAlgorithm| {
+            algorithm
+                .checksum(&path, &std::fs::metadata(&path).unwrap())
+                .unwrap()
+        };
+
+        assert_eq!(
+            checksum(HashAlgorithm::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            checksum("blake3".parse().unwrap()),
+            "blake3:6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            checksum(HashAlgorithm::Xxh3),
+            format!("xxh3:{:032x}", xxh3_128(b"abc"))
+        );
+        assert!(checksum(HashAlgorithm::Metadata).starts_with("s3_c"));
+
+        // spans several reads
+        let contents: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        assert_eq!(
+            checksum(HashAlgorithm::Xxh3),
+            format!("xxh3:{:032x}", xxh3_128(&contents))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checksums_name_their_algorithm() {
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3,
+        ] {
+            let checksum = algorithm.tag(&"0".repeat(64));
+            assert_eq!(HashAlgorithm::of(&checksum), algorithm);
+            // with --preserve-owner
+            assert_eq!(
+                HashAlgorithm::of(&format!("{checksum}_p644_o1000:1000")),
+                algorithm
+            );
+        }
+        assert_eq!(
+            HashAlgorithm::of("s1024_c1700000000_m1700000000_p644"),
+            HashAlgorithm::Metadata
+        );
+    }
+
+    #[test]
+    fn tells_touched_files() {
+        assert!(only_times_differ("s1024_c1_m1_p644", "s1024_c1_m2_p644"));
+        assert!(!only_times_differ("s1024_c1_m1", "s1024_c1_m1"));
+        assert!(!only_times_differ("s1024_c1_m1", "s1025_c1_m2"));
+        assert!(!only_times_differ("s1024_c1_m1_p644", "s1024_c1_m2_p600"));
+        assert!(!only_times_differ(&"0".repeat(64), &"1".repeat(64)));
+
+        assert_eq!(without_permissions("s1024_c1_m1_p644"), "s1024_c1_m1");
+        assert_eq!(without_permissions("xxh3:ab_p600_o1000:1000"), "xxh3:ab");
+        assert_eq!(without_permissions("xxh3:ab"), "xxh3:ab");
+
+        let path = std::env::temp_dir().join(format!("syncbox-quick-{}", std::process::id()));
+        let quick = |contents: &[u8]| {
+            std::fs::write(&path, contents).unwrap();
+            quick_hash(&path, &std::fs::metadata(&path).unwrap()).unwrap()
+        };
+        let mut contents = vec![0; 3 * QUICK_HASH_SPAN as usize];
+        let before = quick(&contents);
+        contents[QUICK_HASH_SPAN as usize + 1] = 1;
+        assert_eq!(quick(&contents), before, "the middle isn't read");
+        *contents.last_mut().unwrap() = 1;
+        assert_ne!(quick(&contents), before);
+        assert_ne!(quick(b"abc"), quick(b"abd"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{action_filter::shell, invalidate::post};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, time::Duration};
+
+/// A webhook answering slower than this fails, a monitoring service being
+/// down shouldn't hold up syncs
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// When a hook fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum When {
+    /// Before anything is scanned, a hook failing stops the run
+    Before,
+    /// The run went through without errors
+    After,
+    /// The run failed, some actions failed or it was stopped early
+    Failure,
+}
+
+impl fmt::Display for When {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            When::Before => "before",
+            When::After => "after",
+            When::Failure => "failure",
+        })
+    }
+}
+
+/// A shell command run or a URL posted to around a sync, like
+///
+/// ```toml
+/// [[hooks]]
+/// on = "before"
+/// run = "./maintenance.sh on"
+///
+/// [[hooks]]
+/// on = "failure"
+/// url = "https://hc-ping.com/<uuid>/fail"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub on: When,
+    /// Run by the shell in the synced directory, with the report as JSON in
+    /// `SYNCBOX_REPORT`
+    #[serde(default)]
+    pub run: Option<String>,
+    /// Posted the report as JSON
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// What hooks are told about the run
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub event: When,
+    pub command: String,
+    pub directory: String,
+    /// The remote like it's named in the state database
+    pub remote: Option<String>,
+    pub bytes: u64,
+    /// Some actions failed
+    pub errors: bool,
+    /// Why the run failed
+    pub error: Option<String>,
+    pub seconds: f64,
+}
+
+impl Hook {
+    /// Either the command or the URL, for messages
+    pub fn name(&self) -> &str {
+        self.run
+            .as_deref()
+            .or(self.url.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Tells a hook with neither or both of `run` and `url` apart
+    pub fn check(&self) -> Result<(), String> {
+        match (&self.run, &self.url) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(format!("a hook on {} needs either run or url", self.on)),
+        }
+    }
+
+    /// Runs the command or posts to the URL with `report`, errors don't
+    /// repeat which hook it was
+    pub async fn fire(
+        &self,
+        report: &Report,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let json = serde_json::to_vec(report)?;
+        if let Some(url) = &self.url {
+            return tokio::time::timeout(WEBHOOK_TIMEOUT, post(url, &[], json))
+                .await
+                .map_err(|_| format!("{url} didn't answer within {WEBHOOK_TIMEOUT:?}"))?;
+        }
+        let Some(command) = &self.run else {
+            return Ok(());
+        };
+        let status = tokio::process::Command::from(shell(command))
+            .current_dir(&report.directory)
+            .env("SYNCBOX_EVENT", report.event.to_string())
+            .env("SYNCBOX_REPORT", String::from_utf8(json)?)
+            .status()
+            .await
+            .map_err(|e| format!("could not be started: {e}"))?;
+        if !status.success() {
+            return Err(status.to_string().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(event: When) -> Report {
+        Report {
+            event,
+            command: "sync".to_string(),
+            directory: std::env::temp_dir().to_string_lossy().into_owned(),
+            remote: Some("sftp://deploy@example.com/www".to_string()),
+            bytes: 2048,
+            errors: false,
+            error: None,
+            seconds: 1.5,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_get_the_report() {
+        let hook = Hook {
+            on: When::After,
+            run: Some(
+                r#"test "$SYNCBOX_EVENT" = after && echo "$SYNCBOX_REPORT" | grep -q '"bytes":2048'"#
+                    .to_string(),
+            ),
+            url: None,
+        };
+        hook.check().unwrap();
+        hook.fire(&report(When::After)).await.unwrap();
+        let error = hook.fire(&report(When::Failure)).await.unwrap_err();
+        assert!(error.to_string().contains("exit status"), "{error}");
+
+        let neither = Hook {
+            on: When::Before,
+            run: None,
+            url: None,
+        };
+        assert!(neither.check().is_err());
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Language of the printed output. Errors stay in English, so they can be
+/// searched for and reported as they are
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Lang {
+    #[default]
+    En,
+    Cs,
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+impl Lang {
+    /// From `LC_ALL`, `LC_MESSAGES` or `LANG` like `cs_CZ.UTF-8`, English
+    /// when none of them names a known language
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| value.split(['_', '.', '-']).next()?.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Selects the language of all messages from now on
+    pub fn set(self) {
+        LANG.store(self as u8, Ordering::Relaxed);
+    }
+
+    pub fn current() -> Self {
+        match LANG.load(Ordering::Relaxed) {
+            1 => Self::Cs,
+            _ => Self::En,
+        }
+    }
+
+    fn catalog(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::En => EN,
+            Self::Cs => CS,
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Self::En),
+            "cs" => Ok(Self::Cs),
+            _ => Err(format!("unknown language {s:?}, expected en or cs")),
+        }
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::En => write!(f, "en"),
+            Self::Cs => write!(f, "cs"),
+        }
+    }
+}
+
+/// Message `key` in the current language with its `{placeholders}` filled
+/// in, falling back to English for messages not translated yet
+pub fn message(key: &str, values: &[(&str, String)]) -> String {
+    let lookup = |lang: Lang| {
+        lang.catalog()
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, text)| *text)
+    };
+    let Some(text) = lookup(Lang::current()).or_else(|| lookup(Lang::En)) else {
+        return key.to_string();
+    };
+    values.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+/// Looks up a message of the catalog, `t!("purged", what = n, cdn = name)`
+#[macro_export]
+macro_rules! t {
+    ($key:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::message($key, &[$((stringify!($name), $value.to_string())),*])
+    };
+}
+
+const EN: &[(&str, &str)] = &[
+    ("notify_done", "✨ Sync done"),
+    ("notify_errors", "❌ Sync finished with errors"),
+    ("notify_failed", "❌ Sync failed"),
+    ("notify_transferred", "Transferred {size} in {duration}"),
+    ("notify_failed_after", "{error} after {duration}"),
+    ("notify_unavailable", "⚠️  Could not show a desktop notification: {error}"),
+    ("remote_reachable", "🔌 Remote is reachable ({connections} connection(s))"),
+    ("dry_run", "🧪 Dry run, the remote is read but nothing is changed on it"),
+    ("resolving_files", "🔍 Resolving files"),
+    ("calculating_checksums", "🧬 Calculating checksums"),
+    ("writing_checksum_file", "💿 Writing checksum file to {path}"),
+    ("fetching_checksum_file", "📄 Fetching last checksum file"),
+    ("transfers_compressed", "🗜️  Transfers are compressed"),
+    ("clock_ahead", "⚠️  Remote clock is {seconds}s ahead of the local one, more than the mtime tolerance of {tolerance}s"),
+    ("clock_behind", "⚠️  Remote clock is {seconds}s behind the local one, more than the mtime tolerance of {tolerance}s"),
+    ("clock_unknown", "⚠️  Could not measure remote clock drift: {error}"),
+    ("listing_remote", "🔎 Comparing the checksum file with the remote listing"),
+    ("drift_changed", "⚠️  {path} was changed on the remote since it was synced"),
+    ("drift_deleted", "⚠️  {path} was deleted on the remote since it was synced"),
+    ("checksum_file_cut_off", "⚠️  Checksum file was cut off, files missing from it are uploaded again"),
+    ("stored_uncompressed", "⚠️  Files on the remote are stored uncompressed, uploading everything again"),
+    ("stored_compressed", "⚠️  Files on the remote are stored compressed with {compression}, uploading everything again"),
+    ("rehashed", "🧮 {total} file(s) were hashed with another algorithm before, {unchanged} of them are unchanged"),
+    ("touched", "👆 {count} large file(s) were only touched, their first and last megabyte are unchanged"),
+    ("reconciling", "🚚 Reconciling changes"),
+    ("remote_copy_unsupported", "⚠️  The remote can't copy files, --server-side-copy is ignored"),
+    ("copied_on_remote", "📑 {count} file(s) are copied on the remote instead of uploaded"),
+    ("lock_replaced", "🔓 Took over the lock of {host} (pid {pid}) from {time}"),
+    ("journal_replayed", "↩️  {count} action(s) done by an interrupted run were taken over from {path}"),
+    ("skipped_not_utf8", "⚠️  Skipped {path}, its name is not valid UTF-8"),
+    ("nothing_to_do", "🤷 Nothing to do"),
+    ("wrote_checksum_file", "📄 Wrote checksum file {path}"),
+    ("executing", "🚀 Executing {count} action(s)"),
+    ("plan_summary", "📋 {count} action(s): {kinds}"),
+    ("plan_bytes", "📦 {upload} to upload, {remove} to remove"),
+    ("control_socket", "🎛️  Listening for control commands on {path}"),
+    ("creating_directories", "📂 Creating directories"),
+    ("created_directory", "✅ Creating directory {index}/{total} {path} in {seconds}s"),
+    ("create_directory_failed", "❌ Error while creating directory {index}/{total} {path}: {error}"),
+    ("remove_link_failed", "⚠️  Couldn't remove the link {path} before replacing it: {error}"),
+    ("created_link", "✅ Creating link {index}/{total} {path} -> {target} in {seconds}s"),
+    ("create_link_failed", "❌ Error while creating link {index}/{total} {path}: {error}"),
+    ("set_metadata", "✅ Setting modification time and permissions {index}/{total} {path} in {seconds}s"),
+    ("set_metadata_failed", "❌ Error while setting modification time and permissions {index}/{total} {path}: {error}"),
+    ("uploading", "🏂 Uploading {count} files ({size})"),
+    ("connect_to_copy_failed", "❌ Could not connect to copy {path}: {error}"),
+    ("remaining", "{path} | {size} remaining"),
+    ("copied_from", " | 📑 copied from {path}"),
+    ("renamed_from", " | 🔤 renamed from {path}"),
+    ("mtime_failed", " | ⚠️ could not set modification time: {error}"),
+    ("permissions_failed", " | ⚠️ could not set permissions: {error}"),
+    ("uploading_intermittent_checksum", "📸 Uploading intermittent checksum"),
+    ("intermittent_checksum_failed", "❌ Error while uploading intermittent checksum: {error}"),
+    ("copy_failed", "❌ Error while copying {path}: {error}"),
+    ("pull_cut_off", "⚠️  Checksum file was cut off, no local files are removed"),
+    ("restore_empty", "🤷 Nothing to restore below {path}"),
+    ("verifying_downloads", "🔎 Verifying the checksums of {count} file(s)"),
+    ("restore_corrupt", "❌ {path} doesn't match its checksum"),
+    ("bisync_cut_off", "⚠️  Checksum file was cut off, files missing from it are taken as unchanged since the last two-way sync"),
+    ("conflict", "⚠️  {path} differs locally and on the remote, left as it is"),
+    ("conflict_local", "⚖️  {path} differs locally and on the remote, keeping the local version"),
+    ("conflict_remote", "⚖️  {path} differs locally and on the remote, keeping the remote version"),
+    ("conflict_keep_both", "⚖️  {path} differs locally and on the remote, keeping both, the local version as {copy}"),
+    ("conflict_ask", "❓ {path} differs locally and on the remote. Keep the [l]ocal or [r]emote version, [b]oth, or [s]kip it? "),
+    ("downloading", "🛬 Downloading {count} files ({size})"),
+    ("download_failed", "❌ Error while downloading {path}: {error}"),
+    ("removing_files", "🧻 Removing files"),
+    ("removing", "🧻 Removing {count} files ({size})"),
+    ("removing_files_skipped", "🧻 Removing files (skipping)"),
+    ("connect_to_remove_failed", "❌ Could not connect to remove {path}: {error}"),
+    ("removed", "✅ Removed {index}/{total} file: {path} in {seconds}s"),
+    ("removed_already_gone", "✅ Removed {index}/{total} file: {path} (was already gone)"),
+    ("remove_failed", "❌ Error while removing {path}: {error}"),
+    ("removed_directory", "✅ Removed {index}/{total} directory: {path} in {seconds}s"),
+    ("remove_directory_failed", "❌ Error while removing directory {path}: {error}"),
+    ("archiving", "📥 Archiving {path}"),
+    ("run_timeout", "⏱️  Run timeout of {timeout} reached, {count} action(s) left for the next run"),
+    ("cancelled", "🛑 Cancelled, the remaining actions are picked up by the next run"),
+    ("uploading_checksum", "🏁 Uploading checksum"),
+    ("snapshot_taken", "📸 Taking snapshot {path}"),
+    ("snapshot_pruned", "🧻 Pruned snapshot {path}"),
+    ("snapshot_prune_failed", "⚠️ Could not prune snapshot {path}: {error}"),
+    ("purged", "🧹 Purged {what} from {cdn}"),
+    ("hook_failed", "⚠️  The {event} hook {hook} failed: {error}"),
+    ("purge_failed", "❌ Error while purging {cdn}: {error}"),
+    ("done", "✨ Done. Transfered {size} in {seconds}s"),
+    ("risky_sync", "⚠️  {reason}, sync it anyway? [y/N] "),
+    ("scan_cache_hits", "⚡ {count} unchanged file(s) weren't read again (see --no-scan-cache)"),
+    ("state_db_read", "🗃️  Read the state from {path}"),
+    ("state_db_failed", "⚠️  Could not update the state database, the next run reads the checksum file: {error}"),
+    ("scan_cache_unwritable", "⚠️  Could not write the scan cache {path}: {error}"),
+    ("merge_base_unwritable", "⚠️  Could not write {path}, the next two-way sync finds the same changes again: {error}"),
+    ("chmod_failed", "⚠️ Could not set permissions of {path}: {error}"),
+    ("stats", "📊 {count} files, {size} in total"),
+    ("no_duplicates", "🤷 No duplicate files found"),
+    ("hashing_same_size", "🔬 Hashing {count} file(s) above the size threshold that share a size"),
+    ("duplicates", "👯 {count} group(s) of duplicate files wasting {size}"),
+    ("duplicate_group", "{count} × {size} ({wasted} wasted)"),
+    ("no_snapshots", "🤷 No snapshots found"),
+    ("checksum_valid", "✅ {path} is valid"),
+    ("checksum_problems", "⚠️  {count} problems found in {path}:"),
+    ("checksum_repaired", "🩹 Repaired {path}, the original was kept as {broken}"),
+    ("log_empty", "🤷 No files below {path}"),
+    ("log_unknown", "before times were kept"),
+    ("watching", "👀 Watching {count} file(s), press Ctrl+C to stop"),
+    ("daemon_next", "⏰ Next sync at {time}, press Ctrl+C to stop"),
+    ("daemon_failed", "❌ Sync failed: {error}"),
+    ("daemon_overran", "⏭️  The sync ran past its next runs, they were skipped"),
+    ("daemon_stopping", "🛑 Stopping once the sync in progress has saved its progress, press Ctrl+C again to stop right away"),
+    ("daemon_stopped", "👋 Stopped"),
+    ("push_failed", "❌ Error while pushing {path}: {error}"),
+    ("pushed", "✅ Pushed {path} ({size}) in {seconds}s"),
+    ("adopted", "🔎 {adopted} of {probed} file(s) are already on the remote"),
+    ("init_profile", "Profile name"),
+    ("init_transport", "Transport (ftp, sftp, local or s3)"),
+    ("init_option", "{name}"),
+    ("init_testing", "🔌 Testing the connection"),
+    ("init_written", "✅ Wrote {path}"),
+    ("init_kept", "🤷 {path} already exists, it was left as it is (see --force)"),
+    ("init_done", "✨ Done, sync with syncbox --profile {profile}"),
+    ("prune_nothing", "✅ Everything on the remote is in {path}"),
+    ("prune_orphans", "🧹 {count} file(s) on the remote aren't in {path}:"),
+    ("verify_hashing", "🔎 Downloading the files to compare their contents"),
+    ("verify_clean", "✅ The remote matches {path}, {count} file(s) checked"),
+    ("verify_missing", "⚠️  {path} is missing on the remote"),
+    ("verify_mismatched", "⚠️  {path} differs on the remote"),
+    ("verify_extra", "⚠️  {path} is on the remote but not in the checksum file"),
+    ("verify_reuploaded", "🩹 Uploaded {path} again"),
+    ("verify_forgotten", "🩹 {path} changed locally as well, it was left out of the checksum file for the next sync to upload"),
+    ("column_action", "ACTION"),
+    ("column_path", "PATH"),
+    ("column_size", "SIZE"),
+    ("column_status", "STATUS"),
+    ("column_duration", "DURATION"),
+    ("column_directory", "DIRECTORY"),
+    ("column_actions", "ACTIONS"),
+    ("column_upload", "UPLOAD"),
+    ("column_remove", "REMOVE"),
+    ("column_synced", "SYNCED"),
+    ("column_remote", "REMOTE"),
+    ("status_ok", "ok"),
+    ("remote_present", "ok"),
+    ("remote_missing", "missing"),
+    ("remote_resized", "{size} instead"),
+    ("remote_untracked", "not in the checksum file"),
+    ("status_failed", "failed: {error}"),
+    ("status_not_run", "not run"),
+];
+
+const CS: &[(&str, &str)] = &[
+    ("notify_done", "✨ Synchronizace dokončena"),
+    ("notify_errors", "❌ Synchronizace skončila s chybami"),
+    ("notify_failed", "❌ Synchronizace selhala"),
+    ("notify_transferred", "Přeneseno {size} za {duration}"),
+    ("notify_failed_after", "{error} po {duration}"),
+    ("notify_unavailable", "⚠️  Nelze zobrazit upozornění: {error}"),
+    ("remote_reachable", "🔌 Server je dostupný (spojení: {connections})"),
+    ("dry_run", "🧪 Zkušební běh, server se čte, ale nic se na něm nemění"),
+    ("resolving_files", "🔍 Hledání souborů"),
+    ("calculating_checksums", "🧬 Výpočet kontrolních součtů"),
+    ("writing_checksum_file", "💿 Zápis souboru kontrolních součtů do {path}"),
+    ("fetching_checksum_file", "📄 Stahování posledního souboru kontrolních součtů"),
+    ("transfers_compressed", "🗜️  Přenosy jsou komprimované"),
+    ("clock_ahead", "⚠️  Hodiny serveru jdou o {seconds} s napřed oproti místním, více než povolená odchylka {tolerance} s"),
+    ("clock_behind", "⚠️  Hodiny serveru jdou o {seconds} s pozadu oproti místním, více než povolená odchylka {tolerance} s"),
+    ("clock_unknown", "⚠️  Odchylku hodin serveru nelze změřit: {error}"),
+    ("listing_remote", "🔎 Porovnávání kontrolních součtů s výpisem serveru"),
+    ("drift_changed", "⚠️  {path} byl od synchronizace na serveru změněn"),
+    ("drift_deleted", "⚠️  {path} byl od synchronizace na serveru smazán"),
+    ("checksum_file_cut_off", "⚠️  Soubor kontrolních součtů je useknutý, chybějící soubory se nahrají znovu"),
+    ("stored_uncompressed", "⚠️  Soubory na serveru jsou uložené bez komprese, nahrává se vše znovu"),
+    ("stored_compressed", "⚠️  Soubory na serveru jsou uložené s kompresí {compression}, nahrává se vše znovu"),
+    ("rehashed", "🧮 Souborů s kontrolním součtem jiného algoritmu: {total}, z toho beze změny: {unchanged}"),
+    ("touched", "👆 Velkých souborů, kterých se jen někdo dotkl (první a poslední megabajt beze změny): {count}"),
+    ("reconciling", "🚚 Porovnávání změn"),
+    ("remote_copy_unsupported", "⚠️  Server neumí kopírovat soubory, --server-side-copy se ignoruje"),
+    ("copied_on_remote", "📑 Souborů zkopírovaných na serveru místo nahrání: {count}"),
+    ("lock_replaced", "🔓 Převzat zámek od {host} (pid {pid}) z {time}"),
+    ("journal_replayed", "↩️  Převzato {count} akcí dokončených přerušeným během z {path}"),
+    ("skipped_not_utf8", "⚠️  Přeskočen {path}, jeho název není platné UTF-8"),
+    ("nothing_to_do", "🤷 Není co dělat"),
+    ("wrote_checksum_file", "📄 Zapsán soubor kontrolních součtů {path}"),
+    ("executing", "🚀 Provádění akcí: {count}"),
+    ("plan_summary", "📋 Akcí: {count} ({kinds})"),
+    ("plan_bytes", "📦 K nahrání {upload}, ke smazání {remove}"),
+    ("control_socket", "🎛️  Příkazy se přijímají na {path}"),
+    ("creating_directories", "📂 Vytváření adresářů"),
+    ("created_directory", "✅ Vytvořen adresář {index}/{total} {path} za {seconds} s"),
+    ("create_directory_failed", "❌ Chyba při vytváření adresáře {index}/{total} {path}: {error}"),
+    ("remove_link_failed", "⚠️  Odkaz {path} se před nahrazením nepodařilo odstranit: {error}"),
+    ("created_link", "✅ Vytvořen odkaz {index}/{total} {path} -> {target} za {seconds} s"),
+    ("create_link_failed", "❌ Chyba při vytváření odkazu {index}/{total} {path}: {error}"),
+    ("set_metadata", "✅ Nastaven čas změny a oprávnění {index}/{total} {path} za {seconds} s"),
+    ("set_metadata_failed", "❌ Chyba při nastavování času změny a oprávnění {index}/{total} {path}: {error}"),
+    ("uploading", "🏂 Nahrávání souborů: {count} ({size})"),
+    ("connect_to_copy_failed", "❌ Nelze se připojit pro nahrání {path}: {error}"),
+    ("remaining", "{path} | zbývá {size}"),
+    ("copied_from", " | 📑 zkopírováno z {path}"),
+    ("renamed_from", " | 🔤 přejmenováno z {path}"),
+    ("mtime_failed", " | ⚠️ nelze nastavit čas změny: {error}"),
+    ("permissions_failed", " | ⚠️ nelze nastavit oprávnění: {error}"),
+    ("uploading_intermittent_checksum", "📸 Průběžné nahrávání kontrolních součtů"),
+    ("intermittent_checksum_failed", "❌ Chyba při průběžném nahrávání kontrolních součtů: {error}"),
+    ("copy_failed", "❌ Chyba při nahrávání {path}: {error}"),
+    ("pull_cut_off", "⚠️  Soubor kontrolních součtů je useknutý, žádné místní soubory se nemažou"),
+    ("restore_empty", "🤷 Pod {path} není nic k obnovení"),
+    ("verifying_downloads", "🔎 Ověřování kontrolních součtů souborů: {count}"),
+    ("restore_corrupt", "❌ {path} neodpovídá svému kontrolnímu součtu"),
+    ("bisync_cut_off", "⚠️  Soubor kontrolních součtů je useknutý, chybějící soubory se berou jako nezměněné od poslední obousměrné synchronizace"),
+    ("conflict", "⚠️  {path} se liší místně a na serveru, ponechává se beze změny"),
+    ("conflict_local", "⚖️  {path} se liší místně a na serveru, ponechává se místní verze"),
+    ("conflict_remote", "⚖️  {path} se liší místně a na serveru, ponechává se verze ze serveru"),
+    ("conflict_keep_both", "⚖️  {path} se liší místně a na serveru, ponechávají se obě, místní verze jako {copy}"),
+    ("conflict_ask", "❓ {path} se liší místně a na serveru. Ponechat [l] místní verzi, [r] verzi ze serveru, [b] obě, nebo [s] přeskočit? "),
+    ("downloading", "🛬 Stahování souborů: {count} ({size})"),
+    ("download_failed", "❌ Chyba při stahování {path}: {error}"),
+    ("removing_files", "🧻 Mazání souborů"),
+    ("removing", "🧻 Mazání souborů: {count} ({size})"),
+    ("removing_files_skipped", "🧻 Mazání souborů (přeskočeno)"),
+    ("connect_to_remove_failed", "❌ Nelze se připojit pro smazání {path}: {error}"),
+    ("removed", "✅ Smazán soubor {index}/{total}: {path} za {seconds} s"),
+    ("removed_already_gone", "✅ Smazán soubor {index}/{total}: {path} (už neexistoval)"),
+    ("remove_failed", "❌ Chyba při mazání {path}: {error}"),
+    ("removed_directory", "✅ Smazán adresář {index}/{total}: {path} za {seconds} s"),
+    ("remove_directory_failed", "❌ Chyba při mazání adresáře {path}: {error}"),
+    ("archiving", "📥 Archivace {path}"),
+    ("run_timeout", "⏱️  Časový limit {timeout} vypršel, akcí ponechaných na příští běh: {count}"),
+    ("cancelled", "🛑 Zrušeno, zbývající akce provede příští běh"),
+    ("uploading_checksum", "🏁 Nahrávání kontrolních součtů"),
+    ("snapshot_taken", "📸 Ukládání snímku {path}"),
+    ("snapshot_pruned", "🧻 Smazán snímek {path}"),
+    ("snapshot_prune_failed", "⚠️ Snímek {path} nelze smazat: {error}"),
+    ("purged", "🧹 Z {cdn} vyčištěno: {what}"),
+    ("hook_failed", "⚠️  Hook {hook} při události {event} selhal: {error}"),
+    ("purge_failed", "❌ Chyba při čištění {cdn}: {error}"),
+    ("done", "✨ Hotovo. Přeneseno {size} za {seconds} s"),
+    ("risky_sync", "⚠️  {reason}, přesto synchronizovat? [y/N] "),
+    ("scan_cache_hits", "⚡ Nezměněných souborů, které se nečetly znovu: {count} (viz --no-scan-cache)"),
+    ("state_db_read", "🗃️  Stav načten z {path}"),
+    ("state_db_failed", "⚠️  Databázi stavu se nepodařilo aktualizovat, příští běh načte soubor kontrolních součtů: {error}"),
+    ("scan_cache_unwritable", "⚠️  Mezipaměť {path} nelze zapsat: {error}"),
+    ("merge_base_unwritable", "⚠️  {path} nelze zapsat, příští obousměrná synchronizace najde stejné změny znovu: {error}"),
+    ("chmod_failed", "⚠️ Nelze nastavit oprávnění {path}: {error}"),
+    ("stats", "📊 Souborů: {count}, celkem {size}"),
+    ("no_duplicates", "🤷 Žádné duplicitní soubory"),
+    ("hashing_same_size", "🔬 Hašování souborů nad prahem velikosti se shodnou velikostí: {count}"),
+    ("duplicates", "👯 Skupin duplicitních souborů: {count}, zbytečně zabírají {size}"),
+    ("duplicate_group", "{count} × {size} (zbytečně {wasted})"),
+    ("no_snapshots", "🤷 Žádné snímky"),
+    ("checksum_valid", "✅ {path} je v pořádku"),
+    ("checksum_problems", "⚠️  V {path} nalezeno problémů: {count}"),
+    ("checksum_repaired", "🩹 {path} opraven, původní soubor zůstal jako {broken}"),
+    ("log_empty", "🤷 Pod {path} nejsou žádné soubory"),
+    ("log_unknown", "před zaznamenáváním času"),
+    ("watching", "👀 Sledování souborů: {count}, ukončete pomocí Ctrl+C"),
+    ("daemon_next", "⏰ Další synchronizace v {time}, ukončete pomocí Ctrl+C"),
+    ("daemon_failed", "❌ Synchronizace selhala: {error}"),
+    ("daemon_overran", "⏭️  Synchronizace trvala přes další běhy, ty byly vynechány"),
+    ("daemon_stopping", "🛑 Ukončení, jakmile probíhající synchronizace uloží svůj postup, dalším Ctrl+C ihned"),
+    ("daemon_stopped", "👋 Ukončeno"),
+    ("push_failed", "❌ Chyba při nahrávání {path}: {error}"),
+    ("pushed", "✅ Nahrán {path} ({size}) za {seconds} s"),
+    ("adopted", "🔎 Na serveru už je {adopted} z {probed} souborů"),
+    ("init_profile", "Název profilu"),
+    ("init_transport", "Přenos (ftp, sftp, local nebo s3)"),
+    ("init_option", "{name}"),
+    ("init_testing", "🔌 Zkouška připojení"),
+    ("init_written", "✅ Zapsán {path}"),
+    ("init_kept", "🤷 {path} už existuje, zůstal beze změny (viz --force)"),
+    ("init_done", "✨ Hotovo, synchronizujte pomocí syncbox --profile {profile}"),
+    ("prune_nothing", "✅ Vše na serveru je v {path}"),
+    ("prune_orphans", "🧹 Souborů na serveru, které nejsou v {path}: {count}"),
+    ("verify_hashing", "🔎 Stahování souborů pro porovnání obsahu"),
+    ("verify_clean", "✅ Server odpovídá {path}, zkontrolováno souborů: {count}"),
+    ("verify_missing", "⚠️  {path} na serveru chybí"),
+    ("verify_mismatched", "⚠️  {path} se na serveru liší"),
+    ("verify_extra", "⚠️  {path} je na serveru, ale ne v kontrolních součtech"),
+    ("verify_reuploaded", "🩹 {path} znovu nahrán"),
+    ("verify_forgotten", "🩹 {path} se změnil i lokálně, byl vynechán z kontrolních součtů, nahraje ho další synchronizace"),
+    ("column_action", "AKCE"),
+    ("column_path", "CESTA"),
+    ("column_size", "VELIKOST"),
+    ("column_status", "STAV"),
+    ("column_duration", "TRVÁNÍ"),
+    ("column_directory", "ADRESÁŘ"),
+    ("column_actions", "AKCE"),
+    ("column_upload", "NAHRÁNÍ"),
+    ("column_remove", "SMAZÁNÍ"),
+    ("column_synced", "SYNCHRONIZOVÁNO"),
+    ("column_remote", "SERVER"),
+    ("status_ok", "ok"),
+    ("remote_present", "ok"),
+    ("remote_missing", "chybí"),
+    ("remote_resized", "místo toho {size}"),
+    ("remote_untracked", "není v kontrolních součtech"),
+    ("status_failed", "chyba: {error}"),
+    ("status_not_run", "neprovedeno"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(text: &str) -> BTreeSet<&str> {
+        text.split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}'))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn translations_match_english() {
+        for (key, text) in CS {
+            let english = EN.iter().find(|(k, _)| k == key);
+            let Some((_, english)) = english else {
+                panic!("{key} isn't an English message");
+            };
+            assert_eq!(placeholders(text), placeholders(english), "{key}");
+        }
+    }
+
+    #[test]
+    fn used_messages_exist() {
+        let sources = [
+            include_str!("commands.rs"),
+            include_str!("connect.rs"),
+            include_str!("daemon.rs"),
+            include_str!("prompt.rs"),
+            include_str!("pull.rs"),
+            include_str!("push.rs"),
+            include_str!("report.rs"),
+            include_str!("run.rs"),
+            include_str!("scan.rs"),
+            include_str!("upload.rs"),
+        ];
+        for source in sources {
+            for (at, call) in source.match_indices("t!(") {
+                // not print!( and the like
+                if source[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                    continue;
+                }
+                // the key may be on the next line
+                let Some(rest) = source[at + call.len()..].trim_start().strip_prefix('"') else {
+                    continue;
+                };
+                let key = rest.split('"').next().unwrap();
+                assert!(EN.iter().any(|(k, _)| *k == key), "{key} is missing");
+            }
+        }
+    }
+
+    #[test]
+    fn messages_are_filled_in() {
+        assert_eq!(
+            t!("stats", count = 3, size = "1KB"),
+            "📊 3 files, 1KB in total"
+        );
+        assert_eq!(message("no_such_message", &[]), "no_such_message");
+        assert_eq!("cs".parse::<Lang>(), Ok(Lang::Cs));
+        assert!("de".parse::<Lang>().is_err());
+    }
+}
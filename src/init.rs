@@ -0,0 +1,184 @@
+use crate::config::CONFIG_FILENAME;
+use std::fmt::Write as _;
+
+/// Read by syncbox on start, never synced itself
+pub const ENV_FILENAME: &str = ".env.syncbox";
+
+/// Paths left out of the local scan by default, see `syncbox init`
+pub const IGNORE_TEMPLATE: &str = "\
+# Written like .gitignore, matching files aren't uploaded and are removed
+# from the remote
+node_modules/
+*.log
+*.tmp
+.env
+.env.*
+";
+
+/// An option `syncbox init` asks for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// Option of the transport, like `host`
+    pub name: &'static str,
+    pub default: Option<&'static str>,
+    /// Credentials are kept in `.env.syncbox` under the variable the
+    /// transport reads them from, instead of `syncbox.toml`
+    pub env: Option<&'static str>,
+}
+
+const fn field(name: &'static str, default: Option<&'static str>) -> Field {
+    Field {
+        name,
+        default,
+        env: None,
+    }
+}
+
+const fn secret(name: &'static str, env: &'static str) -> Field {
+    Field {
+        name,
+        default: None,
+        env: Some(env),
+    }
+}
+
+const FTP: &[Field] = &[
+    field("ftp_host", None),
+    field("ftp_user", None),
+    secret("ftp_pass", "FTP_PASS"),
+    field("ftp_dir", Some(".")),
+];
+
+const SFTP: &[Field] = &[
+    field("host", None),
+    field("user", None),
+    // empty for keys or the agent
+    secret("pass", "SFTP_PASS"),
+    field("dir", Some(".")),
+];
+
+const LOCAL: &[Field] = &[field("destination", None)];
+
+const S3: &[Field] = &[
+    field("bucket", None),
+    field("region", Some("us-east-1")),
+    secret("access_key", "S3_ACCESS_KEY"),
+    secret("secret_key", "S3_SECRET_KEY"),
+    field("directory", Some(".")),
+];
+
+/// What `syncbox init` asks for a transport, none for one it doesn't know
+pub fn fields(transport: &str) -> Option<&'static [Field]> {
+    match transport {
+        "ftp" => Some(FTP),
+        "sftp" => Some(SFTP),
+        "local" => Some(LOCAL),
+        "s3" => Some(S3),
+        _ => None,
+    }
+}
+
+/// The answers to `syncbox init`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Answers {
+    pub profile: String,
+    pub transport: String,
+    /// Values of the fields of the transport, in their order
+    pub values: Vec<(Field, String)>,
+}
+
+impl Answers {
+    /// The transport and its options as command line arguments, credentials
+    /// included, to test the connection with
+    pub fn transport_arguments(&self) -> Vec<String> {
+        let options = self
+            .values
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(field, value)| format!("--{}={value}", field.name.replace('_', "-")));
+        [self.transport.clone()]
+            .into_iter()
+            .chain(options)
+            .collect()
+    }
+
+    /// The profile for `syncbox.toml`, without credentials
+    pub fn profile_toml(&self) -> String {
+        let mut toml = format!("[profiles.{}.transport]\n", self.profile);
+        let _ = writeln!(
+            toml,
+            "type = {}",
+            toml::Value::from(self.transport.as_str())
+        );
+        for (field, value) in &self.values {
+            if field.env.is_none() && !value.is_empty() {
+                let _ = writeln!(
+                    toml,
+                    "{} = {}",
+                    field.name,
+                    toml::Value::from(value.as_str())
+                );
+            }
+        }
+        toml
+    }
+
+    /// The credentials for `.env.syncbox`
+    pub fn env_file(&self) -> String {
+        let mut env = format!(
+            "# Read by syncbox on start, keep it out of version control. The other\n\
+             # options of the profile are in {CONFIG_FILENAME}\n"
+        );
+        for (field, value) in &self.values {
+            if let Some(variable) = field.env {
+                let _ = writeln!(env, "{variable}={value:?}");
+            }
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn credentials_are_kept_apart_from_the_profile() {
+        let sftp = fields("sftp").unwrap();
+        let answers = Answers {
+            profile: "prod".to_string(),
+            transport: "sftp".to_string(),
+            values: sftp
+                .iter()
+                .zip(["example.com", "deploy", "se\"cret", "www"])
+                .map(|(field, value)| (*field, value.to_string()))
+                .collect(),
+        };
+        assert_eq!(
+            answers.transport_arguments(),
+            [
+                "sftp",
+                "--host=example.com",
+                "--user=deploy",
+                "--pass=se\"cret",
+                "--dir=www"
+            ]
+        );
+
+        let toml = answers.profile_toml();
+        assert!(!toml.contains("cret"));
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(
+            config.profiles["prod"]
+                .transport_arguments()
+                .unwrap()
+                .unwrap(),
+            ["sftp", "--dir=www", "--host=example.com", "--user=deploy"]
+        );
+
+        let env = answers.env_file();
+        assert!(env.ends_with("SFTP_PASS=\"se\\\"cret\"\n"));
+        assert!(fields("webdav").is_none());
+    }
+}
@@ -0,0 +1,307 @@
+use rusoto_core::{
+    request::{DispatchSignedRequest, HttpClient},
+    signature::SignedRequest,
+    Region,
+};
+use rusoto_credential::{ChainProvider, ProvideAwsCredentials, StaticProvider};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    path::{Component, Path},
+    time::{Duration, SystemTime},
+};
+
+/// CloudFront takes up to 3000 paths per invalidation, more changes than that
+/// invalidate everything
+const CLOUDFRONT_MAX_PATHS: usize = 3000;
+/// Cloudflare purges at most 30 URLs per request
+const CLOUDFLARE_BATCH_SIZE: usize = 30;
+/// Fastly purges at most 256 surrogate keys per request
+const FASTLY_BATCH_SIZE: usize = 256;
+
+/// Characters left as they are in URL paths
+const PATH_SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A CDN whose cache is purged of the uploaded and removed paths after a sync
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "cdn", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Invalidation {
+    /// Signed with the credentials of the S3 transport, otherwise the usual
+    /// AWS environment variables and profiles
+    Cloudfront {
+        distribution_id: String,
+        /// Prepended to every path, when the distribution serves the files
+        /// below a path of its own
+        #[serde(default)]
+        path_prefix: String,
+    },
+    /// Purges the URLs of the paths below `base_url`
+    Cloudflare {
+        zone_id: String,
+        base_url: String,
+        #[serde(default = "cloudflare_token_env")]
+        api_token_env: String,
+    },
+    /// Purges the surrogate keys `<key_prefix><path>`, so the origin has to
+    /// tag responses with their path
+    Fastly {
+        service_id: String,
+        #[serde(default)]
+        key_prefix: String,
+        #[serde(default = "fastly_token_env")]
+        api_token_env: String,
+    },
+}
+
+fn cloudflare_token_env() -> String {
+    "CLOUDFLARE_API_TOKEN".to_string()
+}
+
+fn fastly_token_env() -> String {
+    "FASTLY_API_TOKEN".to_string()
+}
+
+impl Invalidation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cloudfront { .. } => "CloudFront",
+            Self::Cloudflare { .. } => "Cloudflare",
+            Self::Fastly { .. } => "Fastly",
+        }
+    }
+
+    /// Purges the synced `paths` (like `./dir/index.html`), `aws_keys` sign
+    /// CloudFront requests when given. Returns what was purged
+    pub async fn run(
+        &self,
+        paths: &[impl AsRef<Path>],
+        aws_keys: Option<(String, String)>,
+    ) -> Result<String, Box<dyn Error + Send + Sync + 'static>> {
+        let url_paths = url_paths(paths);
+        match self {
+            Self::Cloudfront {
+                distribution_id,
+                path_prefix,
+            } => {
+                let paths = cloudfront_paths(path_prefix, &url_paths);
+                let id = create_invalidation(distribution_id, &paths, aws_keys).await?;
+                Ok(format!("{} path(s), invalidation {id}", paths.len()))
+            }
+            Self::Cloudflare {
+                zone_id,
+                base_url,
+                api_token_env,
+            } => {
+                let token = api_token(api_token_env)?;
+                let urls: Vec<_> = url_paths
+                    .iter()
+                    .map(|path| format!("{}{path}", base_url.trim_end_matches('/')))
+                    .collect();
+                for batch in urls.chunks(CLOUDFLARE_BATCH_SIZE) {
+                    post(
+                        &format!(
+                            "https://api.cloudflare.com/client/v4/zones/{zone_id}/purge_cache"
+                        ),
+                        &[("Authorization", format!("Bearer {token}"))],
+                        serde_json::to_vec(&serde_json::json!({ "files": batch }))?,
+                    )
+                    .await?;
+                }
+                Ok(format!("{} URL(s)", urls.len()))
+            }
+            Self::Fastly {
+                service_id,
+                key_prefix,
+                api_token_env,
+            } => {
+                let token = api_token(api_token_env)?;
+                let keys: Vec<_> = url_paths
+                    .iter()
+                    .map(|path| format!("{key_prefix}{path}"))
+                    .collect();
+                for batch in keys.chunks(FASTLY_BATCH_SIZE) {
+                    post(
+                        &format!("https://api.fastly.com/service/{service_id}/purge"),
+                        &[
+                            ("Fastly-Key", token.clone()),
+                            ("Surrogate-Key", batch.join(" ")),
+                        ],
+                        vec![],
+                    )
+                    .await?;
+                }
+                Ok(format!("{} surrogate key(s)", keys.len()))
+            }
+        }
+    }
+}
+
+/// URL paths of the synced files, a directory index also stands for the
+/// directory itself
+fn url_paths(paths: &[impl AsRef<Path>]) -> Vec<String> {
+    let mut url_paths = vec![];
+    for path in paths {
+        let segments: Vec<_> = path
+            .as_ref()
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(segment) => Some(
+                    percent_encoding::utf8_percent_encode(&segment.to_string_lossy(), PATH_SEGMENT)
+                        .to_string(),
+                ),
+                _ => None,
+            })
+            .collect();
+        let Some((name, directories)) = segments.split_last() else {
+            continue;
+        };
+        let directory: String = directories.iter().map(|dir| format!("/{dir}")).collect();
+        if name == "index.html" {
+            url_paths.push(format!("{directory}/"));
+        }
+        url_paths.push(format!("{directory}/{name}"));
+    }
+    url_paths.sort();
+    url_paths.dedup();
+    url_paths
+}
+
+fn cloudfront_paths(path_prefix: &str, url_paths: &[String]) -> Vec<String> {
+    let prefix = path_prefix.trim_end_matches('/');
+    if url_paths.len() > CLOUDFRONT_MAX_PATHS {
+        return vec![format!("{prefix}/*")];
+    }
+    url_paths
+        .iter()
+        .map(|path| format!("{prefix}{path}"))
+        .collect()
+}
+
+fn cloudfront_batch(paths: &[String], caller_reference: &str) -> String {
+    let items: String = paths
+        .iter()
+        .map(|path| format!("<Path>{path}</Path>"))
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><InvalidationBatch xmlns="http://cloudfront.amazonaws.com/doc/2020-05-31/"><Paths><Quantity>{}</Quantity><Items>{items}</Items></Paths><CallerReference>{caller_reference}</CallerReference></InvalidationBatch>"#,
+        paths.len()
+    )
+}
+
+/// Returns the id of the created invalidation
+async fn create_invalidation(
+    distribution_id: &str,
+    paths: &[String],
+    aws_keys: Option<(String, String)>,
+) -> Result<String, Box<dyn Error + Send + Sync + 'static>> {
+    let credentials = match aws_keys {
+        Some((access_key, secret_key)) => {
+            StaticProvider::new_minimal(access_key, secret_key)
+                .credentials()
+                .await?
+        }
+        None => ChainProvider::new().credentials().await?,
+    };
+    let caller_reference = format!(
+        "syncbox-{}-{}",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis(),
+        rand::random::<u32>()
+    );
+    // CloudFront is global, its only endpoint signs for us-east-1
+    let mut request = SignedRequest::new(
+        "POST",
+        "cloudfront",
+        &Region::UsEast1,
+        &format!("/2020-05-31/distribution/{distribution_id}/invalidation"),
+    );
+    request.set_content_type("application/xml".to_string());
+    request.set_payload(Some(cloudfront_batch(paths, &caller_reference)));
+    request.sign(&credentials);
+    let response = HttpClient::new()?
+        .dispatch(request, Some(Duration::from_secs(30)))
+        .await?
+        .buffer()
+        .await?;
+    let body = String::from_utf8_lossy(&response.body);
+    if !response.status.is_success() {
+        return Err(format!("CloudFront answered {}: {body}", response.status).into());
+    }
+    Ok(body
+        .split_once("<Id>")
+        .and_then(|(_, rest)| rest.split_once("</Id>"))
+        .map_or("(no id)".to_string(), |(id, _)| id.to_string()))
+}
+
+fn api_token(env: &str) -> Result<String, Box<dyn Error + Send + Sync + 'static>> {
+    std::env::var(env).map_err(|_| format!("set {env} to the API token").into())
+}
+
+/// Posts the JSON `body` to `url`, failing unless it answers with a success
+pub async fn post(
+    url: &str,
+    headers: &[(&str, String)],
+    body: Vec<u8>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let client = hyper::Client::builder().build::<_, hyper::Body>(hyper_tls::HttpsConnector::new());
+    let mut request = hyper::Request::post(url).header("Content-Type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let response = client.request(request.body(body.into())?).await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        return Err(format!(
+            "{url} answered {status}: {}",
+            String::from_utf8_lossy(&body)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_become_url_paths() {
+        assert_eq!(
+            url_paths(&[
+                "./index.html",
+                "./blog/my post.html",
+                "./blog/index.html",
+                "./a.css"
+            ]),
+            vec![
+                "/",
+                "/a.css",
+                "/blog/",
+                "/blog/index.html",
+                "/blog/my%20post.html",
+                "/index.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn cloudfront_batches_fall_back_to_everything() {
+        let paths = vec!["/a.css".to_string(), "/b.css".to_string()];
+        assert_eq!(
+            cloudfront_paths("/site/", &paths),
+            vec!["/site/a.css", "/site/b.css"]
+        );
+        let many: Vec<_> = (0..=CLOUDFRONT_MAX_PATHS)
+            .map(|i| format!("/{i}"))
+            .collect();
+        assert_eq!(cloudfront_paths("", &many), vec!["/*"]);
+        assert!(cloudfront_batch(&paths, "ref")
+            .contains("<Quantity>2</Quantity><Items><Path>/a.css</Path>"));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::checksum_tree::{ChecksumElement, ChecksumTree};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// Kept in the synced directory while a sync runs, never synced itself
+pub const JOURNAL_FILENAME: &str = ".syncbox.journal";
+
+/// What a journal was written against, its first line. A journal is only
+/// replayed by a run against the same remote whose checksum file nobody
+/// wrote since
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub remote: String,
+    pub checksum_file: String,
+    /// Size of the checksum file on the remote, none when it had none
+    pub checksum_file_size: Option<u64>,
+}
+
+/// An action the remote confirmed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Entry {
+    /// A file, link or directory now on the remote as recorded
+    Put {
+        path: PathBuf,
+        element: ChecksumElement,
+    },
+    Mkdir {
+        path: PathBuf,
+    },
+    Remove {
+        path: PathBuf,
+    },
+}
+
+/// An append-only record of the actions of a sync, a line each as soon as
+/// the remote confirmed it. A run that dies before uploading the checksum
+/// file leaves it behind, and the next run replays it onto the checksum file
+/// it reads, so none of that work is done again
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal at `path` for a run against `header`, returning the
+    /// entries an interrupted run against the same state left to replay. Any
+    /// other journal is started over
+    pub fn open(path: &Path, header: &Header) -> io::Result<(Self, Vec<Entry>)> {
+        let entries = load(path, header)?;
+        // rewritten, a line cut off by a crash would garble the next one
+        let mut file = File::create(path)?;
+        let mut contents = line(header)?;
+        for entry in &entries {
+            contents.extend(line(entry)?);
+        }
+        file.write_all(&contents)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        let journal = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Written at once, so a crash loses the line at most
+    pub fn append(&mut self, entry: &Entry) -> io::Result<()> {
+        self.file.write_all(&line(entry)?)
+    }
+
+    /// Starts over once the checksum file on the remote covers what was
+    /// done, e.g. after an intermittent upload
+    pub fn restart(&mut self, header: &Header) -> io::Result<()> {
+        self.file = File::create(&self.path)?;
+        self.file.write_all(&line(header)?)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Removes the journal once the run uploaded its checksum file
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)
+    }
+}
+
+/// The entries of the journal at `path` if it was written against `header`,
+/// without the lines after one a crash cut off
+pub fn load(path: &Path, header: &Header) -> io::Result<Vec<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut lines = BufReader::new(file).lines();
+    let written_for = match lines.next() {
+        Some(line) => serde_json::from_str::<Header>(&line?).ok(),
+        None => None,
+    };
+    if written_for.as_ref() != Some(header) {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for line in lines {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+    }
+    Ok(entries)
+}
+
+/// Applies the entries of a journal to the checksum tree of the remote
+pub fn replay(tree: &mut ChecksumTree, entries: &[Entry]) {
+    for entry in entries {
+        match entry {
+            Entry::Put { path, element } => tree.insert_at(path, element.clone()),
+            Entry::Mkdir { path } => {
+                if !matches!(tree.get_at(path), Some(ChecksumElement::Directory(_))) {
+                    tree.insert_at(path, ChecksumElement::default());
+                }
+            }
+            Entry::Remove { path } => tree.remove_at(path),
+        }
+    }
+}
+
+fn line(value: &impl Serialize) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum_tree::FileEntry;
+
+    fn header(size: Option<u64>) -> Header {
+        Header {
+            remote: "file:///srv".to_string(),
+            checksum_file: "./.syncbox.json.gz".to_string(),
+            checksum_file_size: size,
+        }
+    }
+
+    #[test]
+    fn an_interrupted_run_is_replayed() {
+        let path = std::env::temp_dir().join(format!("syncbox-journal-{}", std::process::id()));
+        let (mut journal, entries) = Journal::open(&path, &header(Some(10))).unwrap();
+        assert!(entries.is_empty());
+        let file = ChecksumElement::File(FileEntry::from("a".to_string()));
+        for entry in [
+            Entry::Mkdir {
+                path: "./dir".into(),
+            },
+            Entry::Put {
+                path: "./dir/new.txt".into(),
+                element: file.clone(),
+            },
+            Entry::Remove {
+                path: "./old.txt".into(),
+            },
+        ] {
+            journal.append(&entry).unwrap();
+        }
+        drop(journal);
+        // the crash cut off the last line
+        let mut contents = std::fs::read(&path).unwrap();
+        contents.extend(b"{\"remove\":{\"pa");
+        std::fs::write(&path, contents).unwrap();
+
+        assert!(load(&path, &header(Some(11))).unwrap().is_empty());
+        let (journal, entries) = Journal::open(&path, &header(Some(10))).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(load(&path, &header(Some(10))).unwrap().len(), 3);
+        journal.remove().unwrap();
+        assert!(!path.exists());
+
+        let mut tree = ChecksumTree::default();
+        tree.insert_at(Path::new("./old.txt"), file.clone());
+        replay(&mut tree, &entries);
+        assert!(tree.contains(Path::new("./dir/new.txt")));
+        assert!(!tree.contains(Path::new("./old.txt")));
+    }
+}
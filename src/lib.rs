@@ -1,4 +1,53 @@
+pub mod action_filter;
+pub mod browse;
 pub mod checksum_tree;
+pub mod chmod;
+pub mod cli;
+pub mod collision;
+pub mod commands;
+pub mod compression;
+pub mod config;
+pub mod conflict;
+pub mod connect;
+pub mod control;
+pub mod daemon;
+pub mod deadline;
+pub mod dedup;
+pub mod drift;
+pub mod exit;
+pub mod guard;
+pub mod hash;
+pub mod hooks;
+pub mod i18n;
+pub mod init;
+pub mod invalidate;
+pub mod journal;
+pub mod links;
+pub mod lock;
+pub mod logging;
+pub mod merge_base;
+pub mod notification;
+pub mod path_filter;
+pub mod plan;
+pub mod prefix;
 pub mod progress;
+pub mod prompt;
+pub mod prune;
+pub mod pull;
+pub mod push;
 pub mod reconciler;
+pub mod remote_copy;
+pub mod report;
+pub mod run;
+pub mod scan;
+pub mod scan_cache;
+pub mod script;
+pub mod shard;
+pub mod snapshots;
+pub mod state;
+pub mod state_db;
+pub mod table;
 pub mod transport;
+pub mod upload;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,51 @@
+use std::{fmt, str::FromStr};
+
+/// What to do with symbolic links found while scanning
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// Sync what the link points to as if it was there, directories included
+    Follow,
+    /// Recreate the link on the remote, which not every transport can
+    Preserve,
+    /// Leave links out of the sync
+    #[default]
+    Skip,
+}
+
+impl FromStr for LinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "preserve" => Ok(Self::Preserve),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!(
+                "unknown link policy {s:?}, expected follow, preserve or skip"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Follow => write!(f, "follow"),
+            Self::Preserve => write!(f, "preserve"),
+            Self::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_prints() {
+        for policy in [LinkPolicy::Follow, LinkPolicy::Preserve, LinkPolicy::Skip] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        assert!("copy".parse::<LinkPolicy>().is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    deadline::parse_duration,
+    prefix,
+    transport::{pool::TransportPool, Transport},
+};
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task::JoinHandle,
+};
+
+/// How long a run waits after writing its lock before reading it back, a
+/// run that wrote at about the same time has overwritten it by then
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// How long a run waiting for a lock waits before looking again
+const RETRY: Duration = Duration::from_secs(1);
+
+/// Kept next to the checksum file while a run changes the remote, so a
+/// second run against it refuses to start instead of overwriting the
+/// checksum file the first one uploads
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Lock {
+    pub host: String,
+    pub pid: u32,
+    /// When the lock was taken or last refreshed
+    pub refreshed: DateTime<Utc>,
+    /// Seconds after `refreshed` the lock is considered abandoned, e.g. by a
+    /// killed run
+    pub ttl: u64,
+    /// Tells runs with the same host and pid apart, like in containers
+    pub id: String,
+}
+
+/// A duration like `10m` for `--lock-ttl`
+pub fn parse_ttl(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        ttl if ttl.is_zero() => Err("the lock has to outlive a run by more than zero".to_string()),
+        ttl => Ok(ttl),
+    }
+}
+
+impl Lock {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            host: prefix::hostname().unwrap_or_else(|| "unknown".to_string()),
+            pid: std::process::id(),
+            refreshed: Utc::now(),
+            ttl: ttl.as_secs().max(1),
+            id: format!("{:016x}", rand::random::<u64>()),
+        }
+    }
+
+    pub fn expires(&self) -> DateTime<Utc> {
+        self.refreshed + TimeDelta::seconds(self.ttl as i64)
+    }
+
+    pub fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires()
+    }
+}
+
+/// Where a run writing `checksum_file` keeps its lock. Shards of a sync run
+/// side by side, each has a lock of its own
+pub fn path(checksum_file: &str, shard: Option<u64>) -> PathBuf {
+    match shard {
+        Some(index) => PathBuf::from(format!("{checksum_file}.shard-{index}.lock")),
+        None => PathBuf::from(format!("{checksum_file}.lock")),
+    }
+}
+
+/// Another run holds a fresh lock on the remote
+#[derive(Debug)]
+pub struct LockHeld(pub Lock);
+
+impl fmt::Display for LockHeld {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Lock { host, pid, .. } = &self.0;
+        let expires = self.0.expires().with_timezone(&Local);
+        write!(
+            f,
+            "the remote is locked by syncbox on {host} (pid {pid}) until {}, rerun once it's done or with --break-lock if it's gone",
+            expires.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+impl Error for LockHeld {}
+
+/// The lock at `path`, none when there's none or it can't be read
+pub async fn read<T: Transport + Send + ?Sized>(transport: &mut T, path: &Path) -> Option<Lock> {
+    let bytes = transport.read(path).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+async fn write<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    path: &Path,
+    lock: &Lock,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let bytes = serde_json::to_vec_pretty(lock)?;
+    let size = bytes.len() as u64;
+    transport
+        .write(path, Box::new(Cursor::new(bytes)), size)
+        .await?;
+    Ok(())
+}
+
+/// Takes the lock at `path` unless another run holds a fresh one, returning
+/// the abandoned lock it replaced. Writing and reading back isn't atomic, of
+/// two runs starting at once the one that wrote last keeps the lock and the
+/// other gives up
+pub async fn acquire<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    path: &Path,
+    lock: &Lock,
+    break_lock: bool,
+) -> Result<Option<Lock>, Box<dyn Error + Send + Sync + 'static>> {
+    let found = read(transport, path).await;
+    if let Some(found) = found.as_ref().filter(|found| found.id != lock.id) {
+        if found.is_fresh(Utc::now()) && !break_lock {
+            return Err(LockHeld(found.clone()).into());
+        }
+    }
+    write(transport, path, lock).await?;
+    tokio::time::sleep(SETTLE).await;
+    match read(transport, path).await {
+        Some(written) if written.id == lock.id => Ok(found),
+        Some(other) => Err(LockHeld(other).into()),
+        None => Err(format!("the lock written to {path:?} can't be read back").into()),
+    }
+}
+
+/// Takes the lock at `path` once no other run holds a fresh one, for writes
+/// short enough to need no refreshing, like a shard merging its progress
+/// into the checksum file
+pub async fn wait_for<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    path: &Path,
+    lock: &Lock,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    loop {
+        match acquire(transport, path, lock, false).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.is::<LockHeld>() => tokio::time::sleep(RETRY).await,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Removes the lock at `path` unless another run took it over meanwhile
+pub async fn release<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    path: &Path,
+    id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if read(transport, path)
+        .await
+        .is_some_and(|found| found.id == id)
+    {
+        transport.remove(path).await?;
+    }
+    Ok(())
+}
+
+/// A lock this run took, refreshed in the background so a run taking longer
+/// than its TTL keeps it, and removed when dropped, whichever way the run ends
+pub struct HeldLock {
+    pool: Arc<TransportPool>,
+    path: PathBuf,
+    id: String,
+    heartbeat: JoinHandle<()>,
+}
+
+impl HeldLock {
+    /// Keeps `lock` at `path` through connections of `pool`
+    pub fn keep(pool: Arc<TransportPool>, path: PathBuf, mut lock: Lock) -> Self {
+        let id = lock.id.clone();
+        let heartbeat = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            let path = path.clone();
+            async move {
+                let every = Duration::from_secs(lock.ttl) / 3;
+                loop {
+                    tokio::time::sleep(every).await;
+                    lock.refreshed = Utc::now();
+                    match refresh(&pool, &path, &lock).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tracing::warn!("another run took over the lock on the remote");
+                            return;
+                        }
+                        Err(e) => tracing::warn!("refreshing the lock on the remote failed: {e}"),
+                    }
+                }
+            }
+        });
+        Self {
+            pool,
+            path,
+            id,
+            heartbeat,
+        }
+    }
+}
+
+/// Rewrites the lock unless it was broken by another run, telling which
+async fn refresh(
+    pool: &Arc<TransportPool>,
+    path: &Path,
+    lock: &Lock,
+) -> Result<bool, Box<dyn Error + Send + Sync + 'static>> {
+    let mut transport = pool.get().await?;
+    if read(&mut **transport, path)
+        .await
+        .is_some_and(|found| found.id != lock.id)
+    {
+        return Ok(false);
+    }
+    if let Err(e) = write(&mut **transport, path, lock).await {
+        transport.discard();
+        return Err(e);
+    }
+    Ok(true)
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+        // a run may end on any error, there's no later point to await this
+        let Ok(handle) = Handle::try_current() else {
+            return;
+        };
+        if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+            return;
+        }
+        let released = tokio::task::block_in_place(|| {
+            handle.block_on(async {
+                let mut transport = self.pool.get().await?;
+                release(&mut **transport, &self.path, &self.id).await
+            })
+        });
+        if let Err(e) = released {
+            tracing::warn!("removing the lock from the remote failed, it expires on its own: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+
+    #[tokio::test]
+    async fn a_fresh_lock_keeps_other_runs_out() {
+        let mut remote = MemoryTransport::new();
+        let path = path("./.syncbox.json.gz", None);
+        let first = Lock::new(Duration::from_secs(600));
+        assert_eq!(
+            acquire(&mut remote, &path, &first, false).await.unwrap(),
+            None
+        );
+
+        let second = Lock::new(Duration::from_secs(600));
+        let error = acquire(&mut remote, &path, &second, false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.downcast_ref::<LockHeld>().unwrap().0, first);
+        // releasing someone else's lock leaves it alone
+        release(&mut remote, &path, &second.id).await.unwrap();
+        assert_eq!(read(&mut remote, &path).await, Some(first.clone()));
+
+        let replaced = acquire(&mut remote, &path, &second, true).await.unwrap();
+        assert_eq!(replaced, Some(first));
+        release(&mut remote, &path, &second.id).await.unwrap();
+        assert_eq!(read(&mut remote, &path).await, None);
+    }
+
+    #[tokio::test]
+    async fn an_expired_lock_is_replaced() {
+        let mut remote = MemoryTransport::new();
+        let path = path("./.syncbox.json.gz", Some(2));
+        let mut abandoned = Lock::new(Duration::from_secs(60));
+        abandoned.refreshed -= TimeDelta::minutes(2);
+        assert!(!abandoned.is_fresh(Utc::now()));
+        acquire(&mut remote, &path, &abandoned, false)
+            .await
+            .unwrap();
+
+        let lock = Lock::new(Duration::from_secs(60));
+        let replaced = acquire(&mut remote, &path, &lock, false).await.unwrap();
+        assert_eq!(replaced, Some(abandoned));
+        assert_eq!(read(&mut remote, &path).await, Some(lock));
+    }
+
+    #[tokio::test]
+    async fn waiting_outlasts_the_lock_held() {
+        let mut remote = MemoryTransport::new();
+        let path = path("./.syncbox.json.gz", None);
+        let held = Lock::new(Duration::from_secs(1));
+        acquire(&mut remote, &path, &held, false).await.unwrap();
+
+        let waiting = Lock::new(Duration::from_secs(60));
+        wait_for(&mut remote, &path, &waiting).await.unwrap();
+        assert!(!held.is_fresh(Utc::now()));
+        assert_eq!(read(&mut remote, &path).await, Some(waiting));
+    }
+}
@@ -0,0 +1,58 @@
+use std::{error::Error, fs::OpenOptions, path::Path, sync::Mutex};
+use tracing_subscriber::{
+    filter::{filter_fn, LevelFilter},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+/// Target of the events telling how a run and its actions went. The
+/// terminal already reports those in its own words, so they only go to the
+/// log file
+pub const RUN: &str = "syncbox::run";
+
+/// Sends log events, those of the transports' libraries included, to the
+/// terminal and to `log_file`. The terminal shows warnings only, or what
+/// `level` asks for when there's no log file. The log file gets everything
+/// at `level` with timestamps and the action each event belongs to
+pub fn init(
+    level: Option<LevelFilter>,
+    log_file: Option<&Path>,
+    colors: bool,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let console_level = match log_file {
+        Some(_) => LevelFilter::WARN,
+        None => level.unwrap_or(LevelFilter::WARN),
+    };
+    let console = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(colors)
+        .without_time()
+        .with_target(false)
+        .compact()
+        // spans are kept to show which action a warning is about
+        .with_filter(filter_fn(move |metadata| {
+            metadata.is_span() || (metadata.target() != RUN && *metadata.level() <= console_level)
+        }));
+    let file = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Cannot open the log file {path:?}: {e}"))?;
+            let layer = fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(level.unwrap_or(LevelFilter::INFO));
+            Some(layer)
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init()?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    compression::ChecksumCompression,
+};
+use std::{error::Error, io, path::Path};
+
+/// Kept in the synced directory, never synced itself
+pub const BASE_FILENAME: &str = ".syncbox.base.json.gz";
+
+/// The tree the synced directory and the remote agreed on after the last
+/// two-way sync, see `Reconciler::merge`. Without one nothing was agreed
+/// yet, so every file the two sides have differently is a conflict
+pub fn load(path: &Path) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+    match std::fs::read(path) {
+        Ok(bytes) => ChecksumTree::from_compressed(&bytes)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()).into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ChecksumTree::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces `path` at once, an interrupted write leaves the old base
+pub fn save(
+    path: &Path,
+    tree: &ChecksumTree,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let temporary = path.with_extension("gz.tmp");
+    tree.write_compressed(
+        std::fs::File::create(&temporary)?,
+        ChecksumCompression::default(),
+    )?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// The base for the next two-way sync: the tree uploaded to the remote, with
+/// `unresolved` paths, the conflicts and whatever failed to be pulled, left
+/// as they were in `previous` so they come up again
+pub fn agreed<'a>(
+    uploaded: &ChecksumTree,
+    previous: &ChecksumTree,
+    unresolved: impl IntoIterator<Item = &'a Path>,
+) -> ChecksumTree {
+    let mut agreed = uploaded.clone();
+    for path in unresolved {
+        match previous.get_at(path) {
+            Some(element) => agreed.insert_at(path, element.clone()),
+            None => agreed.remove_at(path),
+        }
+    }
+    agreed
+}
+
+/// Takes the files missing from a checksum file that was cut off as
+/// unchanged since `base` rather than removed on the remote
+pub fn fill_in(remote: &mut ChecksumTree, base: &ChecksumTree) {
+    for (path, entry) in base {
+        let in_the_way = path.ancestors().skip(1).any(|parent| {
+            matches!(
+                remote.get_at(parent),
+                Some(ChecksumElement::File(_) | ChecksumElement::Symlink(_))
+            )
+        });
+        if !in_the_way && !remote.contains(&path) {
+            remote.insert_at(&path, ChecksumElement::File(entry.clone()));
+        }
+    }
+}
+
+/// Whether the remote lists no files though the last two-way sync agreed on
+/// some, more likely a checksum file gone missing than everything removed.
+/// Merging would remove every local file unchanged since
+pub fn emptied(base: &ChecksumTree, remote: &ChecksumTree) -> bool {
+    remote.is_empty() && !base.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum_tree::fixtures::tree,
+        conflict::Resolution,
+        reconciler::{Action, ReconcileOptions, Reconciler},
+    };
+
+    #[test]
+    fn unresolved_paths_stay_as_they_were() {
+        let base = tree(&[("./a.txt", "a"), ("./both.txt", "b")]);
+        let local = tree(&[("./a.txt", "a"), ("./both.txt", "local")]);
+        let remote = tree(&[("./both.txt", "remote")]);
+        let merge = Reconciler::merge(&base, &local, &remote, &ReconcileOptions::default(), |_| {
+            Resolution::Skip
+        })
+        .unwrap();
+        let mut actions = merge.actions.clone();
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                Action::LocalRemove("./a.txt".into()),
+                Action::Conflict("./both.txt".into()),
+            ]
+        );
+
+        // the local removal failed
+        let agreed = agreed(
+            &merge.remote,
+            &base,
+            actions.iter().map(|action| action.path()),
+        );
+        let again = Reconciler::merge(&agreed, &local, &remote, &Default::default(), |_| {
+            Resolution::Skip
+        })
+        .unwrap();
+        assert_eq!(again.actions, actions);
+
+        let dir = std::env::temp_dir().join(format!("syncbox-base-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(BASE_FILENAME);
+        assert!(load(&path).unwrap().is_empty());
+        save(&path, &agreed).unwrap();
+        assert_eq!(load(&path).unwrap().diff(&agreed), Default::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_emptied_remote_is_told_apart() {
+        let base = tree(&[("./a.txt", "a"), ("./b.txt", "b")]);
+        let local = base.clone();
+        let remote = ChecksumTree::default();
+        assert!(emptied(&base, &remote));
+        // what merging would do anyway
+        let merge = Reconciler::merge(&base, &local, &remote, &Default::default(), |_| {
+            Resolution::Skip
+        })
+        .unwrap();
+        assert!(merge
+            .actions
+            .iter()
+            .all(|action| matches!(action, Action::LocalRemove(_))));
+        assert_eq!(merge.actions.len(), 2);
+
+        assert!(!emptied(&ChecksumTree::default(), &remote));
+        assert!(!emptied(&base, &tree(&[("./a.txt", "a")])));
+    }
+}
@@ -0,0 +1,42 @@
+use std::{error::Error, time::Duration};
+
+/// Shows a native desktop notification, which fails without a notification
+/// service, e.g. on a headless machine
+pub async fn notify(
+    summary: String,
+    body: String,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname("syncbox")
+            .summary(&summary)
+            .body(&body)
+            .timeout(notify_rust::Timeout::Milliseconds(10_000))
+            .show()
+    })
+    .await??;
+    Ok(())
+}
+
+/// Like `2m 5s`, or `3h 1m` from an hour up
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds}s"),
+        _ => format!("{hours}h {minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_read_like_a_clock() {
+        assert_eq!(format_duration(Duration::from_millis(4_900)), "4s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 61)), "3h 1m");
+    }
+}
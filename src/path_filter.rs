@@ -0,0 +1,228 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    reconciler::{leaf_at, leaves, take_from},
+};
+use ignore::overrides::{Override, OverrideBuilder};
+use std::{
+    error::Error,
+    path::{Component, Path, PathBuf},
+};
+
+/// `--include` and `--exclude` globs, matched like `.syncboxignore` lines
+/// against paths relative to the synced directory, and `--only` subtrees.
+/// Unlike `.syncboxignore` they apply to the remote too, what they leave out
+/// is neither uploaded, downloaded nor removed anywhere
+#[derive(Clone, Debug)]
+pub struct PathFilter {
+    globs: Override,
+    /// Directories below which everything else is left out, like `./public/assets`
+    only: Vec<PathBuf>,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            globs: Override::empty(),
+            only: vec![],
+        }
+    }
+}
+
+impl PathFilter {
+    /// With any `include` globs only the files matching one of them are
+    /// kept, `exclude` globs win over these
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let mut builder = OverrideBuilder::new(".");
+        for glob in include {
+            builder
+                .add(glob)
+                .map_err(|e| format!("invalid --include {glob:?}: {e}"))?;
+        }
+        for glob in exclude {
+            builder
+                .add(&format!("!{glob}"))
+                .map_err(|e| format!("invalid --exclude {glob:?}: {e}"))?;
+        }
+        Ok(Self {
+            globs: builder.build()?,
+            only: vec![],
+        })
+    }
+
+    /// Leaves out everything but what's below one of the `prefixes`,
+    /// relative paths like `public/assets`
+    pub fn with_only(
+        mut self,
+        prefixes: &[String],
+    ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        for prefix in prefixes {
+            let mut path = PathBuf::from(".");
+            for component in Path::new(prefix).components() {
+                match component {
+                    Component::Normal(name) => path.push(name),
+                    Component::CurDir => {}
+                    _ => {
+                        return Err(format!(
+                            "invalid --only {prefix:?}: not a path within the synced directory"
+                        )
+                        .into())
+                    }
+                }
+            }
+            if path.components().count() == 1 {
+                return Err(format!(
+                    "invalid --only {prefix:?}: that's the whole synced directory"
+                )
+                .into());
+            }
+            self.only.push(path);
+        }
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.only.is_empty()
+    }
+
+    /// For walking the synced directory, see `ignore::WalkBuilder::overrides`
+    pub fn overrides(&self) -> &Override {
+        &self.globs
+    }
+
+    /// Whether `path` or a directory it's in is left out, the same way the
+    /// walk leaves it out
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if self.is_outside_only(path, is_dir) || self.globs.matched(path, is_dir).is_ignore() {
+            return true;
+        }
+        path.ancestors()
+            .skip(1)
+            .take_while(|parent| parent.components().count() > 1)
+            .any(|parent| self.globs.matched(parent, true).is_ignore())
+    }
+
+    /// Whether `path` is neither below an `--only` directory nor a
+    /// directory on the way to one. Always false without `--only`
+    pub fn is_outside_only(&self, path: &Path, is_dir: bool) -> bool {
+        !self.only.is_empty()
+            && !self
+                .only
+                .iter()
+                .any(|only| path.starts_with(only) || (is_dir && only.starts_with(path)))
+    }
+
+    /// `tree` with every path left out as it is in `source`, so the two
+    /// never differ there
+    pub fn keep_excluded(&self, source: &ChecksumTree, tree: &ChecksumTree) -> ChecksumTree {
+        let mut kept = tree.clone();
+        if self.is_empty() {
+            return kept;
+        }
+        for path in leaves(source).into_iter().chain(leaves(tree)) {
+            let is_dir = [source, tree]
+                .into_iter()
+                .any(|tree| matches!(leaf_at(tree, &path), Some(ChecksumElement::Directory(_))));
+            // a directory on the way to an `--only` one is only walked
+            // through, left as it is when it's empty or a file on one side
+            let on_the_way = self
+                .only
+                .iter()
+                .any(|only| only != &path && only.starts_with(&path));
+            if on_the_way || self.is_excluded(&path, is_dir) {
+                take_from(source, &mut kept, &path);
+            }
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum_tree::fixtures::tree,
+        reconciler::{Action, ReconcileOptions, Reconciler},
+    };
+
+    #[test]
+    fn excluded_paths_are_neither_uploaded_nor_removed() {
+        let filter = PathFilter::new(&["*.txt".into()], &["build".into()]).unwrap();
+        assert!(filter.is_excluded(Path::new("./image.png"), false));
+        assert!(filter.is_excluded(Path::new("./build/notes.txt"), false));
+        assert!(!filter.is_excluded(Path::new("./docs/notes.txt"), false));
+        assert!(!filter.is_excluded(Path::new("./docs"), true));
+
+        let remote = tree(&[
+            ("./a.txt", "a"),
+            ("./remote.png", "remote"),
+            ("./build/old.txt", "old"),
+        ]);
+        let local = tree(&[
+            ("./b.txt", "b"),
+            ("./local.png", "local"),
+            ("./build/new.txt", "new"),
+        ]);
+        let options = ReconcileOptions {
+            filter,
+            ..Default::default()
+        };
+        let mut actions =
+            Reconciler::reconcile_with_options(remote.clone(), &local, &options).unwrap();
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Put("./b.txt".into()),
+                Action::Remove("./a.txt".into())
+            ]
+        );
+
+        let uploaded = options.filter.keep_excluded(&remote, &local);
+        assert!(uploaded.contains(Path::new("./remote.png")));
+        assert!(uploaded.contains(Path::new("./build/old.txt")));
+        assert!(!uploaded.contains(Path::new("./local.png")));
+        assert!(!uploaded.contains(Path::new("./build/new.txt")));
+
+        assert!(PathFilter::new(&[], &["a[".into()]).is_err());
+    }
+
+    #[test]
+    fn only_reconciles_the_subtree() {
+        let filter = PathFilter::default()
+            .with_only(&["public/assets/".into()])
+            .unwrap();
+        assert!(!filter.is_excluded(Path::new("./public"), true));
+        assert!(!filter.is_excluded(Path::new("./public/assets/app.css"), false));
+        assert!(filter.is_excluded(Path::new("./public/index.html"), false));
+        assert!(filter.is_excluded(Path::new("./src"), true));
+
+        let remote = tree(&[
+            ("./public/assets/old.css", "old"),
+            ("./public/index.html", "index"),
+            ("./a.txt", "a"),
+        ]);
+        let local = tree(&[("./public/assets/new.css", "new"), ("./b.txt", "b")]);
+        let options = ReconcileOptions {
+            filter,
+            ..Default::default()
+        };
+        let actions = Reconciler::reconcile_with_options(remote.clone(), &local, &options).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Put("./public/assets/new.css".into()),
+                Action::Remove("./public/assets/old.css".into())
+            ]
+        );
+        let uploaded = options.filter.keep_excluded(&remote, &local);
+        assert!(uploaded.contains(Path::new("./public/index.html")));
+        assert!(uploaded.contains(Path::new("./a.txt")));
+        assert!(!uploaded.contains(Path::new("./b.txt")));
+
+        assert!(PathFilter::default().with_only(&["../up".into()]).is_err());
+        assert!(PathFilter::default().with_only(&["./".into()]).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    action_filter::{self, ActionDecision},
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    reconciler::Action,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+/// What `Reconciler::reconcile` planned, the actions in the order they were
+/// planned along with the sizes of the files they upload and remove. Derefs
+/// to the actions, what it amounts to follows whatever is left of them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    actions: Vec<Action>,
+    /// Of uploaded files as the next tree has them, of removed ones as the
+    /// previous tree has them
+    sizes: HashMap<PathBuf, u64>,
+}
+
+/// What a plan does below one directory, see `SyncPlan::directories`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirectorySummary {
+    pub actions: usize,
+    pub upload_bytes: u64,
+    pub remove_bytes: u64,
+}
+
+impl SyncPlan {
+    pub fn new(actions: Vec<Action>, previous: &ChecksumTree, next: &ChecksumTree) -> Self {
+        let size = |tree: &ChecksumTree, path: &Path| match tree.get_at(path) {
+            Some(ChecksumElement::File(entry)) => entry.size,
+            _ => None,
+        };
+        let sizes = actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Put(path) => Some((path.clone(), size(next, path)?)),
+                Action::Remove(path) => Some((path.clone(), size(previous, path)?)),
+                _ => None,
+            })
+            .collect();
+        Self { actions, sizes }
+    }
+
+    pub(crate) fn with_sizes(actions: Vec<Action>, sizes: HashMap<PathBuf, u64>) -> Self {
+        Self { actions, sizes }
+    }
+
+    pub fn into_actions(self) -> Vec<Action> {
+        self.actions
+    }
+
+    /// Lets `decide` drop, rewrite or defer each action before anything is
+    /// executed, e.g. to never remove what's under some directory. A
+    /// rewritten action counts with the size of its path in the plan, if any
+    pub fn filter_actions(&mut self, decide: impl FnMut(&Action) -> ActionDecision) {
+        let actions = std::mem::take(&mut self.actions);
+        self.actions = action_filter::filter(actions, decide);
+    }
+
+    fn size(&self, action: &Action) -> u64 {
+        self.sizes.get(action.path()).copied().unwrap_or_default()
+    }
+
+    /// Bytes of the files to upload
+    pub fn upload_bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .filter(|action| matches!(action, Action::Put(_)))
+            .map(|action| self.size(action))
+            .sum()
+    }
+
+    /// Bytes of the files to remove, as they were uploaded
+    pub fn remove_bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .filter(|action| matches!(action, Action::Remove(_)))
+            .map(|action| self.size(action))
+            .sum()
+    }
+
+    /// The actions grouped by kind, see `Action::kind`, each group in the
+    /// order planned
+    pub fn by_kind(&self) -> BTreeMap<&'static str, Vec<&Action>> {
+        let mut kinds: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for action in &self.actions {
+            kinds.entry(action.kind()).or_default().push(action);
+        }
+        kinds
+    }
+
+    /// What's done below each directory of the root, `.` for what's in the
+    /// root itself
+    pub fn directories(&self) -> BTreeMap<PathBuf, DirectorySummary> {
+        let mut directories: BTreeMap<_, DirectorySummary> = BTreeMap::new();
+        for action in &self.actions {
+            let path = action.path();
+            let directory = match (action, path.components().count()) {
+                // a directory of the root counts for itself
+                (Action::Mkdir(_) | Action::Rmdir(_) | Action::LocalMkdir(_), 2) => {
+                    path.to_path_buf()
+                }
+                (_, 0..=2) => PathBuf::from("."),
+                _ => path.iter().take(2).collect(),
+            };
+            let summary = directories.entry(directory).or_default();
+            summary.actions += 1;
+            match action {
+                Action::Put(_) => summary.upload_bytes += self.size(action),
+                Action::Remove(_) => summary.remove_bytes += self.size(action),
+                _ => {}
+            }
+        }
+        directories
+    }
+}
+
+impl Deref for SyncPlan {
+    type Target = Vec<Action>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.actions
+    }
+}
+
+impl DerefMut for SyncPlan {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.actions
+    }
+}
+
+impl PartialEq<Vec<Action>> for SyncPlan {
+    fn eq(&self, other: &Vec<Action>) -> bool {
+        self.actions == *other
+    }
+}
+
+impl IntoIterator for SyncPlan {
+    type Item = Action;
+    type IntoIter = std::vec::IntoIter<Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a SyncPlan {
+    type Item = &'a Action;
+    type IntoIter = std::slice::Iter<'a, Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum_tree::{
+            fixtures::{file, tree_of},
+            FileEntry,
+        },
+        reconciler::Reconciler,
+    };
+
+    #[test]
+    fn sums_up_what_is_uploaded_and_removed() {
+        let sized = |checksum, size| FileEntry {
+            size: Some(size),
+            ..file(checksum)
+        };
+        let previous = tree_of([
+            ("./same.txt", sized("same", 1)),
+            ("./old.txt", sized("old", 10)),
+            ("./assets/old.png", sized("old", 200)),
+        ]);
+        let next = tree_of([
+            ("./same.txt", sized("same", 1)),
+            ("./assets/new.png", sized("new", 3000)),
+            ("./assets/icons/new.svg", sized("new", 40000)),
+        ]);
+
+        let mut plan = Reconciler::reconcile(previous, &next).unwrap();
+        assert_eq!(plan.upload_bytes(), 43000);
+        assert_eq!(plan.remove_bytes(), 210);
+        assert_eq!(
+            plan.by_kind()
+                .iter()
+                .map(|(kind, actions)| (*kind, actions.len()))
+                .collect::<Vec<_>>(),
+            vec![("mkdir", 1), ("put", 2), ("remove", 2)]
+        );
+        assert_eq!(
+            plan.directories(),
+            BTreeMap::from([
+                (
+                    PathBuf::from("."),
+                    DirectorySummary {
+                        actions: 1,
+                        upload_bytes: 0,
+                        remove_bytes: 10
+                    }
+                ),
+                (
+                    PathBuf::from("./assets"),
+                    DirectorySummary {
+                        actions: 4,
+                        upload_bytes: 43000,
+                        remove_bytes: 200
+                    }
+                ),
+            ])
+        );
+
+        // what's left out no longer counts
+        plan.retain(|action| !matches!(action, Action::Put(_)));
+        assert_eq!(plan.upload_bytes(), 0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{fmt, path::Path, process::Command, str::FromStr};
+
+/// Variables a prefix template can refer to
+pub const VARIABLES: [&str; 4] = ["git_branch", "git_sha", "date", "hostname"];
+
+/// Remote path prefix filled in at run time, e.g. `previews/{git_branch}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefixTemplate(String);
+
+impl PrefixTemplate {
+    pub fn expand(&self, variables: &Variables) -> Result<String, String> {
+        expand(&self.0, variables)
+    }
+}
+
+impl FromStr for PrefixTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // expanding with every variable set catches unknown names and stray braces
+        expand(s, &Variables::placeholders())?;
+        Ok(Self(s.trim_matches('/').to_string()))
+    }
+}
+
+impl fmt::Display for PrefixTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Values of the template variables for the current run
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    pub git_branch: Option<String>,
+    pub git_sha: Option<String>,
+    pub date: String,
+    pub hostname: Option<String>,
+}
+
+impl Variables {
+    /// Reads the variables from the git repository in the current directory
+    /// and the machine syncbox runs on
+    pub fn detect() -> Self {
+        Self::detect_in(Path::new("."))
+    }
+
+    /// Like `detect`, with the git repository of `directory`
+    pub fn detect_in(directory: &Path) -> Self {
+        let git_branch = git(directory, &["rev-parse", "--abbrev-ref", "HEAD"])
+            .filter(|branch| branch != "HEAD")
+            // CI checkouts are usually detached, the branch is only known to the CI
+            .or_else(|| {
+                ["GITHUB_HEAD_REF", "GITHUB_REF_NAME", "CI_COMMIT_REF_NAME"]
+                    .iter()
+                    .filter_map(|name| std::env::var(name).ok())
+                    .find(|branch| !branch.is_empty())
+            });
+        Self {
+            git_branch,
+            git_sha: git(directory, &["rev-parse", "--short", "HEAD"]),
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            hostname: hostname(),
+        }
+    }
+
+    fn placeholders() -> Self {
+        Self {
+            git_branch: Some(String::new()),
+            git_sha: Some(String::new()),
+            date: String::new(),
+            hostname: Some(String::new()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<&str, String> {
+        let value = match name {
+            "git_branch" => self.git_branch.as_deref(),
+            "git_sha" => self.git_sha.as_deref(),
+            "date" => Some(self.date.as_str()),
+            "hostname" => self.hostname.as_deref(),
+            _ => {
+                return Err(format!(
+                    "unknown variable {{{name}}}, expected one of {}",
+                    VARIABLES.join(", ")
+                ))
+            }
+        };
+        value.ok_or_else(|| format!("{{{name}}} could not be determined"))
+    }
+}
+
+/// Replaces `{name}` with the value of the variable, values are turned into
+/// a single path segment so a branch like `feature/login` doesn't nest
+pub fn expand(template: &str, variables: &Variables) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        if rest[start..].starts_with('}') {
+            return Err(format!("unmatched }} in {template:?}"));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unmatched {{ in {template:?}"))?;
+        let value = variables.get(&rest[start + 1..start + end])?;
+        expanded.push_str(&path_segment(value));
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn path_segment(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn git(directory: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .output()
+        .ok()?;
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Name of this machine, none when it can't be told
+pub fn hostname() -> Option<String> {
+    if let Some(hostname) = ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+    {
+        return Some(hostname);
+    }
+    let output = Command::new("hostname").output().ok()?;
+    let hostname = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!hostname.is_empty()).then_some(hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables {
+            git_branch: Some("feature/login".to_string()),
+            git_sha: Some("1a2b3c4".to_string()),
+            date: "2024-05-01".to_string(),
+            hostname: None,
+        }
+    }
+
+    #[test]
+    fn expands_variables_into_path_segments() {
+        let template = "/previews/{git_branch}-{git_sha}/"
+            .parse::<PrefixTemplate>()
+            .unwrap();
+        assert_eq!(
+            template.expand(&variables()).unwrap(),
+            "previews/feature-login-1a2b3c4"
+        );
+        assert_eq!(
+            expand("./.syncbox-{date}.json.gz", &variables()).unwrap(),
+            "./.syncbox-2024-05-01.json.gz"
+        );
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        let template = "hosts/{hostname}".parse::<PrefixTemplate>().unwrap();
+        assert!(template.expand(&variables()).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in ["{branch}", "previews/{git_branch", "previews}"] {
+            assert!(template.parse::<PrefixTemplate>().is_err(), "{template}");
+        }
+    }
+}
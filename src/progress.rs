@@ -33,8 +33,32 @@ where
         let before = buf.filled().len();
         let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
         let after = buf.filled().len();
-        (self.update_progress_callback)(self.bytes_read as u64);
         self.bytes_read += after - before;
+        (self.update_progress_callback)(self.bytes_read as u64);
         poll
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn reports_bytes_read() {
+        let reported = Arc::new(AtomicU64::new(0));
+        let reported_inner = Arc::clone(&reported);
+        let mut stream = ProgressStream::new(
+            std::io::Cursor::new(vec![0u8; 1000]),
+            Box::new(move |read| reported_inner.store(read, Ordering::SeqCst)),
+        );
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(stream.progress(), 1000);
+        assert_eq!(reported.load(Ordering::SeqCst), 1000);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{cli::Args, t};
+use std::{
+    error::Error,
+    io::{IsTerminal, Write as _},
+};
+
+/// Asks on a terminal before going on with a sync that looks like a mistake,
+/// other runs abort unless --yes-i-mean-it is given
+pub fn confirm_risky_sync(
+    args: &Args,
+    reason: &str,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if args.yes_i_mean_it {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(format!("{reason}, pass --yes-i-mean-it to sync it anyway").into());
+    }
+    print!("{}", t!("risky_sync", reason = reason));
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err("Aborted".into())
+    }
+}
+
+/// Asks before removing more than `--max-delete` files, a checksum file
+/// that went missing or a mistyped `--only` may remove far more than intended
+pub fn confirm_removals(
+    args: &Args,
+    removals: usize,
+    max_delete: usize,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if removals <= max_delete {
+        return Ok(());
+    }
+    confirm_risky_sync(
+        args,
+        &format!("{removals} files would be removed, more than --max-delete {max_delete}"),
+    )
+}
+
+/// Asks `question` on the terminal, an empty answer takes `default`.
+/// Secrets aren't echoed
+pub fn ask(
+    question: &str,
+    default: Option<&str>,
+    secret: bool,
+) -> Result<String, Box<dyn Error + Send + Sync + 'static>> {
+    loop {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        std::io::stdout().flush()?;
+        let answer = if secret {
+            console::Term::stdout().read_secure_line()?
+        } else {
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            answer
+        };
+        let answer = answer.trim();
+        match (answer, default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            // credentials may be left out, like an SFTP password for keys
+            ("", None) if !secret => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{checksum_tree::ChecksumTree, transport::RemoteEntry, verify::is_checksum_file};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// What's on the remote but not in the checksum file, left behind by runs
+/// that crashed or uploaded by hand, see `syncbox prune`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Orphans {
+    /// In the order of their paths
+    pub files: Vec<PathBuf>,
+    /// Directories nothing is kept in, children before their parents
+    pub directories: Vec<PathBuf>,
+}
+
+impl Orphans {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.directories.is_empty()
+    }
+}
+
+/// The entries of `listing` that `tree` doesn't have. Files stored
+/// compressed are matched by their names without the suffix. The checksum
+/// file and its snapshots are kept, and so are the directories they're in
+pub fn orphans(
+    tree: &ChecksumTree,
+    listing: &HashMap<PathBuf, RemoteEntry>,
+    checksum_file: &Path,
+) -> Orphans {
+    let is_tracked = |path: &Path| {
+        tree.contains(path)
+            || tree.compression().is_some_and(|compression| {
+                path.to_str()
+                    .and_then(|path| path.strip_suffix(compression.suffix()))
+                    .is_some_and(|path| tree.contains(Path::new(path)))
+            })
+    };
+    let kept: Vec<&Path> = listing
+        .values()
+        .filter(|remote| !remote.is_dir)
+        .filter(|remote| is_tracked(&remote.path) || is_checksum_file(&remote.path, checksum_file))
+        .map(|remote| remote.path.as_path())
+        .collect();
+    let mut orphans = Orphans::default();
+    for remote in listing.values() {
+        if remote.is_dir {
+            if !is_tracked(&remote.path) && !kept.iter().any(|path| path.starts_with(&remote.path))
+            {
+                orphans.directories.push(remote.path.clone());
+            }
+        } else if !kept.contains(&remote.path.as_path()) {
+            orphans.files.push(remote.path.clone());
+        }
+    }
+    orphans.files.sort();
+    orphans.directories.sort_by(|a, b| b.cmp(a));
+    orphans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksum_tree::fixtures::tree, compression::ObjectCompression};
+
+    fn listing(entries: &[(&str, bool)]) -> HashMap<PathBuf, RemoteEntry> {
+        entries
+            .iter()
+            .map(|&(path, is_dir)| {
+                let entry = RemoteEntry {
+                    path: path.into(),
+                    is_dir,
+                    size: 1,
+                    modified: None,
+                };
+                (PathBuf::from(path), entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_what_the_checksum_file_does_not_have() {
+        let tree = tree(&[("./a.txt", "a"), ("./dir/b.txt", "b")]);
+        let listing = listing(&[
+            ("./a.txt", false),
+            ("./.syncbox.json.gz", false),
+            ("./.syncbox.json.gz.snapshots.json", false),
+            ("./.upload.syncbox.tmp", false),
+            ("./dir", true),
+            ("./dir/b.txt", false),
+            ("./dir/stray.txt", false),
+            ("./old", true),
+            ("./old/nested", true),
+            ("./old/nested/c.txt", false),
+        ]);
+        assert_eq!(
+            orphans(&tree, &listing, Path::new(".syncbox.json.gz")),
+            Orphans {
+                files: vec![
+                    "./.upload.syncbox.tmp".into(),
+                    "./dir/stray.txt".into(),
+                    "./old/nested/c.txt".into(),
+                ],
+                directories: vec!["./old/nested".into(), "./old".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn compressed_files_are_matched_without_their_suffix() {
+        let mut tree = tree(&[("./a.txt", "a")]);
+        tree.set_compression(Some(ObjectCompression::Gzip));
+        let listing = listing(&[("./a.txt.gz", false), ("./b.txt.gz", false)]);
+        assert_eq!(
+            orphans(&tree, &listing, Path::new(".syncbox.json.gz")).files,
+            vec![PathBuf::from("./b.txt.gz")]
+        );
+    }
+}
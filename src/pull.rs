@@ -0,0 +1,732 @@
+use crate::{
+    action_filter::{self, FilterCommand},
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    cli::Args,
+    compression::ObjectCompression,
+    config::Config,
+    conflict::{settle_pull, Conflict, ConflictPolicy, Resolution},
+    exit::Failure,
+    hash::{self, HashAlgorithm},
+    reconciler::{sort_actions, Action, ReconcileOptions, Reconciler},
+    report::{
+        action_span, plan_table, record, results_table, HumanBytes, Results, PROGRESS_BAR_CHARS,
+    },
+    run::Outcome,
+    scan::{path_filter, rehash_unchanged, take_over_touched},
+    script::Script,
+    t,
+    transport::{download::download, pool::TransportPool, temporary_path},
+    upload::remote_path,
+};
+use console::style;
+use futures::{stream, StreamExt};
+use indicatif::ProgressStyle;
+use std::{
+    error::Error,
+    io::{IsTerminal, Write as _},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::fs;
+use tracing::Instrument;
+
+/// Downloads what changed in the checksum file on the remote into the
+/// directory, whose scan is `local_checksum_tree`, see `syncbox pull`
+pub async fn pull_remote(
+    args: &Args,
+    config: &Config,
+    pool: &Arc<TransportPool>,
+    local_checksum_tree: ChecksumTree,
+    now: std::time::Instant,
+) -> Result<Outcome, Box<dyn Error + Send + Sync + 'static>> {
+    // scanning and hashing the directory were steps 1 and 2
+    let steps = 8;
+    let step = |n: usize| style(format!("[{n}/{steps}]")).dim().bold();
+    println!("{} {}", step(3), t!("fetching_checksum_file"));
+    // unlike when syncing, a missing checksum file would remove everything
+    let bytes = pool
+        .get()
+        .await?
+        .read(Path::new(&args.checksum_file))
+        .await
+        .map_err(|e| {
+            Failure::ChecksumFile.error(format!("Could not read {}: {e}", args.checksum_file))
+        })?;
+    let mut remote_checksum_tree =
+        ChecksumTree::from_compressed(&bytes).map_err(|e| Failure::ChecksumFile.error(e))?;
+    let cut_off = remote_checksum_tree.is_recovered();
+    if cut_off {
+        println!("      {}", t!("pull_cut_off"));
+    }
+    rehash_unchanged(args, &mut remote_checksum_tree, &local_checksum_tree).await?;
+    take_over_touched(args, &mut remote_checksum_tree, &local_checksum_tree);
+
+    println!("{} {}", step(4), t!("reconciling"));
+    let todo = Reconciler::pull(
+        local_checksum_tree.clone(),
+        &remote_checksum_tree,
+        &ReconcileOptions {
+            case_insensitive: args.case_insensitive,
+            filter: path_filter(args)?,
+            ..Default::default()
+        },
+    )?;
+    let (mut todo, moved) = match args.on_conflict {
+        None | Some(ConflictPolicy::RemoteWins) => (todo, vec![]),
+        Some(policy) => settle_pull(
+            todo,
+            &local_checksum_tree,
+            &remote_checksum_tree,
+            conflict_resolver(policy),
+        ),
+    };
+    if cut_off {
+        todo.retain(|action| !matches!(action, Action::LocalRemove(_)));
+    }
+    if args.deterministic {
+        sort_actions(&mut todo);
+    }
+    if let Some(source) = &config.script {
+        let mut script = Script::compile(source, Path::new("."))?;
+        if script.filters() {
+            todo = action_filter::filter(todo, |action| script.decide(action));
+        }
+        script.finish()?;
+    }
+    if let Some(command) = &args.filter_cmd {
+        let mut command = FilterCommand::spawn(command)?;
+        todo = action_filter::filter(todo, |action| command.decide(action));
+        command.finish()?;
+    }
+    for action in &todo {
+        if let Action::Conflict(path) = action {
+            println!("      {}", t!("conflict", path = format!("{path:?}")));
+        }
+    }
+    if todo.is_empty() {
+        println!("      {}", t!("nothing_to_do"));
+        return Ok(Outcome {
+            bytes: 0,
+            errors: false,
+            interrupted: false,
+            nothing_to_do: true,
+        });
+    }
+    println!(
+        "{} {}",
+        step(5),
+        t!("executing", count = style(todo.len()).bold())
+    );
+    if args.table {
+        print!("{}", plan_table(&todo));
+    }
+    let has_error = AtomicBool::new(false);
+    let results: Results = Default::default();
+    move_aside(args, &moved).await?;
+    let bytes = execute_pull(
+        args,
+        pool,
+        &todo,
+        &remote_checksum_tree,
+        &results,
+        &has_error,
+        (6, steps),
+    )
+    .await;
+
+    pool.close().await?;
+    if args.table {
+        print!("{}", results_table(&todo, &*results.lock().await));
+    }
+    println!(
+        "{}",
+        t!(
+            "done",
+            size = bytes.to_human_size(),
+            seconds = format!("{:.2}", now.elapsed().as_secs_f64())
+        )
+    );
+    Ok(Outcome {
+        bytes,
+        errors: has_error.load(SeqCst),
+        interrupted: false,
+        nothing_to_do: false,
+    })
+}
+
+/// Downloads what the checksum file on the remote has below `path`, or
+/// everything, into `target` and checks each file against its checksum,
+/// see `syncbox restore`
+pub async fn restore_files(
+    args: &Args,
+    pool: &Arc<TransportPool>,
+    path: Option<&Path>,
+    target: &Path,
+    now: std::time::Instant,
+) -> Result<Outcome, Box<dyn Error + Send + Sync + 'static>> {
+    let step = |n: usize| style(format!("[{n}/4]")).dim().bold();
+    println!("{} {}", step(1), t!("fetching_checksum_file"));
+    let bytes = pool
+        .get()
+        .await?
+        .read(Path::new(&args.checksum_file))
+        .await
+        .map_err(|e| {
+            Failure::ChecksumFile.error(format!("Could not read {}: {e}", args.checksum_file))
+        })?;
+    let remote_checksum_tree =
+        ChecksumTree::from_compressed(&bytes).map_err(|e| Failure::ChecksumFile.error(e))?;
+    let mut filter = path_filter(args)?;
+    if let Some(path) = path {
+        filter = filter.with_only(&[path.to_string_lossy().into_owned()])?;
+    }
+    // everything is missing from an empty tree
+    let todo = Reconciler::pull(
+        ChecksumTree::default(),
+        &remote_checksum_tree,
+        &ReconcileOptions {
+            filter,
+            ..Default::default()
+        },
+    )?;
+    if !todo.iter().any(|action| matches!(action, Action::Get(_))) {
+        let path = path.unwrap_or(Path::new("."));
+        println!("      {}", t!("restore_empty", path = format!("{path:?}")));
+        pool.close().await?;
+        return Ok(Outcome {
+            bytes: 0,
+            errors: false,
+            interrupted: false,
+            nothing_to_do: true,
+        });
+    }
+
+    if !args.dry_run {
+        fs::create_dir_all(target).await?;
+        std::env::set_current_dir(target)?;
+    }
+    let has_error = AtomicBool::new(false);
+    let results: Results = Default::default();
+    let bytes = download_files(
+        args,
+        pool,
+        &todo,
+        &remote_checksum_tree,
+        &results,
+        &has_error,
+        (2, 4),
+    )
+    .await;
+    pool.close().await?;
+
+    let results = results.lock().await;
+    // a dry run downloaded nothing to verify
+    if !args.dry_run {
+        let downloaded: Vec<_> = todo
+            .iter()
+            .filter(|action| matches!(results.get(action), Some((Ok(()), _))))
+            .filter_map(|action| match remote_checksum_tree.get_at(action.path()) {
+                Some(ChecksumElement::File(entry))
+                    if HashAlgorithm::of(&entry.checksum) != HashAlgorithm::Metadata =>
+                {
+                    Some((action.path().to_path_buf(), entry.checksum.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        println!(
+            "{} {}",
+            step(4),
+            t!("verifying_downloads", count = downloaded.len())
+        );
+        for (path, checksum) in downloaded {
+            let matches = tokio::task::spawn_blocking(move || {
+                let metadata = std::fs::metadata(&path)?;
+                let restored = HashAlgorithm::of(&checksum).checksum(&path, &metadata)?;
+                Ok::<_, std::io::Error>((restored == hash::without_permissions(&checksum), path))
+            })
+            .await??;
+            if let (false, path) = matches {
+                eprintln!("{}", t!("restore_corrupt", path = format!("{path:?}")));
+                has_error.store(true, SeqCst);
+            }
+        }
+    }
+
+    if args.table {
+        print!("{}", results_table(&todo, &results));
+    }
+    println!(
+        "{}",
+        t!(
+            "done",
+            size = bytes.to_human_size(),
+            seconds = format!("{:.2}", now.elapsed().as_secs_f64())
+        )
+    );
+    Ok(Outcome {
+        bytes,
+        errors: has_error.load(SeqCst),
+        interrupted: false,
+        nothing_to_do: false,
+    })
+}
+
+/// Settles conflicts with `policy`, printing how each was settled. `Ask`
+/// asks on the terminal, where there's none conflicts are skipped
+pub fn conflict_resolver(policy: ConflictPolicy) -> impl FnMut(&Conflict) -> Resolution {
+    let at = SystemTime::now();
+    move |conflict| {
+        let resolution = match policy {
+            ConflictPolicy::Ask => ask_conflict(conflict, at),
+            policy => policy.resolve(conflict, at),
+        };
+        let path = format!("{:?}", conflict.path);
+        match &resolution {
+            Resolution::Skip => {}
+            Resolution::Local => println!("      {}", t!("conflict_local", path = path)),
+            Resolution::Remote => println!("      {}", t!("conflict_remote", path = path)),
+            Resolution::KeepBoth(copy) => println!(
+                "      {}",
+                t!(
+                    "conflict_keep_both",
+                    path = path,
+                    copy = format!("{copy:?}")
+                )
+            ),
+        }
+        resolution
+    }
+}
+
+fn ask_conflict(conflict: &Conflict, at: SystemTime) -> Resolution {
+    if !std::io::stdin().is_terminal() {
+        return Resolution::Skip;
+    }
+    print!(
+        "{}",
+        t!("conflict_ask", path = format!("{:?}", conflict.path))
+    );
+    let mut answer = String::new();
+    if std::io::stdout().flush().is_err() || std::io::stdin().read_line(&mut answer).is_err() {
+        return Resolution::Skip;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "l" => Resolution::Local,
+        "r" => Resolution::Remote,
+        "b" => ConflictPolicy::KeepBoth.resolve(conflict, at),
+        _ => Resolution::Skip,
+    }
+}
+
+/// Moves local files aside to their copies to keep both versions of a
+/// conflict, before the remote versions are downloaded over them
+pub async fn move_aside(
+    args: &Args,
+    moved: &[(PathBuf, PathBuf)],
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    // nothing is downloaded over them in a dry run
+    if args.dry_run {
+        return Ok(());
+    }
+    for (path, copy) in moved {
+        fs::rename(path, copy)
+            .await
+            .map_err(|e| format!("Could not move {path:?} aside to {copy:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Creates the local directories, downloads the files and removes what the
+/// pull actions of `todo` ask for, as steps `first_step` to `first_step + 2`
+/// of `steps`. Returns the bytes downloaded
+pub async fn execute_pull(
+    args: &Args,
+    pool: &Arc<TransportPool>,
+    todo: &[Action],
+    remote_checksum_tree: &ChecksumTree,
+    results: &Results,
+    has_error: &AtomicBool,
+    (first_step, steps): (usize, usize),
+) -> u64 {
+    let bytes = download_files(
+        args,
+        pool,
+        todo,
+        remote_checksum_tree,
+        results,
+        has_error,
+        (first_step, steps),
+    )
+    .await;
+
+    let step = |n: usize| style(format!("[{}/{steps}]", first_step + n)).dim().bold();
+    if args.skip_removal {
+        println!("{} {}", step(2), t!("removing_files_skipped"));
+    } else {
+        println!("{} {}", step(2), t!("removing_files"));
+        // children before their parents
+        let mut remove_actions: Vec<_> = todo
+            .iter()
+            .filter(|action| matches!(action, Action::LocalRemove(_)))
+            .collect();
+        remove_actions.sort_by_key(|action| std::cmp::Reverse(action.path().iter().count()));
+        for (i, action) in remove_actions.iter().enumerate() {
+            let path = action.path();
+            let n = std::time::Instant::now();
+            async {
+                let removed = match fs::symlink_metadata(path).await {
+                    // like the remote in a dry run, only pretended
+                    Ok(metadata) if args.dry_run => Ok(metadata.is_dir()),
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir(path).await.map(|()| true),
+                    Ok(_) => fs::remove_file(path).await.map(|()| false),
+                    Err(e) => Err(e),
+                };
+                match removed {
+                    Ok(directory) => {
+                        record(results, action, Ok(()), n.elapsed()).await;
+                        if !args.table {
+                            let seconds = format!("{:.2}", n.elapsed().as_secs_f64());
+                            let (index, total, path) =
+                                (i + 1, remove_actions.len(), format!("{path:?}"));
+                            println!(
+                                "{}",
+                                if directory {
+                                    t!(
+                                        "removed_directory",
+                                        index = index,
+                                        total = total,
+                                        path = path,
+                                        seconds = seconds
+                                    )
+                                } else {
+                                    t!(
+                                        "removed",
+                                        index = index,
+                                        total = total,
+                                        path = path,
+                                        seconds = seconds
+                                    )
+                                }
+                            );
+                        }
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        record(results, action, Ok(()), n.elapsed()).await;
+                        if !args.table {
+                            println!(
+                                "{}",
+                                t!(
+                                    "removed_already_gone",
+                                    index = i + 1,
+                                    total = remove_actions.len(),
+                                    path = format!("{path:?}"),
+                                )
+                            );
+                        }
+                    }
+                    Err(error) => {
+                        record(results, action, Err(error.to_string()), n.elapsed()).await;
+                        eprintln!(
+                            "{}",
+                            t!("remove_failed", path = format!("{path:?}"), error = error)
+                        );
+                        has_error.store(true, SeqCst);
+                    }
+                }
+            }
+            .instrument(action_span(action))
+            .await;
+        }
+    }
+
+    bytes
+}
+
+/// Creates the local directories and downloads the files the pull actions
+/// of `todo` ask for, as steps `first_step` and `first_step + 1` of `steps`.
+/// Returns the bytes downloaded
+async fn download_files(
+    args: &Args,
+    pool: &Arc<TransportPool>,
+    todo: &[Action],
+    remote_checksum_tree: &ChecksumTree,
+    results: &Results,
+    has_error: &AtomicBool,
+    (first_step, steps): (usize, usize),
+) -> u64 {
+    let step = |n: usize| style(format!("[{}/{steps}]", first_step + n)).dim().bold();
+    println!("{} {}", step(0), t!("creating_directories"));
+    let mkdir_actions: Vec<_> = todo
+        .iter()
+        .filter(|action| matches!(action, Action::LocalMkdir(_)))
+        .collect();
+    for (i, action) in mkdir_actions.iter().enumerate() {
+        let path = action.path();
+        let n = std::time::Instant::now();
+        async {
+            let created = if args.dry_run {
+                Ok(())
+            } else {
+                create_local_directory(path).await
+            };
+            match created {
+                Ok(()) => {
+                    record(results, action, Ok(()), n.elapsed()).await;
+                    if !args.table {
+                        println!(
+                            "{}",
+                            t!(
+                                "created_directory",
+                                index = i + 1,
+                                total = mkdir_actions.len(),
+                                path = format!("{path:?}"),
+                                seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                            )
+                        )
+                    }
+                }
+                Err(error) => {
+                    record(results, action, Err(error.to_string()), n.elapsed()).await;
+                    eprintln!(
+                        "{}",
+                        t!(
+                            "create_directory_failed",
+                            index = i + 1,
+                            total = mkdir_actions.len(),
+                            path = format!("{path:?}"),
+                            error = error,
+                        )
+                    );
+                    has_error.store(true, SeqCst);
+                }
+            }
+        }
+        .instrument(action_span(action))
+        .await;
+    }
+
+    let get_actions: Vec<_> = todo
+        .iter()
+        .filter(|action| matches!(action, Action::Get(_)))
+        .collect();
+    let total_to_download: u64 = get_actions
+        .iter()
+        .filter_map(|action| match remote_checksum_tree.get_at(action.path()) {
+            Some(ChecksumElement::File(entry)) => entry.size,
+            _ => None,
+        })
+        .sum();
+    println!(
+        "{} {}",
+        step(1),
+        t!(
+            "downloading",
+            count = get_actions.len(),
+            size = total_to_download.to_human_size()
+        )
+    );
+    let progress_bars = if args.deterministic {
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden())
+    } else {
+        indicatif::MultiProgress::new()
+    };
+    let bytes = AtomicU64::new(0);
+    // compressed files are downloaded smaller than they are restored
+    let restored = AtomicU64::new(0);
+    let compression = remote_checksum_tree.compression();
+    let get_actions_len = get_actions.len();
+    stream::iter(get_actions.iter().enumerate())
+        .map(|(i, action)| {
+            let path = action.path();
+            let element = remote_checksum_tree.get_at(path);
+            let recorded = match element {
+                Some(ChecksumElement::File(entry)) => entry.size.unwrap_or_default(),
+                _ => 0,
+            };
+            let (progress_bars, bytes, restored, has_error, results) =
+                (&progress_bars, &bytes, &restored, has_error, results);
+            let span = action_span(action);
+            async move {
+                let n = std::time::Instant::now();
+                let pb = Arc::new(progress_bars.add(indicatif::ProgressBar::new(0)));
+                let mut template = format!("[{}/{}] ", i + 1, get_actions_len);
+                template.push_str("[{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} [{bytes_per_sec}] {msg}");
+                pb.set_style(
+                    ProgressStyle::with_template(&template)
+                        .unwrap()
+                        .progress_chars(PROGRESS_BAR_CHARS),
+                );
+                pb.set_message(path.to_string_lossy().into_owned());
+                let downloaded = match element {
+                    Some(_) if args.dry_run => Ok(recorded),
+                    Some(element) => {
+                        get_file(pool, path, element, compression, Arc::clone(&pb)).await
+                    }
+                    None => Err("the remote checksum file no longer has it".into()),
+                };
+                match downloaded {
+                    Ok(b) => {
+                        bytes.fetch_add(b, SeqCst);
+                        let restored = restored.fetch_add(recorded, SeqCst) + recorded;
+                        record(results, action, Ok(()), n.elapsed()).await;
+                        let message = t!(
+                            "remaining",
+                            path = path.to_string_lossy(),
+                            size = total_to_download.saturating_sub(restored).to_human_size(),
+                        );
+                        pb.finish_with_message(message.clone());
+                        if std::env::var("CI").is_ok() || args.deterministic {
+                            println!("✅ {}", message);
+                        }
+                    }
+                    Err(error) => {
+                        record(results, action, Err(error.to_string()), n.elapsed()).await;
+                        let message =
+                            t!("download_failed", path = format!("{path:?}"), error = error);
+                        pb.abandon_with_message(message.clone());
+                        has_error.store(true, SeqCst);
+                        if std::env::var("CI").is_ok() || args.deterministic {
+                            println!("{message}");
+                        }
+                    }
+                }
+            }
+            .instrument(span)
+        })
+        .buffer_unordered(if args.deterministic {
+            1
+        } else {
+            args.concurrency
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    bytes.load(SeqCst)
+}
+
+/// Creates a directory of the remote tree, replacing a file in the way
+async fn create_local_directory(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)
+        .await
+        .is_ok_and(|metadata| !metadata.is_dir())
+    {
+        fs::remove_file(path).await?;
+    }
+    fs::create_dir_all(path).await
+}
+
+/// Downloads a file of the remote tree into a temporary file moved into
+/// place once it's complete, restoring the recorded modification time and
+/// permissions, or recreates a link. Returns the bytes downloaded
+async fn get_file(
+    pool: &Arc<TransportPool>,
+    path: &Path,
+    element: &ChecksumElement,
+    compression: Option<ObjectCompression>,
+    pb: Arc<indicatif::ProgressBar>,
+) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let entry = match element {
+        ChecksumElement::File(entry) => entry,
+        ChecksumElement::Symlink(target) => {
+            clear_local_path(path).await?;
+            create_local_link(target, path).await?;
+            return Ok(0);
+        }
+        ChecksumElement::Directory(_) => return Err("it's a directory on the remote".into()),
+    };
+    let remote = remote_path(path, compression);
+    // compressed files are stored smaller than recorded
+    let size = match (entry.size, compression) {
+        (Some(size), None) => size,
+        _ => pool.get().await?.size(&remote).await?,
+    };
+    pb.set_length(size);
+    let downloaded = temporary_path(&remote);
+    let progress = {
+        let pb = Arc::clone(&pb);
+        Arc::new(move |bytes| pb.set_position(bytes))
+    };
+    let written = match download(pool, &remote, size, &downloaded, progress).await {
+        Ok(written) => written,
+        Err(e) => {
+            fs::remove_file(&downloaded).await.ok();
+            return Err(e);
+        }
+    };
+    let temporary = temporary_path(path);
+    let restored = match compression {
+        Some(compression) => {
+            let (source, target) = (downloaded.clone(), temporary.clone());
+            let restored =
+                tokio::task::spawn_blocking(move || compression.decompress_file(&source, &target))
+                    .await?;
+            fs::remove_file(&downloaded).await.ok();
+            restored
+        }
+        None => Ok(written),
+    };
+    let restored = match restored {
+        Ok(size) if entry.size.is_some_and(|expected| expected != size) => Err(format!(
+            "expected {} bytes, got {size}",
+            entry.size.unwrap_or_default()
+        )
+        .into()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = restored {
+        fs::remove_file(&temporary).await.ok();
+        return Err(e);
+    }
+    clear_local_path(path).await?;
+    fs::rename(&temporary, path).await?;
+    let file = std::fs::File::options().write(true).open(path)?;
+    if let Some(mtime) = entry.mtime {
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64);
+        file.set_modified(mtime)?;
+    }
+    if let Some(mode) = entry.mode {
+        set_mode(path, mode)?;
+    }
+    Ok(written)
+}
+
+/// Removes a link, or a directory with everything in it, in the way of a file
+async fn clear_local_path(path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).await,
+        Ok(metadata) if metadata.is_symlink() => fs::remove_file(path).await,
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+async fn create_local_link(target: &str, path: &Path) -> std::io::Result<()> {
+    fs::symlink(target, path).await
+}
+
+#[cfg(not(unix))]
+async fn create_local_link(_target: &str, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::other(
+        "links can't be created on this system",
+    ))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree, FileEntry},
+    cli::{Args, TransportType},
+    connect::make_transport,
+    exit::Failure,
+    hash::MetadataChangePolicy,
+    lock::{self, Lock},
+    report::{HumanBytes, PROGRESS_BAR_CHARS},
+    scan::entry_of,
+    t,
+    transport::{throttle::RateLimiter, Transport},
+    upload::{check_compression, create_directory, remote_path, upload_file},
+    watch::FileWatcher,
+};
+use chrono::Utc;
+use console::style;
+use indicatif::ProgressStyle;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Uploads the given files whenever they change and keeps the remote checksum
+/// file up to date, without scanning the rest of the directory
+pub async fn push_on_save(
+    args: &Args,
+    paths: &[PathBuf],
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let root = std::env::current_dir()?.canonicalize()?;
+    let files = paths
+        .iter()
+        .map(|path| match path.strip_prefix(&root) {
+            Ok(relative) if path.is_file() => Ok(Path::new(".").join(relative)),
+            _ => Err(format!("{path:?} is not a file inside {root:?}")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let rate_limiter =
+        Arc::new(RateLimiter::new(args.max_rps.or_else(|| {
+            args.transport().and_then(TransportType::default_max_rps)
+        })));
+    let mut transport = make_transport(args, &rate_limiter).await?;
+    let mut checksum_tree = read_pushed_checksum(args, &mut transport).await?;
+
+    let mut watcher = FileWatcher::new(&files, Duration::from_millis(200))?;
+    println!("{}", t!("watching", count = style(files.len()).bold()));
+    loop {
+        let changed = tokio::select! {
+            changed = watcher.changed() => changed,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(changed) = changed else {
+            break;
+        };
+        for file in changed {
+            if let Err(e) = push_file(args, &mut transport, &mut checksum_tree, &file).await {
+                eprintln!(
+                    "{}",
+                    t!("push_failed", path = format!("{file:?}"), error = e)
+                );
+            }
+        }
+    }
+
+    transport.close().await?;
+    Ok(())
+}
+
+/// The checksum file push-on-save adds the files it pushes to
+async fn read_pushed_checksum(
+    args: &Args,
+    transport: &mut Box<dyn Transport + Send + Sync>,
+) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+    let mut checksum_tree = match transport
+        .read_last_checksum(Path::new(&args.checksum_file))
+        .await
+    {
+        Ok(checksum_tree) => checksum_tree,
+        Err(_) if args.force => ChecksumTree::default(),
+        Err(e) => return Err(Failure::ChecksumFile.error(e)),
+    };
+    check_compression(args, &mut checksum_tree)?;
+    checksum_tree.set_compression(args.compress);
+    Ok(checksum_tree)
+}
+
+async fn push_file(
+    args: &Args,
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    checksum_tree: &mut ChecksumTree,
+    path: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let n = std::time::Instant::now();
+    let entry = entry_of(
+        path,
+        args.file_size_threshold * 1024 * 1024,
+        args.hash,
+        args.metadata_changes == MetadataChangePolicy::QuickHash,
+        args.preserve_permissions,
+        args.preserve_owner,
+    )
+    .await?;
+    // editors happily save files without changing them
+    match checksum_tree.get_at(path) {
+        Some(ChecksumElement::File(previous)) if previous.checksum == entry.checksum => {
+            return Ok(());
+        }
+        Some(ChecksumElement::File(previous)) if entry.is_touched(previous) => {
+            let entry = FileEntry {
+                synced: previous.synced,
+                ..entry
+            };
+            checksum_tree.insert_at(path, ChecksumElement::File(entry));
+            return Ok(());
+        }
+        _ => {}
+    }
+    // a sync may be running, or have written the checksum file since. Held
+    // for a single file, refreshing it isn't worth a connection of its own
+    let lock_path = lock::path(&args.checksum_file, None);
+    let lock = (!args.no_lock && !args.is_dry()).then(|| Lock::new(args.lock_ttl));
+    if let Some(lock) = &lock {
+        lock::acquire(&mut **transport, &lock_path, lock, args.break_lock).await?;
+    }
+    let pushed = push_changed(args, transport, checksum_tree, path, entry).await;
+    if let Some(lock) = &lock {
+        if let Err(e) = lock::release(&mut **transport, &lock_path, &lock.id).await {
+            tracing::warn!("removing the lock from the remote failed, it expires on its own: {e}");
+        }
+    }
+    println!(
+        "{}",
+        t!(
+            "pushed",
+            path = format!("{path:?}"),
+            size = pushed?.to_human_size(),
+            seconds = format!("{:.2}", n.elapsed().as_secs_f64())
+        )
+    );
+    Ok(())
+}
+
+/// Uploads the file at `path` and adds `entry` to the checksum file read
+/// again, returning the bytes written
+async fn push_changed(
+    args: &Args,
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    checksum_tree: &mut ChecksumTree,
+    path: &Path,
+    mut entry: FileEntry,
+) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+    *checksum_tree = read_pushed_checksum(args, transport).await?;
+
+    let mut parents = path
+        .ancestors()
+        .skip(1)
+        .filter(|parent| !matches!(parent.to_str(), Some("" | ".")))
+        .collect::<Vec<_>>();
+    parents.reverse();
+    for parent in parents {
+        if !matches!(
+            checksum_tree.get_at(parent),
+            Some(ChecksumElement::Directory(_))
+        ) {
+            create_directory(transport, parent, args.on_collision, args.chmod.dirs).await?;
+        }
+    }
+
+    let pb = Arc::new(indicatif::ProgressBar::new(entry.size.unwrap_or_default()));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} [{bytes_per_sec}] {msg}",
+        )
+        .unwrap()
+        .progress_chars(PROGRESS_BAR_CHARS),
+    );
+    pb.set_message(path.to_string_lossy().to_string());
+    let written = upload_file(transport, path, &pb, args.atomic_uploads, args.compress).await;
+    pb.finish_and_clear();
+    let written = written?;
+    if let Some(mode) = args.chmod.files {
+        let remote = remote_path(path, args.compress);
+        if let Err(e) = transport.set_permissions(&remote, mode, None).await {
+            eprintln!(
+                "{}",
+                t!("chmod_failed", path = format!("{path:?}"), error = e)
+            );
+        }
+    }
+
+    entry.synced = Some(Utc::now().timestamp());
+    checksum_tree.insert_at(path, ChecksumElement::File(entry));
+    transport
+        .write_last_checksum(
+            Path::new(&args.checksum_file),
+            checksum_tree,
+            args.checksum_compression(),
+        )
+        .await?;
+    Ok(written)
+}
@@ -1,30 +1,290 @@
-use crate::checksum_tree::{ChecksumElement, ChecksumTree};
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree, Entries, FileEntry, Name},
+    collision::CollisionPolicy,
+    conflict::{Conflict, Resolution},
+    hash::{self, HashAlgorithm},
+    path_filter::PathFilter,
+    plan::SyncPlan,
+};
 use std::error::Error;
-use std::{collections::VecDeque, ops::Deref, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Action {
     Mkdir(PathBuf),
     Put(PathBuf),
+    /// Creates the symbolic link of the next tree
+    Link(PathBuf),
+    /// Sets the modification time and permissions of a file whose contents
+    /// didn't change, see `ReconcileOptions::sync_mtime`
+    SetMeta(PathBuf),
     Remove(PathBuf),
+    /// Removes a directory the next tree doesn't have, once emptied by the
+    /// removals before it
+    Rmdir(PathBuf),
+    /// Downloads a file of the remote tree or recreates its link, see `Reconciler::pull`
+    Get(PathBuf),
+    /// Removes a local file or empty directory the remote tree doesn't have
+    LocalRemove(PathBuf),
+    LocalMkdir(PathBuf),
+    /// Changed on both sides since they last agreed, left as it is on each,
+    /// see `Reconciler::merge`
+    Conflict(PathBuf),
+}
+
+impl Action {
+    /// What the action does, as shown in plans
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::Mkdir(_) => "mkdir",
+            Action::Put(_) => "put",
+            Action::Link(_) => "link",
+            Action::SetMeta(_) => "set-meta",
+            Action::Remove(_) => "remove",
+            Action::Rmdir(_) => "rmdir",
+            Action::Get(_) => "get",
+            Action::LocalRemove(_) => "local-remove",
+            Action::LocalMkdir(_) => "local-mkdir",
+            Action::Conflict(_) => "conflict",
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        let (Action::Mkdir(path)
+        | Action::Put(path)
+        | Action::Link(path)
+        | Action::SetMeta(path)
+        | Action::Remove(path)
+        | Action::Rmdir(path)
+        | Action::Get(path)
+        | Action::LocalRemove(path)
+        | Action::LocalMkdir(path)
+        | Action::Conflict(path)) = self;
+        path
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReconcileOptions {
+    /// Match paths of the previous tree ignoring letter case, for destinations
+    /// with case-insensitive filesystems
+    pub case_insensitive: bool,
+    /// How to handle paths that changed between file and directory, `Fail`
+    /// stops before anything is executed
+    pub collision: CollisionPolicy,
+    /// Paths left out are kept as they are on the side being changed
+    pub filter: PathFilter,
+    /// Files with the same contents but another modification time get a
+    /// `SetMeta` rather than nothing, for remotes keeping modification times
+    pub sync_mtime: bool,
+    /// Likewise for files with other permissions
+    pub sync_mode: bool,
+}
+
+/// What `Reconciler::merge` planned
+#[derive(Debug)]
+pub struct Merge {
+    /// Uploads and removals on the remote, downloads and removals of local
+    /// files, and conflicts
+    pub actions: Vec<Action>,
+    /// The remote tree once the uploads and removals are done, what the
+    /// remote is reconciled against
+    pub remote: ChecksumTree,
+    /// Conflicts settled by the resolver, in the order of their paths
+    pub resolved: Vec<(PathBuf, Resolution)>,
 }
 
 pub struct Reconciler {}
 
 impl Reconciler {
     pub fn reconcile(
-        mut prev: ChecksumTree,
+        prev: ChecksumTree,
         next: &ChecksumTree,
-    ) -> Result<Vec<Action>, Box<dyn Error + Send + Sync + 'static>> {
+    ) -> Result<SyncPlan, Box<dyn Error + Send + Sync + 'static>> {
+        Self::reconcile_with_options(prev, next, &ReconcileOptions::default())
+    }
+
+    pub fn reconcile_with_options(
+        prev: ChecksumTree,
+        next: &ChecksumTree,
+        options: &ReconcileOptions,
+    ) -> Result<SyncPlan, Box<dyn Error + Send + Sync + 'static>> {
         check_version(prev.get_version(), next.get_version())?;
+        Self::actions(prev, next, options)
+    }
+
+    /// The other direction, the remote tree is what the local one is made to
+    /// match. Files and links that differ are downloaded with `Get`, the
+    /// local ones the remote doesn't have are removed with `LocalRemove`.
+    /// Paths that changed between file and directory are replaced, the
+    /// collision policy is about the remote
+    pub fn pull(
+        local: ChecksumTree,
+        remote: &ChecksumTree,
+        options: &ReconcileOptions,
+    ) -> Result<Vec<Action>, Box<dyn Error + Send + Sync + 'static>> {
+        check_version(remote.get_version(), local.get_version())?;
+        // downloads are given the recorded times and permissions anyway
+        let options = ReconcileOptions {
+            collision: CollisionPolicy::Replace,
+            sync_mtime: false,
+            sync_mode: false,
+            ..options.clone()
+        };
+        let mut actions: Vec<_> = Self::actions(local, remote, &options)?
+            .into_iter()
+            .map(|action| match action {
+                Action::Mkdir(path) => Action::LocalMkdir(path),
+                Action::Put(path) | Action::Link(path) => Action::Get(path),
+                Action::Remove(path) | Action::Rmdir(path) => Action::LocalRemove(path),
+                pulled => pulled,
+            })
+            .collect();
+        sort_actions(&mut actions);
+        Ok(actions)
+    }
+
+    /// Both directions at once, against `base`, the tree both sides agreed
+    /// on the last time. What changed on one side only is uploaded or
+    /// downloaded, what changed on both sides differently is settled by
+    /// `resolve` or otherwise left as a `Conflict`. Anything below or above
+    /// a conflict is a conflict too, these are never settled. Files, links
+    /// and empty directories are compared, directories otherwise follow
+    /// what's in them
+    pub fn merge(
+        base: &ChecksumTree,
+        local: &ChecksumTree,
+        remote: &ChecksumTree,
+        options: &ReconcileOptions,
+        mut resolve: impl FnMut(&Conflict) -> Resolution,
+    ) -> Result<Merge, Box<dyn Error + Send + Sync + 'static>> {
+        check_version(remote.get_version(), local.get_version())?;
+        // what's left out stays on both sides as the remote has it
+        let kept;
+        let local = if options.filter.is_empty() {
+            local
+        } else {
+            kept = options.filter.keep_excluded(remote, local);
+            &kept
+        };
+        let paths: BTreeSet<_> = [base, local, remote].into_iter().flat_map(leaves).collect();
+        let mut pulled = vec![];
+        let mut conflicts = vec![];
+        for path in paths {
+            let (base, local, remote) = (
+                leaf_at(base, &path),
+                leaf_at(local, &path),
+                leaf_at(remote, &path),
+            );
+            if same(local, remote) || same(remote, base) {
+                // unchanged or uploaded, the local tree is what the remote becomes
+            } else if same(local, base) {
+                pulled.push(path);
+            } else {
+                conflicts.push(path);
+            }
+        }
+        // uploads and downloads nested in a conflict would replace what the
+        // other side has there
+        let nested: Vec<_> = [local, remote]
+            .into_iter()
+            .flat_map(leaves)
+            .filter(|path| {
+                nested_in_conflict(path, &conflicts)
+                    && !same(leaf_at(local, path), leaf_at(remote, path))
+            })
+            .collect();
+        pulled.retain(|path| !nested_in_conflict(path, &conflicts));
+        conflicts.extend(nested);
+        conflicts.sort();
+        conflicts.dedup();
+        let (alone, mut conflicts): (Vec<_>, Vec<_>) =
+            conflicts.iter().cloned().partition(|path| {
+                conflicts
+                    .iter()
+                    .filter(|conflict| *conflict != path)
+                    .all(|conflict| !path.starts_with(conflict) && !conflict.starts_with(path))
+            });
+        let mut resolved = vec![];
+        for path in alone {
+            let resolution = resolve(&Conflict {
+                path: &path,
+                local: leaf_at(local, &path),
+                remote: leaf_at(remote, &path),
+            });
+            match resolution {
+                Resolution::Skip => conflicts.push(path),
+                resolution => resolved.push((path, resolution)),
+            }
+        }
+        conflicts.sort();
+
+        let mut pulled_local = local.clone();
+        for path in &pulled {
+            take_from(remote, &mut pulled_local, path);
+        }
+        let mut copies = vec![];
+        for (path, resolution) in &resolved {
+            match resolution {
+                Resolution::Remote => take_from(remote, &mut pulled_local, path),
+                Resolution::KeepBoth(copy) => {
+                    if let Some(element) = leaf_at(local, path) {
+                        pulled_local.insert_at(copy, element.clone());
+                    }
+                    take_from(remote, &mut pulled_local, path);
+                    copies.push(copy);
+                }
+                Resolution::Local | Resolution::Skip => {}
+            }
+        }
+        let mut merged_remote = pulled_local.clone();
+        for path in &conflicts {
+            take_from(remote, &mut merged_remote, path);
+        }
+        let mut actions = Self::actions(remote.clone(), &merged_remote, options)?.into_actions();
+        // copies are moved aside locally rather than downloaded
+        actions.extend(
+            Self::pull(local.clone(), &pulled_local, options)?
+                .into_iter()
+                .filter(|action| !matches!(action, Action::Get(path) if copies.contains(&path))),
+        );
+        actions.extend(conflicts.into_iter().map(Action::Conflict));
+        sort_actions(&mut actions);
+        Ok(Merge {
+            actions,
+            remote: merged_remote,
+            resolved,
+        })
+    }
+
+    fn actions(
+        mut prev: ChecksumTree,
+        next: &ChecksumTree,
+        options: &ReconcileOptions,
+    ) -> Result<SyncPlan, Box<dyn Error + Send + Sync + 'static>> {
+        let filtered;
+        let next = if options.filter.is_empty() {
+            next
+        } else {
+            filtered = options.filter.keep_excluded(&prev, next);
+            &filtered
+        };
+        if options.case_insensitive {
+            check_case_collisions(next)?;
+        }
         let mut previous_checksum = prev.get_root().take().unwrap_or_default();
         let mut actions = vec![];
+        let mut sizes = HashMap::new();
         let root = next.deref().as_ref().unwrap();
         let mut to_reconcile = VecDeque::from([(vec![], root)]);
         while !to_reconcile.is_empty() {
             let (next_depth, next) = to_reconcile.pop_front().unwrap();
             match next {
-                ChecksumElement::Directory(dir) => {
+                ChecksumElement::Directory(dir) if !dir.is_empty() || next_depth.is_empty() => {
                     // create vec of path to do lookup for
                     for (path, element) in dir {
                         let mut new_depth = next_depth.clone();
@@ -32,22 +292,32 @@ impl Reconciler {
                         to_reconcile.push_back((new_depth, element));
                     }
                 }
-                ChecksumElement::File(new_checksum) => {
-                    // see if we had it in previous - create directories
+                next_leaf => {
+                    // see if we had it in previous - create directories, an
+                    // empty directory is created along with its parents
+                    let is_dir = matches!(next_leaf, ChecksumElement::Directory(_));
+                    let directories = next_depth.len() - usize::from(!is_dir);
                     let mut stack = vec![previous_checksum];
                     let mut path = vec![];
-                    for key in next_depth.iter().take(next_depth.len() - 1) {
+                    for key in next_depth.iter().take(directories) {
                         path.push(*key);
                         let currently_searching = stack.last_mut().unwrap();
                         if let ChecksumElement::Directory(dir) = currently_searching {
-                            if let Some(next_to_search) = dir.remove(*key) {
-                                stack.push(next_to_search);
-                            } else {
-                                let new_dir = ChecksumElement::Directory(Default::default());
-                                stack.push(new_dir);
-                                // ignore "." directories
-                                if path.len() > 1 {
-                                    actions.push(Action::Mkdir(path.iter().collect()));
+                            match take_entry(dir, key, options) {
+                                Some(next_to_search @ ChecksumElement::Directory(_)) => {
+                                    stack.push(next_to_search);
+                                }
+                                found => {
+                                    if found.is_some() {
+                                        check_collision(&path, "file", options)?;
+                                    }
+                                    // a file in the way gets cleared when creating the directory
+                                    let new_dir = ChecksumElement::Directory(Default::default());
+                                    stack.push(new_dir);
+                                    // ignore "." directories
+                                    if path.len() > 1 {
+                                        actions.push(Action::Mkdir(joined(&path)));
+                                    }
                                 }
                             }
                         };
@@ -56,21 +326,51 @@ impl Reconciler {
                     // check for file or create file
                     let leaf = stack.last_mut().unwrap();
                     match leaf {
+                        _ if is_dir => {}
                         ChecksumElement::Directory(dir) => {
                             let filename = *next_depth.last().unwrap();
+                            // only the letter case changed, uploaded to take the new name
+                            let renamed = !dir.contains_key(filename);
 
-                            if let Some(element) = dir.remove(filename) {
-                                let matches = match element {
-                                    ChecksumElement::File(previous_checksum) => {
-                                        previous_checksum == *new_checksum
+                            if let Some(element) = take_entry(dir, filename, options) {
+                                let mut metadata_changed = false;
+                                let matches = match (element, next_leaf) {
+                                    (
+                                        ChecksumElement::File(previous_checksum),
+                                        ChecksumElement::File(new_checksum),
+                                    ) => {
+                                        metadata_changed = metadata_changed_of(
+                                            &previous_checksum,
+                                            new_checksum,
+                                            options,
+                                        );
+                                        previous_checksum.checksum == new_checksum.checksum
+                                            || metadata_changed
+                                    }
+                                    (
+                                        ChecksumElement::Symlink(previous_target),
+                                        ChecksumElement::Symlink(new_target),
+                                    ) => previous_target == *new_target,
+                                    // the directory gets cleared when uploading the file
+                                    (ChecksumElement::Directory(_), _) => {
+                                        check_collision(&next_depth, "directory", options)?;
+                                        false
                                     }
-                                    _ => unreachable!(),
+                                    // a file replaced by a link or the other way around
+                                    _ => false,
                                 };
-                                if !matches {
-                                    actions.push(Action::Put(next_depth.iter().collect()));
+                                if renamed || !matches {
+                                    actions.push(leaf_action(next_leaf, &next_depth));
+                                } else if metadata_changed {
+                                    actions.push(Action::SetMeta(joined(&next_depth)));
                                 }
                             } else {
-                                actions.push(Action::Put(next_depth.iter().collect()));
+                                actions.push(leaf_action(next_leaf, &next_depth));
+                            }
+                            if let (Some(Action::Put(path)), ChecksumElement::File(entry)) =
+                                (actions.last(), next_leaf)
+                            {
+                                sizes.extend(entry.size.map(|size| (path.clone(), size)));
                             }
                         }
                         _ => unreachable!(),
@@ -89,23 +389,338 @@ impl Reconciler {
             }
         }
 
-        // collect files that left in previous and mark them to be removed
+        // collect files that left in previous and mark them to be removed,
+        // along with the directories next doesn't have any more, children
+        // before their parents so each is empty by the time it's removed
+        let mut directories = vec![];
         let mut stack: Vec<(PathBuf, &ChecksumElement)> = vec![("".into(), &previous_checksum)];
         while let Some((path, current)) = stack.pop() {
             match current {
                 ChecksumElement::Directory(dir) => {
+                    let gone = match next.get_at(&path) {
+                        Some(ChecksumElement::Directory(_)) => false,
+                        // an empty directory in the way of a file is cleared
+                        // when uploading it
+                        Some(_) => dir.is_empty(),
+                        None => true,
+                    };
+                    if gone && path.components().count() > 1 {
+                        directories.push(path.clone());
+                    }
                     dir.iter().for_each(|(dir_name, element)| {
                         let mut new_path = path.clone();
-                        new_path.push(dir_name);
+                        new_path.push(&**dir_name);
                         stack.push((new_path, element));
                     });
                 }
-                ChecksumElement::File(_) => actions.push(Action::Remove(path)),
+                ChecksumElement::File(entry) => {
+                    sizes.extend(entry.size.map(|size| (path.clone(), size)));
+                    actions.push(Action::Remove(path))
+                }
+                ChecksumElement::Symlink(_) => actions.push(Action::Remove(path)),
             }
         }
+        actions.extend(directories.into_iter().map(Action::Rmdir));
+        sort_actions(&mut actions);
 
-        Ok(actions)
+        Ok(SyncPlan::with_sizes(actions, sizes))
+    }
+
+    /// Files whose previous checksum was made with another algorithm than the
+    /// next one, along with that algorithm. They look changed to `reconcile`
+    /// however their contents are, hashing them again with the previous
+    /// algorithm tells which of them really are
+    pub fn algorithm_changes(
+        prev: &ChecksumTree,
+        next: &ChecksumTree,
+    ) -> Vec<(PathBuf, HashAlgorithm)> {
+        next.iter()
+            .filter_map(|(path, entry)| match prev.get_at(&path) {
+                Some(ChecksumElement::File(previous)) => {
+                    let algorithm = HashAlgorithm::of(&previous.checksum);
+                    (algorithm != HashAlgorithm::of(&entry.checksum)).then_some((path, algorithm))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Takes over the next entries of files that were only touched into
+    /// `prev`, so they aren't uploaded again, see `FileEntry::is_touched`.
+    /// Returns how many
+    pub fn take_over_touched(prev: &mut ChecksumTree, next: &ChecksumTree) -> usize {
+        let touched = next
+            .iter()
+            .filter_map(|(path, entry)| match prev.get_at(&path) {
+                Some(ChecksumElement::File(previous)) if entry.is_touched(previous) => {
+                    let entry = FileEntry {
+                        synced: previous.synced,
+                        ..entry.clone()
+                    };
+                    Some((path, entry))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let count = touched.len();
+        for (path, entry) in touched {
+            prev.insert_at(&path, ChecksumElement::File(entry));
+        }
+        count
+    }
+}
+
+/// Sorts actions the way `Reconciler::reconcile` plans them, so the same
+/// changes always make the same plan: directories are created before what's
+/// in them, then uploads and links, then removals, each by path, and
+/// directories are removed last, children before their parents. Local
+/// actions follow in the same order, then conflicts
+pub fn sort_actions(actions: &mut [Action]) {
+    let rank = |action: &Action| match action {
+        Action::Mkdir(_) => 0,
+        Action::Put(_) | Action::Link(_) | Action::SetMeta(_) => 1,
+        Action::Remove(_) => 2,
+        Action::Rmdir(_) => 3,
+        Action::LocalMkdir(_) => 4,
+        Action::Get(_) => 5,
+        Action::LocalRemove(_) => 6,
+        Action::Conflict(_) => 7,
+    };
+    actions.sort_by(|a, b| {
+        rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+            (Action::Rmdir(a), Action::Rmdir(b))
+            | (Action::LocalRemove(a), Action::LocalRemove(b)) => b.cmp(a),
+            _ => a.path().cmp(b.path()),
+        })
+    });
+}
+
+/// Whether `next` has the contents of `previous` but another modification
+/// time or permissions that `options` keep in sync. Unknown ones never differ
+fn metadata_changed_of(previous: &FileEntry, next: &FileEntry, options: &ReconcileOptions) -> bool {
+    fn differ<T: PartialEq>(previous: Option<T>, next: Option<T>) -> bool {
+        previous.zip(next).is_some_and(|(a, b)| a != b)
+    }
+    // permissions that are preserved are part of the checksum
+    if hash::without_permissions(&previous.checksum) != hash::without_permissions(&next.checksum) {
+        return false;
+    }
+    (options.sync_mtime && differ(previous.mtime, next.mtime))
+        || (options.sync_mode
+            && (previous.checksum != next.checksum || differ(previous.mode, next.mode)))
+}
+
+/// Paths of the files, links and empty directories of `tree`
+pub(crate) fn leaves(tree: &ChecksumTree) -> Vec<PathBuf> {
+    let mut leaves = vec![];
+    let mut stack: Vec<(PathBuf, &ChecksumElement)> = tree
+        .deref()
+        .iter()
+        .map(|root| (PathBuf::new(), root))
+        .collect();
+    while let Some((path, element)) = stack.pop() {
+        match element {
+            ChecksumElement::Directory(entries) if !entries.is_empty() => stack.extend(
+                entries
+                    .iter()
+                    .map(|(name, element)| (path.join(&**name), element)),
+            ),
+            // the root itself
+            ChecksumElement::Directory(_) if path.components().count() < 2 => {}
+            _ => leaves.push(path),
+        }
+    }
+    leaves
+}
+
+/// The file, link or empty directory at `path`
+pub(crate) fn leaf_at<'a>(tree: &'a ChecksumTree, path: &Path) -> Option<&'a ChecksumElement> {
+    match tree.get_at(path)? {
+        ChecksumElement::Directory(entries) if !entries.is_empty() => None,
+        leaf => Some(leaf),
+    }
+}
+
+/// Whether `path` is a conflict or below or above one
+fn nested_in_conflict(path: &Path, conflicts: &[PathBuf]) -> bool {
+    conflicts
+        .iter()
+        .any(|conflict| path.starts_with(conflict) || conflict.starts_with(path))
+}
+
+fn same(a: Option<&ChecksumElement>, b: Option<&ChecksumElement>) -> bool {
+    match (a, b) {
+        (Some(ChecksumElement::File(a)), Some(ChecksumElement::File(b))) => {
+            a.checksum == b.checksum
+        }
+        (Some(ChecksumElement::Symlink(a)), Some(ChecksumElement::Symlink(b))) => a == b,
+        (Some(ChecksumElement::Directory(_)), Some(ChecksumElement::Directory(_))) => true,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Makes `path` in `tree` what it is in `source`, directories left empty by
+/// a removal go too unless they are empty in `source` as well
+pub(crate) fn take_from(source: &ChecksumTree, tree: &mut ChecksumTree, path: &Path) {
+    if let Some(element) = source.get_at(path) {
+        tree.insert_at(path, element.clone());
+        return;
+    }
+    tree.remove_at(path);
+    for parent in path.ancestors().skip(1) {
+        let empty = matches!(
+            tree.get_at(parent),
+            Some(ChecksumElement::Directory(entries)) if entries.is_empty()
+        );
+        if parent.components().count() < 2 || !empty || leaf_at(source, parent).is_some() {
+            break;
+        }
+        tree.remove_at(parent);
+    }
+}
+
+/// Uploads a file, recreates a link
+fn leaf_action(leaf: &ChecksumElement, path: &[&Name]) -> Action {
+    let path = joined(path);
+    match leaf {
+        ChecksumElement::Symlink(_) => Action::Link(path),
+        _ => Action::Put(path),
+    }
+}
+
+fn joined(path: &[&Name]) -> PathBuf {
+    path.iter().map(|name| &***name).collect()
+}
+
+/// Removes the entry under `key`, falling back to a case-insensitive match when enabled
+fn take_entry(dir: &mut Entries, key: &str, options: &ReconcileOptions) -> Option<ChecksumElement> {
+    if let Some(element) = dir.remove(key) {
+        return Some(element);
+    }
+    if !options.case_insensitive {
+        return None;
+    }
+    let lowercase_key = key.to_lowercase();
+    let existing_key = dir
+        .keys()
+        .find(|existing| existing.to_lowercase() == lowercase_key)?
+        .clone();
+    dir.remove(&existing_key)
+}
+
+/// Errors on names of the same directory that differ only in letter case,
+/// a case-insensitive destination keeps one of them
+fn check_case_collisions(
+    tree: &ChecksumTree,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let mut stack: Vec<(PathBuf, &ChecksumElement)> = tree
+        .deref()
+        .iter()
+        .map(|root| (PathBuf::new(), root))
+        .collect();
+    while let Some((path, element)) = stack.pop() {
+        let ChecksumElement::Directory(entries) = element else {
+            continue;
+        };
+        let mut names: HashMap<String, &Name> = HashMap::new();
+        for (name, element) in entries {
+            if let Some(other) = names.insert(name.to_lowercase(), name) {
+                return Err(format!(
+                    "{:?} and {:?} differ only in letter case, a case-insensitive destination can't hold both",
+                    path.join(&**other),
+                    path.join(&**name)
+                )
+                .into());
+            }
+            stack.push((path.join(&**name), element));
+        }
+    }
+    Ok(())
+}
+
+/// The element at `path` or at a path differing only in letter case, along
+/// with the path it's at
+fn get_ignoring_case<'a>(
+    tree: &'a ChecksumTree,
+    path: &Path,
+) -> Option<(PathBuf, &'a ChecksumElement)> {
+    let mut found = PathBuf::new();
+    let mut current = tree.deref().as_ref()?;
+    for component in path {
+        let ChecksumElement::Directory(entries) = current else {
+            return None;
+        };
+        let name = component.to_string_lossy();
+        let lowercase = name.to_lowercase();
+        let (existing, element) = entries.get_key_value(name.as_ref()).or_else(|| {
+            entries
+                .iter()
+                .find(|(existing, _)| existing.to_lowercase() == lowercase)
+        })?;
+        found.push(&**existing);
+        current = element;
+    }
+    Some((found, current))
+}
+
+/// A file uploaded under a name that differs from its previous one only in
+/// letter case, see `case_renames`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaseRename {
+    /// The path the file has on the remote
+    pub from: PathBuf,
+    /// Renaming is all there is to do
+    pub unchanged: bool,
+}
+
+/// For case-insensitive destinations, where uploading `Logo.png` overwrites
+/// `logo.png` but keeps its name, the uploads to rename on the remote before
+/// uploading anything
+pub fn case_renames(
+    previous: &ChecksumTree,
+    next: &ChecksumTree,
+    actions: &[Action],
+) -> HashMap<PathBuf, CaseRename> {
+    let mut renames = HashMap::new();
+    for action in actions {
+        let Action::Put(path) = action else {
+            continue;
+        };
+        let Some(ChecksumElement::File(entry)) = next.get_at(path) else {
+            continue;
+        };
+        if let Some((from, ChecksumElement::File(previous_entry))) =
+            get_ignoring_case(previous, path)
+        {
+            if from != *path {
+                renames.insert(
+                    path.clone(),
+                    CaseRename {
+                        from,
+                        unchanged: previous_entry.checksum == entry.checksum,
+                    },
+                );
+            }
+        }
     }
+    renames
+}
+
+/// Errors on a path that changed its kind unless the policy resolves collisions
+fn check_collision(
+    path: &[&Name],
+    existing: &str,
+    options: &ReconcileOptions,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if options.collision == CollisionPolicy::Fail {
+        return Err(format!(
+            "{:?} is a {existing} on the remote but not locally (see --on-collision)",
+            joined(path)
+        )
+        .into());
+    }
+    Ok(())
 }
 
 /// Panics if previous version is newer
@@ -123,7 +738,11 @@ fn check_version(prev: &str, next: &str) -> Result<(), Box<dyn Error + Send + Sy
 mod tests {
 
     use super::*;
-    use std::collections::HashMap;
+    use proptest::prelude::*;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::Path,
+    };
 
     #[test]
     fn empty() {
@@ -134,6 +753,151 @@ mod tests {
         assert!(diff.is_empty());
     }
 
+    #[test]
+    fn algorithm_changes_are_found() {
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let prev: ChecksumTree = HashMap::from([
+            ("./same.txt".to_string(), sha256.to_string()),
+            ("./switched.txt".to_string(), sha256.to_string()),
+            ("./big.iso".to_string(), "s9_c1_m2".to_string()),
+        ])
+        .into();
+        let next: ChecksumTree = HashMap::from([
+            ("./same.txt".to_string(), sha256.to_string()),
+            ("./switched.txt".to_string(), format!("blake3:{sha256}")),
+            ("./big.iso".to_string(), format!("xxh3:{sha256}")),
+            ("./new.txt".to_string(), format!("blake3:{sha256}")),
+        ])
+        .into();
+
+        assert_eq!(
+            Reconciler::algorithm_changes(&prev, &next),
+            vec![
+                ("./big.iso".into(), HashAlgorithm::Metadata),
+                ("./switched.txt".into(), HashAlgorithm::Sha256),
+            ]
+        );
+    }
+
+    #[test]
+    fn touched_files_are_taken_over() {
+        let file = |checksum: &str, quick_hash: &str| {
+            ChecksumElement::File(FileEntry {
+                checksum: checksum.to_string(),
+                quick_hash: Some(quick_hash.to_string()),
+                synced: Some(1),
+                ..Default::default()
+            })
+        };
+        let mut prev = ChecksumTree::default();
+        let mut next = ChecksumTree::default();
+        for (path, before, after) in [
+            (
+                "./touched.iso",
+                file("s9_c1_m1", "q"),
+                file("s9_c1_m2", "q"),
+            ),
+            (
+                "./changed.iso",
+                file("s9_c1_m1", "q"),
+                file("s9_c1_m2", "r"),
+            ),
+            ("./grown.iso", file("s9_c1_m1", "q"), file("s10_c1_m2", "q")),
+        ] {
+            prev.insert_at(Path::new(path), before);
+            next.insert_at(Path::new(path), after);
+        }
+
+        assert_eq!(Reconciler::take_over_touched(&mut prev, &next), 1);
+        assert!(matches!(
+            prev.get_at(Path::new("./touched.iso")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "s9_c1_m2" && entry.synced == Some(1)
+        ));
+        let mut actions = Reconciler::reconcile(prev, &next).unwrap();
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Put("./changed.iso".into()),
+                Action::Put("./grown.iso".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn files_with_other_metadata_only_get_it_set() {
+        let file = |checksum: &str, mtime: i64, mode: u32| {
+            ChecksumElement::File(FileEntry {
+                checksum: checksum.to_string(),
+                mtime: Some(mtime),
+                mode: Some(mode),
+                ..Default::default()
+            })
+        };
+        let mut prev = ChecksumTree::default();
+        let mut next = ChecksumTree::default();
+        for (path, before, after) in [
+            ("./touched.txt", file("a", 1, 0o644), file("a", 2, 0o644)),
+            ("./chmoded.txt", file("a", 1, 0o644), file("a", 1, 0o755)),
+            ("./changed.txt", file("a", 1, 0o644), file("b", 2, 0o755)),
+            ("./same.txt", file("a", 1, 0o644), file("a", 1, 0o644)),
+            // --preserve-permissions makes them part of the checksum
+            (
+                "./owned.txt",
+                file("a_p644_o1:1", 1, 0o644),
+                file("a_p644_o2:2", 1, 0o644),
+            ),
+        ] {
+            prev.insert_at(Path::new(path), before);
+            next.insert_at(Path::new(path), after);
+        }
+        // unknown metadata of files synced by older versions doesn't count
+        prev.insert_at(
+            Path::new("./old.txt"),
+            ChecksumElement::File("a".to_string().into()),
+        );
+        next.insert_at(Path::new("./old.txt"), file("a", 2, 0o755));
+
+        let plan = Reconciler::reconcile(prev.clone(), &next).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                Action::Put("./changed.txt".into()),
+                Action::Put("./owned.txt".into()),
+            ]
+        );
+
+        let options = ReconcileOptions {
+            sync_mtime: true,
+            ..Default::default()
+        };
+        let plan = Reconciler::reconcile_with_options(prev.clone(), &next, &options).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                Action::Put("./changed.txt".into()),
+                Action::Put("./owned.txt".into()),
+                Action::SetMeta("./touched.txt".into()),
+            ]
+        );
+
+        let options = ReconcileOptions {
+            sync_mtime: true,
+            sync_mode: true,
+            ..Default::default()
+        };
+        let plan = Reconciler::reconcile_with_options(prev, &next, &options).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                Action::Put("./changed.txt".into()),
+                Action::SetMeta("./chmoded.txt".into()),
+                Action::SetMeta("./owned.txt".into()),
+                Action::SetMeta("./touched.txt".into()),
+            ]
+        );
+    }
+
     #[test]
     fn insert_into_root() {
         let prev = ChecksumTree::default();
@@ -273,10 +1037,217 @@ mod tests {
 
         let diff = Reconciler::reconcile(prev, &next).unwrap();
 
-        assert!(diff.len() == 1);
-        diff.into_iter()
-            .zip(vec![Action::Remove("./direktory/file.txt".into())])
-            .for_each(|(a, b)| assert_eq!(a, b));
+        assert_eq!(
+            diff,
+            vec![
+                Action::Remove("./direktory/file.txt".into()),
+                Action::Rmdir("./direktory".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_remove_is_retried_when_kept_in_tree() {
+        let mut prev = HashMap::new();
+        prev.insert("./direktory/file.txt".to_string(), "sha256hash".to_string());
+        let prev: ChecksumTree = prev.into();
+        let local = ChecksumTree::default();
+
+        let diff = Reconciler::reconcile(prev.clone(), &local).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                Action::Remove("./direktory/file.txt".into()),
+                Action::Rmdir("./direktory".into())
+            ]
+        );
+
+        // the remove failed, so the uploaded tree keeps the previous entry
+        let path = Path::new("./direktory/file.txt");
+        let mut uploaded = local.clone();
+        uploaded.insert_at(path, prev.get_at(path).unwrap().clone());
+
+        let diff = Reconciler::reconcile(uploaded, &local).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                Action::Remove("./direktory/file.txt".into()),
+                Action::Rmdir("./direktory".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn directory_replaced_by_file() {
+        let mut prev = HashMap::new();
+        prev.insert("./name/file.txt".to_string(), "sha256hash".to_string());
+        let mut next = HashMap::new();
+        next.insert("./name".to_string(), "sha256hash".to_string());
+        let (prev, next): (ChecksumTree, ChecksumTree) = (prev.into(), next.into());
+
+        assert!(Reconciler::reconcile(prev.clone(), &next).is_err());
+
+        let options = ReconcileOptions {
+            collision: CollisionPolicy::Replace,
+            ..Default::default()
+        };
+        let diff = Reconciler::reconcile_with_options(prev, &next, &options).unwrap();
+        assert_eq!(diff, vec![Action::Put("./name".into())]);
+    }
+
+    #[test]
+    fn file_replaced_by_directory() {
+        let mut prev = HashMap::new();
+        prev.insert("./name".to_string(), "sha256hash".to_string());
+        let mut next = HashMap::new();
+        next.insert("./name/file.txt".to_string(), "sha256hash".to_string());
+        let (prev, next): (ChecksumTree, ChecksumTree) = (prev.into(), next.into());
+
+        assert!(Reconciler::reconcile(prev.clone(), &next).is_err());
+
+        let options = ReconcileOptions {
+            collision: CollisionPolicy::Rename,
+            ..Default::default()
+        };
+        let diff = Reconciler::reconcile_with_options(prev, &next, &options).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                Action::Mkdir("./name".into()),
+                Action::Put("./name/file.txt".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_directories_are_created_and_removed() {
+        let mut with_empty = ChecksumTree::default();
+        with_empty.insert_at(Path::new("./a/empty"), ChecksumElement::default());
+        let empty = ChecksumTree::default();
+
+        let diff = Reconciler::reconcile(empty.clone(), &with_empty).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                Action::Mkdir("./a".into()),
+                Action::Mkdir("./a/empty".into())
+            ]
+        );
+        assert!(Reconciler::reconcile(with_empty.clone(), &with_empty)
+            .unwrap()
+            .is_empty());
+        let diff = Reconciler::reconcile(with_empty.clone(), &empty).unwrap();
+        assert_eq!(
+            diff,
+            vec![
+                Action::Rmdir("./a/empty".into()),
+                Action::Rmdir("./a".into())
+            ]
+        );
+
+        // filled, the directory stays
+        let mut filled = with_empty.clone();
+        filled.insert_at(
+            Path::new("./a/empty/file.txt"),
+            ChecksumElement::File("sha256hash".to_string().into()),
+        );
+        let diff = Reconciler::reconcile(with_empty, &filled).unwrap();
+        assert_eq!(diff, vec![Action::Put("./a/empty/file.txt".into())]);
+    }
+
+    #[test]
+    fn actions_are_sorted_by_kind_and_path() {
+        let prev: ChecksumTree = HashMap::from([
+            ("./b.txt".to_string(), "old".to_string()),
+            ("./gone/z.txt".to_string(), "old".to_string()),
+            ("./gone/a.txt".to_string(), "old".to_string()),
+        ])
+        .into();
+        let next: ChecksumTree = HashMap::from([
+            ("./b.txt".to_string(), "new".to_string()),
+            ("./z/deep/a.txt".to_string(), "new".to_string()),
+            ("./a.txt".to_string(), "new".to_string()),
+            ("./a/b.txt".to_string(), "new".to_string()),
+        ])
+        .into();
+
+        let plan = Reconciler::reconcile(prev, &next).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                Action::Mkdir("./a".into()),
+                Action::Mkdir("./z".into()),
+                Action::Mkdir("./z/deep".into()),
+                Action::Put("./a/b.txt".into()),
+                Action::Put("./a.txt".into()),
+                Action::Put("./b.txt".into()),
+                Action::Put("./z/deep/a.txt".into()),
+                Action::Remove("./gone/a.txt".into()),
+                Action::Remove("./gone/z.txt".into()),
+                Action::Rmdir("./gone".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn emptied_directories_are_removed_children_first() {
+        let prev: ChecksumTree = HashMap::from([
+            ("./a/b/c/file.txt".to_string(), "sha256hash".to_string()),
+            ("./a/b/other.txt".to_string(), "sha256hash".to_string()),
+            ("./kept/file.txt".to_string(), "sha256hash".to_string()),
+            ("./kept/gone/file.txt".to_string(), "sha256hash".to_string()),
+        ])
+        .into();
+        let mut next = ChecksumTree::default();
+        next.insert_at(Path::new("./kept"), ChecksumElement::default());
+
+        let diff = Reconciler::reconcile(prev, &next).unwrap();
+        let rmdirs: Vec<_> = diff
+            .iter()
+            .filter(|action| matches!(action, Action::Rmdir(_)))
+            .collect();
+        assert_eq!(
+            rmdirs,
+            vec![
+                &Action::Rmdir("./kept/gone".into()),
+                &Action::Rmdir("./a/b/c".into()),
+                &Action::Rmdir("./a/b".into()),
+                &Action::Rmdir("./a".into()),
+            ]
+        );
+        assert_eq!(diff.len(), 8);
+        assert!(diff[..4]
+            .iter()
+            .all(|action| matches!(action, Action::Remove(_))));
+    }
+
+    #[test]
+    fn links_are_compared_by_target() {
+        let link = |target: &str| {
+            let mut tree = ChecksumTree::default();
+            tree.insert_at(
+                Path::new("./latest"),
+                ChecksumElement::Symlink(target.to_string()),
+            );
+            tree
+        };
+        let empty = ChecksumTree::default();
+
+        let diff = Reconciler::reconcile(empty.clone(), &link("v1")).unwrap();
+        assert_eq!(diff, vec![Action::Link("./latest".into())]);
+        assert!(Reconciler::reconcile(link("v1"), &link("v1"))
+            .unwrap()
+            .is_empty());
+        let diff = Reconciler::reconcile(link("v1"), &link("v2")).unwrap();
+        assert_eq!(diff, vec![Action::Link("./latest".into())]);
+        let diff = Reconciler::reconcile(link("v1"), &empty).unwrap();
+        assert_eq!(diff, vec![Action::Remove("./latest".into())]);
+
+        // a file replaced by a link needs no collision policy
+        let mut file = HashMap::new();
+        file.insert("./latest".to_string(), "sha256hash".to_string());
+        let diff = Reconciler::reconcile(file.into(), &link("v1")).unwrap();
+        assert_eq!(diff, vec![Action::Link("./latest".into())]);
     }
 
     #[test]
@@ -308,13 +1279,540 @@ mod tests {
 
         let diff = Reconciler::reconcile(prev, &next).unwrap();
 
-        assert!(diff.len() == 2);
-        diff.into_iter()
-            .zip(vec![
+        assert_eq!(
+            diff,
+            vec![
                 Action::Put("./direktory2/nested/file2.txt".into()),
                 Action::Remove("./direktory2/other/file3.txt".into()),
-            ])
-            .for_each(|(a, b)| assert_eq!(a, b));
+                Action::Rmdir("./direktory2/other".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn case_insensitive_matches_renamed_case() {
+        let mut prev = HashMap::new();
+        prev.insert("./Photos/IMG_1.JPG".to_string(), "same".to_string());
+        prev.insert("./Photos/IMG_2.JPG".to_string(), "old".to_string());
+        let prev: ChecksumTree = prev.into();
+        let mut next = HashMap::new();
+        next.insert("./photos/img_1.jpg".to_string(), "same".to_string());
+        next.insert("./photos/img_2.jpg".to_string(), "new".to_string());
+        let next: ChecksumTree = next.into();
+        let options = ReconcileOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+
+        let diff = Reconciler::reconcile_with_options(prev.clone(), &next, &options).unwrap();
+
+        // nothing is removed, the remote files are renamed before uploading
+        assert_eq!(
+            diff,
+            vec![
+                Action::Put("./photos/img_1.jpg".into()),
+                Action::Put("./photos/img_2.jpg".into())
+            ]
+        );
+        let renames = case_renames(&prev, &next, &diff);
+        assert_eq!(
+            renames[Path::new("./photos/img_1.jpg")],
+            CaseRename {
+                from: "./Photos/IMG_1.JPG".into(),
+                unchanged: true
+            }
+        );
+        assert!(!renames[Path::new("./photos/img_2.jpg")].unchanged);
+
+        // a case-insensitive destination would keep only one of these
+        let mut both = HashMap::new();
+        both.insert("./logo.png".to_string(), "a".to_string());
+        both.insert("./Logo.png".to_string(), "b".to_string());
+        assert!(Reconciler::reconcile_with_options(prev, &both.into(), &options).is_err());
+    }
+
+    #[test]
+    fn case_insensitive_keeps_directories_renamed_in_case() {
+        let mut prev = HashMap::new();
+        prev.insert("./Photos/old.jpg".to_string(), "old".to_string());
+        let mut next = HashMap::new();
+        next.insert("./photos/new.jpg".to_string(), "new".to_string());
+        let options = ReconcileOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+
+        let diff = Reconciler::reconcile_with_options(prev.into(), &next.into(), &options).unwrap();
+
+        // removing the directory would take the new file with it
+        assert_eq!(
+            diff,
+            vec![
+                Action::Put("./photos/new.jpg".into()),
+                Action::Remove("./photos/old.jpg".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn case_sensitive_by_default() {
+        let mut prev = HashMap::new();
+        prev.insert("./File.txt".to_string(), "same".to_string());
+        let prev: ChecksumTree = prev.into();
+        let mut next = HashMap::new();
+        next.insert("./file.txt".to_string(), "same".to_string());
+        let next: ChecksumTree = next.into();
+
+        let diff = Reconciler::reconcile(prev, &next).unwrap();
+
+        assert_eq!(
+            diff,
+            vec![
+                Action::Put("./file.txt".into()),
+                Action::Remove("./File.txt".into())
+            ]
+        );
+    }
+
+    /// Files and directories (`None`) of the tree
+    fn entries(tree: &ChecksumTree) -> BTreeMap<PathBuf, Option<String>> {
+        let mut entries = BTreeMap::new();
+        let mut stack = vec![(PathBuf::new(), tree.deref().as_ref().unwrap())];
+        while let Some((path, element)) = stack.pop() {
+            match element {
+                ChecksumElement::Directory(dir) => {
+                    stack.extend(
+                        dir.iter()
+                            .map(|(name, element)| (path.join(&**name), element)),
+                    );
+                    entries.insert(path, None);
+                }
+                ChecksumElement::File(entry) => {
+                    entries.insert(path, Some(entry.checksum.clone()));
+                }
+                ChecksumElement::Symlink(_) => unreachable!("generated trees have no links"),
+            }
+        }
+        entries.remove(Path::new(""));
+        entries
+    }
+
+    fn files(tree: &ChecksumTree) -> BTreeMap<PathBuf, String> {
+        entries(tree)
+            .into_iter()
+            .filter_map(|(path, checksum)| Some((path, checksum?)))
+            .collect()
+    }
+
+    /// Executes the actions on a model of the remote holding `prev`, checking
+    /// each one makes sense in the state the ones before it left behind
+    fn apply(
+        prev: &ChecksumTree,
+        next: &ChecksumTree,
+        actions: &[Action],
+    ) -> Result<BTreeMap<PathBuf, Option<String>>, TestCaseError> {
+        let mut remote = entries(prev);
+        remote.insert(".".into(), None);
+        let next = files(next);
+        for action in actions {
+            let path = action.path();
+            match action {
+                Action::Mkdir(_) | Action::Put(_) | Action::Link(_) => {
+                    let parent = path.parent().unwrap();
+                    prop_assert_eq!(
+                        remote.get(parent),
+                        Some(&None),
+                        "{:?} before its parent exists",
+                        action
+                    );
+                    // whatever is in the way is cleared
+                    remote.retain(|existing, _| !existing.starts_with(path));
+                    let checksum = match action {
+                        Action::Put(_) => Some(next[path].clone()),
+                        _ => None,
+                    };
+                    remote.insert(path.to_path_buf(), checksum);
+                }
+                Action::Remove(_) => {
+                    prop_assert!(
+                        matches!(remote.remove(path), Some(Some(_))),
+                        "{:?} of a file that isn't there",
+                        action
+                    );
+                }
+                Action::Rmdir(_) => {
+                    prop_assert!(
+                        !remote
+                            .keys()
+                            .any(|existing| existing != path && existing.starts_with(path)),
+                        "{:?} of a directory that isn't empty",
+                        action
+                    );
+                    prop_assert_eq!(
+                        remote.remove(path),
+                        Some(None),
+                        "{:?} of a directory that isn't there",
+                        action
+                    );
+                }
+                Action::SetMeta(_) => {
+                    prop_assert!(
+                        matches!(remote.get(path), Some(Some(_))),
+                        "{:?} of a file that isn't there",
+                        action
+                    );
+                }
+                Action::Get(_)
+                | Action::LocalRemove(_)
+                | Action::LocalMkdir(_)
+                | Action::Conflict(_) => {
+                    prop_assert!(false, "{:?} when pushing", action);
+                }
+            }
+        }
+        Ok(remote)
+    }
+
+    /// Whether `remote` has the files of `next` and its empty directories
+    fn matches_next(remote: BTreeMap<PathBuf, Option<String>>, next: &ChecksumTree) -> bool {
+        let files: BTreeMap<_, _> = remote
+            .iter()
+            .filter_map(|(path, checksum)| Some((path.clone(), checksum.clone()?)))
+            .collect();
+        files == self::files(next)
+            && entries(next)
+                .iter()
+                .filter(|(_, checksum)| checksum.is_none())
+                .all(|(path, _)| remote.get(path) == Some(&None))
+    }
+
+    /// Paths below "." made of few names, so random trees share paths and
+    /// turn files into directories and back
+    fn any_path() -> impl Strategy<Value = PathBuf> {
+        prop::collection::vec(prop::sample::select(vec!["a", "b", "c"]), 1..4)
+            .prop_map(|names| std::iter::once(".").chain(names).collect())
+    }
+
+    fn any_checksum() -> impl Strategy<Value = String> {
+        prop::sample::select(vec!["1", "2", "3"]).prop_map(String::from)
+    }
+
+    fn any_tree() -> impl Strategy<Value = ChecksumTree> {
+        prop::collection::vec((any_path(), any_checksum()), 0..12)
+            .prop_map(|files| files.into_iter().collect())
+    }
+
+    #[derive(Clone, Debug)]
+    enum Edit {
+        Put(PathBuf, String),
+        Mkdir(PathBuf),
+        Remove(prop::sample::Index),
+    }
+
+    /// `prev` and a tree made from it with a few puts and removals
+    fn any_change() -> impl Strategy<Value = (ChecksumTree, ChecksumTree)> {
+        let edit = prop_oneof![
+            (any_path(), any_checksum()).prop_map(|(path, checksum)| Edit::Put(path, checksum)),
+            any_path().prop_map(Edit::Mkdir),
+            any::<prop::sample::Index>().prop_map(Edit::Remove),
+        ];
+        (any_tree(), prop::collection::vec(edit, 0..8)).prop_map(|(prev, edits)| {
+            let mut next = prev.clone();
+            for edit in edits {
+                match edit {
+                    Edit::Put(path, checksum) => {
+                        next.insert_at(&path, ChecksumElement::File(checksum.into()))
+                    }
+                    Edit::Mkdir(path) => next.insert_at(&path, ChecksumElement::default()),
+                    Edit::Remove(index) => {
+                        let files: Vec<_> = files(&next).into_keys().collect();
+                        if !files.is_empty() {
+                            next.remove_at(&files[index.index(files.len())]);
+                        }
+                    }
+                }
+            }
+            (prev, next)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn actions_turn_prev_into_next((prev, next) in any_change()) {
+            let options = ReconcileOptions {
+                collision: CollisionPolicy::Replace,
+                ..Default::default()
+            };
+            let actions = Reconciler::reconcile_with_options(prev.clone(), &next, &options).unwrap();
+            prop_assert!(matches_next(apply(&prev, &next, &actions)?, &next));
+        }
+
+        #[test]
+        fn unrelated_trees_reconcile_too(prev in any_tree(), next in any_tree()) {
+            let options = ReconcileOptions {
+                collision: CollisionPolicy::Replace,
+                ..Default::default()
+            };
+            let actions = Reconciler::reconcile_with_options(prev.clone(), &next, &options).unwrap();
+            prop_assert!(matches_next(apply(&prev, &next, &actions)?, &next));
+        }
+
+        #[test]
+        fn fails_exactly_on_type_changes((prev, next) in any_change()) {
+            let existing = entries(&prev);
+            let changes_type = entries(&next).iter().any(|(path, checksum)| {
+                matches!(
+                    (existing.get(path), checksum),
+                    (Some(None), Some(_)) | (Some(Some(_)), None)
+                ) || path
+                        .ancestors()
+                        .skip(1)
+                        .any(|parent| matches!(existing.get(parent), Some(Some(_))))
+            });
+            prop_assert_eq!(Reconciler::reconcile(prev, &next).is_err(), changes_type);
+        }
+
+        #[test]
+        fn nothing_created_is_removed((prev, next) in any_change()) {
+            let options = ReconcileOptions {
+                collision: CollisionPolicy::Replace,
+                ..Default::default()
+            };
+            let actions = Reconciler::reconcile_with_options(prev, &next, &options).unwrap();
+            // so removals may run before or after everything else
+            for removed in actions
+                .iter()
+                .filter(|action| matches!(action, Action::Remove(_) | Action::Rmdir(_)))
+            {
+                let recreated = actions.iter().any(|action| {
+                    matches!(action, Action::Mkdir(_) | Action::Put(_) | Action::Link(_))
+                        && action.path().starts_with(removed.path())
+                });
+                prop_assert!(!recreated, "{:?} is created again", removed);
+            }
+        }
+
+        #[test]
+        fn unchanged_trees_need_no_actions(tree in any_tree()) {
+            prop_assert_eq!(Reconciler::reconcile(tree.clone(), &tree).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn pull_makes_the_local_tree_match_the_remote() {
+        let local: ChecksumTree = HashMap::from([
+            ("./same.txt".to_string(), "same".to_string()),
+            ("./changed.txt".to_string(), "old".to_string()),
+            ("./local/only.txt".to_string(), "only".to_string()),
+        ])
+        .into();
+        let remote: ChecksumTree = HashMap::from([
+            ("./same.txt".to_string(), "same".to_string()),
+            ("./changed.txt".to_string(), "new".to_string()),
+            ("./dir/new.txt".to_string(), "new".to_string()),
+        ])
+        .into();
+
+        let actions = Reconciler::pull(local, &remote, &ReconcileOptions::default()).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action::LocalMkdir("./dir".into()),
+                Action::Get("./changed.txt".into()),
+                Action::Get("./dir/new.txt".into()),
+                Action::LocalRemove("./local/only.txt".into()),
+                Action::LocalRemove("./local".into()),
+            ]
+        );
+        // a remote written by a newer version may hold what this one can't restore
+        let newer = ChecksumTree::with_version("99.0.0");
+        assert!(Reconciler::pull(ChecksumTree::default(), &newer, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn pull_into_an_empty_tree_restores_a_subtree() {
+        let remote: ChecksumTree = HashMap::from([
+            ("./a.txt".to_string(), "a".to_string()),
+            ("./dir/sub/b.txt".to_string(), "b".to_string()),
+            ("./dir/c.txt".to_string(), "c".to_string()),
+        ])
+        .into();
+        let options = ReconcileOptions {
+            filter: PathFilter::default()
+                .with_only(&["dir/sub".into()])
+                .unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Reconciler::pull(ChecksumTree::default(), &remote, &options).unwrap(),
+            vec![
+                Action::LocalMkdir("./dir".into()),
+                Action::LocalMkdir("./dir/sub".into()),
+                Action::Get("./dir/sub/b.txt".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_uploads_downloads_and_reports_conflicts() {
+        let tree = |entries: &[(&str, &str)]| -> ChecksumTree {
+            entries
+                .iter()
+                .map(|(path, checksum)| (path.to_string(), checksum.to_string()))
+                .collect::<HashMap<_, _>>()
+                .into()
+        };
+        let base = tree(&[
+            ("./same.txt", "same"),
+            ("./local.txt", "old"),
+            ("./remote.txt", "old"),
+            ("./both.txt", "old"),
+            ("./gone/local.txt", "old"),
+            ("./gone/remote.txt", "old"),
+        ]);
+        let local = tree(&[
+            ("./same.txt", "same"),
+            ("./local.txt", "new"),
+            ("./remote.txt", "old"),
+            ("./both.txt", "local"),
+            ("./gone/remote.txt", "old"),
+            ("./added/local.txt", "new"),
+        ]);
+        let remote = tree(&[
+            ("./same.txt", "same"),
+            ("./local.txt", "old"),
+            ("./remote.txt", "new"),
+            ("./both.txt", "remote"),
+            ("./gone/local.txt", "old"),
+            ("./added/remote.txt", "new"),
+        ]);
+
+        let merge = Reconciler::merge(&base, &local, &remote, &Default::default(), |_| {
+            Resolution::Skip
+        })
+        .unwrap();
+        let mut actions = merge.actions;
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Put("./added/local.txt".into()),
+                Action::Put("./local.txt".into()),
+                Action::Remove("./gone/local.txt".into()),
+                Action::Rmdir("./gone".into()),
+                Action::Get("./added/remote.txt".into()),
+                Action::Get("./remote.txt".into()),
+                Action::LocalRemove("./gone".into()),
+                Action::LocalRemove("./gone/remote.txt".into()),
+                Action::Conflict("./both.txt".into()),
+            ]
+        );
+        // the remote keeps its side of the conflict
+        assert!(matches!(
+            merge.remote.get_at(Path::new("./both.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "remote"
+        ));
+        assert!(!merge.remote.contains(Path::new("./gone")));
+
+        // once both sides agree, nothing is left to do
+        let mut both = remote.clone();
+        both.insert_at(
+            Path::new("./local.txt"),
+            ChecksumElement::File("new".to_string().into()),
+        );
+        let merge = Reconciler::merge(
+            &ChecksumTree::default(),
+            &both,
+            &remote,
+            &Default::default(),
+            |_| Resolution::Skip,
+        )
+        .unwrap();
+        assert_eq!(merge.actions, vec![Action::Conflict("./local.txt".into())]);
+        let merge = Reconciler::merge(&both, &both, &both, &Default::default(), |_| {
+            Resolution::Skip
+        })
+        .unwrap();
+        assert!(merge.actions.is_empty());
+    }
+
+    #[test]
+    fn merge_settles_conflicts_with_the_resolver() {
+        let tree = |entries: &[(&str, &str)]| -> ChecksumTree {
+            entries
+                .iter()
+                .map(|(path, checksum)| (path.to_string(), checksum.to_string()))
+                .collect::<HashMap<_, _>>()
+                .into()
+        };
+        let base = tree(&[
+            ("./local.txt", "old"),
+            ("./remote.txt", "old"),
+            ("./both.txt", "old"),
+        ]);
+        let local = tree(&[
+            ("./local.txt", "l"),
+            ("./remote.txt", "l"),
+            ("./both.txt", "l"),
+        ]);
+        let remote = tree(&[
+            ("./local.txt", "r"),
+            ("./remote.txt", "r"),
+            ("./both.txt", "r"),
+        ]);
+
+        let merge =
+            Reconciler::merge(
+                &base,
+                &local,
+                &remote,
+                &Default::default(),
+                |conflict| match conflict.path.to_str().unwrap() {
+                    "./local.txt" => Resolution::Local,
+                    "./remote.txt" => Resolution::Remote,
+                    _ => Resolution::KeepBoth("./both.copy.txt".into()),
+                },
+            )
+            .unwrap();
+        let mut actions = merge.actions;
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Put("./both.copy.txt".into()),
+                Action::Put("./local.txt".into()),
+                Action::Get("./both.txt".into()),
+                Action::Get("./remote.txt".into()),
+            ]
+        );
+        assert_eq!(merge.resolved.len(), 3);
+        assert!(matches!(
+            merge.remote.get_at(Path::new("./both.copy.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "l"
+        ));
+    }
+
+    #[test]
+    fn merge_leaves_everything_below_a_conflict_alone() {
+        let base: ChecksumTree = HashMap::from([("./name".to_string(), "old".to_string())]).into();
+        let local: ChecksumTree =
+            HashMap::from([("./name/new.txt".to_string(), "new".to_string())]).into();
+        let remote: ChecksumTree =
+            HashMap::from([("./name".to_string(), "changed".to_string())]).into();
+
+        let options = ReconcileOptions {
+            collision: CollisionPolicy::Replace,
+            ..Default::default()
+        };
+        let merge =
+            Reconciler::merge(&base, &local, &remote, &options, |_| Resolution::Skip).unwrap();
+        assert_eq!(
+            merge.actions,
+            vec![
+                Action::Conflict("./name".into()),
+                Action::Conflict("./name/new.txt".into())
+            ]
+        );
     }
 
     #[test]
@@ -0,0 +1,82 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    reconciler::Action,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+/// Finds a file of the previous tree with the same checksum for every upload,
+/// so it can be copied on the remote instead. Files that are uploaded
+/// themselves aren't used as sources, they may be overwritten before the copy
+/// runs, files that are removed are fine since removals come after uploads.
+pub fn copy_sources(
+    previous: &ChecksumTree,
+    next: &ChecksumTree,
+    actions: &[Action],
+) -> HashMap<PathBuf, PathBuf> {
+    let uploaded: HashSet<_> = actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Put(path) => Some(path),
+            _ => None,
+        })
+        .collect();
+
+    let mut by_checksum: HashMap<&str, PathBuf> = HashMap::new();
+    for (path, entry) in previous.iter().filter(|(path, _)| !uploaded.contains(path)) {
+        // the smallest path wins, so the same one is picked every run
+        let source = by_checksum
+            .entry(entry.checksum.as_str())
+            .or_insert_with(|| path.clone());
+        if path < *source {
+            *source = path;
+        }
+    }
+
+    uploaded
+        .into_iter()
+        .filter_map(|path| match next.get_at(path) {
+            Some(ChecksumElement::File(entry)) => Some((
+                path.clone(),
+                by_checksum.get(entry.checksum.as_str())?.clone(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksum_tree::fixtures::tree, reconciler::Reconciler};
+
+    #[test]
+    fn moved_and_duplicated_files_are_copied() {
+        let previous = tree(&[
+            ("./old/video.mp4", "video"),
+            ("./logo.png", "logo"),
+            ("./index.html", "index-1"),
+        ]);
+        let next = tree(&[
+            ("./new/video.mp4", "video"),
+            ("./logo.png", "logo"),
+            ("./assets/logo.png", "logo"),
+            ("./index.html", "index-2"),
+            ("./about.html", "index-1"),
+        ]);
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+
+        let sources = copy_sources(&previous, &next, &actions);
+        assert_eq!(
+            sources,
+            HashMap::from([
+                ("./new/video.mp4".into(), "./old/video.mp4".into()),
+                ("./assets/logo.png".into(), "./logo.png".into()),
+            ])
+        );
+        // the old video is still removed, which completes the move
+        assert!(actions.contains(&Action::Remove("./old/video.mp4".into())));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    logging,
+    plan::SyncPlan,
+    reconciler::{sort_actions, Action},
+    t,
+    table::Table,
+};
+use console::style;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+pub const PROGRESS_BAR_CHARS: &str = "▰▰▱";
+
+/// How each executed action went, for `--table`
+pub type Results = Arc<Mutex<HashMap<Action, (Result<(), String>, Duration)>>>;
+
+pub async fn record(
+    results: &Results,
+    action: &Action,
+    status: Result<(), String>,
+    duration: Duration,
+) {
+    let seconds = duration.as_secs_f64();
+    match &status {
+        Ok(()) => tracing::info!(target: logging::RUN, seconds, "done"),
+        Err(error) => tracing::warn!(target: logging::RUN, seconds, %error, "failed"),
+    }
+    results
+        .lock()
+        .await
+        .insert(action.clone(), (status, duration));
+}
+
+/// The span the events of an action are logged in, like retries of the
+/// transport
+pub fn action_span(action: &Action) -> tracing::Span {
+    tracing::info_span!("action", kind = action.kind(), path = %action.path().display())
+}
+
+/// Kind, path and local size of an action, as shown in the tables
+fn action_cells(action: &Action) -> [String; 3] {
+    let (kind, path) = match action {
+        Action::Mkdir(path) => (style("mkdir").blue(), path),
+        Action::Put(path) => (style("put").green(), path),
+        Action::Link(path) => (style("link").cyan(), path),
+        Action::SetMeta(path) => (style("set-meta").cyan(), path),
+        Action::Remove(path) => (style("remove").red(), path),
+        Action::Rmdir(path) => (style("rmdir").red(), path),
+        Action::Get(path) => (style("get").green(), path),
+        Action::LocalRemove(path) => (style("remove").red(), path),
+        Action::LocalMkdir(path) => (style("mkdir").blue(), path),
+        Action::Conflict(path) => (style("conflict").yellow(), path),
+    };
+    let size = match action {
+        Action::Put(path) => std::fs::metadata(path)
+            .map(|metadata| metadata.len().to_human_size())
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    [kind.to_string(), path.to_string_lossy().into_owned(), size]
+}
+
+/// The plan by kind of action and by directory, with the bytes it uploads
+/// and removes
+pub fn print_summary(todo: &SyncPlan) {
+    let kinds = todo
+        .by_kind()
+        .iter()
+        .map(|(kind, actions)| format!("{} {kind}", actions.len()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!(
+        "      {}",
+        t!("plan_summary", count = todo.len(), kinds = kinds)
+    );
+    println!(
+        "      {}",
+        t!(
+            "plan_bytes",
+            upload = todo.upload_bytes().to_human_size(),
+            remove = todo.remove_bytes().to_human_size()
+        )
+    );
+    let mut table = Table::new(&[
+        t!("column_directory").as_str(),
+        t!("column_actions").as_str(),
+        t!("column_upload").as_str(),
+        t!("column_remove").as_str(),
+    ])
+    .align_right(1)
+    .align_right(2)
+    .align_right(3);
+    for (directory, summary) in todo.directories() {
+        table.push(vec![
+            directory.to_string_lossy().into_owned(),
+            summary.actions.to_string(),
+            summary.upload_bytes.to_human_size(),
+            summary.remove_bytes.to_human_size(),
+        ]);
+    }
+    print!("{table}");
+}
+
+/// What `syncbox plan` prints, the actions grouped by kind and what they
+/// amount to
+pub fn print_plan(todo: &SyncPlan) {
+    if todo.is_empty() {
+        println!("      {}", t!("nothing_to_do"));
+        return;
+    }
+    let mut actions = todo.to_vec();
+    sort_actions(&mut actions);
+    print!("{}", plan_table(&actions));
+    print_summary(todo);
+}
+
+pub fn plan_table(todo: &[Action]) -> Table {
+    let mut table = Table::new(&[
+        t!("column_action").as_str(),
+        t!("column_path").as_str(),
+        t!("column_size").as_str(),
+    ])
+    .align_right(2);
+    for action in todo {
+        table.push(action_cells(action).to_vec());
+    }
+    table
+}
+
+pub fn results_table(
+    todo: &[Action],
+    results: &HashMap<Action, (Result<(), String>, Duration)>,
+) -> Table {
+    let mut table = Table::new(&[
+        t!("column_action").as_str(),
+        t!("column_path").as_str(),
+        t!("column_size").as_str(),
+        t!("column_status").as_str(),
+        t!("column_duration").as_str(),
+    ])
+    .align_right(2)
+    .align_right(4);
+    for action in todo {
+        let mut row = action_cells(action).to_vec();
+        match results.get(action) {
+            Some((Ok(()), duration)) => {
+                row.push(style(t!("status_ok")).green().to_string());
+                row.push(format!("{:.2}s", duration.as_secs_f64()));
+            }
+            Some((Err(error), duration)) => {
+                row.push(style(t!("status_failed", error = error)).red().to_string());
+                row.push(format!("{:.2}s", duration.as_secs_f64()));
+            }
+            None => {
+                row.push(style(t!("status_not_run")).dim().to_string());
+                row.push(String::new());
+            }
+        }
+        table.push(row);
+    }
+    table
+}
+
+pub trait HumanBytes {
+    fn to_human_size(self) -> String;
+}
+
+impl HumanBytes for u64 {
+    fn to_human_size(self) -> String {
+        let value = self;
+        if value > 1024 * 1024 * 1024 {
+            format!("{:.2?}GB", value as f64 / 1024.0 / 1024.0 / 1024.0)
+        } else if value > 1024 * 1024 {
+            format!("{:.2?}MB", value as f64 / 1024.0 / 1024.0)
+        } else if value > 1024 {
+            format!("{:.2?}KB", value as f64 / 1024.0)
+        } else {
+            format!("{}B", value)
+        }
+    }
+}
+
+impl HumanBytes for &AtomicU64 {
+    fn to_human_size(self) -> String {
+        let value = self.load(SeqCst);
+        value.to_human_size()
+    }
+}
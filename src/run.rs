@@ -0,0 +1,1936 @@
+use crate::{
+    action_filter::FilterCommand,
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    cli::{Args, ChecksumCommand, Command, TransportType},
+    collision::resolve_collision,
+    commands::{
+        init, list_snapshots, print_listing, print_log, print_stats, prune_remote,
+        read_snapshot_index, validate_checksum_file, verify_remote,
+    },
+    config::Config,
+    conflict::Resolution,
+    connect::{make_transport, take_lock, transport_pool},
+    control::{self, Controller, Event},
+    deadline::run_until,
+    drift::{self, Drift, DriftPolicy},
+    exit::{self, Failure},
+    guard::{home_dir, risky_directory},
+    hooks::{Hook, Report, When},
+    journal::{self, Header, Journal, JOURNAL_FILENAME},
+    links::LinkPolicy,
+    lock::{self, Lock},
+    logging,
+    merge_base::{self, BASE_FILENAME},
+    notification::{self, format_duration},
+    plan::SyncPlan,
+    prefix::{self, Variables},
+    prompt::{confirm_removals, confirm_risky_sync},
+    pull::{conflict_resolver, execute_pull, move_aside, pull_remote, restore_files},
+    push::push_on_save,
+    reconciler::{case_renames, sort_actions, Action, ReconcileOptions, Reconciler},
+    remote_copy::copy_sources,
+    report::{
+        action_span, plan_table, print_plan, print_summary, record, results_table, HumanBytes,
+        Results, PROGRESS_BAR_CHARS,
+    },
+    scan::{
+        calculate_checksums, path_filter, permissions_of, rehash_unchanged, resolve_files,
+        take_over_touched, LocalFiles,
+    },
+    script::Script,
+    snapshots::{Snapshot, SnapshotIndex},
+    state::StateTracker,
+    state_db::StateDb,
+    t,
+    transport::{clock_drift, is_not_found, pool::TransportPool, throttle::RateLimiter, Transport},
+    upload::{
+        check_compression, create_directory, create_link, remote_path, set_metadata, upload_file,
+        verify_upload,
+    },
+};
+use chrono::Utc;
+use clap::ColorChoice;
+use console::style;
+use futures::{stream, StreamExt};
+use indicatif::ProgressStyle;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, sync::Mutex};
+use tracing::Instrument;
+
+/// What a sync did, for `--notify` and the exit code
+pub struct Outcome {
+    pub bytes: u64,
+    pub errors: bool,
+    /// Stopped by `--run-timeout` or the control socket
+    pub interrupted: bool,
+    pub nothing_to_do: bool,
+}
+
+impl Outcome {
+    pub fn exit_code(&self, detailed: bool) -> u8 {
+        if self.errors {
+            Failure::Partial.code()
+        } else if self.interrupted {
+            Failure::Interrupted.code()
+        } else if self.nothing_to_do && detailed {
+            exit::NOTHING_TO_DO
+        } else {
+            0
+        }
+    }
+}
+
+/// Logs how a run ended
+pub fn log_outcome(result: &Result<Option<Outcome>, Box<dyn Error + Send + Sync + 'static>>) {
+    match result {
+        Ok(Some(Outcome { bytes, errors, .. })) => {
+            tracing::info!(target: logging::RUN, bytes, errors, "finished")
+        }
+        Ok(None) => tracing::info!(target: logging::RUN, "finished"),
+        Err(error) => tracing::error!(target: logging::RUN, %error, "failed"),
+    }
+}
+
+/// Shows a desktop notification about how a sync went
+pub async fn notify_outcome(
+    result: &Result<Option<Outcome>, Box<dyn Error + Send + Sync + 'static>>,
+    took: Duration,
+) {
+    let took = format_duration(took);
+    let message = match result {
+        Ok(Some(Outcome { bytes, errors, .. })) => Some((
+            if *errors {
+                t!("notify_errors")
+            } else {
+                t!("notify_done")
+            },
+            t!(
+                "notify_transferred",
+                size = bytes.to_human_size(),
+                duration = took
+            ),
+        )),
+        Ok(None) => None,
+        Err(e) => Some((
+            t!("notify_failed"),
+            t!("notify_failed_after", error = e, duration = took),
+        )),
+    };
+    if let Some((summary, body)) = message {
+        if let Err(e) = notification::notify(summary, body).await {
+            println!("{}", t!("notify_unavailable", error = e));
+        }
+    }
+}
+
+/// Runs `run` between the hooks of the config file, for the commands that
+/// change files. Dry runs fire none
+pub async fn run_hooked(
+    args: Args,
+    config: &Config,
+    controller: Arc<Controller>,
+    now: std::time::Instant,
+) -> Result<Option<Outcome>, Box<dyn Error + Send + Sync + 'static>> {
+    let command = match args.command {
+        Command::Sync(_) => "sync",
+        Command::Adopt { .. } => "adopt",
+        Command::Bisync { .. } => "bisync",
+        Command::Pull { .. } => "pull",
+        Command::Restore { .. } => "restore",
+        _ => return run(args, config, controller, now).await,
+    };
+    let directory = std::path::absolute(&args.directory)?;
+    let hooks = &config.hooks;
+    if hooks.is_empty() || args.is_dry() {
+        return run(args, config, controller, now).await;
+    }
+    let mut report = Report {
+        event: When::Before,
+        command: command.to_string(),
+        directory: directory.to_string_lossy().into_owned(),
+        remote: args.transport().map(TransportType::remote_id),
+        bytes: 0,
+        errors: false,
+        error: None,
+        seconds: 0.0,
+    };
+    let result = match fire_hooks(hooks, &report).await {
+        Ok(()) => run(args, config, controller, now).await,
+        Err(e) => Err(e),
+    };
+    report.seconds = now.elapsed().as_secs_f64();
+    report.event = match &result {
+        Ok(Some(outcome)) => {
+            report.bytes = outcome.bytes;
+            report.errors = outcome.errors;
+            if outcome.errors || outcome.interrupted {
+                When::Failure
+            } else {
+                When::After
+            }
+        }
+        Ok(None) => When::After,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            When::Failure
+        }
+    };
+    fire_hooks(hooks, &report).await?;
+    result
+}
+
+/// Fires the hooks on `report.event` in order. The first before hook failing
+/// fails the run, the others only report it
+async fn fire_hooks(
+    hooks: &[Hook],
+    report: &Report,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    for hook in hooks.iter().filter(|hook| hook.on == report.event) {
+        match hook.fire(report).await {
+            Ok(()) => {}
+            Err(e) if report.event == When::Before => {
+                return Err(format!("the before hook {:?} failed: {e}", hook.name()).into())
+            }
+            Err(e) => eprintln!(
+                "{}",
+                t!(
+                    "hook_failed",
+                    event = report.event,
+                    hook = hook.name(),
+                    error = e
+                )
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the outcome of syncs, other commands have none
+pub async fn run(
+    mut args: Args,
+    config: &Config,
+    controller: Arc<Controller>,
+    now: std::time::Instant,
+) -> Result<Option<Outcome>, Box<dyn Error + Send + Sync + 'static>> {
+    let started = tokio::time::Instant::from_std(now);
+    tracing::info!(
+        target: logging::RUN,
+        version = env!("CARGO_PKG_VERSION"),
+        directory = args.directory,
+        "started"
+    );
+
+    match args.color {
+        ColorChoice::Always => {
+            console::set_colors_enabled(true);
+            console::set_colors_enabled_stderr(true);
+        }
+        ColorChoice::Never => {
+            console::set_colors_enabled(false);
+            console::set_colors_enabled_stderr(false);
+        }
+        ColorChoice::Auto => {}
+    }
+
+    if args.deterministic {
+        args.concurrency = 1;
+        args.scan_threads = Some(1);
+    }
+
+    // resolve relative to where syncbox was started, not the synced directory
+    if let Some(archive_removed) = args.archive_removed.as_mut() {
+        *archive_removed = std::path::absolute(&archive_removed)?.join(format!(
+            "{}",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs()
+        ));
+    }
+
+    if let Some(TransportType::Local { destination, .. }) = args.transport_mut() {
+        *destination = std::path::absolute(&destination)?
+            .to_string_lossy()
+            .into_owned();
+    }
+
+    if let Command::Restore { target, .. } = &mut args.command {
+        *target = std::path::absolute(&target)?;
+    }
+
+    if let Command::PushOnSave { paths, .. } = &mut args.command {
+        for path in paths.iter_mut() {
+            *path = path
+                .canonicalize()
+                .map_err(|e| format!("Cannot watch {path:?}: {e}"))?;
+        }
+    }
+
+    std::env::set_current_dir(args.directory.clone())?;
+
+    // git variables come from the synced directory
+    if args.prefix_template.is_some() || args.checksum_file.contains(['{', '}']) {
+        let variables = Variables::detect();
+        args.checksum_file = prefix::expand(&args.checksum_file, &variables)
+            .map_err(|e| format!("Invalid checksum file name: {e}"))?;
+        if let Some(template) = &args.prefix_template {
+            let prefix = template
+                .expand(&variables)
+                .map_err(|e| format!("Invalid prefix template: {e}"))?;
+            if let Some(transport) = args.transport_mut() {
+                transport.push_prefix(&prefix);
+                if let TransportType::Local { destination, .. } = transport {
+                    fs::create_dir_all(destination).await?;
+                }
+            }
+        }
+    }
+
+    if let Command::Init { force } = args.command {
+        return init(&args, force).await.map(|()| None);
+    }
+
+    if let Command::Stats { dupes } = args.command {
+        return print_stats(&args, dupes).await.map(|()| None);
+    }
+
+    if let Command::Snapshots { .. } = args.command {
+        return list_snapshots(&args).await.map(|()| None);
+    }
+
+    if let Command::Checksum {
+        command: ChecksumCommand::Validate { repair, .. },
+    } = args.command
+    {
+        return validate_checksum_file(&args, repair).await.map(|()| None);
+    }
+
+    if let Command::Log { path, .. } = &args.command {
+        return print_log(&args, path).await.map(|()| None);
+    }
+
+    if let Command::Ls {
+        path,
+        recursive,
+        live,
+        ..
+    } = &args.command
+    {
+        let path = path.as_deref().unwrap_or(Path::new("."));
+        return print_listing(&args, path, *recursive, *live)
+            .await
+            .map(|()| None);
+    }
+
+    if let Command::Prune { .. } = args.command {
+        return prune_remote(&args).await.map(|()| None);
+    }
+
+    if let Command::Verify { hashes, repair, .. } = args.command {
+        return verify_remote(&args, hashes, repair).await.map(|()| None);
+    }
+
+    if let Command::PushOnSave { paths, .. } = &args.command {
+        return push_on_save(&args, paths).await.map(|()| None);
+    }
+
+    let adopt = matches!(args.command, Command::Adopt { .. });
+    let restore = matches!(args.command, Command::Restore { .. });
+    let pull = matches!(args.command, Command::Pull { .. });
+    let bisync = matches!(args.command, Command::Bisync { .. });
+    let plan = matches!(args.command, Command::Plan { .. });
+    // downloading takes three steps of its own
+    let pulled_steps = if bisync { 3 } else { 0 };
+    let steps = if pull {
+        8
+    } else if plan {
+        4
+    } else {
+        9 + pulled_steps
+    };
+    let step = |n: usize| style(format!("[{n}/{steps}]")).dim().bold();
+    if adopt && args.compress.is_some() {
+        return Err("adopt compares file sizes, which compressed files don't keep, run it without --compress".into());
+    }
+
+    // restoring leaves the synced directory alone
+    if let Some(reason) =
+        risky_directory(&std::env::current_dir()?, home_dir().as_deref()).filter(|_| !restore)
+    {
+        confirm_risky_sync(&args, &reason)?;
+    }
+
+    let rate_limiter =
+        Arc::new(RateLimiter::new(args.max_rps.or_else(|| {
+            args.transport().and_then(TransportType::default_max_rps)
+        })));
+    let pool = transport_pool(&args, &rate_limiter, args.concurrency);
+    if !args.checksum_only {
+        // a typo in the credentials shouldn't wait for every file to be hashed
+        let connections = pool.preflight().await.map_err(exit::connecting)?;
+        println!("{}", t!("remote_reachable", connections = connections));
+        if args.dry_run {
+            println!("{}", t!("dry_run"));
+        }
+        if args.links == LinkPolicy::Preserve && !pool.get().await?.capabilities().symlinks {
+            return Err(
+                "the remote can't create symbolic links, rerun with --links follow or --links skip"
+                    .into(),
+            );
+        }
+    }
+
+    if let Command::Restore { path, target, .. } = &args.command {
+        return restore_files(&args, &pool, path.as_deref(), target, now)
+            .await
+            .map(Some);
+    }
+
+    println!("{} {}", step(1), t!("resolving_files"));
+    let LocalFiles {
+        files,
+        links,
+        directories,
+    } = resolve_files(&args)?;
+    if files.len() > args.max_files {
+        confirm_risky_sync(
+            &args,
+            &format!(
+                "{} files were found, more than --max-files {}",
+                files.len(),
+                args.max_files
+            ),
+        )?;
+    }
+
+    // build map with checksums
+    println!("{} {}", step(2), t!("calculating_checksums"));
+    let checksums = calculate_checksums(&args, files).await?;
+    let local_files = checksums.keys().cloned().collect::<Vec<_>>();
+    let mut next_checksum_tree: ChecksumTree = checksums.into();
+    for (path, target) in links {
+        next_checksum_tree.insert_at(Path::new(&path), ChecksumElement::Symlink(target));
+    }
+    // parents come first, so only directories nothing was found in are added
+    for directory in directories {
+        if !next_checksum_tree.contains(Path::new(&directory)) {
+            next_checksum_tree.insert_at(Path::new(&directory), ChecksumElement::default());
+        }
+    }
+    next_checksum_tree.set_compression(args.compress);
+
+    if pull {
+        return pull_remote(&args, config, &pool, next_checksum_tree, now)
+            .await
+            .map(Some);
+    }
+
+    if args.checksum_only {
+        println!("{}", t!("writing_checksum_file", path = args.checksum_file));
+        next_checksum_tree.write_compressed(
+            std::fs::File::create(&args.checksum_file)?,
+            args.checksum_compression(),
+        )?;
+        return Ok(Some(Outcome {
+            bytes: 0,
+            errors: false,
+            interrupted: false,
+            nothing_to_do: false,
+        }));
+    }
+
+    // get previous checksums using Transport
+    println!("{} {}", step(3), t!("fetching_checksum_file"));
+
+    let mut transport = make_transport(&args, &rate_limiter).await?;
+    if transport.capabilities().compression {
+        println!("      {}", t!("transfers_compressed"));
+    }
+    // taken before the checksum file is read, another run may be about to replace it
+    let _lock = if plan || args.is_dry() {
+        None
+    } else {
+        take_lock(&args, &mut *transport, &rate_limiter, args.shard).await?
+    };
+    // measuring writes a probe file
+    if args.preserve_mtime && !plan {
+        // drift makes modification times set on upload disagree with the remote's own
+        match clock_drift(&mut *transport).await {
+            Ok(drift) if drift.abs() > args.mtime_tolerance => {
+                let seconds = format!("{:.1}", drift.abs());
+                let tolerance = args.mtime_tolerance;
+                if drift > 0.0 {
+                    println!(
+                        "      {}",
+                        t!("clock_ahead", seconds = seconds, tolerance = tolerance)
+                    );
+                } else {
+                    println!(
+                        "      {}",
+                        t!("clock_behind", seconds = seconds, tolerance = tolerance)
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(e) => println!("      {}", t!("clock_unknown", error = e)),
+        }
+    }
+
+    // a dry run must not record what it didn't do, nor a plan
+    let mut state_db = match &args.state_db {
+        Some(_) if plan || args.is_dry() => None,
+        Some(path) => match args.transport() {
+            Some(transport) => Some(StateDb::open(path, &transport.remote_id())?),
+            None => None,
+        },
+        None => None,
+    };
+    let mut remote_size = None;
+    let mut stored_state = None;
+    if let Some(db) = &state_db {
+        if !adopt && !args.force {
+            remote_size = transport.size(Path::new(&args.checksum_file)).await.ok();
+            if db.synced_with()? == remote_size.map(|size| (args.checksum_file.clone(), size)) {
+                stored_state = Some(db.tree()?);
+            }
+        }
+    }
+    let from_state_db = stored_state.is_some();
+
+    let mut previous_checksum_tree = if let Some(tree) = stored_state {
+        println!(
+            "      {}",
+            t!(
+                "state_db_read",
+                path = args.state_db.as_ref().unwrap().display()
+            )
+        );
+        tree
+    } else if adopt {
+        if transport.size(Path::new(&args.checksum_file)).await.is_ok() && !args.force {
+            return Err(format!(
+                "the remote already has a checksum file {}, rerun with --force to replace it",
+                args.checksum_file
+            )
+            .into());
+        }
+        adopt_remote(&args, &pool, &next_checksum_tree, local_files).await?
+    } else if bisync {
+        read_bisync_remote(&args, &mut *transport).await?
+    } else {
+        match transport
+            .read_last_checksum(Path::new(&args.checksum_file))
+            .await
+        {
+            Ok(checksum) => checksum,
+            Err(_) if args.force => ChecksumTree::default(),
+            Err(e) => {
+                return Err(Failure::ChecksumFile.error(format!(
+                    "{e}, rerun with --force to ignore it and upload everything"
+                )))
+            }
+        }
+    };
+
+    if previous_checksum_tree.is_recovered() {
+        if bisync {
+            println!("      {}", t!("bisync_cut_off"));
+        } else {
+            println!("      {}", t!("checksum_file_cut_off"));
+        }
+    }
+    // what an interrupted run did without getting to upload the checksum file
+    let journal_header = match args.transport() {
+        None => None,
+        Some(_) if adopt || args.is_dry() => None,
+        Some(remote) => Some(Header {
+            remote: remote.remote_id(),
+            checksum_file: args.checksum_file.clone(),
+            checksum_file_size: transport.size(Path::new(&args.checksum_file)).await.ok(),
+        }),
+    };
+    let mut journal = None;
+    let mut resumed = 0;
+    if let Some(header) = &journal_header {
+        let entries = if plan {
+            journal::load(Path::new(JOURNAL_FILENAME), header)?
+        } else {
+            let (opened, entries) = Journal::open(Path::new(JOURNAL_FILENAME), header)?;
+            journal = Some(opened);
+            entries
+        };
+        if !entries.is_empty() {
+            journal::replay(&mut previous_checksum_tree, &entries);
+            resumed = entries.len();
+            println!(
+                "      {}",
+                t!("journal_replayed", count = resumed, path = JOURNAL_FILENAME)
+            );
+        }
+    }
+    let compression_changed = previous_checksum_tree.compression() != args.compress;
+    check_compression(&args, &mut previous_checksum_tree)?;
+    let rehashed = rehash_unchanged(&args, &mut previous_checksum_tree, &next_checksum_tree)
+        .await?
+        + take_over_touched(&args, &mut previous_checksum_tree, &next_checksum_tree);
+    let drifted = match args.drift {
+        Some(policy) if !adopt && !bisync => {
+            check_drift(
+                &args,
+                &mut transport,
+                policy,
+                &mut previous_checksum_tree,
+                &mut next_checksum_tree,
+            )
+            .await?
+        }
+        _ => 0,
+    };
+    if let Some(db) = &mut state_db {
+        if !from_state_db || compression_changed || rehashed > 0 || drifted > 0 || resumed > 0 {
+            db.replace(&previous_checksum_tree)?;
+            if let Some(size) = remote_size {
+                db.mark_synced(&args.checksum_file, size)?;
+            }
+        }
+    }
+
+    // reconcile
+    println!("{} {}", step(4), t!("reconciling"));
+    let options = ReconcileOptions {
+        case_insensitive: args.case_insensitive,
+        collision: args.on_collision,
+        filter: path_filter(&args)?,
+        sync_mtime: args.preserve_mtime,
+        sync_mode: args.preserve_permissions,
+    };
+    let (mut todo, next_checksum_tree, merge_base, moved) = if bisync {
+        let base = merge_base::load(Path::new(BASE_FILENAME))?;
+        if previous_checksum_tree.is_recovered() {
+            merge_base::fill_in(&mut previous_checksum_tree, &base);
+        }
+        if merge_base::emptied(&base, &previous_checksum_tree) && !args.force {
+            return Err(Failure::ChecksumFile.error(format!(
+                "{} on the remote lists no files though the last bisync agreed on {}, rerun with --force if they were all removed on purpose",
+                args.checksum_file,
+                base.len()
+            )));
+        }
+        let merge = Reconciler::merge(
+            &base,
+            &next_checksum_tree,
+            &previous_checksum_tree,
+            &options,
+            conflict_resolver(args.on_conflict.unwrap_or_default()),
+        )?;
+        let moved = merge
+            .resolved
+            .into_iter()
+            .filter_map(|(path, resolution)| match resolution {
+                Resolution::KeepBoth(copy) => Some((path, copy)),
+                _ => None,
+            })
+            .collect();
+        let todo = SyncPlan::new(merge.actions, &previous_checksum_tree, &merge.remote);
+        (todo, merge.remote, Some(base), moved)
+    } else {
+        let todo = Reconciler::reconcile_with_options(
+            previous_checksum_tree.clone(),
+            &next_checksum_tree,
+            &options,
+        )?;
+        // the remote keeps what's left out
+        let next_checksum_tree = options
+            .filter
+            .keep_excluded(&previous_checksum_tree, &next_checksum_tree);
+        (todo, next_checksum_tree, None, vec![])
+    };
+    if let Some(shard) = args.shard {
+        let actions = std::mem::take(&mut *todo);
+        *todo = shard.filter(actions);
+    }
+    if args.deterministic {
+        sort_actions(&mut todo);
+    }
+    // what's filtered out stays on the remote as it was
+    let planned = todo.to_vec();
+    let mut filtered = false;
+    if let Some(source) = &config.script {
+        let mut script = Script::compile(source, Path::new("."))?;
+        filtered = script.filters();
+        if filtered {
+            todo.filter_actions(|action| script.decide(action));
+        }
+        script.finish()?;
+    }
+    if let Some(command) = &args.filter_cmd {
+        let mut command = FilterCommand::spawn(command)?;
+        todo.filter_actions(|action| command.decide(action));
+        command.finish()?;
+        filtered = true;
+    }
+    for action in &todo {
+        if let Action::Conflict(path) = action {
+            println!("      {}", t!("conflict", path = format!("{path:?}")));
+        }
+    }
+    if plan {
+        print_plan(&todo);
+        return Ok(None);
+    }
+    let removals = todo
+        .iter()
+        .filter(|action| matches!(action, Action::Remove(_)))
+        .count();
+    if let Some(max_delete) = args.max_delete.filter(|_| !args.skip_removal) {
+        confirm_removals(&args, removals, max_delete)?;
+    }
+    if args.dry_run && !todo.is_empty() {
+        print_plan(&todo);
+    } else if args.summary && !todo.is_empty() {
+        print_summary(&todo);
+    }
+    let copy_sources = if !args.server_side_copy {
+        HashMap::new()
+    } else if transport.capabilities().remote_copy {
+        copy_sources(&previous_checksum_tree, &next_checksum_tree, &todo)
+    } else {
+        println!("      {}", t!("remote_copy_unsupported"));
+        HashMap::new()
+    };
+    if !copy_sources.is_empty() {
+        println!(
+            "      {}",
+            t!("copied_on_remote", count = copy_sources.len())
+        );
+    }
+    let copy_sources = Arc::new(copy_sources);
+    let case_renames = Arc::new(if args.case_insensitive {
+        case_renames(&previous_checksum_tree, &next_checksum_tree, &todo)
+    } else {
+        HashMap::new()
+    });
+    let link_targets = todo
+        .iter()
+        .filter_map(|action| match action {
+            Action::Link(path) => match next_checksum_tree.get_at(path) {
+                Some(ChecksumElement::Symlink(target)) => Some((path.clone(), target.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    // written through, a link would change the file it points to instead
+    let replaced_links = todo
+        .iter()
+        .filter_map(|action| match action {
+            Action::Mkdir(path) | Action::Put(path) => Some(path),
+            _ => None,
+        })
+        .filter(|path| {
+            matches!(
+                previous_checksum_tree.get_at(path),
+                Some(ChecksumElement::Symlink(_))
+            )
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let todo = Arc::new(todo);
+    // downloads are done from the remote tree as it was read
+    let remote_checksum_tree = bisync.then(|| previous_checksum_tree.clone());
+    let pushed = planned
+        .iter()
+        .filter(|action| !is_pulled(action))
+        .cloned()
+        .collect::<Vec<_>>();
+    // the uploaded checksum is derived from confirmed actions only
+    let mut tracker = StateTracker::new(previous_checksum_tree, next_checksum_tree, &pushed);
+    if let Some(db) = state_db {
+        tracker.persist_to(db);
+    }
+    if let Some(journal) = journal {
+        tracker.journal_to(journal);
+    }
+    let tracker = Arc::new(Mutex::new(tracker));
+
+    if todo.is_empty() {
+        println!("      {}", t!("nothing_to_do"));
+        if adopt || rehashed > 0 || resumed > 0 {
+            if args.shard.is_some() {
+                let (merged, size) = write_shard_checksum(&args, &mut *transport, &tracker).await?;
+                mark_state_db_synced(&args, tracker.lock().await.take_db(), Some(&merged), size);
+            } else {
+                let mut tracker = tracker.lock().await;
+                let size = transport
+                    .write_last_checksum(
+                        Path::new(&args.checksum_file),
+                        tracker.state(),
+                        args.checksum_compression(),
+                    )
+                    .await?;
+                mark_state_db_synced(&args, tracker.take_db(), None, size);
+            }
+            println!(
+                "      {}",
+                t!("wrote_checksum_file", path = args.checksum_file)
+            );
+        }
+        remove_journal(&mut *tracker.lock().await);
+        if let Some(base) = &merge_base {
+            save_merge_base(&args, base, tracker.lock().await.state(), &[]);
+        }
+        return Ok(Some(Outcome {
+            bytes: 0,
+            errors: false,
+            interrupted: false,
+            nothing_to_do: true,
+        }));
+    }
+
+    println!(
+        "{} {}",
+        step(5),
+        t!("executing", count = style(todo.len()).bold())
+    );
+    if args.table {
+        print!("{}", plan_table(&todo));
+    }
+
+    let has_error = Arc::new(AtomicBool::new(false));
+    let results: Results = Default::default();
+    let pulled_bytes = match &remote_checksum_tree {
+        Some(remote_checksum_tree) => {
+            move_aside(&args, &moved).await?;
+            execute_pull(
+                &args,
+                &pool,
+                &todo,
+                remote_checksum_tree,
+                &results,
+                &has_error,
+                (6, steps),
+            )
+            .await
+        }
+        None => 0,
+    };
+    let _control_socket = match &args.control_socket {
+        Some(path) => {
+            let socket = control::serve(path, Arc::clone(&controller))?;
+            println!("      {}", t!("control_socket", path = path.display()));
+            Some(socket)
+        }
+        None => None,
+    };
+    let timed_out = Arc::new(AtomicBool::new(false));
+    if let Some(run_timeout) = args.run_timeout {
+        let stop_at = run_timeout.stop_scheduling_at(started);
+        let controller = Arc::clone(&controller);
+        let timed_out = Arc::clone(&timed_out);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(stop_at).await;
+            timed_out.store(true, SeqCst);
+            controller.handle(control::Command::Cancel);
+        });
+    }
+    let abandon_at = args
+        .run_timeout
+        .map(|run_timeout| run_timeout.abandon_at(started));
+
+    // removing files, before anything is created with --delete-first to make room
+    let removal_step = if args.delete_first { 6 } else { 8 } + pulled_steps;
+    let remove = || {
+        if args.skip_removal {
+            println!("{} {}", step(removal_step), t!("removing_files_skipped"));
+            return None;
+        }
+        println!(
+            "{} {}",
+            step(removal_step),
+            t!(
+                "removing",
+                count = todo
+                    .iter()
+                    .filter(|action| matches!(action, Action::Remove(_)))
+                    .count(),
+                size = todo.remove_bytes().to_human_size()
+            )
+        );
+        Some(remove_remote(
+            &args,
+            &pool,
+            &todo,
+            &tracker,
+            &controller,
+            &results,
+            &has_error,
+            abandon_at,
+        ))
+    };
+    let removals_first = usize::from(args.delete_first);
+    if args.delete_first {
+        if let Some(removed) = remove() {
+            removed.await?;
+        }
+    }
+
+    // then create directories
+    println!(
+        "{} {}",
+        step(6 + removals_first + pulled_steps),
+        t!("creating_directories")
+    );
+    for path in &replaced_links {
+        if let Err(e) = transport.remove(path).await {
+            if !is_not_found(e.as_ref()) {
+                eprintln!(
+                    "{}",
+                    t!("remove_link_failed", path = format!("{path:?}"), error = e)
+                );
+            }
+        }
+    }
+    // links go along, their targets need not exist, and so do files only
+    // getting another modification time or permissions
+    let create_directory_actions: Vec<_> = todo
+        .iter()
+        .filter(|action| {
+            matches!(
+                action,
+                Action::Mkdir(_) | Action::Link(_) | Action::SetMeta(_)
+            )
+        })
+        .collect();
+    for (i, action) in create_directory_actions.iter().enumerate() {
+        controller.wait_while_paused().await;
+        if controller.is_cancelled() {
+            break;
+        }
+
+        let n = std::time::Instant::now();
+        async {
+            match action {
+                Action::Mkdir(path) => {
+                    match create_directory(&mut transport, path, args.on_collision, args.chmod.dirs)
+                        .await
+                    {
+                        Ok(_) => {
+                            tracker.lock().await.confirm(action);
+                            record(&results, action, Ok(()), n.elapsed()).await;
+                            if !args.table {
+                                println!(
+                                    "{}",
+                                    t!(
+                                        "created_directory",
+                                        index = i + 1,
+                                        total = create_directory_actions.len(),
+                                        path = format!("{path:?}"),
+                                        seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                                    )
+                                )
+                            }
+                        }
+                        Err(error) => {
+                            record(&results, action, Err(error.to_string()), n.elapsed()).await;
+                            eprintln!(
+                                "{}",
+                                t!(
+                                    "create_directory_failed",
+                                    index = i + 1,
+                                    total = create_directory_actions.len(),
+                                    path = format!("{path:?}"),
+                                    error = error,
+                                )
+                            );
+                            has_error.store(true, SeqCst);
+                        }
+                    }
+                }
+                Action::Link(path) => {
+                    let target = &link_targets[path];
+                    match create_link(&mut transport, target, path, args.on_collision).await {
+                        Ok(_) => {
+                            tracker.lock().await.confirm(action);
+                            record(&results, action, Ok(()), n.elapsed()).await;
+                            if !args.table {
+                                println!(
+                                    "{}",
+                                    t!(
+                                        "created_link",
+                                        index = i + 1,
+                                        total = create_directory_actions.len(),
+                                        path = format!("{path:?}"),
+                                        target = target,
+                                        seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                                    )
+                                )
+                            }
+                        }
+                        Err(error) => {
+                            record(&results, action, Err(error.to_string()), n.elapsed()).await;
+                            eprintln!(
+                                "{}",
+                                t!(
+                                    "create_link_failed",
+                                    index = i + 1,
+                                    total = create_directory_actions.len(),
+                                    path = format!("{path:?}"),
+                                    error = error,
+                                )
+                            );
+                            has_error.store(true, SeqCst);
+                        }
+                    }
+                }
+                Action::SetMeta(path) => match set_metadata(&mut transport, path, &args).await {
+                    Ok(_) => {
+                        tracker.lock().await.confirm(action);
+                        record(&results, action, Ok(()), n.elapsed()).await;
+                        if !args.table {
+                            println!(
+                                "{}",
+                                t!(
+                                    "set_metadata",
+                                    index = i + 1,
+                                    total = create_directory_actions.len(),
+                                    path = format!("{path:?}"),
+                                    seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                                )
+                            )
+                        }
+                    }
+                    Err(error) => {
+                        record(&results, action, Err(error.to_string()), n.elapsed()).await;
+                        eprintln!(
+                            "{}",
+                            t!(
+                                "set_metadata_failed",
+                                index = i + 1,
+                                total = create_directory_actions.len(),
+                                path = format!("{path:?}"),
+                                error = error,
+                            )
+                        );
+                        has_error.store(true, SeqCst);
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+        .instrument(action_span(action))
+        .await;
+    }
+
+    let checksum_path = Arc::new(PathBuf::from(&args.checksum_file));
+    let checksum_compression = args.checksum_compression();
+
+    // upload files
+    let bytes = Arc::new(AtomicU64::new(0));
+    let progress_bars = Arc::new(if args.deterministic {
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden())
+    } else {
+        indicatif::MultiProgress::new()
+    });
+    let mut put_actions = todo
+        .iter()
+        .filter(|action| matches!(action, Action::Put(_)))
+        .cloned()
+        .collect::<Vec<_>>();
+    // smallest first, ties broken by path so the order is stable, unless
+    // a filter ordered them. Files gone since the scan fail last
+    if !filtered {
+        put_actions.sort_by_cached_key(|action| {
+            let Action::Put(path) = action else {
+                unreachable!()
+            };
+            let size = std::fs::metadata(path).map_or(u64::MAX, |metadata| metadata.len());
+            (size, path.clone())
+        });
+    }
+    let put_actions = Arc::new(put_actions);
+    let total_to_upload = Arc::new(AtomicU64::new(todo.upload_bytes()));
+    println!(
+        "{} {}",
+        step(7 + removals_first + pulled_steps),
+        t!(
+            "uploading",
+            count = put_actions.len(),
+            size = total_to_upload.to_human_size()
+        )
+    );
+    let put_actions_len = put_actions.len();
+    controller.emit(Event::Started {
+        files: todo
+            .iter()
+            .filter(|action| matches!(action, Action::Put(_) | Action::Link(_) | Action::Remove(_)))
+            .count(),
+        bytes: total_to_upload.load(SeqCst),
+    });
+    // shards merge their progress into the checksum file rather than replacing it
+    let sharded = args.shard.map(|_| Arc::new(args.clone()));
+    // a dry run moves no conflicts aside, their copies are read where they are
+    let aside: Arc<HashMap<PathBuf, PathBuf>> = Arc::new(if args.dry_run {
+        moved
+            .iter()
+            .map(|(path, copy)| (copy.clone(), path.clone()))
+            .collect()
+    } else {
+        HashMap::new()
+    });
+    let put_actions = put_actions.iter()
+        .enumerate()
+        .map(|(i, action)| {
+            let sharded = sharded.clone();
+            let aside = Arc::clone(&aside);
+            let total_to_upload = Arc::clone(&total_to_upload);
+            let checksum_path = Arc::clone(&checksum_path);
+            let pool = Arc::clone(&pool);
+            let progress_bars = Arc::clone(&progress_bars);
+            let bytes = Arc::clone(&bytes);
+            let tracker = Arc::clone(&tracker);
+            let has_error = Arc::clone(&has_error);
+            let controller = Arc::clone(&controller);
+            let results = Arc::clone(&results);
+            let copy_sources = Arc::clone(&copy_sources);
+            let case_renames = Arc::clone(&case_renames);
+            let journal_header = journal_header.clone();
+            let action = action.clone();
+            let span = action_span(&action);
+            tokio::spawn(async move {
+                let Action::Put(path) = action.clone() else {
+                    unreachable!();
+                };
+                let source = aside.get(&path).unwrap_or(&path).clone();
+                controller.wait_while_paused().await;
+                if controller.is_cancelled() {
+                    return;
+                }
+                let n = std::time::Instant::now();
+
+                let metadata = match fs::metadata(&source).await {
+                    Ok(metadata) => metadata,
+                    // removed or replaced since the scan, the next run plans it again
+                    Err(error) => {
+                        record(&results, &action, Err(error.to_string()), n.elapsed()).await;
+                        controller.emit(Event::FileFailed {
+                            path: path.clone(),
+                            error: error.to_string(),
+                        });
+                        eprintln!("{}", t!("copy_failed", path = format!("{path:?}"), error = error));
+                        has_error.store(true, SeqCst);
+                        return;
+                    }
+                };
+                controller.emit(Event::FileStarted {
+                    path: path.clone(),
+                    size: metadata.len(),
+                });
+                let mut transport = match pool.get().await {
+                    Ok(transport) => transport,
+                    Err(error) => {
+                        eprintln!("{}", t!("connect_to_copy_failed", path = format!("{path:?}"), error = error));
+                        has_error.store(true, SeqCst);
+                        return;
+                    }
+                };
+                let pb = indicatif::ProgressBar::new(metadata.len());
+                let pb = Arc::new(progress_bars.add(pb));
+                let mut template = format!("[{}/{}] ", i + 1, put_actions_len);
+                template.push_str("[{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} [{bytes_per_sec}] {msg}");
+                pb.set_style(
+                    ProgressStyle::with_template(&template)
+                    .unwrap()
+                    .progress_chars(PROGRESS_BAR_CHARS),
+                );
+                let msg = path.to_string_lossy().into_owned();
+                pb.set_message(msg);
+                pb.inc(0);
+                let remote = remote_path(&path, args.compress);
+                // the old name would be kept by uploading over it
+                let renamed = match case_renames.get(&path) {
+                    Some(rename) => match transport.rename(&remote_path(&rename.from, args.compress), remote.as_path()).await {
+                        Ok(()) => Some(rename),
+                        Err(e) => {
+                            tracing::warn!("renaming {:?} on the remote failed, uploading {path:?} instead: {e}", rename.from);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                let copied = match copy_sources.get(&path) {
+                    _ if renamed.is_some_and(|rename| rename.unchanged) => None,
+                    Some(source) => match transport.copy_remote(&remote_path(source, args.compress), remote.as_path()).await {
+                        Ok(b) => Some((source, b)),
+                        Err(e) => {
+                            tracing::warn!("copying {source:?} on the remote failed, uploading {path:?} instead: {e}");
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                let mut written = match copied {
+                    Some((_, b)) => {
+                        pb.set_position(metadata.len());
+                        Ok(b)
+                    }
+                    None if renamed.is_some_and(|rename| rename.unchanged) => {
+                        pb.set_position(metadata.len());
+                        total_to_upload.fetch_sub(metadata.len(), SeqCst);
+                        Ok(0)
+                    }
+                    None => upload_file(&mut transport, source.as_path(), &pb, args.atomic_uploads, args.compress).await,
+                };
+                if written.is_err() {
+                    match resolve_collision(&mut transport, remote.as_path(), false, args.on_collision).await {
+                        Ok(true) => {
+                            written = upload_file(&mut transport, source.as_path(), &pb, args.atomic_uploads, args.compress).await
+                        }
+                        Ok(false) => {}
+                        Err(e) => written = Err(e),
+                    }
+                }
+                let written = match written {
+                    // nothing was written to check
+                    Ok(b) if args.verify_uploads && !args.dry_run => {
+                        // compressed files are as large as what was written
+                        let expected = if args.compress.is_some() { b } else { metadata.len() };
+                        verify_upload(&mut transport, remote.as_path(), expected).await.map(|_| b)
+                    }
+                    written => written,
+                };
+                match written {
+                    Ok(b) => {
+                        bytes.fetch_add(b, SeqCst);
+                        tracker.lock().await.confirm(&action);
+                        record(&results, &action, Ok(()), n.elapsed()).await;
+                        controller.emit(Event::FileFinished {
+                            path: path.clone(),
+                            bytes: b,
+                        });
+                        let mut message = t!("remaining",
+                            path = path.to_string_lossy(),
+                            size = (total_to_upload.load(SeqCst) - bytes.load(SeqCst)).to_human_size(),
+                        );
+                        if let Some((source, _)) = copied {
+                            message.push_str(&t!("copied_from", path = source.to_string_lossy()));
+                        }
+                        if let Some(rename) = renamed {
+                            message.push_str(&t!("renamed_from", path = rename.from.to_string_lossy()));
+                        }
+                        if args.preserve_mtime {
+                            let mtime = metadata.modified();
+                            let result = match mtime {
+                                Ok(mtime) => transport.set_mtime(remote.as_path(), mtime).await,
+                                Err(e) => Err(e.into()),
+                            };
+                            if let Err(e) = result {
+                                message.push_str(&t!("mtime_failed", error = e));
+                            }
+                        }
+                        if args.preserve_permissions {
+                            if let Some((mode, owner)) = permissions_of(&metadata) {
+                                let owner = args.preserve_owner.then_some(owner);
+                                if let Err(e) = transport.set_permissions(remote.as_path(), mode, owner).await {
+                                    message.push_str(&t!("permissions_failed", error = e));
+                                }
+                            }
+                        } else if let Some(mode) = args.chmod.files {
+                            if let Err(e) = transport.set_permissions(remote.as_path(), mode, None).await {
+                                message.push_str(&t!("permissions_failed", error = e));
+                            }
+                        }
+                        pb.finish_with_message(message.clone());
+
+                        // if we are running on the CI, print successful message
+                        if std::env::var("CI").is_ok() || args.deterministic {
+                            println!("✅ {}", message);
+                        }
+
+                        // if we are uploading checksums intermittently, do it now
+                        let confirmed_files = tracker.lock().await.confirmed_files();
+                        if args.intermittent_checksum_upload > 0
+                            && confirmed_files > 0
+                            && confirmed_files % args.intermittent_checksum_upload == 0
+                        {
+                            pb.set_message(t!("uploading_intermittent_checksum"));
+                            let written = if let Some(args) = &sharded {
+                                write_shard_checksum(args, &mut **transport, &tracker).await.map(|(_, size)| size)
+                            } else {
+                                // serialized right away rather than copied, the tree can be huge
+                                let intermittent_checksum = tracker
+                                    .lock()
+                                    .await
+                                    .state()
+                                    .to_compressed(checksum_compression);
+                                match intermittent_checksum {
+                                    Ok(bytes) => {
+                                        let size = bytes.len() as u64;
+                                        transport.write(checksum_path.as_path(), Box::new(std::io::Cursor::new(bytes)), size).await.map(|_| size)
+                                    }
+                                    Err(e) => Err(e),
+                                }
+                            };
+                            match written {
+                                Err(e) => pb.set_message(t!("intermittent_checksum_failed", error = e)),
+                                Ok(size) => {
+                                    // actions confirmed while it was uploaded are redone after a crash
+                                    if let Some(header) = &journal_header {
+                                        let header = Header { checksum_file_size: Some(size), ..header.clone() };
+                                        tracker.lock().await.restart_journal(&header);
+                                    }
+                                    pb.set_message(message);
+                                }
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        record(&results, &action, Err(error.to_string()), n.elapsed()).await;
+                        let message = t!("copy_failed", path = format!("{path:?}"), error = error);
+                        controller.emit(Event::FileFailed {
+                            path: path.clone(),
+                            error: error.to_string(),
+                        });
+                        pb.abandon_with_message(message.clone());
+                        has_error.store(true, SeqCst);
+                        // the connection may be what broke, get a fresh one for the next file
+                        transport.discard();
+
+                        // if we are running on the CI, print error message
+                        if std::env::var("CI").is_ok() || args.deterministic {
+                            println!("{message}");
+                        }
+                    }
+                };
+            }.instrument(span))
+        });
+
+    if let Some(results) = run_until(abandon_at, args.concurrency, put_actions).await {
+        results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    }
+    bytes.fetch_add(pulled_bytes, SeqCst);
+
+    if !args.delete_first {
+        if let Some(removed) = remove() {
+            removed.await?;
+        }
+    }
+
+    if timed_out.load(SeqCst) {
+        println!(
+            "      {}",
+            t!(
+                "run_timeout",
+                timeout = args.run_timeout.unwrap(),
+                count = tracker.lock().await.unconfirmed_count()
+            )
+        );
+    } else if controller.is_cancelled() {
+        println!("      {}", t!("cancelled"));
+    }
+    let interrupted = timed_out.load(SeqCst) || controller.is_cancelled();
+
+    let mut transport = make_transport(&args, &rate_limiter).await?;
+
+    println!("{} {}", step(9 + pulled_steps), t!("uploading_checksum"));
+    let merged = if args.shard.is_some() {
+        Some(write_shard_checksum(&args, &mut *transport, &tracker).await?)
+    } else {
+        None
+    };
+    let mut tracker = tracker.lock().await;
+    let uploaded_checksum_tree = if let Some((merged_checksum_tree, size)) = merged {
+        // the database lacks the other shards' progress
+        mark_state_db_synced(&args, tracker.take_db(), Some(&merged_checksum_tree), size);
+        merged_checksum_tree
+    } else {
+        let checksum_tree = tracker.state().clone();
+        let size = transport
+            .write_last_checksum(
+                checksum_path.as_path(),
+                &checksum_tree,
+                args.checksum_compression(),
+            )
+            .await?;
+        mark_state_db_synced(&args, tracker.take_db(), None, size);
+        checksum_tree
+    };
+    remove_journal(&mut tracker);
+
+    if let Some(base) = &merge_base {
+        let results = results.lock().await;
+        // conflicts and whatever wasn't pulled come up again next time
+        let unresolved = planned
+            .iter()
+            .filter(|action| is_pulled(action))
+            .filter(|action| !matches!(results.get(action), Some((Ok(()), _))))
+            .map(Action::path)
+            .collect::<Vec<_>>();
+        save_merge_base(&args, base, &uploaded_checksum_tree, &unresolved);
+    }
+
+    if args.snapshots && !has_error.load(SeqCst) && !controller.is_cancelled() {
+        take_snapshot(&args, &mut transport, &uploaded_checksum_tree, &pool).await?;
+    }
+
+    let changed_paths: Vec<_> = tracker
+        .changed_paths()
+        .into_iter()
+        .map(|path| remote_path(path, args.compress))
+        .collect();
+    if !changed_paths.is_empty() && !args.is_dry() {
+        let aws_keys = match args.transport() {
+            Some(TransportType::S3 {
+                access_key,
+                secret_key,
+                ..
+            }) => Some((access_key.clone(), secret_key.clone())),
+            _ => None,
+        };
+        for invalidation in &config.invalidate {
+            match invalidation.run(&changed_paths, aws_keys.clone()).await {
+                Ok(purged) => println!(
+                    "      {}",
+                    t!("purged", what = purged, cdn = invalidation.name())
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "{}",
+                        t!("purge_failed", cdn = invalidation.name(), error = e)
+                    );
+                    has_error.store(true, SeqCst);
+                }
+            }
+        }
+    }
+
+    transport.close().await?;
+    pool.close().await?;
+    controller.emit(Event::Finished {
+        bytes: bytes.load(SeqCst),
+        errors: has_error.load(SeqCst),
+    });
+
+    if args.table {
+        print!("{}", results_table(&todo, &*results.lock().await));
+    }
+
+    println!(
+        "{}",
+        t!(
+            "done",
+            size = bytes.to_human_size(),
+            seconds = format!("{:.2}", now.elapsed().as_secs_f64())
+        )
+    );
+
+    Ok(Some(Outcome {
+        bytes: bytes.load(SeqCst),
+        errors: has_error.load(SeqCst),
+        interrupted,
+        nothing_to_do: false,
+    }))
+}
+
+/// Writes the progress of a shard on top of the checksum file on the remote,
+/// under the lock of the checksum file, so shards writing at once don't lose
+/// each other's progress and a full run holding it isn't overwritten
+async fn write_shard_checksum(
+    args: &Args,
+    transport: &mut (dyn Transport + Send + Sync),
+    tracker: &Mutex<StateTracker>,
+) -> Result<(ChecksumTree, u64), Box<dyn Error + Send + Sync + 'static>> {
+    let path = lock::path(&args.checksum_file, None);
+    let lock = Lock::new(args.lock_ttl);
+    // dry runs write no lock to read back
+    let locking = !args.no_lock && !args.is_dry();
+    if locking {
+        lock::wait_for(transport, &path, &lock).await?;
+    }
+    let written = merge_shard_checksum(args, transport, tracker).await;
+    if locking {
+        let released = lock::release(transport, &path, &lock.id).await;
+        if let Err(e) = released {
+            tracing::warn!("removing the lock from the remote failed, it expires on its own: {e}");
+        }
+    }
+    written
+}
+
+async fn merge_shard_checksum(
+    args: &Args,
+    transport: &mut (dyn Transport + Send + Sync),
+    tracker: &Mutex<StateTracker>,
+) -> Result<(ChecksumTree, u64), Box<dyn Error + Send + Sync + 'static>> {
+    let checksum_path = Path::new(&args.checksum_file);
+    // other shards may have uploaded their progress meanwhile, only apply ours on top
+    let mut merged_checksum_tree = transport.read_last_checksum(checksum_path).await?;
+    tracker.lock().await.apply_to(&mut merged_checksum_tree);
+    let size = transport
+        .write_last_checksum(
+            checksum_path,
+            &merged_checksum_tree,
+            args.checksum_compression(),
+        )
+        .await?;
+    Ok((merged_checksum_tree, size))
+}
+
+/// Compares the checksum file with what the remote lists and settles the
+/// files that drifted as `policy` says, see `--drift`. Returns how many
+async fn check_drift(
+    args: &Args,
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    policy: DriftPolicy,
+    previous: &mut ChecksumTree,
+    next: &mut ChecksumTree,
+) -> Result<usize, Box<dyn Error + Send + Sync + 'static>> {
+    println!("      {}", t!("listing_remote"));
+    let listing = drift::list_tracked(&mut **transport, previous)
+        .await
+        .map_err(|e| format!("Listing the remote failed: {e}"))?;
+    let drifts = drift::detect(
+        previous,
+        &listing,
+        Duration::from_secs_f64(args.mtime_tolerance),
+    );
+    for drift in &drifts {
+        let path = format!("{:?}", drift.path());
+        match drift {
+            Drift::Changed(_) => println!("      {}", t!("drift_changed", path = path)),
+            Drift::Deleted(_) => println!("      {}", t!("drift_deleted", path = path)),
+        }
+    }
+    if policy == DriftPolicy::Error && !drifts.is_empty() {
+        return Err(format!(
+            "{} file(s) changed on the remote since they were synced, rerun with --drift reupload or --drift adopt",
+            drifts.len()
+        )
+        .into());
+    }
+    drift::settle(policy, &drifts, previous, next, &listing);
+    Ok(drifts.len())
+}
+
+/// Downloads, local removals and conflicts, what `Reconciler::merge` plans
+/// besides what a sync would
+fn is_pulled(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::Get(_) | Action::LocalRemove(_) | Action::LocalMkdir(_) | Action::Conflict(_)
+    )
+}
+
+/// Writes what the two sides agree on for the next `bisync`, a dry run
+/// agrees on nothing. Failing to is reported, the next run finds the same
+/// changes again
+fn save_merge_base(
+    args: &Args,
+    base: &ChecksumTree,
+    uploaded: &ChecksumTree,
+    unresolved: &[&Path],
+) {
+    if args.is_dry() {
+        return;
+    }
+    let agreed = merge_base::agreed(uploaded, base, unresolved.iter().copied());
+    if let Err(e) = merge_base::save(Path::new(BASE_FILENAME), &agreed) {
+        println!(
+            "      {}",
+            t!("merge_base_unwritable", path = BASE_FILENAME, error = e)
+        );
+    }
+}
+
+/// Records that the state database matches the checksum file just written,
+/// after replacing its contents with `tree` if given. When that fails the
+/// next run reads the checksum file instead
+fn mark_state_db_synced(
+    args: &Args,
+    db: Result<Option<StateDb>, rusqlite::Error>,
+    tree: Option<&ChecksumTree>,
+    size: u64,
+) {
+    let marked = db.and_then(|db| {
+        let Some(mut db) = db else {
+            return Ok(());
+        };
+        if let Some(tree) = tree {
+            db.replace(tree)?;
+        }
+        db.mark_synced(&args.checksum_file, size)
+    });
+    if let Err(e) = marked {
+        println!("      {}", t!("state_db_failed", error = e));
+    }
+}
+
+/// Checksum tree of the local files the remote already has, judged by size
+async fn adopt_remote(
+    args: &Args,
+    pool: &Arc<TransportPool>,
+    local_checksum_tree: &ChecksumTree,
+    files: Vec<String>,
+) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+    let probed = files.len();
+    let matching = stream::iter(files)
+        .map(|file| async move {
+            let path = PathBuf::from(file);
+            let local_size = fs::metadata(&path).await?.len();
+            let remote_size = match pool.get().await?.size(&path).await {
+                Ok(size) => Some(size),
+                Err(e) if is_not_found(e.as_ref()) => None,
+                Err(e) => return Err(format!("probing {path:?} failed: {e}").into()),
+            };
+            Ok::<_, Box<dyn Error + Send + Sync + 'static>>(
+                (remote_size == Some(local_size)).then_some(path),
+            )
+        })
+        .buffer_unordered(args.concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut adopted = ChecksumTree::default();
+    let mut adopted_files = 0;
+    for path in matching.into_iter().flatten() {
+        if let Some(element) = local_checksum_tree.get_at(&path) {
+            adopted.insert_at(&path, element.clone());
+            adopted_files += 1;
+        }
+    }
+    println!(
+        "      {}",
+        t!("adopted", adopted = adopted_files, probed = probed)
+    );
+    Ok(adopted)
+}
+
+/// Makes the synced directory match the checksum file on the remote, which
+/// is only read, see `Reconciler::pull`
+/// The checksum file on the remote for bisync. Unlike when syncing, one that
+/// can't be read fails the run rather than being taken as empty, which
+/// would remove every local file unchanged since the last bisync
+async fn read_bisync_remote<T: Transport + Send + ?Sized>(
+    args: &Args,
+    transport: &mut T,
+) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+    let path = Path::new(&args.checksum_file);
+    let read = match transport.read(path).await {
+        Ok(bytes) => ChecksumTree::from_compressed(&bytes),
+        // not every transport tells a missing file apart when reading it
+        Err(e) if is_not_found(e.as_ref()) => Ok(ChecksumTree::default()),
+        Err(e) => match transport.size(path).await {
+            Err(missing) if is_not_found(missing.as_ref()) => Ok(ChecksumTree::default()),
+            _ => Err(format!("Could not read {}: {e}", args.checksum_file).into()),
+        },
+    };
+    match read {
+        Ok(tree) => Ok(tree),
+        Err(_) if args.force => Ok(ChecksumTree::default()),
+        Err(e) => Err(Failure::ChecksumFile.error(format!(
+            "{e}, rerun with --force to take the remote as empty"
+        ))),
+    }
+}
+
+/// Removes the files the remote doesn't need any more, then the directories
+/// emptied by that, children before their parents
+#[allow(clippy::too_many_arguments)]
+async fn remove_remote(
+    args: &Args,
+    pool: &Arc<TransportPool>,
+    todo: &[Action],
+    tracker: &Arc<Mutex<StateTracker>>,
+    controller: &Arc<Controller>,
+    results: &Results,
+    has_error: &Arc<AtomicBool>,
+    abandon_at: Option<tokio::time::Instant>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let remove_actions: Vec<_> = todo
+        .iter()
+        .filter(|action| matches!(action, Action::Remove(_)))
+        .cloned()
+        .collect();
+    let remove_actions_len = remove_actions.len();
+    let remove_actions = remove_actions.iter().enumerate().map(|(i, action)| {
+        let pool = Arc::clone(pool);
+        let has_error = Arc::clone(has_error);
+        let tracker = Arc::clone(tracker);
+        let controller = Arc::clone(controller);
+        // archiving writes local files, like a removal the dry run only pretends
+        let archive_removed = args.archive_removed.clone().filter(|_| !args.dry_run);
+        let compress = args.compress;
+        let results = Arc::clone(results);
+        let table = args.table;
+        let action = action.clone();
+        let span = action_span(&action);
+        tokio::spawn(
+            async move {
+                controller.wait_while_paused().await;
+                if controller.is_cancelled() {
+                    return;
+                }
+                let mut transport = match pool.get().await {
+                    Ok(transport) => transport,
+                    Err(error) => {
+                        eprintln!(
+                            "{}",
+                            t!(
+                                "connect_to_remove_failed",
+                                path = format!("{action:?}"),
+                                error = error
+                            )
+                        );
+                        has_error.store(true, SeqCst);
+                        return;
+                    }
+                };
+
+                let n = std::time::Instant::now();
+
+                match action.clone() {
+                    Action::Remove(path) => {
+                        let remote = remote_path(&path, compress);
+                        let archived = match archive_removed {
+                            Some(archive_dir) => {
+                                archive_file(&mut transport, remote.as_path(), &archive_dir).await
+                            }
+                            None => Ok(()),
+                        };
+                        let removed = match archived {
+                            Ok(_) => transport.remove(remote.as_path()).await,
+                            Err(error) if is_not_found(error.as_ref()) => Err(error),
+                            Err(error) => {
+                                Err(format!("archiving failed, not removing: {error}").into())
+                            }
+                        };
+                        match removed {
+                            Ok(_) => {
+                                tracker.lock().await.confirm(&action);
+                                record(&results, &action, Ok(()), n.elapsed()).await;
+                                controller.emit(Event::Removed { path: path.clone() });
+                                if !table {
+                                    println!(
+                                        "{}",
+                                        t!(
+                                            "removed",
+                                            index = i + 1,
+                                            total = remove_actions_len,
+                                            path = format!("{path:?}"),
+                                            seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                                        )
+                                    );
+                                }
+                            }
+                            Err(error) if is_not_found(error.as_ref()) => {
+                                // the remote already matches, nothing to retry
+                                tracker.lock().await.confirm(&action);
+                                record(&results, &action, Ok(()), n.elapsed()).await;
+                                controller.emit(Event::Removed { path: path.clone() });
+                                if !table {
+                                    println!(
+                                        "{}",
+                                        t!(
+                                            "removed_already_gone",
+                                            index = i + 1,
+                                            total = remove_actions_len,
+                                            path = format!("{path:?}"),
+                                        )
+                                    );
+                                }
+                            }
+                            Err(error) => {
+                                record(&results, &action, Err(error.to_string()), n.elapsed())
+                                    .await;
+                                // left unconfirmed, so the removal is retried next run
+                                eprintln!(
+                                    "{}",
+                                    t!("remove_failed", path = format!("{path:?}"), error = error)
+                                );
+                                controller.emit(Event::FileFailed {
+                                    path: path.clone(),
+                                    error: error.to_string(),
+                                });
+                                has_error.store(true, SeqCst);
+                                transport.discard();
+                            }
+                        };
+                    }
+                    _ => unreachable!(),
+                };
+            }
+            .instrument(span),
+        )
+    });
+
+    if let Some(results) = run_until(abandon_at, args.concurrency, remove_actions).await {
+        results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    }
+
+    // then directories, children before their parents
+    let mut rmdir_actions: Vec<_> = todo
+        .iter()
+        .filter(|action| matches!(action, Action::Rmdir(_)))
+        .collect();
+    rmdir_actions.sort_by_key(|action| std::cmp::Reverse(action.path().iter().count()));
+    if !rmdir_actions.is_empty() && !controller.is_cancelled() {
+        let mut transport = pool.get().await?;
+        for (i, action) in rmdir_actions.iter().enumerate() {
+            controller.wait_while_paused().await;
+            if controller.is_cancelled() {
+                break;
+            }
+            let path = action.path();
+            let n = std::time::Instant::now();
+            async {
+                match transport.remove_dir(path).await {
+                    Err(error) if !is_not_found(error.as_ref()) => {
+                        record(results, action, Err(error.to_string()), n.elapsed()).await;
+                        eprintln!(
+                            "{}",
+                            t!(
+                                "remove_directory_failed",
+                                path = format!("{path:?}"),
+                                error = error
+                            )
+                        );
+                        has_error.store(true, SeqCst);
+                    }
+                    _ => {
+                        tracker.lock().await.confirm(action);
+                        record(results, action, Ok(()), n.elapsed()).await;
+                        if !args.table {
+                            println!(
+                                "{}",
+                                t!(
+                                    "removed_directory",
+                                    index = i + 1,
+                                    total = rmdir_actions.len(),
+                                    path = format!("{path:?}"),
+                                    seconds = format!("{:.2}", n.elapsed().as_secs_f64()),
+                                )
+                            );
+                        }
+                    }
+                }
+            }
+            .instrument(action_span(action))
+            .await;
+        }
+    }
+    Ok(())
+}
+
+/// Uploads a copy of the checksum file and prunes old snapshots in parallel
+async fn take_snapshot(
+    args: &Args,
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    checksum_tree: &ChecksumTree,
+    pool: &Arc<TransportPool>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let checksum_file = Path::new(&args.checksum_file);
+    let created = Utc::now();
+    let path = SnapshotIndex::snapshot_path(checksum_file, created);
+    println!("      {}", t!("snapshot_taken", path = path.display()));
+    transport
+        .write_last_checksum(&path, checksum_tree, args.checksum_compression())
+        .await?;
+
+    let mut index = read_snapshot_index(transport, checksum_file).await;
+    index.snapshots.push(Snapshot { path, created });
+    let (mut keep, remove) = args.retention_policy().apply(&index.snapshots);
+
+    let failed = stream::iter(remove)
+        .map(|snapshot| {
+            let pool = Arc::clone(pool);
+            tokio::spawn(async move {
+                let result = match pool.get().await {
+                    Ok(mut transport) => transport.remove(&snapshot.path).await,
+                    Err(error) => Err(error),
+                };
+                match result {
+                    Ok(_) => {
+                        println!(
+                            "      {}",
+                            t!("snapshot_pruned", path = snapshot.path.display())
+                        );
+                        None
+                    }
+                    Err(error) => {
+                        eprintln!(
+                            "{}",
+                            t!(
+                                "snapshot_prune_failed",
+                                path = snapshot.path.display(),
+                                error = error
+                            )
+                        );
+                        Some(snapshot)
+                    }
+                }
+            })
+        })
+        .buffer_unordered(args.concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    // snapshots that failed to be removed are retried on the next run
+    keep.extend(failed.into_iter().flatten());
+    index.snapshots = keep;
+
+    let json = index.to_json()?;
+    let size = json.len() as u64;
+    transport
+        .write(
+            &SnapshotIndex::index_path(checksum_file),
+            Box::new(std::io::Cursor::new(json)),
+            size,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Removes the journal once the checksum file on the remote covers what it has
+fn remove_journal(tracker: &mut StateTracker) {
+    if let Some(Err(error)) = tracker.take_journal().map(Journal::remove) {
+        tracing::warn!("the journal {JOURNAL_FILENAME} can't be removed: {error}");
+    }
+}
+
+/// Downloads the remote file into `archive_dir`, keeping its relative path
+pub async fn archive_file(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    archive_dir: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let pb = Arc::new(indicatif::ProgressBar::new_spinner());
+    pb.set_style(ProgressStyle::with_template(
+        "[{elapsed_precise}] {spinner} {bytes} [{bytes_per_sec}] {wide_msg}",
+    )?);
+    pb.set_message(t!("archiving", path = path.display()));
+    let pb_inner = Arc::clone(&pb);
+    let mut reader = transport
+        .read_with_progress(
+            path,
+            Box::new(move |downloaded| pb_inner.set_position(downloaded)),
+        )
+        .await?;
+    let target = archive_dir.join(path);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let mut file = fs::File::create(target).await?;
+    tokio::io::copy(&mut reader, &mut file).await?;
+    pb.finish_and_clear();
+    Ok(())
+}
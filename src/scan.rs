@@ -0,0 +1,374 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree, FileEntry},
+    cli::Args,
+    config::CONFIG_FILENAME,
+    hash::{self, HashAlgorithm, MetadataChangePolicy},
+    init::ENV_FILENAME,
+    journal::JOURNAL_FILENAME,
+    links::LinkPolicy,
+    merge_base::BASE_FILENAME,
+    path_filter::PathFilter,
+    reconciler::Reconciler,
+    report::PROGRESS_BAR_CHARS,
+    scan_cache::{ScanCache, CACHE_FILENAME},
+    t,
+};
+use futures::{stream, StreamExt};
+use indicatif::ProgressStyle;
+use std::{
+    collections::HashMap,
+    error::Error,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// `--include`, `--exclude` and `--only`
+pub fn path_filter(args: &Args) -> Result<PathFilter, Box<dyn Error + Send + Sync + 'static>> {
+    PathFilter::new(&args.include, &args.exclude)?.with_only(&args.only)
+}
+
+/// What a scan of the directory found
+pub struct LocalFiles {
+    /// Regular files to checksum
+    pub files: Vec<String>,
+    /// Symbolic links and their targets, with `--links preserve` only
+    pub links: Vec<(String, String)>,
+    /// Every directory below the root, parents before their children
+    pub directories: Vec<String>,
+}
+
+pub fn resolve_files(args: &Args) -> Result<LocalFiles, Box<dyn Error + Send + Sync + 'static>> {
+    let mut ignored_files = vec![
+        OsString::from(".git"),
+        OsString::from(".syncboxignore"),
+        OsString::from(CONFIG_FILENAME),
+        OsString::from(ENV_FILENAME),
+        OsString::from(CACHE_FILENAME),
+        OsString::from(JOURNAL_FILENAME),
+        OsString::from(BASE_FILENAME),
+        OsString::from(".DS_Store"),
+    ];
+    ignored_files.push((&args.checksum_file).into());
+    if let Some(name) = args.state_db.as_ref().and_then(|path| path.file_name()) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut name = name.to_os_string();
+            name.push(suffix);
+            ignored_files.push(name);
+        }
+    }
+    let filter = path_filter(args)?;
+    let overrides = filter.overrides().clone();
+    let mut walker = ignore::WalkBuilder::new(".");
+    walker
+        .hidden(false)
+        .filter_entry(move |entry| {
+            !ignored_files.contains(&entry.file_name().to_os_string())
+                && !filter.is_outside_only(
+                    entry.path(),
+                    entry.file_type().is_some_and(|kind| kind.is_dir()),
+                )
+        })
+        .add_custom_ignore_filename(".syncboxignore")
+        .overrides(overrides);
+    if args.deterministic {
+        // otherwise files come in whatever order the filesystem lists them
+        walker.sort_by_file_name(|a, b| a.cmp(b));
+    }
+    walker.follow_links(args.links == LinkPolicy::Follow);
+    let mut files = vec![];
+    let mut links = vec![];
+    let mut directories = vec![];
+    for entry in walker.build() {
+        let entry = match entry {
+            // a followed link pointing nowhere has nothing to upload
+            Err(e)
+                if args.links == LinkPolicy::Follow
+                    && e.io_error()
+                        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            entry => entry?,
+        };
+        // checksum files keep names as text, such a name couldn't be found again
+        let Some(path) = entry.path().to_str().map(str::to_string) else {
+            println!(
+                "      {}",
+                t!("skipped_not_utf8", path = entry.path().display())
+            );
+            continue;
+        };
+        match entry.file_type() {
+            Some(t) if t.is_file() => files.push(path),
+            Some(t) if t.is_dir() && entry.depth() > 0 => directories.push(path),
+            Some(t) if t.is_symlink() && args.links == LinkPolicy::Preserve => {
+                let target = std::fs::read_link(entry.path())?;
+                links.push((path, target.to_string_lossy().to_string()));
+            }
+            _ => {}
+        }
+    }
+    Ok(LocalFiles {
+        files,
+        links,
+        directories,
+    })
+}
+
+pub async fn calculate_checksums(
+    args: &Args,
+    files: Vec<String>,
+) -> Result<HashMap<String, FileEntry>, Box<dyn Error + Send + Sync + 'static>> {
+    let file_size_threshold = args.file_size_threshold * 1024 * 1024;
+    let hash = args.hash;
+    let quick_hash = args.metadata_changes == MetadataChangePolicy::QuickHash;
+    let cache = Arc::new(if args.no_scan_cache {
+        ScanCache::default()
+    } else {
+        ScanCache::load(Path::new(CACHE_FILENAME))
+    });
+    let pb = &if args.deterministic {
+        indicatif::ProgressBar::hidden()
+    } else {
+        indicatif::ProgressBar::new(files.len().try_into()?)
+    };
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:50.cyan/blue} {pos:>7}/{len:7} {wide_msg}",
+        )
+        .unwrap()
+        .progress_chars(PROGRESS_BAR_CHARS),
+    );
+    #[allow(clippy::type_complexity)]
+    let scanned: Vec<(String, std::fs::Metadata, String, Option<String>, bool)> =
+        stream::iter(files)
+            .map(|filepath| {
+                let pb = pb.clone();
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    pb.set_message(filepath.clone());
+                    let path = Path::new(&filepath);
+                    let metadata = tokio::fs::metadata(path).await?;
+                    let algorithm = if metadata.len() > file_size_threshold {
+                        HashAlgorithm::Metadata
+                    } else {
+                        hash
+                    };
+                    let (checksum, cached) = match cache.get(&filepath, &metadata, algorithm) {
+                        Some(checksum) => (checksum.to_string(), true),
+                        None => (
+                            algorithm.checksum(path, &metadata).map_err(|e| {
+                                format!("Failed checksum of {path:?} with error {e:?}")
+                            })?,
+                            false,
+                        ),
+                    };
+                    let quick_hash = quick_hash
+                        .then(|| quick_hash_of(path, &metadata, &checksum))
+                        .transpose()?
+                        .flatten();
+                    pb.inc(1);
+                    Ok((filepath, metadata, checksum, quick_hash, cached))
+                        as Result<_, Box<dyn Error + Send + Sync + 'static>>
+                })
+            })
+            .buffer_unordered(args.scan_threads.unwrap_or_else(num_cpus::get).max(1))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+    pb.finish_and_clear();
+
+    let mut next_cache = ScanCache::default();
+    let mut checksums = HashMap::new();
+    let mut cached_files = 0;
+    for (filepath, metadata, checksum, quick_hash, cached) in scanned {
+        cached_files += usize::from(cached);
+        // metadata checksums are as fast to make as to look up
+        if HashAlgorithm::of(&checksum) != HashAlgorithm::Metadata {
+            next_cache.insert(filepath.clone(), &metadata, checksum.clone());
+        }
+        let checksum = with_permissions(
+            checksum,
+            &metadata,
+            args.preserve_permissions,
+            args.preserve_owner,
+        );
+        let mut entry = FileEntry::new(checksum, &metadata);
+        entry.quick_hash = quick_hash;
+        checksums.insert(filepath, entry);
+    }
+    if cached_files > 0 {
+        println!("      {}", t!("scan_cache_hits", count = cached_files));
+    }
+    if !args.no_scan_cache {
+        if let Err(e) = next_cache.save(Path::new(CACHE_FILENAME)) {
+            println!(
+                "      {}",
+                t!("scan_cache_unwritable", path = CACHE_FILENAME, error = e)
+            );
+        }
+    }
+    Ok(checksums)
+}
+
+/// Quick hash of a file with a metadata checksum, see `--metadata-changes`
+fn quick_hash_of(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    checksum: &str,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync + 'static>> {
+    if HashAlgorithm::of(checksum) != HashAlgorithm::Metadata {
+        return Ok(None);
+    }
+    let quick_hash = hash::quick_hash(path, metadata)
+        .map_err(|e| format!("Failed quick hash of {path:?} with error {e:?}"))?;
+    Ok(Some(quick_hash))
+}
+
+/// Entry of a single file, see `--file-size-threshold`
+pub async fn entry_of(
+    path: &Path,
+    file_size_threshold: u64,
+    hash: HashAlgorithm,
+    quick_hash: bool,
+    preserve_permissions: bool,
+    preserve_owner: bool,
+) -> Result<FileEntry, Box<dyn Error + Send + Sync + 'static>> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let algorithm = if metadata.len() > file_size_threshold {
+        HashAlgorithm::Metadata
+    } else {
+        hash
+    };
+    let checksum = checksum_with(
+        path,
+        &metadata,
+        algorithm,
+        preserve_permissions,
+        preserve_owner,
+    )?;
+    let mut entry = FileEntry::new(checksum, &metadata);
+    if quick_hash {
+        entry.quick_hash = quick_hash_of(path, &metadata, &entry.checksum)?;
+    }
+    Ok(entry)
+}
+
+/// Checksum of a file made with `algorithm`, with its permissions when they
+/// are preserved
+pub fn checksum_with(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    algorithm: HashAlgorithm,
+    preserve_permissions: bool,
+    preserve_owner: bool,
+) -> Result<String, Box<dyn Error + Send + Sync + 'static>> {
+    let checksum = algorithm
+        .checksum(path, metadata)
+        .map_err(|e| format!("Failed checksum of {path:?} with error {e:?}"))?;
+    Ok(with_permissions(
+        checksum,
+        metadata,
+        preserve_permissions,
+        preserve_owner,
+    ))
+}
+
+/// Permission-only changes have to change the checksum to be re-applied
+fn with_permissions(
+    mut checksum: String,
+    metadata: &std::fs::Metadata,
+    preserve_permissions: bool,
+    preserve_owner: bool,
+) -> String {
+    if preserve_permissions {
+        if let Some((mode, owner)) = permissions_of(metadata) {
+            checksum.push_str(&format!("_p{mode:o}"));
+            if preserve_owner {
+                checksum.push_str(&format!("_o{}:{}", owner.0, owner.1));
+            }
+        }
+    }
+    checksum
+}
+
+/// Hashes files again whose previous checksum was made with another algorithm,
+/// e.g. after switching `--hash`, and takes over the new checksums of those that
+/// didn't change, so they aren't uploaded again. Returns how many were taken over
+pub async fn rehash_unchanged(
+    args: &Args,
+    previous: &mut ChecksumTree,
+    next: &ChecksumTree,
+) -> Result<usize, Box<dyn Error + Send + Sync + 'static>> {
+    let changes = Reconciler::algorithm_changes(previous, next);
+    if changes.is_empty() {
+        return Ok(0);
+    }
+    let total = changes.len();
+    let preserve_permissions = args.preserve_permissions;
+    let preserve_owner = args.preserve_owner;
+    let checksums = stream::iter(changes)
+        .map(|(path, algorithm)| {
+            tokio::spawn(async move {
+                let metadata = tokio::fs::metadata(&path).await?;
+                let checksum = checksum_with(
+                    &path,
+                    &metadata,
+                    algorithm,
+                    preserve_permissions,
+                    preserve_owner,
+                )?;
+                Ok((path, checksum)) as Result<_, Box<dyn Error + Send + Sync + 'static>>
+            })
+        })
+        .buffer_unordered(args.scan_threads.unwrap_or_else(num_cpus::get).max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut unchanged = 0;
+    for result in checksums {
+        let (path, checksum): (PathBuf, String) = result??;
+        if matches!(previous.get_at(&path), Some(ChecksumElement::File(previous)) if previous.checksum == checksum)
+        {
+            if let Some(element) = next.get_at(&path) {
+                previous.insert_at(&path, element.clone());
+                unchanged += 1;
+            }
+        }
+    }
+    println!(
+        "      {}",
+        t!("rehashed", total = total, unchanged = unchanged)
+    );
+    Ok(unchanged)
+}
+
+/// Takes over the entries of files that were only touched with
+/// `--metadata-changes quick-hash`, so they aren't transferred again.
+/// Returns how many were taken over
+pub fn take_over_touched(args: &Args, previous: &mut ChecksumTree, next: &ChecksumTree) -> usize {
+    if args.metadata_changes != MetadataChangePolicy::QuickHash {
+        return 0;
+    }
+    let touched = Reconciler::take_over_touched(previous, next);
+    if touched > 0 {
+        println!("      {}", t!("touched", count = touched));
+    }
+    touched
+}
+
+/// Mode bits and (uid, gid) of a file, only available on unix
+#[cfg(unix)]
+pub fn permissions_of(metadata: &std::fs::Metadata) -> Option<(u32, (u32, u32))> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.mode() & 0o7777, (metadata.uid(), metadata.gid())))
+}
+
+#[cfg(not(unix))]
+pub fn permissions_of(_metadata: &std::fs::Metadata) -> Option<(u32, (u32, u32))> {
+    None
+}
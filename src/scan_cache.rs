@@ -0,0 +1,153 @@
+use crate::hash::HashAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    io::{self, Read},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Kept in the synced directory, never synced itself
+pub const CACHE_FILENAME: &str = ".syncbox.cache";
+
+/// Files modified this recently may change again within the resolution of
+/// their modification time without it changing, they are hashed every time
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Checksums of the last scan by path, reused for files whose size and
+/// modification time are the same, so only changed files are read again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanCache {
+    files: HashMap<String, CachedFile>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedFile {
+    size: u64,
+    /// Nanoseconds since the epoch
+    modified: u128,
+    checksum: String,
+}
+
+impl ScanCache {
+    /// Reads `path`, a missing or unreadable cache is an empty one
+    pub fn load(path: &Path) -> Self {
+        let read = || -> Option<Self> {
+            let bytes = std::fs::read(path).ok()?;
+            let mut json = Vec::new();
+            flate2::read::GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut json)
+                .ok()?;
+            serde_json::from_slice(&json).ok()
+        };
+        read().unwrap_or_default()
+    }
+
+    /// Replaces `path` at once, an interrupted write leaves the old cache
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        serde_json::to_writer(&mut encoder, self)?;
+        let temporary = path.with_extension("cache.tmp");
+        std::fs::write(&temporary, encoder.finish()?)?;
+        std::fs::rename(&temporary, path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Checksum of `path` made with `algorithm` when the file didn't change
+    /// since it was cached
+    pub fn get(&self, path: &str, metadata: &Metadata, algorithm: HashAlgorithm) -> Option<&str> {
+        let cached = self.files.get(path)?;
+        (cached.size == metadata.len()
+            && Some(cached.modified) == modified(metadata)
+            && HashAlgorithm::of(&cached.checksum) == algorithm)
+            .then_some(cached.checksum.as_str())
+    }
+
+    pub fn insert(&mut self, path: String, metadata: &Metadata, checksum: String) {
+        let Some(modified) = modified(metadata) else {
+            return;
+        };
+        let racy = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(true, |now| {
+                now.as_nanos() < modified + RACY_WINDOW.as_nanos()
+            });
+        if racy {
+            return;
+        }
+        self.files.insert(
+            path,
+            CachedFile {
+                size: metadata.len(),
+                modified,
+                checksum,
+            },
+        );
+    }
+}
+
+fn modified(metadata: &Metadata) -> Option<u128> {
+    Some(
+        metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+
+    #[test]
+    fn unchanged_files_are_cached() {
+        let dir = std::env::temp_dir().join(format!("syncbox-scan-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "abc").unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+
+        let mut cache = ScanCache::default();
+        cache.insert("./a.txt".into(), &metadata, "blake3:abc".into());
+        assert_eq!(
+            cache.get("./a.txt", &metadata, HashAlgorithm::Blake3),
+            Some("blake3:abc")
+        );
+        assert_eq!(cache.get("./a.txt", &metadata, HashAlgorithm::Sha256), None);
+        assert_eq!(cache.get("./b.txt", &metadata, HashAlgorithm::Blake3), None);
+
+        let path = dir.join(CACHE_FILENAME);
+        cache.save(&path).unwrap();
+        let loaded = ScanCache::load(&path);
+        assert_eq!(
+            loaded.get("./a.txt", &metadata, HashAlgorithm::Blake3),
+            Some("blake3:abc")
+        );
+
+        fs::write(&file, "abcd").unwrap();
+        let changed = fs::metadata(&file).unwrap();
+        assert_eq!(loaded.get("./a.txt", &changed, HashAlgorithm::Blake3), None);
+        // just written, the next write may keep its modification time
+        let mut cache = ScanCache::default();
+        cache.insert("./a.txt".into(), &changed, "blake3:abcd".into());
+        assert!(cache.is_empty());
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(ScanCache::load(&path).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
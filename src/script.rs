@@ -0,0 +1,210 @@
+use crate::{
+    action_filter::{self, ActionDecision},
+    prefix::Variables,
+    reconciler::Action,
+};
+use chrono::{Datelike, Local, Timelike};
+use rhai::{CallFnOptions, Dynamic, Engine, Scope, AST};
+use std::{error::Error, path::Path};
+
+/// The `script` of `syncbox.toml`, Rhai code that may define
+///
+/// - `options(profile)`, returning a map of options the way a profile has
+///   them, added after the profile's when syncbox starts
+/// - `action(kind, path)`, asked about every planned action with the answers
+///   of `--filter-cmd`, or `true` to keep it and `false` to drop it
+///
+/// Both can call `git_branch()`, `git_sha()`, `hostname()`, `date()`,
+/// `weekday()` (1 for Monday to 7), `hour()` and `env(name)`, which give `()`
+/// for what can't be told
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    error: Option<Box<dyn Error + Send + Sync + 'static>>,
+}
+
+impl Script {
+    /// Compiles `source` with the git variables of `directory`
+    pub fn compile(
+        source: &str,
+        directory: &Path,
+    ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let mut engine = Engine::new();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format!("the script of syncbox.toml: {e}"))?;
+        let variables = Variables::detect_in(directory);
+        for (name, value) in [
+            ("git_branch", variables.git_branch),
+            ("git_sha", variables.git_sha),
+            ("hostname", variables.hostname),
+        ] {
+            engine.register_fn(name, move || {
+                value.clone().map_or(Dynamic::UNIT, Dynamic::from)
+            });
+        }
+        engine
+            .register_fn("date", move || variables.date.clone())
+            .register_fn("weekday", || {
+                i64::from(Local::now().weekday().number_from_monday())
+            })
+            .register_fn("hour", || i64::from(Local::now().hour()))
+            .register_fn("env", |name: &str| {
+                std::env::var(name).map_or(Dynamic::UNIT, Dynamic::from)
+            });
+        Ok(Self {
+            engine,
+            ast,
+            error: None,
+        })
+    }
+
+    /// Checks the syntax without running anything
+    pub fn check(source: &str) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Engine::new_raw()
+            .compile(source)
+            .map_err(|e| format!("the script: {e}"))?;
+        Ok(())
+    }
+
+    fn defines(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name)
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Result<Dynamic, String> {
+        // only the function runs, not the statements around it
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args)
+            .map_err(|e| e.to_string())
+    }
+
+    /// What `options(profile)` returns, nothing when it's not defined
+    pub fn options(
+        &self,
+        profile: Option<&str>,
+    ) -> Result<toml::Table, Box<dyn Error + Send + Sync + 'static>> {
+        if !self.defines("options") {
+            return Ok(toml::Table::new());
+        }
+        let profile = profile.map_or(Dynamic::UNIT, |profile| profile.into());
+        let options = self
+            .call("options", (profile,))
+            .map_err(|e| format!("options() of the script failed: {e}"))?;
+        Ok(rhai::serde::from_dynamic(&options)
+            .map_err(|e| format!("options() of the script returned no options: {e}"))?)
+    }
+
+    /// Whether it defines `action`, so `decide` has anything to say
+    pub fn filters(&self) -> bool {
+        self.defines("action")
+    }
+
+    /// Keeps everything after the first failure, which `finish` reports
+    pub fn decide(&mut self, action: &Action) -> ActionDecision {
+        if self.error.is_some() {
+            return ActionDecision::Keep;
+        }
+        let path = action.path().to_string_lossy().into_owned();
+        let answer = self
+            .call("action", (action.kind().to_string(), path.clone()))
+            .and_then(|answer| match answer.as_bool() {
+                Ok(true) => Ok(ActionDecision::Keep),
+                Ok(false) => Ok(ActionDecision::Drop),
+                Err(_) => answer
+                    .into_string()
+                    .ok()
+                    .and_then(|answer| action_filter::decision(&answer, action))
+                    .ok_or_else(|| {
+                        "expected true, false, keep, drop, defer or conflict".to_string()
+                    }),
+            });
+        answer.unwrap_or_else(|e| {
+            self.error = Some(format!("action() of the script failed for {path:?}: {e}").into());
+            ActionDecision::Keep
+        })
+    }
+
+    /// An error if `action` failed anywhere, in which case the plan it
+    /// filtered is not to be executed
+    pub fn finish(mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_options_for_the_profile() {
+        let script = Script::compile(
+            r#"
+            fn options(profile) {
+                #{ exclude: ["drafts/"], prefix_template: `previews/${profile}` }
+            }
+            "#,
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        let options = script.options(Some("staging")).unwrap();
+        assert_eq!(
+            options["prefix_template"].as_str(),
+            Some("previews/staging")
+        );
+        assert_eq!(options["exclude"][0].as_str(), Some("drafts/"));
+        assert!(!script.filters());
+
+        let none = Script::compile("let a = 1;", &std::env::temp_dir()).unwrap();
+        assert!(none.options(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decides_on_each_action() {
+        let mut script = Script::compile(
+            r#"
+            fn action(kind, path) {
+                if path.starts_with("./uploads/") { return false; }
+                if kind == "put" && path.ends_with(".iso") { return "defer"; }
+                if kind == "remove" && weekday() <= 7 { return "conflict"; }
+                true
+            }
+            "#,
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        assert!(script.filters());
+        let filtered = action_filter::filter(
+            vec![
+                Action::Put("./big.iso".into()),
+                Action::Put("./uploads/a.jpg".into()),
+                Action::Remove("./b.txt".into()),
+                Action::Put("./a.txt".into()),
+            ],
+            |action| script.decide(action),
+        );
+        script.finish().unwrap();
+        assert_eq!(
+            filtered,
+            vec![
+                Action::Conflict("./b.txt".into()),
+                Action::Put("./a.txt".into()),
+                Action::Put("./big.iso".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_are_reported_once_filtered() {
+        let mut script = Script::compile(
+            r#"fn action(kind, path) { "maybe" }"#,
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        script.decide(&Action::Put("./a.txt".into()));
+        assert!(script.finish().is_err());
+        assert!(Script::check("fn action(kind, path) {").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::reconciler::Action;
+use std::{collections::HashSet, error::Error, path::Path, str::FromStr};
+
+/// One deterministic slice of a sync plan, so several workers can split the work
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    /// 1-based
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    pub fn new(index: u64, count: u64) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("invalid shard {index}/{count}, expected 1 <= i <= N").into());
+        }
+        Ok(Self { index, count })
+    }
+
+    /// 1-based
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Whether the file at `path` belongs to this shard, the same on every machine
+    pub fn contains(&self, path: &Path) -> bool {
+        let digest = sha256::digest(path.to_string_lossy().as_ref());
+        let hash = u64::from_str_radix(&digest[..16], 16).unwrap();
+        hash % self.count == self.index - 1
+    }
+
+    /// Keeps the actions of this shard and the directories they need
+    pub fn filter(&self, actions: Vec<Action>) -> Vec<Action> {
+        // a directory nothing else is created in is an empty one of its own
+        let all_parents: HashSet<_> = parents(&actions).map(Path::to_path_buf).collect();
+        let actions: Vec<_> = actions
+            .into_iter()
+            .filter(|action| match action {
+                Action::Mkdir(path) if !all_parents.contains(path) => self.contains(path),
+                Action::Mkdir(_) => true,
+                Action::Put(path)
+                | Action::Link(path)
+                | Action::SetMeta(path)
+                | Action::Remove(path)
+                | Action::Rmdir(path)
+                | Action::Get(path)
+                | Action::LocalRemove(path)
+                | Action::LocalMkdir(path)
+                | Action::Conflict(path) => self.contains(path),
+            })
+            .collect();
+        let needed_directories: HashSet<_> = parents(&actions).collect();
+        actions
+            .iter()
+            .filter(|action| match action {
+                Action::Mkdir(path) => {
+                    !all_parents.contains(path) || needed_directories.contains(path.as_path())
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Directories above the created files, links and directories
+fn parents(actions: &[Action]) -> impl Iterator<Item = &Path> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Mkdir(path) | Action::Put(path) | Action::Link(path) => {
+                Some(path.ancestors().skip(1))
+            }
+            _ => None,
+        })
+        .flatten()
+}
+
+impl FromStr for Shard {
+    type Err = Box<dyn Error + Send + Sync + 'static>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or("shard should be in form i/N, e.g. 1/4")?;
+        Self::new(index.trim().parse()?, count.trim().parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse() {
+        assert_eq!("2/4".parse::<Shard>().unwrap(), Shard::new(2, 4).unwrap());
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("4".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn every_path_in_exactly_one_shard() {
+        let shards: Vec<_> = (1..=3).map(|i| Shard::new(i, 3).unwrap()).collect();
+        for i in 0..100 {
+            let path = PathBuf::from(format!("./dir/file{i}.txt"));
+            assert_eq!(
+                shards.iter().filter(|shard| shard.contains(&path)).count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_directories_of_own_puts() {
+        let actions = vec![
+            Action::Mkdir("./a".into()),
+            Action::Put("./a/file.txt".into()),
+            Action::Mkdir("./b".into()),
+            Action::Put("./b/file.txt".into()),
+        ];
+        let shard = (1..=2)
+            .map(|i| Shard::new(i, 2).unwrap())
+            .find(|shard| shard.contains(Path::new("./a/file.txt")))
+            .unwrap();
+        let other_in_same_shard = shard.contains(Path::new("./b/file.txt"));
+
+        let filtered = shard.filter(actions);
+
+        assert!(filtered.contains(&Action::Mkdir("./a".into())));
+        assert!(filtered.contains(&Action::Put("./a/file.txt".into())));
+        assert_eq!(
+            filtered.contains(&Action::Mkdir("./b".into())),
+            other_in_same_shard
+        );
+    }
+
+    #[test]
+    fn empty_directories_belong_to_one_shard() {
+        let actions = vec![
+            Action::Mkdir("./a".into()),
+            Action::Mkdir("./a/empty".into()),
+            Action::Rmdir("./gone".into()),
+        ];
+        let shards: Vec<_> = (1..=3).map(|i| Shard::new(i, 3).unwrap()).collect();
+        for action in &actions[1..] {
+            assert_eq!(
+                shards
+                    .iter()
+                    .filter(|shard| shard.filter(actions.clone()).contains(action))
+                    .count(),
+                1
+            );
+        }
+        let owner = shards
+            .iter()
+            .find(|shard| shard.contains(Path::new("./a/empty")))
+            .unwrap();
+        assert!(owner
+            .filter(actions.clone())
+            .contains(&Action::Mkdir("./a".into())));
+    }
+}
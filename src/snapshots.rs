@@ -0,0 +1,188 @@
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// A copy of the checksum file kept on the remote after a successful run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub created: DateTime<Utc>,
+}
+
+/// Remote index of all snapshots, so they can be listed and pruned without listing the remote
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SnapshotIndex {
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotIndex {
+    pub fn index_path(checksum_file: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.snapshots.json", checksum_file.display()))
+    }
+
+    pub fn snapshot_path(checksum_file: &Path, created: DateTime<Utc>) -> PathBuf {
+        PathBuf::from(format!(
+            "{}.{}",
+            checksum_file.display(),
+            created.format("%Y%m%dT%H%M%SZ")
+        ))
+    }
+
+    pub fn from_json(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Which snapshots survive pruning, mirroring `keep-*` options of backup tools
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+    }
+
+    /// Splits snapshots into the ones to keep and the ones to remove, newest first.
+    /// An empty policy keeps everything.
+    pub fn apply(&self, snapshots: &[Snapshot]) -> (Vec<Snapshot>, Vec<Snapshot>) {
+        let mut snapshots = snapshots.to_vec();
+        snapshots.sort_by_key(|snapshot| Reverse(snapshot.created));
+        if self.is_empty() {
+            return (snapshots, vec![]);
+        }
+
+        // (remaining, bucket of a date, last kept bucket)
+        type Rule = (usize, fn(&DateTime<Utc>) -> (i32, u32), Option<(i32, u32)>);
+        let mut rules: Vec<Rule> = vec![];
+        if let Some(n) = self.keep_daily {
+            rules.push((n, |date| (date.year(), date.ordinal()), None));
+        }
+        if let Some(n) = self.keep_weekly {
+            rules.push((
+                n,
+                |date| (date.iso_week().year(), date.iso_week().week()),
+                None,
+            ));
+        }
+        if let Some(n) = self.keep_monthly {
+            rules.push((n, |date| (date.year(), date.month()), None));
+        }
+
+        let (mut keep, mut remove) = (vec![], vec![]);
+        for (i, snapshot) in snapshots.into_iter().enumerate() {
+            let mut kept = self.keep_last.is_some_and(|n| i < n);
+            // the newest snapshot of every bucket is kept until the rule runs out
+            for (remaining, bucket, last_bucket) in rules.iter_mut() {
+                let current = bucket(&snapshot.created);
+                if *remaining > 0 && *last_bucket != Some(current) {
+                    *remaining -= 1;
+                    *last_bucket = Some(current);
+                    kept = true;
+                }
+            }
+            if kept {
+                keep.push(snapshot);
+            } else {
+                remove.push(snapshot);
+            }
+        }
+        (keep, remove)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn snapshot(day: u32, hour: u32) -> Snapshot {
+        let created = Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap();
+        Snapshot {
+            path: SnapshotIndex::snapshot_path(Path::new("./.syncbox.json.gz"), created),
+            created,
+        }
+    }
+
+    #[test]
+    fn snapshot_path() {
+        assert_eq!(
+            snapshot(2, 3).path,
+            PathBuf::from("./.syncbox.json.gz.20240102T030000Z")
+        );
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let snapshots = vec![snapshot(1, 0), snapshot(2, 0)];
+        let (keep, remove) = RetentionPolicy::default().apply(&snapshots);
+        assert_eq!(keep.len(), 2);
+        assert!(remove.is_empty());
+    }
+
+    #[test]
+    fn keep_last() {
+        let snapshots = vec![snapshot(1, 0), snapshot(3, 0), snapshot(2, 0)];
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..Default::default()
+        };
+        let (keep, remove) = policy.apply(&snapshots);
+        assert_eq!(keep, vec![snapshot(3, 0), snapshot(2, 0)]);
+        assert_eq!(remove, vec![snapshot(1, 0)]);
+    }
+
+    #[test]
+    fn keep_daily_keeps_newest_of_each_day() {
+        let snapshots = vec![
+            snapshot(1, 8),
+            snapshot(1, 20),
+            snapshot(2, 8),
+            snapshot(2, 20),
+            snapshot(3, 8),
+        ];
+        let policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..Default::default()
+        };
+        let (keep, remove) = policy.apply(&snapshots);
+        assert_eq!(keep, vec![snapshot(3, 8), snapshot(2, 20)]);
+        assert_eq!(
+            remove,
+            vec![snapshot(2, 8), snapshot(1, 20), snapshot(1, 8)]
+        );
+    }
+
+    #[test]
+    fn rules_combine() {
+        let snapshots = vec![
+            snapshot(1, 8),
+            snapshot(8, 8),
+            snapshot(9, 8),
+            snapshot(9, 9),
+        ];
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            keep_weekly: Some(2),
+            ..Default::default()
+        };
+        let (keep, remove) = policy.apply(&snapshots);
+        assert_eq!(keep, vec![snapshot(9, 9), snapshot(1, 8)]);
+        assert_eq!(remove, vec![snapshot(9, 8), snapshot(8, 8)]);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    journal::{Entry, Header, Journal},
+    reconciler::Action,
+    state_db::StateDb,
+};
+use std::{collections::HashSet, path::Path};
+
+/// Tracks which planned actions were confirmed by the remote, so the checksum
+/// tree that gets uploaded only claims what actually happened
+pub struct StateTracker {
+    next: ChecksumTree,
+    /// `next` with the actions not confirmed yet reverted to the previous state
+    state: ChecksumTree,
+    planned: Vec<Action>,
+    confirmed: HashSet<Action>,
+    db: Option<StateDb>,
+    db_error: Option<rusqlite::Error>,
+    journal: Option<Journal>,
+}
+
+impl StateTracker {
+    pub fn new(previous: ChecksumTree, mut next: ChecksumTree, planned: &[Action]) -> Self {
+        keep_synced_times(&previous, &mut next);
+        let mut state = next.clone();
+        for action in planned {
+            let path = action.path();
+            match previous.get_at(path) {
+                Some(element) => state.insert_at(path, element.clone()),
+                None => state.remove_at(path),
+            }
+        }
+        Self {
+            next,
+            state,
+            planned: planned.to_vec(),
+            confirmed: HashSet::new(),
+            db: None,
+            db_error: None,
+            journal: None,
+        }
+    }
+
+    /// Commits every confirmed action to `db` as it's confirmed
+    pub fn persist_to(&mut self, db: StateDb) {
+        self.db = Some(db);
+    }
+
+    /// The database given to `persist_to`, or the error that made it stop
+    /// persisting, after which it no longer matches the remote
+    pub fn take_db(&mut self) -> Result<Option<StateDb>, rusqlite::Error> {
+        match self.db_error.take() {
+            Some(error) => Err(error),
+            None => Ok(self.db.take()),
+        }
+    }
+
+    /// Appends every confirmed action to `journal` as it's confirmed
+    pub fn journal_to(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// The journal given to `journal_to`, unless writing it failed
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Starts the journal over, the checksum file described by `header`
+    /// covers what it had
+    pub fn restart_journal(&mut self, header: &Header) {
+        if let Some(journal) = &mut self.journal {
+            if let Err(error) = journal.restart(header) {
+                tracing::warn!("the journal can't be written, a crash redoes what's done: {error}");
+                self.journal = None;
+            }
+        }
+    }
+
+    pub fn confirm(&mut self, action: &Action) {
+        self.confirmed.insert(action.clone());
+        if let Action::Put(path) = action {
+            if let Some(ChecksumElement::File(entry)) = self.next.get_at_mut(path) {
+                entry.synced = Some(chrono::Utc::now().timestamp());
+            }
+        }
+        apply(&self.next, &mut self.state, action);
+        if let Some(db) = &mut self.db {
+            let persisted = match action {
+                Action::Put(path)
+                | Action::Link(path)
+                | Action::SetMeta(path)
+                | Action::Mkdir(path) => {
+                    match self.next.get_at(path) {
+                        // only empty directories are stored on their own
+                        Some(ChecksumElement::Directory(entries)) if !entries.is_empty() => Ok(()),
+                        Some(element) => db.put(path, element),
+                        None => Ok(()),
+                    }
+                }
+                Action::Remove(path) | Action::Rmdir(path) => db.remove(path),
+                // pulling leaves the remote as it is
+                Action::Get(_)
+                | Action::LocalRemove(_)
+                | Action::LocalMkdir(_)
+                | Action::Conflict(_) => Ok(()),
+            };
+            if let Err(error) = persisted {
+                self.db = None;
+                self.db_error = Some(error);
+            }
+        }
+        if let Some(journal) = &mut self.journal {
+            let entry = match action {
+                Action::Put(path) | Action::Link(path) | Action::SetMeta(path) => {
+                    self.next.get_at(path).map(|element| Entry::Put {
+                        path: path.clone(),
+                        element: element.clone(),
+                    })
+                }
+                Action::Mkdir(path) => Some(Entry::Mkdir { path: path.clone() }),
+                Action::Remove(path) | Action::Rmdir(path) => {
+                    Some(Entry::Remove { path: path.clone() })
+                }
+                Action::Get(_)
+                | Action::LocalRemove(_)
+                | Action::LocalMkdir(_)
+                | Action::Conflict(_) => None,
+            };
+            if let Some(Err(error)) = entry.map(|entry| journal.append(&entry)) {
+                tracing::warn!("the journal can't be written, a crash redoes what's done: {error}");
+                self.journal = None;
+            }
+        }
+    }
+
+    /// Number of confirmed file uploads and removals
+    pub fn confirmed_files(&self) -> usize {
+        self.confirmed
+            .iter()
+            .filter(|action| !matches!(action, Action::Mkdir(_) | Action::Rmdir(_)))
+            .count()
+    }
+
+    /// The local tree with every unconfirmed action reverted to the previous
+    /// state, kept up to date as actions are confirmed
+    pub fn state(&self) -> &ChecksumTree {
+        &self.state
+    }
+
+    /// Applies the confirmed actions on top of a tree written by someone else meanwhile
+    pub fn apply_to(&self, tree: &mut ChecksumTree) {
+        for action in self.planned.iter().filter(|a| self.confirmed.contains(a)) {
+            apply(&self.next, tree, action);
+        }
+    }
+
+    /// Paths of the confirmed uploads and removals, in the order they were planned
+    pub fn changed_paths(&self) -> Vec<&Path> {
+        self.planned
+            .iter()
+            .filter(|action| self.confirmed.contains(action))
+            .filter_map(|action| match action {
+                Action::Put(path) | Action::Link(path) | Action::Remove(path) => {
+                    Some(path.as_path())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Number of planned actions not confirmed yet
+    pub fn unconfirmed_count(&self) -> usize {
+        self.unconfirmed().count()
+    }
+
+    fn unconfirmed(&self) -> impl Iterator<Item = &Action> {
+        self.planned
+            .iter()
+            .filter(|action| !self.confirmed.contains(action))
+    }
+}
+
+/// Applies a confirmed action of the plan towards `next` on `tree`
+fn apply(next: &ChecksumTree, tree: &mut ChecksumTree, action: &Action) {
+    match action {
+        Action::Put(path) | Action::Link(path) | Action::SetMeta(path) => {
+            if let Some(element) = next.get_at(path) {
+                tree.insert_at(path, element.clone());
+            }
+        }
+        Action::Mkdir(path) => {
+            if !matches!(tree.get_at(path), Some(ChecksumElement::Directory(_))) {
+                tree.insert_at(path, ChecksumElement::default());
+            }
+        }
+        Action::Remove(path) | Action::Rmdir(path) => tree.remove_at(path),
+        Action::Get(_) | Action::LocalRemove(_) | Action::LocalMkdir(_) | Action::Conflict(_) => {}
+    }
+}
+
+/// Copies when each file last reached the remote from `previous` to the
+/// files of `next` with the same contents
+fn keep_synced_times(previous: &ChecksumTree, next: &mut ChecksumTree) {
+    for (path, previous) in previous {
+        if let Some(ChecksumElement::File(entry)) = next.get_at_mut(&path) {
+            if entry.checksum == previous.checksum {
+                entry.synced = previous.synced;
+                // a scan without --metadata-changes quick-hash doesn't make one
+                if entry.quick_hash.is_none() {
+                    entry.quick_hash.clone_from(&previous.quick_hash);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksum_tree::fixtures::tree, reconciler::Reconciler};
+    use std::path::Path;
+
+    #[test]
+    fn confirmed_actions_are_applied() {
+        let previous = tree(&[("./old.txt", "a"), ("./changed.txt", "b")]);
+        let next = tree(&[("./changed.txt", "c"), ("./new.txt", "d")]);
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+        let mut tracker = StateTracker::new(previous, next.clone(), &actions);
+        actions.iter().for_each(|action| tracker.confirm(action));
+
+        assert!(Reconciler::reconcile(tracker.state().clone(), &next)
+            .unwrap()
+            .is_empty());
+        assert_eq!(tracker.confirmed_files(), 3);
+    }
+
+    #[test]
+    fn unconfirmed_actions_keep_previous_state() {
+        let previous = tree(&[("./old.txt", "a"), ("./changed.txt", "b")]);
+        let next = tree(&[("./changed.txt", "c"), ("./new.txt", "d")]);
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+        let tracker = StateTracker::new(previous.clone(), next.clone(), &actions);
+
+        let state = tracker.state();
+        assert!(Reconciler::reconcile(previous, state).unwrap().is_empty());
+        // so everything is planned again next run
+        assert_eq!(
+            Reconciler::reconcile(state.clone(), &next).unwrap().len(),
+            3
+        );
+    }
+
+    #[test]
+    fn state_follows_confirmations() {
+        let previous = tree(&[("./old.txt", "a"), ("./changed.txt", "b")]);
+        let next = tree(&[("./changed.txt", "c"), ("./dir/new.txt", "d")]);
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+        let mut tracker = StateTracker::new(previous, next, &actions);
+        tracker.confirm(&Action::Mkdir("./dir".into()));
+        tracker.confirm(&Action::Put("./dir/new.txt".into()));
+
+        let state = tracker.state();
+        assert!(state.contains(Path::new("./dir/new.txt")));
+        assert!(state.contains(Path::new("./old.txt")));
+        assert!(matches!(
+            state.get_at(Path::new("./changed.txt")),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "b"
+        ));
+        tracker.confirm(&Action::Remove("./old.txt".into()));
+        assert!(!tracker.state().contains(Path::new("./old.txt")));
+    }
+
+    #[test]
+    fn apply_to_merges_confirmed_actions_only() {
+        let previous = tree(&[("./removed.txt", "a"), ("./kept.txt", "b")]);
+        let next = tree(&[("./new.txt", "c"), ("./failed.txt", "d")]);
+        let actions = vec![
+            Action::Put("./new.txt".into()),
+            Action::Put("./failed.txt".into()),
+            Action::Remove("./removed.txt".into()),
+            Action::Remove("./kept.txt".into()),
+        ];
+        let mut tracker = StateTracker::new(previous, next, &actions);
+        tracker.confirm(&actions[0]);
+        tracker.confirm(&actions[2]);
+
+        let mut remote = tree(&[
+            ("./removed.txt", "a"),
+            ("./kept.txt", "b"),
+            ("./other.txt", "e"),
+        ]);
+        assert_eq!(
+            tracker.changed_paths(),
+            vec![Path::new("./new.txt"), Path::new("./removed.txt")]
+        );
+        tracker.apply_to(&mut remote);
+        assert!(remote.get_at(Path::new("./new.txt")).is_some());
+        assert!(remote.get_at(Path::new("./failed.txt")).is_none());
+        assert!(remote.get_at(Path::new("./removed.txt")).is_none());
+        assert!(remote.get_at(Path::new("./kept.txt")).is_some());
+        assert!(remote.get_at(Path::new("./other.txt")).is_some());
+    }
+
+    #[test]
+    fn uploads_record_when_they_were_synced() {
+        let synced = |tree: &ChecksumTree, path: &str| match tree.get_at(Path::new(path)) {
+            Some(ChecksumElement::File(entry)) => entry.synced,
+            other => panic!("{path} is {other:?}"),
+        };
+        let mut previous = tree(&[("./kept.txt", "a"), ("./changed.txt", "b")]);
+        for path in ["./kept.txt", "./changed.txt"] {
+            if let Some(ChecksumElement::File(entry)) = previous.get_at_mut(Path::new(path)) {
+                entry.synced = Some(1_700_000_000);
+            }
+        }
+        let next = tree(&[
+            ("./kept.txt", "a"),
+            ("./changed.txt", "c"),
+            ("./new.txt", "d"),
+        ]);
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+        let mut tracker = StateTracker::new(previous, next, &actions);
+        tracker.confirm(&Action::Put("./new.txt".into()));
+
+        let before = chrono::Utc::now().timestamp() - 60;
+        let state = tracker.state();
+        assert_eq!(synced(state, "./kept.txt"), Some(1_700_000_000));
+        // not uploaded yet, so still the time the old contents were
+        assert_eq!(synced(state, "./changed.txt"), Some(1_700_000_000));
+        assert!(synced(state, "./new.txt").is_some_and(|synced| synced > before));
+    }
+
+    #[test]
+    fn empty_directories_are_claimed_once_created() {
+        let previous = tree(&[("./a.txt", "a")]);
+        let mut next = previous.clone();
+        next.insert_at(Path::new("./empty"), ChecksumElement::default());
+        next.insert_at(Path::new("./other"), ChecksumElement::default());
+        let actions = Reconciler::reconcile(previous.clone(), &next).unwrap();
+        let mut tracker = StateTracker::new(previous, next, &actions);
+        tracker.confirm(&Action::Mkdir("./empty".into()));
+
+        let state = tracker.state();
+        assert!(state.get_at(Path::new("./empty")).is_some());
+        assert!(state.get_at(Path::new("./other")).is_none());
+        assert_eq!(tracker.confirmed_files(), 0);
+    }
+}
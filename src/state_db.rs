@@ -0,0 +1,365 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree, FileEntry},
+    compression::ObjectCompression,
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{path::Path, time::Duration};
+
+/// Bumped when the tables change, older layouts are dropped and rebuilt from
+/// the checksum file
+const SCHEMA_VERSION: i64 = 3;
+
+/// The state of a remote kept in a local SQLite database. Unlike the checksum
+/// file, which is rewritten whole, every confirmed upload and removal is
+/// committed on its own, so an interrupted run leaves the database exactly as
+/// far as the remote got. One database holds the state of several remotes,
+/// each under its own id
+pub struct StateDb {
+    connection: Connection,
+    remote: String,
+}
+
+impl StateDb {
+    /// Opens the state of the remote identified by `remote`
+    pub fn open(path: &Path, remote: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        // syncs to other remotes may be writing at the same time
+        connection.busy_timeout(Duration::from_secs(30))?;
+        let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            connection.execute_batch(
+                "DROP TABLE IF EXISTS files;
+                DROP TABLE IF EXISTS meta;",
+            )?;
+        }
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS files (
+                remote TEXT NOT NULL,
+                path TEXT NOT NULL,
+                -- both NULL for an empty directory
+                checksum TEXT,
+                target TEXT,
+                size INTEGER,
+                mtime INTEGER,
+                mode INTEGER,
+                synced INTEGER,
+                quick_hash TEXT,
+                PRIMARY KEY (remote, path)
+            );
+            CREATE TABLE IF NOT EXISTS meta (
+                remote TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (remote, key)
+            );",
+        )?;
+        connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self {
+            connection,
+            remote: remote.to_string(),
+        })
+    }
+
+    /// Checksum file and its size on the remote when the stored state was
+    /// last known to match it. A different size means another run wrote the
+    /// checksum file since, and the stored state is stale
+    pub fn synced_with(&self) -> rusqlite::Result<Option<(String, u64)>> {
+        let (Some(checksum_file), Some(size)) = (
+            self.meta("checksum_file")?,
+            self.meta("checksum_file_size")?,
+        ) else {
+            return Ok(None);
+        };
+        Ok(size.parse().ok().map(|size| (checksum_file, size)))
+    }
+
+    pub fn mark_synced(&mut self, checksum_file: &str, size: u64) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        for (key, value) in [
+            ("checksum_file", checksum_file.to_string()),
+            ("checksum_file_size", size.to_string()),
+        ] {
+            transaction.execute(
+                "INSERT OR REPLACE INTO meta (remote, key, value) VALUES (?1, ?2, ?3)",
+                params![self.remote, key, value],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Replaces the stored state with `tree` in one transaction
+    pub fn replace(&mut self, tree: &ChecksumTree) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM files WHERE remote = ?1", params![self.remote])?;
+        transaction.execute("DELETE FROM meta WHERE remote = ?1", params![self.remote])?;
+        {
+            let mut result = Ok(());
+            if let Some(root) = tree.as_ref() {
+                walk(root, "", &mut |path, entry| {
+                    if result.is_ok() {
+                        result = insert(&transaction, &self.remote, path, entry);
+                    }
+                });
+            }
+            result?;
+        }
+        if let Some(compression) = tree.compression() {
+            transaction.execute(
+                "INSERT INTO meta (remote, key, value) VALUES (?1, 'compression', ?2)",
+                params![self.remote, compression.to_string()],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// The stored state as a tree for the reconciler
+    pub fn tree(&self) -> rusqlite::Result<ChecksumTree> {
+        let mut tree = ChecksumTree::default();
+        let mut select = self.connection.prepare(&format!(
+            "SELECT path, {COLUMNS} FROM files WHERE remote = ?1 ORDER BY path"
+        ))?;
+        let mut rows = select.query(params![self.remote])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            tree.insert_at(Path::new(&path), element(row, 1)?);
+        }
+        tree.set_compression(
+            self.meta("compression")?
+                .and_then(|compression| compression.parse::<ObjectCompression>().ok()),
+        );
+        Ok(tree)
+    }
+
+    /// The file, link or empty directory at `path`
+    pub fn get(&self, path: &Path) -> rusqlite::Result<Option<ChecksumElement>> {
+        self.connection
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM files WHERE remote = ?1 AND path = ?2"),
+                params![self.remote, path.to_string_lossy()],
+                |row| element(row, 0),
+            )
+            .optional()
+    }
+
+    /// Number of stored files and empty directories
+    pub fn len(&self) -> rusqlite::Result<u64> {
+        self.connection.query_row(
+            "SELECT COUNT(*) FROM files WHERE remote = ?1",
+            params![self.remote],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn is_empty(&self) -> rusqlite::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Stores a file, link or empty directory, replacing whatever was at
+    /// `path` or below it
+    pub fn put(&mut self, path: &Path, element: &ChecksumElement) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        delete(&transaction, &self.remote, path)?;
+        insert(&transaction, &self.remote, &path.to_string_lossy(), element)?;
+        transaction.commit()
+    }
+
+    /// Removes `path` and everything below it
+    pub fn remove(&mut self, path: &Path) -> rusqlite::Result<()> {
+        delete(&self.connection, &self.remote, path)
+    }
+
+    fn meta(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        self.connection
+            .query_row(
+                "SELECT value FROM meta WHERE remote = ?1 AND key = ?2",
+                params![self.remote, key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+}
+
+const COLUMNS: &str = "checksum, target, size, mtime, mode, synced, quick_hash";
+
+/// The element of a row selecting `COLUMNS` from `offset` on
+fn element(row: &Row, offset: usize) -> rusqlite::Result<ChecksumElement> {
+    if let Some(checksum) = row.get(offset)? {
+        return Ok(ChecksumElement::File(FileEntry {
+            checksum,
+            size: row.get(offset + 2)?,
+            mtime: row.get(offset + 3)?,
+            mode: row.get(offset + 4)?,
+            synced: row.get(offset + 5)?,
+            quick_hash: row.get(offset + 6)?,
+        }));
+    }
+    Ok(match row.get(offset + 1)? {
+        Some(target) => ChecksumElement::Symlink(target),
+        None => ChecksumElement::default(),
+    })
+}
+
+fn insert(
+    connection: &Connection,
+    remote: &str,
+    path: &str,
+    element: &ChecksumElement,
+) -> rusqlite::Result<()> {
+    let (entry, target) = match element {
+        ChecksumElement::File(entry) => (Some(entry), None),
+        ChecksumElement::Symlink(target) => (None, Some(target)),
+        ChecksumElement::Directory(_) => (None, None),
+    };
+    connection
+        .prepare_cached(&format!(
+            "INSERT INTO files (remote, path, {COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+        ))?
+        .execute(params![
+            remote,
+            path,
+            entry.map(|entry| &entry.checksum),
+            target,
+            entry.and_then(|entry| entry.size),
+            entry.and_then(|entry| entry.mtime),
+            entry.and_then(|entry| entry.mode),
+            entry.and_then(|entry| entry.synced),
+            entry.and_then(|entry| entry.quick_hash.as_ref()),
+        ])?;
+    Ok(())
+}
+
+fn delete(connection: &Connection, remote: &str, path: &Path) -> rusqlite::Result<()> {
+    let path = path.to_string_lossy();
+    // '0' follows '/', so the range holds exactly the paths below `path`
+    connection.execute(
+        "DELETE FROM files
+        WHERE remote = ?1 AND (path = ?2 OR (path >= ?2 || '/' AND path < ?2 || '0'))",
+        params![remote, path],
+    )?;
+    Ok(())
+}
+
+/// Calls `visit` with every file, link and empty directory below `element`
+fn walk(element: &ChecksumElement, path: &str, visit: &mut impl FnMut(&str, &ChecksumElement)) {
+    match element {
+        ChecksumElement::Directory(entries) if entries.is_empty() => {
+            if !path.is_empty() {
+                visit(path, element)
+            }
+        }
+        ChecksumElement::Directory(entries) => {
+            for (name, entry) in entries {
+                let path = if path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{path}/{name}")
+                };
+                walk(entry, &path, visit);
+            }
+        }
+        ChecksumElement::File(_) | ChecksumElement::Symlink(_) => visit(path, element),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksum_tree::fixtures::tree, reconciler::Reconciler};
+
+    #[test]
+    fn stores_trees() {
+        let path = std::env::temp_dir().join(format!("syncbox-state-{}.db", std::process::id()));
+        let mut db = StateDb::open(&path, "s3://bucket").unwrap();
+        let mut stored = tree(&[("./a.txt", "a"), ("./blog/index.html", "b")]);
+        stored.insert_at(Path::new("./empty"), ChecksumElement::default());
+        db.replace(&stored).unwrap();
+        assert_eq!(db.len().unwrap(), 3);
+        assert!(Reconciler::reconcile(db.tree().unwrap(), &stored)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(db.synced_with().unwrap(), None);
+        db.mark_synced(".syncbox.json.gz", 42).unwrap();
+        drop(db);
+        let mut db = StateDb::open(&path, "s3://bucket").unwrap();
+        assert_eq!(
+            db.synced_with().unwrap(),
+            Some((".syncbox.json.gz".to_string(), 42))
+        );
+
+        let changed = FileEntry {
+            checksum: "c".into(),
+            size: Some(3),
+            mtime: Some(1_700_000_000),
+            mode: Some(0o644),
+            synced: Some(1_700_000_100),
+            quick_hash: Some("0".repeat(32)),
+        };
+        db.put(
+            Path::new("./blog/index.html"),
+            &ChecksumElement::File(changed.clone()),
+        )
+        .unwrap();
+        assert!(matches!(
+            db.get(Path::new("./blog/index.html")).unwrap(),
+            Some(ChecksumElement::File(entry)) if entry == changed
+        ));
+        // a directory replaced by a link
+        db.put(
+            Path::new("./blog"),
+            &ChecksumElement::Symlink("../shared/blog".into()),
+        )
+        .unwrap();
+        assert!(db.get(Path::new("./blog/index.html")).unwrap().is_none());
+        assert!(matches!(
+            db.get(Path::new("./blog")).unwrap(),
+            Some(ChecksumElement::Symlink(target)) if target == "../shared/blog"
+        ));
+        db.remove(Path::new("./blog")).unwrap();
+        // a sibling sharing the prefix stays
+        db.put(
+            Path::new("./blog.txt"),
+            &ChecksumElement::File("e".to_string().into()),
+        )
+        .unwrap();
+        db.remove(Path::new("./blog")).unwrap();
+        assert!(db.get(Path::new("./blog.txt")).unwrap().is_some());
+        assert_eq!(db.len().unwrap(), 3);
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{suffix}", path.display())).ok();
+        }
+    }
+
+    #[test]
+    fn keeps_remotes_apart() {
+        let path = std::env::temp_dir().join(format!("syncbox-remotes-{}.db", std::process::id()));
+        let mut s3 = StateDb::open(&path, "s3://bucket").unwrap();
+        let mut sftp = StateDb::open(&path, "sftp://me@mirror/www").unwrap();
+        s3.replace(&tree(&[("./a.txt", "a"), ("./b.txt", "b")]))
+            .unwrap();
+        s3.mark_synced(".syncbox.json.gz", 42).unwrap();
+        sftp.replace(&tree(&[("./a.txt", "old")])).unwrap();
+        sftp.remove(Path::new("./b.txt")).unwrap();
+
+        assert_eq!(s3.len().unwrap(), 2);
+        assert_eq!(sftp.len().unwrap(), 1);
+        assert!(matches!(
+            s3.get(Path::new("./a.txt")).unwrap(),
+            Some(ChecksumElement::File(entry)) if entry.checksum == "a"
+        ));
+        assert_eq!(sftp.synced_with().unwrap(), None);
+        assert_eq!(
+            s3.synced_with().unwrap(),
+            Some((".syncbox.json.gz".to_string(), 42))
+        );
+
+        drop((s3, sftp));
+        for suffix in ["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{suffix}", path.display())).ok();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+/// Column-aligned text table, cells may contain ANSI styles
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    header: Vec<String>,
+    right_aligned: Vec<bool>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Self {
+            header: header.iter().map(|title| title.to_string()).collect(),
+            right_aligned: vec![false; header.len()],
+            rows: vec![],
+        }
+    }
+
+    /// Aligns the column to the right, for numbers and durations
+    pub fn align_right(mut self, column: usize) -> Self {
+        self.right_aligned[column] = true;
+        self
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        let mut widths = self
+            .header
+            .iter()
+            .map(|title| console::measure_text_width(title))
+            .collect::<Vec<_>>();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(console::measure_text_width(cell));
+            }
+        }
+        widths
+    }
+
+    fn write_row(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        row: &[String],
+        widths: &[usize],
+    ) -> fmt::Result {
+        let mut line = String::new();
+        for (column, (cell, width)) in row.iter().zip(widths).enumerate() {
+            let padding = " ".repeat(width - console::measure_text_width(cell));
+            if self.right_aligned[column] {
+                line.push_str(&padding);
+                line.push_str(cell);
+            } else {
+                line.push_str(cell);
+                line.push_str(&padding);
+            }
+            line.push_str("  ");
+        }
+        // empty trailing cells don't leave whitespace behind
+        writeln!(f, "{}", line.trim_end())
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widths = self.widths();
+        let header = self
+            .header
+            .iter()
+            .map(|title| console::style(title).bold().to_string())
+            .collect::<Vec<_>>();
+        self.write_row(f, &header, &widths)?;
+        for row in &self.rows {
+            self.write_row(f, row, &widths)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_columns() {
+        console::set_colors_enabled(false);
+        let mut table = Table::new(&["ACTION", "PATH", "SIZE"]).align_right(2);
+        table.push(vec!["put".into(), "./a.txt".into(), "12B".into()]);
+        table.push(vec!["remove".into(), "./dir/b.txt".into(), "1.50KB".into()]);
+        table.push(vec!["mkdir".into(), "./dir".into(), "".into()]);
+        assert_eq!(
+            table.to_string(),
+            "ACTION  PATH           SIZE\n\
+             put     ./a.txt         12B\n\
+             remove  ./dir/b.txt  1.50KB\n\
+             mkdir   ./dir\n"
+        );
+    }
+}
@@ -1,15 +1,78 @@
-use crate::checksum_tree::ChecksumTree;
-use std::{error::Error, io::Cursor, path::Path};
+use crate::{
+    checksum_tree::ChecksumTree, compression::ChecksumCompression, progress::ProgressStream,
+};
+use rusoto_core::RusotoError;
+use rusoto_s3::ListObjectsV2Error;
+use russh_sftp::{client::error::Error as SftpError, protocol::StatusCode};
+use std::{
+    collections::HashSet,
+    error::Error,
+    io::Cursor,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use suppaftp::{FtpError, Status};
 use tokio::io::AsyncRead;
 
+pub mod download;
 pub mod dry;
 pub mod ftp;
 pub mod local;
+pub mod memory;
+pub mod pool;
+pub mod resume;
 pub mod s3;
 pub mod sftp;
+pub mod throttle;
+pub mod trash;
+
+/// Written and removed again to read the server time, at the root of the transport
+pub const CLOCK_PROBE_FILENAME: &str = ".syncbox.clock";
+
+/// Optional features a connected transport ended up supporting
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    /// Transfers are compressed on the wire
+    pub compression: bool,
+    /// Local files can be copied by the transport itself with `copy_local`
+    pub local_copy: bool,
+    /// Parts of a file can be read with `read_range`
+    pub ranged_reads: bool,
+    /// Remote files can be copied on the remote with `copy_remote`
+    pub remote_copy: bool,
+    /// Symbolic links can be created with `symlink`
+    pub symlinks: bool,
+}
+
+/// A file or directory found on the remote
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// Path relative to the root of the transport
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Size in bytes, 0 for directories
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
 
 #[async_trait::async_trait]
 pub trait Transport {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Probes whether the connection still works
+    async fn check_health(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    /// Cheap round trip proving the remote is reachable and accepts the
+    /// credentials, run before any files are scanned
+    async fn ping(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.check_health().await
+    }
+
     async fn read_last_checksum(
         &mut self,
         checksum_filename: &Path,
@@ -18,7 +81,7 @@ pub trait Transport {
             .read(checksum_filename)
             .await
             .ok()
-            .map(|bytes| ChecksumTree::from_gzip(&bytes))
+            .map(|bytes| ChecksumTree::from_compressed(&bytes))
             .transpose()?
             .unwrap_or_default())
     }
@@ -27,8 +90,9 @@ pub trait Transport {
         &mut self,
         checksum_filename: &Path,
         checksum_tree: &ChecksumTree,
+        compression: ChecksumCompression,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
-        let json = checksum_tree.to_gzip()?;
+        let json = checksum_tree.to_compressed(compression)?;
         let file_size = json.len();
         let cursor = Cursor::new(json);
         self.write(checksum_filename, Box::new(cursor), file_size as u64)
@@ -40,6 +104,38 @@ pub trait Transport {
         filename: &Path,
     ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>>;
 
+    /// Streams a remote file instead of buffering it whole,
+    /// transports that can't stream fall back to `read`
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(Box::new(Cursor::new(self.read(filename).await?)))
+    }
+
+    /// Reads only the bytes in `range`, large downloads are split into ranges
+    /// fetched in parallel
+    async fn read_range(
+        &mut self,
+        _filename: &Path,
+        _range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        Err("ranged reads are not supported by this transport".into())
+    }
+
+    /// Like `read_stream`, calling `update_progress_callback` with the bytes downloaded so far
+    async fn read_with_progress(
+        &mut self,
+        filename: &Path,
+        update_progress_callback: Box<dyn Fn(u64) + Send>,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        let reader = self.read_stream(filename).await?;
+        Ok(Box::new(ProgressStream::new(
+            reader,
+            update_progress_callback,
+        )))
+    }
+
     async fn mkdir(&mut self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
 
     async fn write(
@@ -49,10 +145,298 @@ pub trait Transport {
         file_size: u64,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>>;
 
+    /// Uploads into a temporary file next to `filename` and renames it into place
+    /// once complete, so an interrupted upload never leaves a half-written file behind
+    async fn write_atomic(
+        &mut self,
+        filename: &Path,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let temporary_filename = temporary_path(filename);
+        let written = self.write(&temporary_filename, reader, file_size).await?;
+        self.rename(&temporary_filename, filename).await?;
+        Ok(written)
+    }
+
+    /// Copies the local file `source` to `filename` without streaming it through
+    /// syncbox, only available when `Capabilities::local_copy` is set
+    async fn copy_local(
+        &mut self,
+        _source: &Path,
+        _filename: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Err("copying local files is not supported by this transport".into())
+    }
+
+    /// Copies the remote file `from` to `to` without transferring it, returns
+    /// the size of the copy. Only available when `Capabilities::remote_copy`
+    /// is set
+    async fn copy_remote(
+        &mut self,
+        _from: &Path,
+        _to: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Err("copying remote files is not supported by this transport".into())
+    }
+
+    /// Entries directly inside the remote directory `path`
+    async fn list(
+        &mut self,
+        _path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        Err("listing is not supported by this transport".into())
+    }
+
+    /// The remote entry at `path`, `None` when nothing exists there
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let name = path.file_name();
+        Ok(self
+            .list(parent)
+            .await?
+            .into_iter()
+            .find(|entry| entry.path.file_name() == name))
+    }
+
+    async fn rename(
+        &mut self,
+        _from: &Path,
+        _to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Err("rename is not supported by this transport".into())
+    }
+
+    /// Size of a remote file in bytes
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(self.read(path).await?.len() as u64)
+    }
+
+    /// Sets the modification time of an uploaded file
+    async fn set_mtime(
+        &mut self,
+        _path: &Path,
+        _mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Err("setting modification time is not supported by this transport".into())
+    }
+
+    /// Sets mode bits and optionally (uid, gid) of an uploaded file
+    async fn set_permissions(
+        &mut self,
+        _path: &Path,
+        _mode: u32,
+        _owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Err("setting permissions is not supported by this transport".into())
+    }
+
+    /// Current time of the remote clock, usually taken from the modification
+    /// time the server gives a freshly written probe file
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        Err("reading the server time is not supported by this transport".into())
+    }
+
     async fn remove(
         &mut self,
         pathname: &Path,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
 
+    /// Removes an empty remote directory
+    async fn remove_dir(
+        &mut self,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Err("removing directories is not supported by this transport".into())
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`, replacing a
+    /// file or link that's in the way
+    async fn symlink(
+        &mut self,
+        _target: &str,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Err("symbolic links are not supported by this transport".into())
+    }
+
     async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
 }
+
+/// Whether the operation failed only because nothing exists at the path
+pub fn is_not_found(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        return error.kind() == std::io::ErrorKind::NotFound;
+    }
+    if let Some(SftpError::Status(status)) = error.downcast_ref::<SftpError>() {
+        return status.status_code == StatusCode::NoSuchFile;
+    }
+    if let Some(FtpError::UnexpectedResponse(response)) = error.downcast_ref::<FtpError>() {
+        // 550 is also used for permission problems, so the message has to tell
+        let message = String::from_utf8_lossy(&response.body).to_lowercase();
+        return response.status == Status::FileUnavailable
+            && ["no such file", "not found", "does not exist"]
+                .iter()
+                .any(|pattern| message.contains(pattern));
+    }
+    false
+}
+
+/// Whether connecting failed because the remote turned down the credentials
+pub fn is_auth_failure(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(FtpError::UnexpectedResponse(response)) = error.downcast_ref::<FtpError>() {
+        return response.status == Status::NotLoggedIn;
+    }
+    if let Some(error) = error.downcast_ref::<RusotoError<ListObjectsV2Error>>() {
+        return match error {
+            RusotoError::Credentials(_) => true,
+            RusotoError::Unknown(response) => response.status.as_u16() == 403,
+            _ => false,
+        };
+    }
+    // ssh only tells in what it printed
+    error.to_string().contains("Permission denied (")
+}
+
+/// How many seconds the remote clock is ahead of the local one, negative when
+/// it is behind. Accurate to about half the round trip plus the server's
+/// timestamp resolution.
+pub async fn clock_drift<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+) -> Result<f64, Box<dyn Error + Send + Sync + 'static>> {
+    let sent = SystemTime::now();
+    let server_time = transport.server_time().await?;
+    let received = SystemTime::now();
+    Ok(clock_offset(sent, server_time, received))
+}
+
+/// Offset of `server_time` from the middle of the local request window
+fn clock_offset(sent: SystemTime, server_time: SystemTime, received: SystemTime) -> f64 {
+    let local_time = sent + received.duration_since(sent).unwrap_or_default() / 2;
+    match server_time.duration_since(local_time) {
+        Ok(ahead) => ahead.as_secs_f64(),
+        Err(behind) => -behind.duration().as_secs_f64(),
+    }
+}
+
+/// Path of the temporary file `write_atomic` uploads into, e.g. `dir/.name.syncbox.tmp`
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}.syncbox.tmp"))
+}
+
+/// Directories a connection knows to exist on the remote, so writes can create
+/// missing parents without asking the server about every level each time
+#[derive(Debug, Default)]
+pub struct KnownDirs(HashSet<PathBuf>);
+
+impl KnownDirs {
+    /// Parents of `path` not known to exist, outermost first
+    pub fn missing_parents(&self, path: &Path) -> Vec<PathBuf> {
+        let mut missing = path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !matches!(dir.to_str(), Some("" | ".")))
+            .take_while(|dir| !self.0.contains(*dir))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        missing.reverse();
+        missing
+    }
+
+    /// Records `dir`, its parents have to exist as well
+    pub fn insert(&mut self, dir: &Path) {
+        for dir in dir.ancestors() {
+            if !self.0.insert(dir.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    /// Forgets `path` and its parents, removing a file takes empty parents along
+    pub fn forget(&mut self, path: &Path) {
+        for dir in path.ancestors() {
+            self.0.remove(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dirs_skip_existing_parents() {
+        let mut known = KnownDirs::default();
+        assert_eq!(
+            known.missing_parents(Path::new("./a/b/file.txt")),
+            [PathBuf::from("./a"), PathBuf::from("./a/b")]
+        );
+        known.insert(Path::new("./a/b"));
+        assert!(known
+            .missing_parents(Path::new("./a/b/file.txt"))
+            .is_empty());
+        assert_eq!(
+            known.missing_parents(Path::new("./a/c/file.txt")),
+            [PathBuf::from("./a/c")]
+        );
+        known.forget(Path::new("./a/b/file.txt"));
+        assert_eq!(known.missing_parents(Path::new("./a/file.txt")).len(), 1);
+        assert!(known.missing_parents(Path::new("file.txt")).is_empty());
+    }
+
+    #[test]
+    fn temporary_path_is_hidden_sibling() {
+        assert_eq!(
+            temporary_path(Path::new("./dir/file.txt")),
+            PathBuf::from("./dir/.file.txt.syncbox.tmp")
+        );
+        assert_eq!(
+            temporary_path(Path::new("file.txt")),
+            PathBuf::from(".file.txt.syncbox.tmp")
+        );
+    }
+
+    #[test]
+    fn clock_offset_is_measured_from_the_middle_of_the_request() {
+        let sent = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(100);
+        let received = sent + std::time::Duration::from_secs(4);
+        let ahead = sent + std::time::Duration::from_secs(12);
+        assert_eq!(clock_offset(sent, ahead, received), 10.0);
+        assert_eq!(clock_offset(sent, sent, received), -2.0);
+    }
+
+    #[test]
+    fn detects_not_found_errors() {
+        let missing: Box<dyn Error + Send + Sync> =
+            std::io::Error::new(std::io::ErrorKind::NotFound, "gone").into();
+        let denied: Box<dyn Error + Send + Sync> =
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied").into();
+        let ftp_missing: Box<dyn Error + Send + Sync> = FtpError::UnexpectedResponse(
+            suppaftp::types::Response::new(Status::FileUnavailable, b"550 No such file".to_vec()),
+        )
+        .into();
+        let ftp_denied: Box<dyn Error + Send + Sync> =
+            FtpError::UnexpectedResponse(suppaftp::types::Response::new(
+                Status::FileUnavailable,
+                b"550 Permission denied".to_vec(),
+            ))
+            .into();
+        let sftp_missing: Box<dyn Error + Send + Sync> =
+            SftpError::Status(russh_sftp::protocol::Status {
+                id: 1,
+                status_code: StatusCode::NoSuchFile,
+                error_message: "No such file".to_string(),
+                language_tag: "en-US".to_string(),
+            })
+            .into();
+        assert!(is_not_found(missing.as_ref()));
+        assert!(!is_not_found(denied.as_ref()));
+        assert!(is_not_found(ftp_missing.as_ref()));
+        assert!(!is_not_found(ftp_denied.as_ref()));
+        assert!(is_not_found(sftp_missing.as_ref()));
+    }
+}
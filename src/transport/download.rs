@@ -0,0 +1,145 @@
+use super::{pool::TransportPool, resume::MAX_RESUMES};
+use futures::{stream, StreamExt};
+use std::{
+    error::Error,
+    io::SeekFrom,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
+};
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+/// Files are downloaded in ranges of this size, smaller ones in one go
+pub const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Downloads `filename` of `size` bytes into `target`. Transports supporting
+/// ranged reads fetch the ranges in parallel on connections from the pool,
+/// a range that fails is fetched again on a fresh connection. `progress` is
+/// called with the bytes downloaded so far.
+pub async fn download(
+    pool: &Arc<TransportPool>,
+    filename: &Path,
+    size: u64,
+    target: &Path,
+    progress: Arc<dyn Fn(u64) + Send + Sync>,
+) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+    let mut transport = pool.get().await?;
+    if size <= CHUNK_SIZE || !transport.capabilities().ranged_reads {
+        let mut reader = transport
+            .read_with_progress(filename, Box::new(move |read| progress(read)))
+            .await?;
+        let mut file = fs::File::create(target).await?;
+        let written = tokio::io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+        return Ok(written);
+    }
+    drop(transport);
+
+    let file = fs::File::create(target).await?;
+    file.set_len(size).await?;
+    drop(file);
+    let downloaded = Arc::new(AtomicU64::new(0));
+    let ranges = (0..size)
+        .step_by(CHUNK_SIZE as usize)
+        .map(|start| start..(start + CHUNK_SIZE).min(size));
+    stream::iter(ranges)
+        .map(|range| {
+            let downloaded = Arc::clone(&downloaded);
+            let progress = Arc::clone(&progress);
+            async move {
+                let mut attempt = 0;
+                let bytes = loop {
+                    let mut transport = pool.get().await?;
+                    match transport.read_range(filename, range.clone()).await {
+                        Ok(bytes) if bytes.len() as u64 == range.end - range.start => break bytes,
+                        Ok(bytes) => {
+                            return Err(format!(
+                                "expected {} bytes at {}, got {}",
+                                range.end - range.start,
+                                range.start,
+                                bytes.len()
+                            )
+                            .into())
+                        }
+                        Err(e) if attempt == MAX_RESUMES => return Err(e),
+                        Err(e) => {
+                            tracing::warn!("retrying range {range:?} of {filename:?}: {e}");
+                            attempt += 1;
+                            // the connection may be what broke
+                            transport.discard();
+                        }
+                    }
+                };
+                let mut file = fs::OpenOptions::new().write(true).open(target).await?;
+                file.seek(SeekFrom::Start(range.start)).await?;
+                file.write_all(&bytes).await?;
+                file.flush().await?;
+                progress(downloaded.fetch_add(bytes.len() as u64, SeqCst) + bytes.len() as u64);
+                Ok::<_, Box<dyn Error + Send + Sync + 'static>>(())
+            }
+        })
+        .buffer_unordered(pool.size())
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{memory::MemoryTransport, Transport};
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn downloads_large_files_in_ranges() {
+        let remote = MemoryTransport::new();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 1234)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        remote
+            .clone()
+            .write(
+                Path::new("big.bin"),
+                Box::new(std::io::Cursor::new(data.clone())),
+                data.len() as u64,
+            )
+            .await
+            .unwrap();
+        let pool = TransportPool::new(
+            3,
+            Box::new(move || {
+                let remote = remote.clone();
+                Box::pin(async move { Ok(Box::new(remote) as Box<dyn Transport + Send + Sync>) })
+            }),
+        );
+        let target = std::env::temp_dir().join(format!("syncbox-download-{}", std::process::id()));
+        let reported = Arc::new(Mutex::new(vec![]));
+        let progress = {
+            let reported = Arc::clone(&reported);
+            Arc::new(move |bytes| reported.lock().unwrap().push(bytes))
+        };
+
+        let written = download(
+            &pool,
+            Path::new("big.bin"),
+            data.len() as u64,
+            &target,
+            progress,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(std::fs::read(&target).unwrap(), data);
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 3);
+        assert_eq!(reported.iter().max(), Some(&(data.len() as u64)));
+        std::fs::remove_file(&target).unwrap();
+    }
+}
@@ -1,14 +1,21 @@
-use std::{error::Error, io::Cursor, path::Path};
+use std::{error::Error, io::Cursor, ops::Range, path::Path, time::SystemTime};
 
 use tokio::io::AsyncRead;
 
-use super::Transport;
-use crate::checksum_tree::ChecksumTree;
+use super::{Capabilities, RemoteEntry, Transport};
+use crate::{checksum_tree::ChecksumTree, compression::ChecksumCompression};
 
 pub struct DryTransport;
 
 #[async_trait::async_trait]
 impl Transport for DryTransport {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: true,
+            ..Default::default()
+        }
+    }
+
     async fn read_last_checksum(
         &mut self,
         _checksum_filename: &Path,
@@ -20,8 +27,9 @@ impl Transport for DryTransport {
         &mut self,
         checksum_filename: &Path,
         checksum_tree: &ChecksumTree,
+        compression: ChecksumCompression,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
-        let json = checksum_tree.to_gzip()?;
+        let json = checksum_tree.to_compressed(compression)?;
         let file_size = json.len();
         let cursor = Cursor::new(json);
         self.write(checksum_filename, Box::new(cursor), file_size as u64)
@@ -35,6 +43,10 @@ impl Transport for DryTransport {
         Ok(Vec::new())
     }
 
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(SystemTime::now())
+    }
+
     async fn mkdir(&mut self, _path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         Ok(())
     }
@@ -48,6 +60,50 @@ impl Transport for DryTransport {
         Ok(file_size)
     }
 
+    async fn list(
+        &mut self,
+        _path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(vec![])
+    }
+
+    async fn rename(
+        &mut self,
+        _from: &Path,
+        _to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn size(&mut self, _path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Err("dry transport doesn't store any files".into())
+    }
+
+    async fn set_mtime(
+        &mut self,
+        _path: &Path,
+        _mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn set_permissions(
+        &mut self,
+        _path: &Path,
+        _mode: u32,
+        _owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn symlink(
+        &mut self,
+        _target: &str,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
     async fn remove(
         &mut self,
         _pathname: &Path,
@@ -55,7 +111,229 @@ impl Transport for DryTransport {
         Ok(())
     }
 
+    async fn remove_dir(
+        &mut self,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
     async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         Ok(())
     }
 }
+
+/// Reads from the remote of `inner` and pretends to write to it, for
+/// `--dry-run`. Runs plan against what's really on the remote, while uploads,
+/// removals and everything else changing it succeed without doing anything
+pub struct DryRun {
+    inner: Box<dyn Transport + Send + Sync>,
+}
+
+impl DryRun {
+    pub fn new(inner: Box<dyn Transport + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for DryRun {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn check_health(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.check_health().await
+    }
+
+    async fn ping(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.ping().await
+    }
+
+    async fn read_last_checksum(
+        &mut self,
+        checksum_filename: &Path,
+    ) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.read_last_checksum(checksum_filename).await
+    }
+
+    async fn write_last_checksum(
+        &mut self,
+        _checksum_filename: &Path,
+        checksum_tree: &ChecksumTree,
+        compression: ChecksumCompression,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(checksum_tree.to_compressed(compression)?.len() as u64)
+    }
+
+    async fn read(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.read(filename).await
+    }
+
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.read_stream(filename).await
+    }
+
+    async fn read_range(
+        &mut self,
+        filename: &Path,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.read_range(filename, range).await
+    }
+
+    async fn mkdir(&mut self, _path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        _filename: &Path,
+        _reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(file_size)
+    }
+
+    async fn write_atomic(
+        &mut self,
+        _filename: &Path,
+        _reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(file_size)
+    }
+
+    async fn copy_local(
+        &mut self,
+        source: &Path,
+        _filename: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(tokio::fs::metadata(source).await?.len())
+    }
+
+    async fn copy_remote(
+        &mut self,
+        from: &Path,
+        _to: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.size(from).await
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.list(path).await
+    }
+
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.stat(path).await
+    }
+
+    async fn rename(
+        &mut self,
+        _from: &Path,
+        _to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.size(path).await
+    }
+
+    async fn set_mtime(
+        &mut self,
+        _path: &Path,
+        _mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn set_permissions(
+        &mut self,
+        _path: &Path,
+        _mode: u32,
+        _owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    /// Measuring writes a probe file
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(SystemTime::now())
+    }
+
+    async fn remove(
+        &mut self,
+        _pathname: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn remove_dir(
+        &mut self,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn symlink(
+        &mut self,
+        _target: &str,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+
+    #[tokio::test]
+    async fn reads_the_remote_and_leaves_it_alone() {
+        let mut remote = MemoryTransport::new();
+        remote
+            .write(
+                Path::new("kept.txt"),
+                Box::new(Cursor::new(b"kept".to_vec())),
+                4,
+            )
+            .await
+            .unwrap();
+        let mut dry_run = DryRun::new(Box::new(remote.clone()));
+
+        assert_eq!(dry_run.read(Path::new("kept.txt")).await.unwrap(), b"kept");
+        let written = dry_run
+            .write(
+                Path::new("new.txt"),
+                Box::new(Cursor::new(b"new".to_vec())),
+                3,
+            )
+            .await
+            .unwrap();
+        assert_eq!(written, 3);
+        dry_run.mkdir(Path::new("dir")).await.unwrap();
+        dry_run.remove(Path::new("kept.txt")).await.unwrap();
+
+        let listed = remote.list(Path::new("")).await.unwrap();
+        let names: Vec<_> = listed.iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(names, [Path::new("kept.txt")]);
+    }
+}
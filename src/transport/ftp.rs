@@ -1,23 +1,65 @@
-use super::Transport;
+use super::{
+    is_not_found,
+    resume::{ResumableReader, MAX_RESUMES, RESUME_WINDOW},
+    Capabilities, KnownDirs, RemoteEntry, Transport, CLOCK_PROBE_FILENAME,
+};
+use crate::compression::ZlibEncoder;
 use futures::AsyncReadExt;
+use std::io::Read;
 use std::net::ToSocketAddrs;
-use std::{error::Error, path::Path};
+use std::time::{Duration, Instant, SystemTime};
+use std::{error::Error, path::Path, str::FromStr};
 use suppaftp::async_native_tls::TlsConnector;
+use suppaftp::list::File;
 use suppaftp::types::FileType;
 use suppaftp::AsyncNativeTlsConnector;
-use suppaftp::{AsyncNativeTlsFtpStream, FtpError};
+use suppaftp::{AsyncNativeTlsFtpStream, FtpError, Status};
 use tokio::io::AsyncRead;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
+/// Control connections idle for longer than this are probed before being used
+const IDLE_PROBE_AFTER: Duration = Duration::from_secs(30);
+
+/// Runs the operation and when it fails on a control connection that turned out
+/// to be dead, reconnects and runs it once more
+macro_rules! with_reconnect {
+    ($self:ident, $operation:expr) => {
+        match $operation {
+            Err(error) if !$self.is_alive().await => {
+                $self
+                    .reconnect()
+                    .await
+                    .map_err(|e| format!("{error} (reconnecting failed with error: {e})"))?;
+                $operation
+            }
+            result => result,
+        }
+    };
+}
+
 pub struct Connected;
 pub struct Disconnected;
 
+/// What got negotiated with the server after logging in
+struct Negotiated {
+    /// Transfers are deflate compressed
+    mode_z: bool,
+    /// MLSD and MLST are advertised, they report exact sizes and UTC mtimes
+    mlsx: bool,
+}
+
 pub struct Ftp<T = Disconnected> {
     host: String,
     user: String,
     pass: String,
     dir: String,
+    use_tls: bool,
+    compression: bool,
+    mode_z: bool,
+    mlsx: bool,
+    known_dirs: KnownDirs,
     stream: Option<AsyncNativeTlsFtpStream>,
+    last_activity: Instant,
     _data: std::marker::PhantomData<T>,
 }
 
@@ -33,15 +75,51 @@ impl Ftp<Disconnected> {
             user: user.as_ref().to_string(),
             pass: pass.as_ref().to_string(),
             dir: dir.as_ref().to_string(),
+            use_tls: false,
+            compression: false,
+            mode_z: false,
+            mlsx: false,
+            known_dirs: KnownDirs::default(),
             stream: None,
+            last_activity: Instant::now(),
             _data: std::marker::PhantomData,
         }
     }
 
+    /// Use deflate compressed transfers (MODE Z) when the server supports them
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
     pub async fn connect(
         self,
         use_tls: bool,
     ) -> Result<Ftp<Connected>, Box<dyn Error + Send + Sync + 'static>> {
+        let (stream, negotiated) = self.open_stream(use_tls).await?;
+        Ok(Ftp {
+            host: self.host,
+            user: self.user,
+            pass: self.pass,
+            dir: self.dir,
+            use_tls,
+            compression: self.compression,
+            mode_z: negotiated.mode_z,
+            mlsx: negotiated.mlsx,
+            known_dirs: KnownDirs::default(),
+            stream: Some(stream),
+            last_activity: Instant::now(),
+            _data: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<T> Ftp<T> {
+    /// Opens a logged in control connection with the working directory set to `dir`
+    async fn open_stream(
+        &self,
+        use_tls: bool,
+    ) -> Result<(AsyncNativeTlsFtpStream, Negotiated), Box<dyn Error + Send + Sync + 'static>> {
         let ip = &self
             .host
             .to_socket_addrs()?
@@ -74,69 +152,217 @@ impl Ftp<Disconnected> {
                 }
             }
         }
-        Ok(Ftp {
-            host: self.host,
-            user: self.user,
-            pass: self.pass,
-            dir: self.dir,
-            stream: Some(stream),
-            _data: std::marker::PhantomData,
-        })
+        let features = stream.feat().await.unwrap_or_default();
+        let mode_z_advertised = features
+            .get("MODE")
+            .is_some_and(|modes| modes.as_deref().is_some_and(|modes| modes.contains('Z')));
+        let mode_z = self.compression
+            && mode_z_advertised
+            && stream
+                .custom_command("MODE Z", &[Status::CommandOk])
+                .await
+                .is_ok();
+        // MLSD comes with MLST, servers only advertise the latter
+        let mlsx = features.contains_key("MLST");
+        Ok((stream, Negotiated { mode_z, mlsx }))
+    }
+}
+
+impl Ftp<Connected> {
+    async fn is_alive(&mut self) -> bool {
+        self.stream.as_mut().unwrap().noop().await.is_ok()
+    }
+
+    /// Replaces the control connection with a fresh one, restoring the working directory
+    async fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        tracing::warn!(
+            "FTP control connection to {} dropped, reconnecting",
+            self.host
+        );
+        if let Some(mut stream) = self.stream.take() {
+            stream.quit().await.ok();
+        }
+        let (stream, negotiated) = self.open_stream(self.use_tls).await?;
+        self.stream = Some(stream);
+        self.mode_z = negotiated.mode_z;
+        self.mlsx = negotiated.mlsx;
+        Ok(())
+    }
+
+    /// Probes connections that were idle for a while, so operations which can't
+    /// be retried (uploads consume their reader) start on a live connection
+    async fn ensure_connected(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        if self.last_activity.elapsed() > IDLE_PROBE_AFTER && !self.is_alive().await {
+            self.reconnect().await?;
+        }
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Creates the parents of `filename` this connection hasn't seen yet, so
+    /// writes don't depend on mkdir actions having run first
+    async fn create_parents(
+        &mut self,
+        filename: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let missing = self.known_dirs.missing_parents(filename);
+        let Some(parent) = missing.last() else {
+            return Ok(());
+        };
+        // usually the directory is there already, one stat spares a mkdir per level
+        if self.stat(parent).await?.is_some_and(|entry| entry.is_dir) {
+            self.known_dirs.insert(parent);
+            return Ok(());
+        }
+        for dir in missing {
+            Transport::mkdir(self, &dir).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_file(&mut self, filename: &str) -> Result<Vec<u8>, FtpError> {
+        let mut buf = vec![];
+        let stream = self.stream.as_mut().unwrap();
+        stream.transfer_type(FileType::Binary).await?;
+        let mut data_stream = stream.retr_as_stream(filename).await?;
+        data_stream
+            .read_to_end(&mut buf)
+            .await
+            .map_err(FtpError::ConnectionError)?;
+        stream.finalize_retr_stream(data_stream).await?;
+        if self.mode_z {
+            let mut decompressed = vec![];
+            flate2::read::ZlibDecoder::new(buf.as_slice())
+                .read_to_end(&mut decompressed)
+                .map_err(FtpError::ConnectionError)?;
+            return Ok(decompressed);
+        }
+        Ok(buf)
+    }
+
+    /// Uploads the rest of `reader` starting at `offset` of the remote file,
+    /// with REST when the server takes it and APPE otherwise
+    async fn put_file(
+        &mut self,
+        filename: &str,
+        reader: &mut ResumableReader<Box<dyn AsyncRead + Unpin + Send>>,
+        offset: u64,
+    ) -> Result<(), FtpError> {
+        let mode_z = self.mode_z;
+        let stream = self.stream.as_mut().unwrap();
+        stream.transfer_type(FileType::Binary).await?;
+        let append = offset > 0 && stream.resume_transfer(offset as usize).await.is_err();
+        if mode_z {
+            let mut reader = ZlibEncoder::new(reader).compat();
+            match append {
+                true => stream.append_file(filename, &mut reader).await?,
+                false => stream.put_file(filename, &mut reader).await?,
+            };
+        } else {
+            let mut reader = reader.compat();
+            match append {
+                true => stream.append_file(filename, &mut reader).await?,
+                false => stream.put_file(filename, &mut reader).await?,
+            };
+        }
+        Ok(())
+    }
+
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<File>, FtpError> {
+        let stream = self.stream.as_mut().unwrap();
+        // listings are read line by line, so they have to come uncompressed
+        if self.mode_z {
+            stream
+                .custom_command("MODE S", &[Status::CommandOk])
+                .await?;
+        }
+        // LIST output is meant for humans, sizes and dates are whatever the server likes
+        let files = if self.mlsx {
+            stream.mlsd(Some(path)).await.map(|lines| {
+                lines
+                    .iter()
+                    .filter_map(|line| File::from_mlsx_line(line).ok())
+                    .collect()
+            })
+        } else {
+            stream.list(Some(path)).await.map(|lines| {
+                lines
+                    .iter()
+                    .filter_map(|line| File::from_str(line).ok())
+                    .collect()
+            })
+        };
+        if self.mode_z {
+            stream
+                .custom_command("MODE Z", &[Status::CommandOk])
+                .await?;
+        }
+        files
+    }
+
+    /// Facts about a single path from MLST, only when the server advertises it
+    async fn mlst(&mut self, path: &str) -> Result<File, FtpError> {
+        let reply = self.stream.as_mut().unwrap().mlst(Some(path)).await?;
+        // the facts come on their own line between the 250 lines
+        reply
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("250"))
+            .find_map(|line| File::from_mlsx_line(line).ok())
+            .ok_or(FtpError::BadResponse)
+    }
+
+    /// Bytes of `filename` the server stored, from MLST when available as SIZE
+    /// depends on the transfer type
+    async fn stored_size(&mut self, filename: &str) -> Result<u64, FtpError> {
+        if self.mlsx {
+            return self.mlst(filename).await.map(|file| file.size() as u64);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        stream.transfer_type(FileType::Binary).await?;
+        stream.size(filename).await.map(|size| size as u64)
     }
 }
 
 #[async_trait::async_trait]
 impl Transport for Ftp<Connected> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            compression: self.mode_z,
+            ..Default::default()
+        }
+    }
+
+    async fn check_health(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.stream.as_mut().unwrap().noop().await?;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    // no read_stream, the control connection has to confirm the transfer once
+    // the data connection is drained, so it can't be handed out
     async fn read(
         &mut self,
         filename: &Path,
     ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
-        let mut buf = vec![];
-        self.stream
-            .as_mut()
-            .unwrap()
-            .transfer_type(FileType::Binary)
-            .await?;
-        let mut stream = self
-            .stream
-            .as_mut()
-            .unwrap()
-            .retr_as_stream(
-                filename
-                    .to_str()
-                    .ok_or(format!("failed converting Path to str: {filename:?}"))?,
-            )
-            .await?;
-        stream.read_to_end(&mut buf).await?;
-        self.stream
-            .as_mut()
-            .unwrap()
-            .finalize_retr_stream(stream)
-            .await?;
-        Ok(buf)
+        let filename = filename
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {filename:?}"))?;
+        self.ensure_connected().await?;
+        Ok(with_reconnect!(self, self.read_file(filename).await)?)
     }
 
     async fn mkdir(&mut self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        match self
-            .stream
-            .as_mut()
-            .unwrap()
-            .mkdir(path.to_str().ok_or("fail converting path to str")?)
-            .await
-            .map_err(|e| {
-                Box::<dyn Error + Send + Sync + 'static>::from(format!(
-                    "mkdir failed with error: {e}"
-                ))
-            }) {
-            Err(e) => {
-                if e.to_string().contains("File exists") {
-                    // safe to ignore
-                    return Ok(());
-                }
-                Err(e)
+        let dir = path.to_str().ok_or("fail converting path to str")?;
+        self.ensure_connected().await?;
+        if let Err(e) = with_reconnect!(self, self.stream.as_mut().unwrap().mkdir(dir).await) {
+            // servers word "already exists" differently, so look instead of parsing the reply
+            if !self.stat(path).await?.is_some_and(|entry| entry.is_dir) {
+                return Err(format!("mkdir failed with error: {e}").into());
             }
-            x => x,
         }
+        self.known_dirs.insert(path);
+        Ok(())
     }
 
     async fn write(
@@ -145,62 +371,228 @@ impl Transport for Ftp<Connected> {
         reader: Box<dyn AsyncRead + Unpin + Send>,
         _file_size: u64,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
-        self.stream
-            .as_mut()
-            .unwrap()
-            .transfer_type(FileType::Binary)
-            .await?;
-        let size = self
-            .stream
-            .as_mut()
-            .unwrap()
-            .put_file(
-                filename.to_str().ok_or(format!(
-                    "failed converting path to str, filename: {filename:?}"
-                ))?,
-                &mut reader.compat(),
-            )
-            .await?;
-        Ok(size)
+        self.ensure_connected().await?;
+        self.create_parents(filename).await?;
+        let filename = filename.to_str().ok_or(format!(
+            "failed converting path to str, filename: {filename:?}"
+        ))?;
+        let mut reader = ResumableReader::new(reader, RESUME_WINDOW);
+        let mut resumes = 0;
+        loop {
+            let offset = reader.position();
+            let error = match self.put_file(filename, &mut reader, offset).await {
+                Ok(()) => return Ok(reader.position()),
+                Err(error) if resumes < MAX_RESUMES && reader.position() > 0 => error,
+                Err(error) => return Err(error.into()),
+            };
+            resumes += 1;
+            if !self.is_alive().await {
+                self.reconnect()
+                    .await
+                    .map_err(|e| format!("{error} (reconnecting failed with error: {e})"))?;
+            }
+            // whatever the server stored is kept, only the rest gets sent again
+            let stored = self.stored_size(filename).await.unwrap_or(0);
+            if !reader.rewind_to(stored) && !reader.rewind_to(0) {
+                return Err(error.into());
+            }
+            tracing::warn!(
+                "upload of {filename} failed after {} bytes with error: {error}, resuming",
+                reader.position()
+            );
+        }
     }
 
-    async fn remove(
+    async fn list(
         &mut self,
-        mut pathname: &Path,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let dir = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        self.ensure_connected().await?;
+        let files = with_reconnect!(self, self.list_dir(dir).await)?;
+        Ok(files
+            .iter()
+            .filter(|file| file.name() != "." && file.name() != "..")
+            .map(|file| RemoteEntry {
+                path: path.join(file.name()),
+                is_dir: file.is_directory(),
+                size: if file.is_directory() {
+                    0
+                } else {
+                    file.size() as u64
+                },
+                modified: Some(file.modified()),
+            })
+            .collect())
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        self.stream
-            .as_mut()
-            .unwrap()
-            .rm(pathname
-                .to_str()
-                .ok_or(format!("failed converting Path to str: {pathname:?}"))
-                .map_err(FtpError::SecureError)?)
-            .await?;
-
-        while let Some(parent_pathname) = pathname.parent() {
-            if self
-                .stream
+        let from = from
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {from:?}"))?;
+        let to = to
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {to:?}"))?;
+        self.ensure_connected().await?;
+        Ok(with_reconnect!(
+            self,
+            self.stream.as_mut().unwrap().rename(from, to).await
+        )?)
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let path = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        self.ensure_connected().await?;
+        Ok(with_reconnect!(self, self.stored_size(path).await)?)
+    }
+
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        if !self.mlsx {
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let name = path.file_name();
+            return Ok(self
+                .list(parent)
+                .await?
+                .into_iter()
+                .find(|entry| entry.path.file_name() == name));
+        }
+        let name = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        self.ensure_connected().await?;
+        match with_reconnect!(self, self.mlst(name).await) {
+            Ok(file) => Ok(Some(RemoteEntry {
+                path: path.to_path_buf(),
+                is_dir: file.is_directory(),
+                size: if file.is_directory() {
+                    0
+                } else {
+                    file.size() as u64
+                },
+                modified: Some(file.modified()),
+            })),
+            Err(e) => {
+                let e: Box<dyn Error + Send + Sync + 'static> = e.into();
+                if is_not_found(e.as_ref()) {
+                    Ok(None)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        let command = format!(
+            "MFMT {} {path}",
+            chrono::DateTime::<chrono::Utc>::from(mtime).format("%Y%m%d%H%M%S")
+        );
+        self.ensure_connected().await?;
+        with_reconnect!(
+            self,
+            self.stream
                 .as_mut()
                 .unwrap()
-                .rmdir(
-                    parent_pathname
-                        .to_str()
-                        .ok_or(format!("failed converting Path to str: {pathname:?}"))
-                        .map_err(FtpError::SecureError)?,
-                )
+                .custom_command(&command, &[Status::File])
                 .await
-                .ok()
-                .is_none()
-            {
-                // ignore errors about deleting directories but bail out on first error
-                break;
+        )?;
+        if self.mlsx {
+            // some servers acknowledge MFMT without applying it
+            let stored = self.mlst(path).await?.modified();
+            let requested = mtime.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+            if stored.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() != requested {
+                return Err(format!(
+                    "server kept modification time {} after MFMT",
+                    chrono::DateTime::<chrono::Utc>::from(stored).format("%Y-%m-%d %H:%M:%S")
+                )
+                .into());
             }
-            pathname = parent_pathname;
         }
+        Ok(())
+    }
+
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        let probe = Path::new(CLOCK_PROBE_FILENAME);
+        Transport::write(self, probe, Box::new(std::io::Cursor::new(vec![])), 0).await?;
+        let stream = self.stream.as_mut().unwrap();
+        let mtime = stream.mdtm(CLOCK_PROBE_FILENAME).await;
+        stream.rm(CLOCK_PROBE_FILENAME).await.ok();
+        // MDTM is always in UTC
+        Ok(SystemTime::from(mtime?.and_utc()))
+    }
 
+    async fn remove(
+        &mut self,
+        pathname: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.known_dirs.forget(pathname);
+        let filename = pathname
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {pathname:?}"))
+            .map_err(FtpError::SecureError)?;
+        self.ensure_connected().await?;
+        with_reconnect!(self, self.stream.as_mut().unwrap().rm(filename).await)?;
         Ok(())
     }
 
+    async fn set_permissions(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        if owner.is_some() {
+            return Err("changing ownership is not supported over FTP".into());
+        }
+        let path = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        self.ensure_connected().await?;
+        // not part of RFC 959, but understood by most Unix servers
+        with_reconnect!(
+            self,
+            self.stream
+                .as_mut()
+                .unwrap()
+                .site(format!("CHMOD {mode:o} {path}"))
+                .await
+        )?;
+        Ok(())
+    }
+
+    async fn remove_dir(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.known_dirs.forget(path);
+        let path = path
+            .to_str()
+            .ok_or(format!("failed converting Path to str: {path:?}"))?;
+        self.ensure_connected().await?;
+        Ok(with_reconnect!(
+            self,
+            self.stream.as_mut().unwrap().rmdir(path).await
+        )?)
+    }
+
     async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         Ok(self.stream.as_mut().unwrap().quit().await?)
     }
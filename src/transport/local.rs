@@ -1,24 +1,84 @@
-use super::Transport;
+use super::{trash, Capabilities, RemoteEntry, Transport, CLOCK_PROBE_FILENAME};
 use std::{
     error::Error,
+    io,
+    ops::Range,
     path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt},
 };
-use tokio::{fs, io::AsyncRead};
 
 pub struct LocalFilesystem {
     dir: PathBuf,
+    use_trash: bool,
 }
 
 impl LocalFilesystem {
+    /// A relative `dir` is anchored to the current directory right away, so
+    /// changing directories later doesn't move the destination
     pub fn new(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
         Self {
-            dir: dir.as_ref().to_path_buf(),
+            dir: std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()),
+            use_trash: false,
         }
     }
+
+    /// Moves removed files to the trash instead of deleting them
+    pub fn use_trash(mut self, use_trash: bool) -> Self {
+        self.use_trash = use_trash;
+        self
+    }
+}
+
+/// Clones the file's extents on filesystems supporting it (btrfs, XFS), otherwise
+/// leaves it to `std::fs::copy`, which uses `copy_file_range` on Linux and
+/// `fclonefileat` on macOS
+fn reflink_or_copy(source: &Path, destination: &Path) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        let source_file = std::fs::File::open(source)?;
+        let destination_file = std::fs::File::create(destination)?;
+        // SAFETY: both descriptors stay open for the duration of the call
+        let cloned = unsafe {
+            libc::ioctl(
+                destination_file.as_raw_fd(),
+                libc::FICLONE,
+                source_file.as_raw_fd(),
+            )
+        };
+        if cloned == 0 {
+            return Ok(source_file.metadata()?.len());
+        }
+    }
+    std::fs::copy(source, destination)
 }
 
 #[async_trait::async_trait]
 impl Transport for LocalFilesystem {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            local_copy: true,
+            ranged_reads: true,
+            symlinks: cfg!(unix),
+            ..Default::default()
+        }
+    }
+
+    async fn ping(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let metadata = fs::metadata(&self.dir)
+            .await
+            .map_err(|e| format!("cannot access {:?}: {e}", self.dir))?;
+        if !metadata.is_dir() {
+            return Err(format!("{:?} is not a directory", self.dir).into());
+        }
+        Ok(())
+    }
+
     async fn read(
         &mut self,
         filename: &Path,
@@ -28,14 +88,43 @@ impl Transport for LocalFilesystem {
         Ok(fs::read(path).await?)
     }
 
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(Box::new(fs::File::open(self.dir.join(filename)).await?))
+    }
+
+    async fn read_range(
+        &mut self,
+        filename: &Path,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        let mut file = fs::File::open(self.dir.join(filename)).await?;
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let mut buf = vec![0; (range.end - range.start) as usize];
+        file.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
     async fn mkdir(
         &mut self,
         dir_path: &Path,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         let mut path = self.dir.clone();
         path.push(dir_path);
-        tokio::fs::create_dir(path).await?;
-        Ok(())
+        match tokio::fs::create_dir(&path).await {
+            // left behind by a previous run, unlike a link to a directory
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AlreadyExists
+                    && fs::symlink_metadata(&path)
+                        .await
+                        .is_ok_and(|metadata| metadata.is_dir()) =>
+            {
+                Ok(())
+            }
+            result => Ok(result?),
+        }
     }
 
     async fn write(
@@ -51,11 +140,131 @@ impl Transport for LocalFilesystem {
         Ok(tokio::io::copy(&mut source, &mut file).await?)
     }
 
+    async fn copy_local(
+        &mut self,
+        source: &Path,
+        filename: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let source = source.to_path_buf();
+        let destination = self.dir.join(filename);
+        Ok(tokio::task::spawn_blocking(move || reflink_or_copy(&source, &destination)).await??)
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let mut entries = vec![];
+        let mut dir = fs::read_dir(self.dir.join(path)).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            entries.push(RemoteEntry {
+                path: path.join(entry.file_name()),
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: metadata.modified().ok(),
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        match fs::metadata(self.dir.join(path)).await {
+            Ok(metadata) => Ok(Some(RemoteEntry {
+                path: path.to_path_buf(),
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: metadata.modified().ok(),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(tokio::fs::rename(self.dir.join(from), self.dir.join(to)).await?)
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(fs::metadata(self.dir.join(path)).await?.len())
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let file = std::fs::File::options()
+            .write(true)
+            .open(self.dir.join(path))?;
+        Ok(file.set_modified(mtime)?)
+    }
+
+    #[cfg(unix)]
+    async fn set_permissions(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = self.dir.join(path);
+        if let Some((uid, gid)) = owner {
+            std::os::unix::fs::chown(&path, Some(uid), Some(gid))?;
+        }
+        Ok(tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?)
+    }
+
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        // the directory may be a network mount with a clock of its own
+        let probe = self.dir.join(CLOCK_PROBE_FILENAME);
+        fs::write(&probe, b"").await?;
+        let modified = fs::metadata(&probe)
+            .await
+            .and_then(|metadata| metadata.modified());
+        fs::remove_file(&probe).await.ok();
+        Ok(modified?)
+    }
+
     async fn remove(
         &mut self,
         pathname: &Path,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        Ok(tokio::fs::remove_file(pathname).await?)
+        let path = self.dir.join(pathname);
+        if self.use_trash {
+            return Ok(tokio::task::spawn_blocking(move || trash::move_to_trash(&path)).await??);
+        }
+        Ok(tokio::fs::remove_file(path).await?)
+    }
+
+    async fn remove_dir(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(tokio::fs::remove_dir(self.dir.join(path)).await?)
+    }
+
+    #[cfg(unix)]
+    async fn symlink(
+        &mut self,
+        target: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = self.dir.join(path);
+        if fs::symlink_metadata(&path)
+            .await
+            .is_ok_and(|metadata| !metadata.is_dir())
+        {
+            fs::remove_file(&path).await?;
+        }
+        Ok(fs::symlink(target, path).await?)
     }
 
     async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
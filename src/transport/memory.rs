@@ -0,0 +1,351 @@
+use super::{Capabilities, RemoteEntry, Transport};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<PathBuf, (Vec<u8>, SystemTime)>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+/// Keeps the remote in memory, clones share the same state so several
+/// connections can be simulated
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn not_found(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no such file: {path:?}"),
+    )
+}
+
+/// Drops `.` components so `./a/b` and `a/b` are the same entry
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+impl State {
+    fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || self.dirs.contains(path)
+    }
+
+    fn parent_exists(&self, path: &Path) -> bool {
+        self.is_dir(path.parent().unwrap_or(Path::new("")))
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for MemoryTransport {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            ..Default::default()
+        }
+    }
+
+    async fn read_range(
+        &mut self,
+        filename: &Path,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        let state = self.state.lock().unwrap();
+        let contents = &state
+            .files
+            .get(&normalize(filename))
+            .ok_or_else(|| not_found(filename))?
+            .0;
+        Ok(contents
+            .get(range.start as usize..range.end as usize)
+            .ok_or("range is past the end of the file")?
+            .to_vec())
+    }
+
+    async fn read(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .files
+            .get(&normalize(filename))
+            .ok_or_else(|| not_found(filename))?
+            .0
+            .clone())
+    }
+
+    async fn mkdir(&mut self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        if state.files.contains_key(&path) {
+            return Err(format!("a file exists at {path:?}").into());
+        }
+        if !state.parent_exists(&path) {
+            return Err(format!("no such directory: {:?}", path.parent()).into());
+        }
+        state.dirs.insert(path);
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        filename: &Path,
+        mut reader: Box<dyn AsyncRead + Unpin + Send>,
+        _file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let path = normalize(filename);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).await?;
+        let mut state = self.state.lock().unwrap();
+        if !state.parent_exists(&path) || state.is_dir(&path) {
+            return Err(format!("cannot write {path:?}").into());
+        }
+        let written = buf.len() as u64;
+        state.files.insert(path, (buf, SystemTime::now()));
+        Ok(written)
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let dir = normalize(path);
+        let state = self.state.lock().unwrap();
+        if !state.is_dir(&dir) {
+            return Err(format!("no such directory: {path:?}").into());
+        }
+        let in_dir = |entry: &&PathBuf| entry.parent() == Some(dir.as_path());
+        // entries are reported relative to `path` as given, like the other transports do
+        let dirs = state.dirs.iter().filter(in_dir).map(|entry| RemoteEntry {
+            path: path.join(entry.file_name().unwrap_or_default()),
+            is_dir: true,
+            size: 0,
+            modified: None,
+        });
+        let files = state.files.iter().filter(|(entry, _)| in_dir(entry)).map(
+            |(entry, (content, modified))| RemoteEntry {
+                path: path.join(entry.file_name().unwrap_or_default()),
+                is_dir: false,
+                size: content.len() as u64,
+                modified: Some(*modified),
+            },
+        );
+        Ok(dirs.chain(files).collect())
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut state = self.state.lock().unwrap();
+        if !state.parent_exists(&to) {
+            return Err(format!("no such directory: {:?}", to.parent()).into());
+        }
+        if state.dirs.contains(&from) {
+            // move the whole subtree
+            let moved = |path: &PathBuf| to.join(path.strip_prefix(&from).unwrap());
+            state.dirs = std::mem::take(&mut state.dirs)
+                .into_iter()
+                .map(|dir| {
+                    if dir.starts_with(&from) {
+                        moved(&dir)
+                    } else {
+                        dir
+                    }
+                })
+                .collect();
+            state.files = std::mem::take(&mut state.files)
+                .into_iter()
+                .map(|(file, content)| {
+                    if file.starts_with(&from) {
+                        (moved(&file), content)
+                    } else {
+                        (file, content)
+                    }
+                })
+                .collect();
+            return Ok(());
+        }
+        let file = state.files.remove(&from).ok_or_else(|| not_found(&from))?;
+        state.files.insert(to, file);
+        Ok(())
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(self.read(path).await?.len() as u64)
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut state = self.state.lock().unwrap();
+        let file = state
+            .files
+            .get_mut(&normalize(path))
+            .ok_or_else(|| not_found(path))?;
+        file.1 = mtime;
+        Ok(())
+    }
+
+    async fn remove(
+        &mut self,
+        pathname: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .files
+            .remove(&normalize(pathname))
+            .ok_or_else(|| not_found(pathname))?;
+        Ok(())
+    }
+
+    async fn remove_dir(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        if !state.dirs.contains(&path) {
+            return Err(not_found(&path).into());
+        }
+        let is_empty = !state
+            .dirs
+            .iter()
+            .any(|dir| dir.parent() == Some(path.as_path()))
+            && !state
+                .files
+                .keys()
+                .any(|file| file.parent() == Some(path.as_path()));
+        if !is_empty {
+            return Err(format!("directory not empty: {path:?}").into());
+        }
+        state.dirs.remove(&path);
+        Ok(())
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    async fn write(transport: &mut MemoryTransport, path: &str, content: &str) -> u64 {
+        let content = content.as_bytes().to_vec();
+        let size = content.len() as u64;
+        transport
+            .write(Path::new(path), Box::new(Cursor::new(content)), size)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mkdir_is_idempotent() {
+        let mut transport = MemoryTransport::new();
+        transport.mkdir(Path::new("./a")).await.unwrap();
+        transport.mkdir(Path::new("./a")).await.unwrap();
+        transport.mkdir(Path::new("./a/b")).await.unwrap();
+        let entry = transport.stat(Path::new("a/b")).await.unwrap().unwrap();
+        assert!(entry.is_dir);
+    }
+
+    #[tokio::test]
+    async fn mkdir_fails_over_file_or_without_parent() {
+        let mut transport = MemoryTransport::new();
+        write(&mut transport, "./a", "content").await;
+        assert!(transport.mkdir(Path::new("./a")).await.is_err());
+        assert!(transport.mkdir(Path::new("./b/c")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn write_read_and_list() {
+        let mut transport = MemoryTransport::new();
+        transport.mkdir(Path::new("./dir")).await.unwrap();
+        assert_eq!(write(&mut transport, "./dir/file", "hello").await, 5);
+        assert_eq!(
+            transport.read(Path::new("dir/file")).await.unwrap(),
+            b"hello"
+        );
+        assert!(write_fails(&mut transport, "./missing/file").await);
+        let mut streamed = vec![];
+        transport
+            .read_stream(Path::new("./dir/file"))
+            .await
+            .unwrap()
+            .read_to_end(&mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(streamed, b"hello");
+
+        let root = transport.list(Path::new(".")).await.unwrap();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].path, PathBuf::from("./dir"));
+        let dir = transport.list(Path::new("./dir")).await.unwrap();
+        assert_eq!(dir.len(), 1);
+        assert_eq!(dir[0].size, 5);
+        assert!(!dir[0].is_dir);
+    }
+
+    #[tokio::test]
+    async fn rename_moves_file() {
+        let mut transport = MemoryTransport::new();
+        transport.mkdir(Path::new("./dir")).await.unwrap();
+        write(&mut transport, "./file", "content").await;
+        transport
+            .rename(Path::new("./file"), Path::new("./dir/moved"))
+            .await
+            .unwrap();
+        assert!(transport.stat(Path::new("./file")).await.unwrap().is_none());
+        assert_eq!(
+            transport.read(Path::new("./dir/moved")).await.unwrap(),
+            b"content"
+        );
+        assert!(transport
+            .rename(Path::new("./dir/moved"), Path::new("./missing/file"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn clones_share_state() {
+        let mut transport = MemoryTransport::new();
+        let mut other = transport.clone();
+        write(&mut transport, "./file", "content").await;
+        assert!(other.stat(Path::new("./file")).await.unwrap().is_some());
+        other.remove(Path::new("./file")).await.unwrap();
+        assert!(transport.stat(Path::new("./file")).await.unwrap().is_none());
+        let error = transport.remove(Path::new("./file")).await.unwrap_err();
+        assert!(crate::transport::is_not_found(error.as_ref()));
+    }
+
+    async fn write_fails(transport: &mut MemoryTransport, path: &str) -> bool {
+        transport
+            .write(Path::new(path), Box::new(Cursor::new(vec![])), 0)
+            .await
+            .is_err()
+    }
+}
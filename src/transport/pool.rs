@@ -0,0 +1,203 @@
+use super::Transport;
+use futures::future::BoxFuture;
+use std::{
+    error::Error,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Connections idle for longer than this are health checked before being handed out
+const CHECK_AFTER: Duration = Duration::from_secs(30);
+
+pub type Connect = Box<
+    dyn Fn() -> BoxFuture<
+            'static,
+            Result<Box<dyn Transport + Send + Sync>, Box<dyn Error + Send + Sync + 'static>>,
+        > + Send
+        + Sync,
+>;
+
+/// Hands out up to `size` connections, opening them only when needed and
+/// replacing the ones that turned out broken
+pub struct TransportPool {
+    connect: Connect,
+    size: usize,
+    idle: Mutex<Vec<(Box<dyn Transport + Send + Sync>, Instant)>>,
+    permits: Arc<Semaphore>,
+}
+
+impl TransportPool {
+    pub fn new(size: usize, connect: Connect) -> Arc<Self> {
+        Arc::new(Self {
+            connect,
+            size: size.max(1),
+            idle: Mutex::new(vec![]),
+            permits: Arc::new(Semaphore::new(size.max(1))),
+        })
+    }
+
+    /// Waits for a free slot and returns an idle connection or opens a new one
+    pub async fn get(
+        self: &Arc<Self>,
+    ) -> Result<PooledTransport, Box<dyn Error + Send + Sync + 'static>> {
+        let permit = Arc::clone(&self.permits).acquire_owned().await?;
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            let Some((mut transport, since)) = idle else {
+                break;
+            };
+            if since.elapsed() < CHECK_AFTER || transport.check_health().await.is_ok() {
+                return Ok(self.wrap(transport, permit));
+            }
+            tracing::warn!("dropping pooled connection that failed its health check");
+        }
+        let transport = (self.connect)().await?;
+        Ok(self.wrap(transport, permit))
+    }
+
+    fn wrap(
+        self: &Arc<Self>,
+        transport: Box<dyn Transport + Send + Sync>,
+        permit: OwnedSemaphorePermit,
+    ) -> PooledTransport {
+        PooledTransport {
+            transport: Some(transport),
+            pool: Arc::clone(self),
+            _permit: permit,
+        }
+    }
+
+    /// Opens every connection the pool can hand out and pings it, so a wrong
+    /// password or an unreachable host fails the run before any work is done
+    pub async fn preflight(
+        self: &Arc<Self>,
+    ) -> Result<usize, Box<dyn Error + Send + Sync + 'static>> {
+        let connections = futures::future::try_join_all((0..self.size).map(|_| async {
+            let mut transport = self.get().await?;
+            transport.ping().await?;
+            Ok::<_, Box<dyn Error + Send + Sync + 'static>>(transport)
+        }))
+        .await?;
+        Ok(connections.len())
+    }
+
+    /// Most connections handed out at once
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of opened connections waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Closes all idle connections
+    pub async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap());
+        for (transport, _) in idle {
+            transport.close().await?;
+        }
+        Ok(())
+    }
+}
+
+/// A connection checked out of a `TransportPool`, returned to it on drop
+pub struct PooledTransport {
+    transport: Option<Box<dyn Transport + Send + Sync>>,
+    pool: Arc<TransportPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledTransport {
+    /// Drops the connection instead of returning it, the pool opens a new one when needed
+    pub fn discard(mut self) {
+        self.transport.take();
+    }
+}
+
+impl Deref for PooledTransport {
+    type Target = Box<dyn Transport + Send + Sync>;
+
+    fn deref(&self) -> &Self::Target {
+        self.transport.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledTransport {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transport.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledTransport {
+    fn drop(&mut self) {
+        if let Some(transport) = self.transport.take() {
+            self.pool
+                .idle
+                .lock()
+                .unwrap()
+                .push((transport, Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory::MemoryTransport;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn pool(size: usize) -> (Arc<TransportPool>, Arc<AtomicUsize>) {
+        let connected = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connected);
+        let pool = TransportPool::new(
+            size,
+            Box::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async {
+                    Ok(Box::new(MemoryTransport::new()) as Box<dyn Transport + Send + Sync>)
+                })
+            }),
+        );
+        (pool, connected)
+    }
+
+    #[tokio::test]
+    async fn connects_lazily_and_reuses() {
+        let (pool, connected) = pool(2);
+        assert_eq!(connected.load(Ordering::SeqCst), 0);
+        drop(pool.get().await.unwrap());
+        drop(pool.get().await.unwrap());
+        assert_eq!(connected.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn discarded_connections_are_replaced() {
+        let (pool, connected) = pool(1);
+        pool.get().await.unwrap().discard();
+        assert_eq!(pool.idle(), 0);
+        drop(pool.get().await.unwrap());
+        assert_eq!(connected.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn limits_checked_out_connections() {
+        let (pool, _) = pool(1);
+        let first = pool.get().await.unwrap();
+        let second = tokio::time::timeout(Duration::from_millis(50), pool.get()).await;
+        assert!(second.is_err());
+        drop(first);
+        assert!(pool.get().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn preflight_opens_every_connection() {
+        let (pool, connected) = pool(3);
+        assert_eq!(pool.preflight().await.unwrap(), 3);
+        assert_eq!(connected.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.idle(), 3);
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How much of the already uploaded data is kept around for resuming, covers
+/// what can sit in socket buffers when a connection drops
+pub const RESUME_WINDOW: usize = 8 * 1024 * 1024;
+/// Interrupted uploads are resumed this many times before giving up
+pub const MAX_RESUMES: usize = 3;
+
+/// Remembers the last `window` bytes read, so an interrupted upload can
+/// continue from wherever the server stopped receiving instead of from zero
+pub struct ResumableReader<R> {
+    reader: R,
+    position: u64,
+    tail: VecDeque<u8>,
+    replay: VecDeque<u8>,
+    window: usize,
+}
+
+impl<R> ResumableReader<R> {
+    pub fn new(reader: R, window: usize) -> Self {
+        Self {
+            reader,
+            position: 0,
+            tail: VecDeque::new(),
+            replay: VecDeque::new(),
+            window,
+        }
+    }
+
+    /// Number of bytes handed out so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Continues reading from `position` again, returns false when that part
+    /// of the data isn't remembered anymore
+    pub fn rewind_to(&mut self, position: u64) -> bool {
+        let remembered_from = self.position - self.tail.len() as u64;
+        if position < remembered_from || position > self.position {
+            return false;
+        }
+        let keep = (position - remembered_from) as usize;
+        let mut replay = self.tail.split_off(keep);
+        replay.append(&mut self.replay);
+        self.replay = replay;
+        self.position = position;
+        true
+    }
+
+    fn remember(&mut self, data: &[u8]) {
+        self.tail.extend(data);
+        if self.tail.len() > self.window {
+            self.tail.drain(..self.tail.len() - self.window);
+        }
+        self.position += data.len() as u64;
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ResumableReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if !self.replay.is_empty() {
+            let len = self.replay.len().min(buf.remaining());
+            let data = self.replay.drain(..len).collect::<Vec<_>>();
+            buf.put_slice(&data);
+            self.remember(&data);
+            return Poll::Ready(Ok(()));
+        }
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let data = buf.filled()[before..].to_vec();
+            self.remember(&data);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn read(reader: &mut ResumableReader<std::io::Cursor<Vec<u8>>>, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn replays_from_remembered_position() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let mut reader = ResumableReader::new(std::io::Cursor::new(data.clone()), 30);
+        read(&mut reader, 50).await;
+        assert_eq!(reader.position(), 50);
+
+        assert!(!reader.rewind_to(10));
+        assert!(reader.rewind_to(40));
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, data[40..]);
+        assert_eq!(reader.position(), 100);
+    }
+
+    #[tokio::test]
+    async fn rewinds_twice() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let mut reader = ResumableReader::new(std::io::Cursor::new(data.clone()), 100);
+        read(&mut reader, 60).await;
+        assert!(reader.rewind_to(20));
+        assert_eq!(read(&mut reader, 10).await, data[20..30]);
+        assert!(reader.rewind_to(25));
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, data[25..]);
+    }
+}
@@ -1,20 +1,22 @@
 use futures::stream::TryStreamExt;
-use rusoto_core::{ByteStream, Region};
+use rusoto_core::{ByteStream, Region, RusotoError};
 use rusoto_s3::{
-    CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart,
-    CreateMultipartUploadRequest, DeleteObjectRequest, GetObjectRequest,
-    ListMultipartUploadsRequest, ListPartsRequest, PutObjectRequest, S3Client, UploadPartRequest,
-    S3,
+    CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart, CopyObjectRequest,
+    CreateMultipartUploadRequest, DeleteObjectRequest, GetObjectRequest, HeadObjectError,
+    HeadObjectRequest, ListMultipartUploadsRequest, ListObjectsV2Request, ListPartsRequest,
+    PutObjectRequest, S3Client, UploadPartCopyRequest, UploadPartRequest, S3,
 };
 use std::io::{self, Cursor};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::time::SystemTime;
 use std::{error::Error, path::Path};
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
-use crate::checksum_tree::ChecksumTree;
+use crate::{checksum_tree::ChecksumTree, compression::ChecksumCompression};
 
-use super::Transport;
+use super::{Capabilities, RemoteEntry, Transport, CLOCK_PROBE_FILENAME};
 
 pub struct AwsS3 {
     bucket: String,
@@ -31,14 +33,23 @@ impl AwsS3 {
         secret_key: impl AsRef<str>,
         storage_class: impl AsRef<str>,
         directory: PathBuf,
+        endpoint: Option<String>,
     ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let region = match endpoint {
+            // S3 compatible storage like MinIO, addressed by path rather than subdomain
+            Some(endpoint) => Region::Custom {
+                name: region.as_ref().to_string(),
+                endpoint,
+            },
+            None => region.as_ref().parse::<Region>()?,
+        };
         let client = S3Client::new_with(
             rusoto_core::request::HttpClient::new().unwrap(),
             rusoto_credential::StaticProvider::new_minimal(
                 access_key.as_ref().to_string(),
                 secret_key.as_ref().to_string(),
             ),
-            region.as_ref().parse::<Region>()?,
+            region,
         );
         Ok(Self {
             bucket: bucket.as_ref().to_string(),
@@ -216,8 +227,119 @@ impl AwsS3 {
     }
 }
 
+impl AwsS3 {
+    /// Server-side copy, objects above the 5GB CopyObject limit are copied in
+    /// parts. Returns the size of the object
+    async fn copy_object(
+        &self,
+        from_key: &str,
+        to_key: &str,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        const COPY_OBJECT_LIMIT: i64 = 5 * 1024 * 1024 * 1024;
+        const PART_SIZE: i64 = 512 * 1024 * 1024;
+
+        let size = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.to_string(),
+                key: from_key.to_string(),
+                ..Default::default()
+            })
+            .await?
+            .content_length
+            .ok_or("S3 did not report object size")?;
+
+        if size <= COPY_OBJECT_LIMIT {
+            let copy_req = CopyObjectRequest {
+                bucket: self.bucket.to_string(),
+                copy_source: copy_source(&self.bucket, from_key),
+                key: to_key.to_string(),
+                storage_class: Some(self.storage_class.clone()),
+                ..Default::default()
+            };
+            self.client.copy_object(copy_req).await?;
+            return Ok(size as u64);
+        }
+
+        let upload_id = self
+            .client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                bucket: self.bucket.to_string(),
+                key: to_key.to_string(),
+                storage_class: Some(self.storage_class.clone()),
+                ..Default::default()
+            })
+            .await?
+            .upload_id
+            .ok_or("No upload ID received")?;
+        let mut parts = Vec::new();
+        for (i, start) in (0..size).step_by(PART_SIZE as usize).enumerate() {
+            let end = (start + PART_SIZE).min(size) - 1;
+            let part_number = i as i64 + 1;
+            let upload_part_req = UploadPartCopyRequest {
+                bucket: self.bucket.to_string(),
+                key: to_key.to_string(),
+                upload_id: upload_id.clone(),
+                part_number,
+                copy_source: copy_source(&self.bucket, from_key),
+                copy_source_range: Some(format!("bytes={start}-{end}")),
+                ..Default::default()
+            };
+            let result = self.client.upload_part_copy(upload_part_req).await?;
+            parts.push(CompletedPart {
+                e_tag: result.copy_part_result.and_then(|part| part.e_tag),
+                part_number: Some(part_number),
+            });
+        }
+        let complete_req = CompleteMultipartUploadRequest {
+            bucket: self.bucket.to_string(),
+            key: to_key.to_string(),
+            upload_id,
+            multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+            ..Default::default()
+        };
+        self.client.complete_multipart_upload(complete_req).await?;
+        Ok(size as u64)
+    }
+}
+
+/// `CopySource` header value, the key has to be URL encoded
+fn copy_source(bucket: &str, key: &str) -> String {
+    const KEY: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+        .remove(b'/')
+        .remove(b'-')
+        .remove(b'_')
+        .remove(b'.')
+        .remove(b'~');
+    format!(
+        "{bucket}/{}",
+        percent_encoding::utf8_percent_encode(key, KEY)
+    )
+}
+
 #[async_trait::async_trait]
 impl Transport for AwsS3 {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            remote_copy: true,
+            ..Default::default()
+        }
+    }
+
+    async fn ping(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        // needs the same permission as syncing, unlike checking the bucket itself
+        self.client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: self.bucket.to_string(),
+                prefix: Some(self.make_object_key(Path::new(""))),
+                max_keys: Some(1),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
     async fn read(
         &mut self,
         filename: &Path,
@@ -246,6 +368,51 @@ impl Transport for AwsS3 {
         }
     }
 
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        let get_req = GetObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: self.make_object_key(filename),
+            ..Default::default()
+        };
+        let output = self
+            .client
+            .get_object(get_req)
+            .await
+            .map_err(|e| format!("Error getting object: {}", e))?;
+        let body = output.body.ok_or("No content found in S3 object")?;
+        Ok(Box::new(Box::pin(body.into_async_read())))
+    }
+
+    async fn read_range(
+        &mut self,
+        filename: &Path,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        let get_req = GetObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: self.make_object_key(filename),
+            // HTTP ranges include their last byte
+            range: Some(format!("bytes={}-{}", range.start, range.end - 1)),
+            ..Default::default()
+        };
+        let output = self
+            .client
+            .get_object(get_req)
+            .await
+            .map_err(|e| format!("Error getting object: {}", e))?;
+        let mut contents = Vec::with_capacity((range.end - range.start) as usize);
+        output
+            .body
+            .ok_or("No content found in S3 object")?
+            .into_async_read()
+            .read_to_end(&mut contents)
+            .await?;
+        Ok(contents)
+    }
+
     async fn mkdir(&mut self, _path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         // We don't need to create directories in S3
         Ok(())
@@ -255,8 +422,9 @@ impl Transport for AwsS3 {
         &mut self,
         checksum_filename: &Path,
         checksum_tree: &ChecksumTree,
+        compression: ChecksumCompression,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
-        let json = checksum_tree.to_gzip()?;
+        let json = checksum_tree.to_compressed(compression)?;
         let file_size = json.len();
         let cursor = Cursor::new(json);
         AwsS3::write(
@@ -285,6 +453,172 @@ impl Transport for AwsS3 {
         .await
     }
 
+    async fn write_atomic(
+        &mut self,
+        filename: &Path,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        // objects only become visible once fully uploaded
+        Transport::write(self, filename, reader, file_size).await
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let mut prefix = self.make_object_key(path);
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        let mut entries = vec![];
+        let mut continuation_token = None;
+        loop {
+            let list_req = ListObjectsV2Request {
+                bucket: self.bucket.to_string(),
+                prefix: Some(prefix.clone()),
+                delimiter: Some("/".to_string()),
+                continuation_token,
+                ..Default::default()
+            };
+            let output = self.client.list_objects_v2(list_req).await?;
+            // "directories" are the common prefixes of keys below them
+            for common_prefix in output.common_prefixes.unwrap_or_default() {
+                let Some(name) = common_prefix
+                    .prefix
+                    .as_deref()
+                    .and_then(|key| key.strip_prefix(&prefix))
+                else {
+                    continue;
+                };
+                entries.push(RemoteEntry {
+                    path: path.join(name.trim_end_matches('/')),
+                    is_dir: true,
+                    size: 0,
+                    modified: None,
+                });
+            }
+            for object in output.contents.unwrap_or_default() {
+                let Some(name) = object
+                    .key
+                    .as_deref()
+                    .and_then(|key| key.strip_prefix(&prefix))
+                else {
+                    continue;
+                };
+                entries.push(RemoteEntry {
+                    path: path.join(name),
+                    is_dir: false,
+                    size: object.size.unwrap_or_default() as u64,
+                    modified: object
+                        .last_modified
+                        .and_then(|date| chrono::DateTime::parse_from_rfc3339(&date).ok())
+                        .map(SystemTime::from),
+                });
+            }
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated == Some(true) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn copy_remote(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        self.copy_object(&self.make_object_key(from), &self.make_object_key(to))
+            .await
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        // S3 has no rename, the object is copied and the original deleted
+        let from_key = self.make_object_key(from);
+        self.copy_object(&from_key, &self.make_object_key(to))
+            .await?;
+        let delete_req = DeleteObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: from_key,
+            ..Default::default()
+        };
+        self.client.delete_object(delete_req).await?;
+        Ok(())
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let key = self.make_object_key(path);
+        let head_req = HeadObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let output = self.client.head_object(head_req).await.map_err(
+            |e| -> Box<dyn Error + Send + Sync + 'static> {
+                match e {
+                    // HEAD responses have no body to name the error, only the status tells
+                    RusotoError::Unknown(ref response) if response.status.as_u16() == 404 => {
+                        io::Error::new(io::ErrorKind::NotFound, format!("no such object: {key}"))
+                            .into()
+                    }
+                    RusotoError::Service(HeadObjectError::NoSuchKey(_)) => {
+                        io::Error::new(io::ErrorKind::NotFound, format!("no such object: {key}"))
+                            .into()
+                    }
+                    e => e.into(),
+                }
+            },
+        )?;
+        Ok(output
+            .content_length
+            .ok_or("S3 did not report object size")? as u64)
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        // metadata can only be replaced by copying the object onto itself
+        let key = self.make_object_key(path);
+        let mtime = mtime.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let copy_req = CopyObjectRequest {
+            bucket: self.bucket.to_string(),
+            copy_source: copy_source(&self.bucket, &key),
+            key,
+            metadata: Some([("mtime".to_string(), mtime.to_string())].into()),
+            metadata_directive: Some("REPLACE".to_string()),
+            storage_class: Some(self.storage_class.clone()),
+            ..Default::default()
+        };
+        self.client.copy_object(copy_req).await?;
+        Ok(())
+    }
+
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        let probe = Path::new(CLOCK_PROBE_FILENAME);
+        Transport::write(self, probe, Box::new(Cursor::new(vec![])), 0).await?;
+        let head_req = HeadObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: self.make_object_key(probe),
+            ..Default::default()
+        };
+        let output = self.client.head_object(head_req).await;
+        Transport::remove(self, probe).await.ok();
+        // Last-Modified is the Date of the request that created the object
+        let last_modified = output?
+            .last_modified
+            .ok_or("S3 did not report the modification time")?;
+        Ok(chrono::DateTime::parse_from_rfc2822(&last_modified)?.into())
+    }
+
     async fn remove(
         &mut self,
         pathname: &Path,
@@ -298,6 +632,14 @@ impl Transport for AwsS3 {
         Ok(self.client.delete_object(delete_req).await.map(|_| ())?)
     }
 
+    async fn remove_dir(
+        &mut self,
+        _path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        // directories only exist as key prefixes in S3
+        Ok(())
+    }
+
     async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
         Ok(())
     }
@@ -1,20 +1,66 @@
-use super::Transport;
-use ssh2::{Session, Sftp};
+use super::{
+    resume::{ResumableReader, MAX_RESUMES, RESUME_WINDOW},
+    Capabilities, KnownDirs, RemoteEntry, Transport, CLOCK_PROBE_FILENAME,
+};
+use futures::{stream::FuturesUnordered, StreamExt, TryStreamExt};
+use russh_sftp::{
+    client::{error::Error as SftpError, RawSftpSession},
+    protocol::{FileAttributes, OpenFlags, StatusCode},
+};
 use std::{
+    collections::BTreeSet,
     error::Error,
-    io::{Read, Write},
+    io::Write,
     path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
     str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 use tokio::{
-    io::{AsyncRead, AsyncReadExt},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    process::{Child, ChildStdin, ChildStdout, Command},
 };
+use tokio_util::{bytes::Bytes, io::StreamReader};
+
+/// Size of a single read or write request, well below what servers accept
+const CHUNK_SIZE: usize = 32 * 1024;
+/// Requests sent before waiting for the first response, hides the round trip
+/// latency on slow links
+const REQUESTS_IN_FLIGHT: usize = 32;
 
+/// Runs the operation and when it fails on a session that turned out to be
+/// dead, reconnects and runs it once more
+macro_rules! with_reconnect {
+    ($self:ident, $operation:expr) => {
+        match $operation {
+            Err(error) if !$self.is_alive().await => {
+                $self
+                    .reconnect()
+                    .await
+                    .map_err(|e| format!("{error} (reconnecting failed with error: {e})"))?;
+                $operation
+            }
+            result => result,
+        }
+    };
+}
+
+/// SFTP over the system `ssh` client, requests are pipelined over its stdio
 pub struct SFtp {
-    session: Session,
-    sftp: Sftp,
+    host: String,
+    user: String,
+    pass: String,
+    proxy_jump: Option<String>,
+    ssh: Child,
+    sftp: Arc<RawSftpSession>,
     dir: String,
+    known_dirs: KnownDirs,
 }
 
 impl SFtp {
@@ -23,35 +69,196 @@ impl SFtp {
         user: impl AsRef<str>,
         pass: impl AsRef<str>,
         dir: impl Into<String>,
+        proxy_jump: Option<String>,
     ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
-        let tcp = TcpStream::connect(host.as_ref()).await?;
-        let mut session = Session::new().unwrap();
-        session.set_tcp_stream(tcp);
-        session.handshake().unwrap();
+        let dir = dir.into();
+        let (ssh, sftp) = Self::connect(
+            host.as_ref(),
+            user.as_ref(),
+            pass.as_ref(),
+            &dir,
+            proxy_jump.as_deref(),
+        )
+        .await?;
 
-        session
-            .userauth_password(user.as_ref(), pass.as_ref())
-            .unwrap();
+        Ok(Self {
+            host: host.as_ref().to_string(),
+            user: user.as_ref().to_string(),
+            pass: pass.as_ref().to_string(),
+            proxy_jump,
+            ssh,
+            sftp,
+            dir,
+            known_dirs: KnownDirs::default(),
+        })
+    }
 
-        let sftp = session.sftp()?;
-        let dir = dir.into();
-        let dir_path = Path::new(&dir);
-        match sftp.readdir(dir_path) {
-            Ok(_) => {}
-            Err(_) => {
-                for part in dir_path
-                    .ancestors()
-                    .collect::<Vec<_>>()
-                    .into_iter()
-                    .rev()
-                    .filter(|part| !part.to_string_lossy().is_empty())
-                {
-                    sftp.mkdir(part, 0o755)?;
+    async fn connect(
+        host: &str,
+        user: &str,
+        pass: &str,
+        dir: &str,
+        proxy_jump: Option<&str>,
+    ) -> Result<(Child, Arc<RawSftpSession>), Box<dyn Error + Send + Sync + 'static>> {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-s")
+            .args(["-o", "StrictHostKeyChecking=accept-new"])
+            .args(["-o", "ServerAliveInterval=15"])
+            .args(["-o", "NumberOfPasswordPrompts=1"])
+            .args(["-l", user]);
+        if let Some(proxy_jump) = proxy_jump {
+            // ssh connects to the bastion first and tunnels through it
+            command.args(["-J", proxy_jump]);
+        }
+        match host.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => {
+                command.args(["-p", port, host]);
+            }
+            _ => {
+                command.arg(host);
+            }
+        }
+        command
+            .arg("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let askpass = if pass.is_empty() {
+            None
+        } else {
+            let askpass = AskPass::new()?;
+            command
+                .env("SSH_ASKPASS", &askpass.path)
+                .env("SSH_ASKPASS_REQUIRE", "force")
+                .env("SYNCBOX_SFTP_PASS", pass);
+            Some(askpass)
+        };
+
+        let mut ssh = command
+            .spawn()
+            .map_err(|e| format!("could not start ssh, SFTP needs the OpenSSH client: {e}"))?;
+        let stdio = SshStdio {
+            stdin: ssh.stdin.take().unwrap(),
+            stdout: ssh.stdout.take().unwrap(),
+        };
+        let mut stderr = ssh.stderr.take().unwrap();
+        let sftp = RawSftpSession::new(stdio);
+        if let Err(e) = sftp.init().await {
+            // ssh exits on failed authentication, its output tells why
+            let mut output = String::new();
+            stderr.read_to_string(&mut output).await.ok();
+            return Err(format!("{e}: {}", output.trim()).into());
+        }
+        drop(askpass);
+        tokio::spawn(async move {
+            let mut output = String::new();
+            stderr.read_to_string(&mut output).await.ok();
+            for line in output.lines() {
+                tracing::warn!("ssh: {line}");
+            }
+        });
+
+        let sftp = Arc::new(sftp);
+        let dir_path = Path::new(dir);
+        if sftp.stat(dir).await.is_err() {
+            for part in dir_path
+                .ancestors()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .filter(|part| !part.to_string_lossy().is_empty())
+            {
+                if sftp.stat(remote(part)).await.is_err() {
+                    sftp.mkdir(remote(part), dir_attributes()).await?;
                 }
             }
         }
 
-        Ok(Self { session, sftp, dir })
+        Ok((ssh, sftp))
+    }
+
+    async fn is_alive(&self) -> bool {
+        self.sftp.realpath(".").await.is_ok()
+    }
+
+    /// Replaces the session with a fresh one
+    async fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        tracing::warn!("SFTP session to {} dropped, reconnecting", self.host);
+        self.sftp.close_session().ok();
+        self.ssh.kill().await.ok();
+        let (ssh, sftp) = Self::connect(
+            &self.host,
+            &self.user,
+            &self.pass,
+            &self.dir,
+            self.proxy_jump.as_deref(),
+        )
+        .await?;
+        self.ssh = ssh;
+        self.sftp = sftp;
+        Ok(())
+    }
+
+    /// Writes the rest of `reader` at its current position, `acknowledged` is
+    /// kept at the offset below which the server confirmed every write
+    async fn put_file(
+        &mut self,
+        path: &str,
+        reader: &mut ResumableReader<Box<dyn AsyncRead + Unpin + Send>>,
+        acknowledged: &mut u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut offset = reader.position();
+        let flags = if offset == 0 {
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE
+        } else {
+            OpenFlags::WRITE | OpenFlags::CREATE
+        };
+        let handle = with_reconnect!(
+            self,
+            self.sftp.open(path, flags, FileAttributes::empty()).await
+        )?
+        .handle;
+
+        let mut pending = FuturesUnordered::new();
+        let mut in_flight = BTreeSet::new();
+        let result = async {
+            loop {
+                let mut buf = vec![0; CHUNK_SIZE];
+                let mut len = 0;
+                while len < CHUNK_SIZE {
+                    match reader.read(&mut buf[len..]).await? {
+                        0 => break,
+                        read => len += read,
+                    }
+                }
+                if len == 0 {
+                    break;
+                }
+                buf.truncate(len);
+                let sftp = Arc::clone(&self.sftp);
+                let handle = handle.clone();
+                pending.push(async move { sftp.write(&handle, offset, buf).await.map(|_| offset) });
+                in_flight.insert(offset);
+                offset += len as u64;
+                if pending.len() >= REQUESTS_IN_FLIGHT {
+                    in_flight.remove(&pending.next().await.unwrap()?);
+                    *acknowledged = in_flight.first().copied().unwrap_or(offset);
+                }
+            }
+            while let Some(written) = pending.next().await {
+                in_flight.remove(&written?);
+                *acknowledged = in_flight.first().copied().unwrap_or(offset);
+            }
+            Ok::<_, Box<dyn Error + Send + Sync + 'static>>(())
+        }
+        .await;
+        let closed = self.sftp.close(&handle).await;
+        result?;
+        closed?;
+        Ok(())
     }
 
     fn get_path(&self, filename: &Path) -> Result<PathBuf, Box<dyn Error + Send + Sync + 'static>> {
@@ -61,53 +268,385 @@ impl SFtp {
             filename = filename.display()
         ))?)
     }
+
+    /// Creates the parents of `filename` this connection hasn't seen yet, so
+    /// writes don't depend on mkdir actions having run first
+    async fn create_parents(
+        &mut self,
+        filename: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let missing = self.known_dirs.missing_parents(filename);
+        let Some(parent) = missing.last() else {
+            return Ok(());
+        };
+        // usually the directory is there already, one stat spares a mkdir per level
+        if self.stat(parent).await?.is_some_and(|entry| entry.is_dir) {
+            self.known_dirs.insert(parent);
+            return Ok(());
+        }
+        for dir in missing {
+            Transport::mkdir(self, &dir).await?;
+        }
+        Ok(())
+    }
+}
+
+fn remote(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn dir_attributes() -> FileAttributes {
+    FileAttributes {
+        permissions: Some(0o755),
+        ..FileAttributes::empty()
+    }
+}
+
+fn to_entry(path: PathBuf, attrs: &FileAttributes) -> RemoteEntry {
+    RemoteEntry {
+        path,
+        is_dir: attrs.is_dir(),
+        size: if attrs.is_dir() {
+            0
+        } else {
+            attrs.size.unwrap_or_default()
+        },
+        modified: attrs
+            .mtime
+            .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64)),
+    }
+}
+
+/// Script answering the password prompt of ssh, removed once connected
+struct AskPass {
+    /// Private to this user, so nobody else can swap the script
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl AskPass {
+    fn new() -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "syncbox-askpass-{}-{}-{:016x}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            rand::random::<u64>()
+        ));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        // fails on anything already there, a symbolic link included
+        builder.create(&dir)?;
+        let askpass = Self {
+            path: dir.join("askpass"),
+            dir,
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o700);
+        }
+        // the password itself is passed in the environment, never written to disk
+        options
+            .open(&askpass.path)?
+            .write_all(b"#!/bin/sh\nprintf '%s\\n' \"$SYNCBOX_SFTP_PASS\"\n")?;
+        Ok(askpass)
+    }
+}
+
+impl Drop for AskPass {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+        std::fs::remove_dir(&self.dir).ok();
+    }
+}
+
+/// Stdout and stdin of the ssh process as a single duplex stream
+struct SshStdio {
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl AsyncRead for SshStdio {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SshStdio {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_shutdown(cx)
+    }
 }
 
 #[async_trait::async_trait]
 impl Transport for SFtp {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: true,
+            ..Default::default()
+        }
+    }
+
+    async fn check_health(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.sftp.realpath(".").await?;
+        Ok(())
+    }
+
     async fn read(
         &mut self,
         filename: &Path,
     ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
-        let mut file = self.sftp.open(self.get_path(filename)?.as_path())?;
         let mut buf = vec![];
-        let _ = file.read_to_end(&mut buf)?;
+        self.read_stream(filename)
+            .await?
+            .read_to_end(&mut buf)
+            .await?;
         Ok(buf)
     }
 
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        let path = remote(&self.get_path(filename)?);
+        let handle = with_reconnect!(
+            self,
+            self.sftp
+                .open(&path, OpenFlags::READ, FileAttributes::empty())
+                .await
+        )?
+        .handle;
+        let size = match self.sftp.fstat(&handle).await?.attrs.size {
+            Some(size) => size,
+            None => self.sftp.stat(&path).await?.attrs.size.unwrap_or_default(),
+        };
+
+        // read requests for the whole file are queued up front and answered in order
+        let sftp = Arc::clone(&self.sftp);
+        let chunks = futures::stream::iter((0..size).step_by(CHUNK_SIZE))
+            .map({
+                let handle = handle.clone();
+                move |offset| {
+                    let sftp = Arc::clone(&sftp);
+                    let handle = handle.clone();
+                    async move {
+                        let expected = (size - offset).min(CHUNK_SIZE as u64) as usize;
+                        let data = sftp.read(handle, offset, expected as u32).await?.data;
+                        if data.len() != expected {
+                            return Err(format!(
+                                "server returned {} bytes at offset {offset}, expected {expected}",
+                                data.len()
+                            )
+                            .into());
+                        }
+                        Ok::<_, Box<dyn Error + Send + Sync + 'static>>(Bytes::from(data))
+                    }
+                }
+            })
+            .buffered(REQUESTS_IN_FLIGHT)
+            .map_err(std::io::Error::other);
+        let sftp = Arc::clone(&self.sftp);
+        let close = futures::stream::once(async move {
+            sftp.close(handle).await.ok();
+            None
+        })
+        .filter_map(futures::future::ready);
+        Ok(Box::new(StreamReader::new(Box::pin(chunks.chain(close)))))
+    }
+
     async fn mkdir(&mut self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        self.sftp.mkdir(self.get_path(path)?.as_path(), 0o755)?;
+        let remote_path = remote(&self.get_path(path)?);
+        if let Err(e) = with_reconnect!(self, self.sftp.mkdir(&remote_path, dir_attributes()).await)
+        {
+            // servers report an existing directory as a generic failure
+            if !self.stat(path).await?.is_some_and(|entry| entry.is_dir) {
+                return Err(e.into());
+            }
+        }
+        self.known_dirs.insert(path);
         Ok(())
     }
 
     async fn write(
         &mut self,
         filename: &Path,
-        mut reader: Box<dyn AsyncRead + Unpin + Send>,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
         _file_size: u64,
     ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
-        let mut file = self.sftp.create(self.get_path(filename)?.as_path())?;
-        let mut buf = vec![0; 1024 * 16]; // 16KB buffer
-        let mut read = 0;
-        while let Ok(len) = reader.read(&mut buf).await {
-            if len == 0 {
-                break;
+        self.create_parents(filename).await?;
+        let path = remote(&self.get_path(filename)?);
+        let mut reader = ResumableReader::new(reader, RESUME_WINDOW);
+        let mut resumes = 0;
+        loop {
+            let mut acknowledged = reader.position();
+            let error = match self.put_file(&path, &mut reader, &mut acknowledged).await {
+                Ok(()) => return Ok(reader.position()),
+                Err(error) if resumes < MAX_RESUMES && reader.position() > 0 => error,
+                Err(error) => return Err(error),
+            };
+            resumes += 1;
+            if !self.is_alive().await {
+                self.reconnect()
+                    .await
+                    .map_err(|e| format!("{error} (reconnecting failed with error: {e})"))?;
+            }
+            // writes are pipelined, only what the server confirmed is known to
+            // be free of holes
+            let stored = match self.sftp.stat(&path).await {
+                Ok(attrs) => attrs.attrs.size.unwrap_or_default().min(acknowledged),
+                Err(_) => 0,
+            };
+            if !reader.rewind_to(stored) && !reader.rewind_to(0) {
+                return Err(error);
             }
-            tokio::task::block_in_place(|| file.write_all(&buf[..len]))?;
-            read += len;
+            tracing::warn!(
+                "upload of {path} failed after {} bytes with error: {error}, resuming",
+                reader.position()
+            );
         }
-        Ok(read as u64)
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let dir = remote(&self.get_path(path)?);
+        let handle = with_reconnect!(self, self.sftp.opendir(&dir).await)?.handle;
+        let mut entries = vec![];
+        let listed = loop {
+            match self.sftp.readdir(&handle).await {
+                Ok(name) => entries.extend(
+                    name.files
+                        .into_iter()
+                        .filter(|file| file.filename != "." && file.filename != "..")
+                        .map(|file| to_entry(path.join(&file.filename), &file.attrs)),
+                ),
+                Err(SftpError::Status(status)) if status.status_code == StatusCode::Eof => {
+                    break Ok(())
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.sftp.close(&handle).await.ok();
+        listed?;
+        Ok(entries)
+    }
+
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        let remote_path = remote(&self.get_path(path)?);
+        match with_reconnect!(self, self.sftp.stat(&remote_path).await) {
+            Ok(attrs) => Ok(Some(to_entry(path.to_path_buf(), &attrs.attrs))),
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let from = remote(&self.get_path(from)?);
+        let to = remote(&self.get_path(to)?);
+        if with_reconnect!(self, self.sftp.rename(&from, &to).await).is_err() {
+            // SFTPv3 servers refuse to rename over an existing file
+            self.sftp.remove(&to).await.ok();
+            self.sftp.rename(&from, &to).await?;
+        }
+        Ok(())
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        let path = remote(&self.get_path(path)?);
+        let stat = with_reconnect!(self, self.sftp.stat(&path).await)?;
+        Ok(stat.attrs.size.ok_or("server did not report file size")?)
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = remote(&self.get_path(path)?);
+        let mtime = mtime.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as u32;
+        let attrs = FileAttributes {
+            atime: Some(mtime),
+            mtime: Some(mtime),
+            ..FileAttributes::empty()
+        };
+        with_reconnect!(self, self.sftp.setstat(&path, attrs.clone()).await)?;
+        Ok(())
+    }
+
+    async fn set_permissions(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = remote(&self.get_path(path)?);
+        let attrs = FileAttributes {
+            uid: owner.map(|(uid, _)| uid),
+            gid: owner.map(|(_, gid)| gid),
+            permissions: Some(mode),
+            ..FileAttributes::empty()
+        };
+        with_reconnect!(self, self.sftp.setstat(&path, attrs.clone()).await)?;
+        Ok(())
+    }
+
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        let probe = Path::new(CLOCK_PROBE_FILENAME);
+        self.write(probe, Box::new(std::io::Cursor::new(vec![])), 0)
+            .await?;
+        let remote_path = remote(&self.get_path(probe)?);
+        let attrs = self.sftp.stat(&remote_path).await;
+        self.sftp.remove(&remote_path).await.ok();
+        attrs?
+            .attrs
+            .mtime
+            .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64))
+            .ok_or_else(|| "server did not report the modification time".into())
     }
 
     async fn remove(
         &mut self,
         pathname: &Path,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.known_dirs.forget(pathname);
         let mut pathname = self.get_path(pathname)?;
-        self.sftp.unlink(pathname.as_path())?;
+        with_reconnect!(self, self.sftp.remove(remote(&pathname)).await)?;
 
         while let Some(parent_pathname) = pathname.parent() {
-            if self.sftp.rmdir(parent_pathname).ok().is_none() {
+            if self.sftp.rmdir(remote(parent_pathname)).await.is_err() {
                 // ignore errors about deleting directories but bail out on first error
                 break;
             }
@@ -116,8 +655,65 @@ impl Transport for SFtp {
         Ok(())
     }
 
-    async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        self.session.disconnect(None, "close", None)?;
+    async fn remove_dir(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.known_dirs.forget(path);
+        let path = remote(&self.get_path(path)?);
+        with_reconnect!(self, self.sftp.rmdir(&path).await)?;
+        Ok(())
+    }
+
+    async fn symlink(
+        &mut self,
+        target: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let path = remote(&self.get_path(path)?);
+        if let Ok(existing) = self.sftp.lstat(&path).await {
+            if !existing.attrs.is_dir() {
+                self.sftp.remove(&path).await?;
+            }
+        }
+        // OpenSSH, which nearly every server runs, reads the arguments in
+        // the opposite order to the SFTP draft, so the target goes first
+        with_reconnect!(self, self.sftp.symlink(target, &path).await)?;
+        Ok(())
+    }
+
+    async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.sftp.close_session()?;
+        // closing stdin ends the sftp subsystem and ssh exits on its own
+        if tokio::time::timeout(Duration::from_secs(5), self.ssh.wait())
+            .await
+            .is_err()
+        {
+            self.ssh.kill().await?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn askpass_is_private_and_removed() {
+        use std::os::unix::fs::PermissionsExt;
+        let askpass = AskPass::new().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&askpass.dir), 0o700);
+        assert_eq!(mode(&askpass.path), 0o700);
+        let output = std::process::Command::new(&askpass.path)
+            .env("SYNCBOX_SFTP_PASS", "secret")
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"secret\n");
+        let dir = askpass.dir.clone();
+        drop(askpass);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,330 @@
+use super::{Capabilities, RemoteEntry, Transport};
+use crate::{checksum_tree::ChecksumTree, compression::ChecksumCompression};
+use std::{
+    error::Error,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use suppaftp::{FtpError, Status};
+use tokio::io::AsyncRead;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RETRIES: usize = 5;
+
+/// Spaces requests out evenly and backs off whenever the remote signals that
+/// it's being asked too often. Shared by all connections of a run.
+pub struct RateLimiter {
+    interval: Duration,
+    state: Mutex<RateLimiterState>,
+}
+
+struct RateLimiterState {
+    next_slot: Instant,
+    backoff: Duration,
+}
+
+impl RateLimiter {
+    /// `None` only enables the backoff
+    pub fn new(requests_per_second: Option<f64>) -> Self {
+        let interval = requests_per_second
+            .filter(|rps| *rps > 0.0)
+            .map(|rps| Duration::from_secs_f64(1.0 / rps))
+            .unwrap_or_default();
+        Self {
+            interval,
+            state: Mutex::new(RateLimiterState {
+                next_slot: Instant::now(),
+                backoff: Duration::ZERO,
+            }),
+        }
+    }
+
+    /// Waits until the next request may be sent
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut state = self.state.lock().unwrap();
+            let slot = state.next_slot.max(Instant::now());
+            state.next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    /// Delays all following requests, doubling the delay on every consecutive call
+    pub fn back_off(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.backoff = (state.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+        state.next_slot = state.next_slot.max(Instant::now()) + state.backoff;
+    }
+
+    pub fn recover(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.backoff /= 2;
+    }
+}
+
+/// Whether the remote refused the request because of too many requests or connections
+pub fn is_rate_limited(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(FtpError::UnexpectedResponse(response)) = error.downcast_ref::<FtpError>() {
+        return response.status == Status::NotAvailable;
+    }
+    let message = error.to_string();
+    ["SlowDown", "Too Many Requests", "TooManyRequests"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// Runs an idempotent operation through the limiter, retrying it while rate limited
+macro_rules! throttled {
+    ($self:ident, $operation:expr) => {{
+        let mut attempt = 0;
+        loop {
+            $self.limiter.acquire().await;
+            match $operation {
+                Err(error) if is_rate_limited(error.as_ref()) && attempt < MAX_RETRIES => {
+                    $self.limiter.back_off();
+                    attempt += 1;
+                }
+                result => {
+                    if result.is_ok() {
+                        $self.limiter.recover();
+                    }
+                    break result;
+                }
+            }
+        }
+    }};
+}
+
+pub struct Throttled {
+    inner: Box<dyn Transport + Send + Sync>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl Throttled {
+    pub fn new(inner: Box<dyn Transport + Send + Sync>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    /// Uploads can't be retried as they consume their reader, only slow down the rest
+    fn observe<T>(
+        &self,
+        result: Result<T, Box<dyn Error + Send + Sync + 'static>>,
+    ) -> Result<T, Box<dyn Error + Send + Sync + 'static>> {
+        match &result {
+            Err(error) if is_rate_limited(error.as_ref()) => self.limiter.back_off(),
+            Ok(_) => self.limiter.recover(),
+            Err(_) => {}
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for Throttled {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn check_health(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.check_health().await)
+    }
+
+    async fn ping(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.ping().await)
+    }
+
+    async fn read_last_checksum(
+        &mut self,
+        checksum_filename: &Path,
+    ) -> Result<ChecksumTree, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.read_last_checksum(checksum_filename).await)
+    }
+
+    async fn write_last_checksum(
+        &mut self,
+        checksum_filename: &Path,
+        checksum_tree: &ChecksumTree,
+        compression: ChecksumCompression,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(
+            self,
+            self.inner
+                .write_last_checksum(checksum_filename, checksum_tree, compression)
+                .await
+        )
+    }
+
+    async fn read(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.read(filename).await)
+    }
+
+    async fn read_stream(
+        &mut self,
+        filename: &Path,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.read_stream(filename).await)
+    }
+
+    async fn read_range(
+        &mut self,
+        filename: &Path,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.read_range(filename, range.clone()).await)
+    }
+
+    async fn mkdir(&mut self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.mkdir(path).await)
+    }
+
+    async fn write(
+        &mut self,
+        filename: &Path,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        self.limiter.acquire().await;
+        let result = self.inner.write(filename, reader, file_size).await;
+        self.observe(result)
+    }
+
+    async fn write_atomic(
+        &mut self,
+        filename: &Path,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+        file_size: u64,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        self.limiter.acquire().await;
+        let result = self.inner.write_atomic(filename, reader, file_size).await;
+        self.observe(result)
+    }
+
+    async fn copy_local(
+        &mut self,
+        source: &Path,
+        filename: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.copy_local(source, filename).await)
+    }
+
+    async fn copy_remote(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.copy_remote(from, to).await)
+    }
+
+    async fn list(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.list(path).await)
+    }
+
+    async fn stat(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.stat(path).await)
+    }
+
+    async fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.rename(from, to).await)
+    }
+
+    async fn size(&mut self, path: &Path) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.size(path).await)
+    }
+
+    async fn set_mtime(
+        &mut self,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.set_mtime(path, mtime).await)
+    }
+
+    async fn set_permissions(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        owner: Option<(u32, u32)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.set_permissions(path, mode, owner).await)
+    }
+
+    async fn server_time(&mut self) -> Result<SystemTime, Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.server_time().await)
+    }
+
+    async fn remove(
+        &mut self,
+        pathname: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.remove(pathname).await)
+    }
+
+    async fn remove_dir(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.remove_dir(path).await)
+    }
+
+    async fn symlink(
+        &mut self,
+        target: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        throttled!(self, self.inner.symlink(target, path).await)
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spaces_requests() {
+        let limiter = RateLimiter::new(Some(100.0));
+        let started = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn backs_off_and_recovers() {
+        let limiter = RateLimiter::new(None);
+        limiter.back_off();
+        limiter.back_off();
+        assert_eq!(limiter.state.lock().unwrap().backoff, MIN_BACKOFF * 2);
+        limiter.recover();
+        assert_eq!(limiter.state.lock().unwrap().backoff, MIN_BACKOFF);
+    }
+
+    #[test]
+    fn detects_rate_limit_errors() {
+        let slow_down: Box<dyn Error + Send + Sync> = "<Error><Code>SlowDown</Code></Error>".into();
+        let other: Box<dyn Error + Send + Sync> = "No such file".into();
+        assert!(is_rate_limited(slow_down.as_ref()));
+        assert!(!is_rate_limited(other.as_ref()));
+    }
+}
@@ -0,0 +1,36 @@
+use std::{io, path::Path};
+
+/// Moves a file into the trash of the platform instead of unlinking it, the
+/// one of its mount on Linux, where it can be put back from
+pub fn move_to_trash(path: &Path) -> io::Result<()> {
+    ::trash::delete(path).map_err(|e| match e {
+        #[cfg(all(
+            unix,
+            not(target_os = "macos"),
+            not(target_os = "ios"),
+            not(target_os = "android")
+        ))]
+        ::trash::Error::FileSystem { source, .. } => source,
+        // it can't be told apart from a file gone since the scan otherwise
+        ::trash::Error::CouldNotAccess { .. } | ::trash::Error::CanonicalizePath { .. }
+            if path.symlink_metadata().is_err() =>
+        {
+            io::Error::new(io::ErrorKind::NotFound, e)
+        }
+        e => io::Error::other(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let path = std::env::temp_dir().join(format!("syncbox-trash-{}", std::process::id()));
+        assert_eq!(
+            move_to_trash(&path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
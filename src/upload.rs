@@ -0,0 +1,238 @@
+use crate::{
+    checksum_tree::{ChecksumElement, ChecksumTree},
+    cli::Args,
+    collision::{resolve_collision, CollisionPolicy},
+    compression::ObjectCompression,
+    progress,
+    scan::permissions_of,
+    t,
+    transport::{temporary_path, Transport},
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
+
+/// Files stored with another compression than `--compress` can't be reused,
+/// with `--force` the tree is reset so everything is uploaded again
+pub fn check_compression(
+    args: &Args,
+    checksum_tree: &mut ChecksumTree,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    if checksum_tree.compression() == args.compress
+        || matches!(checksum_tree.get_root(), Some(ChecksumElement::Directory(entries)) if entries.is_empty())
+    {
+        return Ok(());
+    }
+    let stored = checksum_tree
+        .compression()
+        .map_or("uncompressed".to_string(), |c| {
+            format!("compressed with {c}")
+        });
+    if !args.force {
+        return Err(format!(
+            "files on the remote are stored {stored}, rerun with the same --compress or with --force to upload everything again"
+        )
+        .into());
+    }
+    match checksum_tree.compression() {
+        Some(compression) => println!(
+            "      {}",
+            t!("stored_compressed", compression = compression)
+        ),
+        None => println!("      {}", t!("stored_uncompressed")),
+    }
+    *checksum_tree = ChecksumTree::default();
+    Ok(())
+}
+
+pub async fn create_directory(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    on_collision: CollisionPolicy,
+    mode: Option<u32>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    match transport.mkdir(path).await {
+        Err(error) => match resolve_collision(transport, path, true, on_collision).await? {
+            true => transport.mkdir(path).await?,
+            false => return Err(error),
+        },
+        result => result?,
+    }
+    if let Some(mode) = mode {
+        // the directory is there, a wrong mode is not worth failing the sync over
+        if let Err(e) = transport.set_permissions(path, mode, None).await {
+            eprintln!(
+                "{}",
+                t!("chmod_failed", path = format!("{path:?}"), error = e)
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_link(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    target: &str,
+    path: &Path,
+    on_collision: CollisionPolicy,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    match transport.symlink(target, path).await {
+        Err(error) => match resolve_collision(transport, path, false, on_collision).await? {
+            true => transport.symlink(target, path).await,
+            false => Err(error),
+        },
+        result => result,
+    }
+}
+
+/// Gives the remote copy of an unchanged file the modification time and
+/// permissions of the local one, as far as `--preserve-mtime` and
+/// `--preserve-permissions` ask for them
+pub async fn set_metadata(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    args: &Args,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let metadata = fs::metadata(path).await?;
+    let remote = remote_path(path, args.compress);
+    if args.preserve_mtime {
+        transport.set_mtime(&remote, metadata.modified()?).await?;
+    }
+    if args.preserve_permissions {
+        if let Some((mode, owner)) = permissions_of(&metadata) {
+            let owner = args.preserve_owner.then_some(owner);
+            transport.set_permissions(&remote, mode, owner).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn upload_file(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    pb: &Arc<indicatif::ProgressBar>,
+    atomic: bool,
+    compress: Option<ObjectCompression>,
+) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+    if let Some(compression) = compress {
+        let compressed = std::env::temp_dir().join(format!(
+            "syncbox-{}-{}{}",
+            std::process::id(),
+            rand::random::<u32>(),
+            compression.suffix()
+        ));
+        let source = path.to_path_buf();
+        let target = compressed.clone();
+        let result =
+            match tokio::task::spawn_blocking(move || compression.compress_file(&source, &target))
+                .await?
+            {
+                Ok(_) => {
+                    upload_compressed(transport, &compressed, path, compression, pb, atomic).await
+                }
+                Err(e) => Err(format!("compression failed with error: {e}").into()),
+            };
+        fs::remove_file(&compressed).await.ok();
+        return result;
+    }
+    if transport.capabilities().local_copy {
+        let written = if atomic {
+            let temporary_path = temporary_path(path);
+            let written = transport.copy_local(path, &temporary_path).await?;
+            transport.rename(&temporary_path, path).await?;
+            written
+        } else {
+            transport.copy_local(path, path).await?
+        };
+        pb.set_position(written);
+        return Ok(written);
+    }
+    let file = fs::File::open(path).await?;
+    let file_size = file.metadata().await?.len();
+    let pb_inner = Arc::clone(pb);
+    let file = progress::ProgressStream::new(
+        file,
+        Box::new(move |uploaded| {
+            pb_inner.set_position(uploaded);
+        }),
+    );
+    if atomic {
+        transport
+            .write_atomic(path, Box::new(file), file_size)
+            .await
+    } else {
+        transport.write(path, Box::new(file), file_size).await
+    }
+}
+
+/// Uploads the already compressed copy of `path` under its suffixed name,
+/// progress is reported in uncompressed bytes
+async fn upload_compressed(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    compressed: &Path,
+    path: &Path,
+    compression: ObjectCompression,
+    pb: &Arc<indicatif::ProgressBar>,
+    atomic: bool,
+) -> Result<u64, Box<dyn Error + Send + Sync + 'static>> {
+    let remote = compression.remote_path(path);
+    if transport.capabilities().local_copy {
+        let written = if atomic {
+            let temporary_path = temporary_path(&remote);
+            let written = transport.copy_local(compressed, &temporary_path).await?;
+            transport.rename(&temporary_path, &remote).await?;
+            written
+        } else {
+            transport.copy_local(compressed, &remote).await?
+        };
+        pb.finish();
+        return Ok(written);
+    }
+    let file = fs::File::open(compressed).await?;
+    let file_size = file.metadata().await?.len();
+    let pb_inner = Arc::clone(pb);
+    let total = pb.length().unwrap_or(file_size);
+    let file = progress::ProgressStream::new(
+        file,
+        Box::new(move |uploaded| {
+            pb_inner.set_position(uploaded * total / file_size.max(1));
+        }),
+    );
+    if atomic {
+        transport
+            .write_atomic(&remote, Box::new(file), file_size)
+            .await
+    } else {
+        transport.write(&remote, Box::new(file), file_size).await
+    }
+}
+
+/// Name a synced file is stored under on the remote
+pub fn remote_path(path: &Path, compress: Option<ObjectCompression>) -> PathBuf {
+    match compress {
+        Some(compression) => compression.remote_path(path),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Catches uploads silently truncated by the server
+pub async fn verify_upload(
+    transport: &mut Box<dyn Transport + Send + Sync>,
+    path: &Path,
+    expected_size: u64,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let size = transport
+        .size(path)
+        .await
+        .map_err(|e| format!("verification failed with error: {e}"))?;
+    if size != expected_size {
+        return Err(format!(
+            "verification failed, remote file has {size} bytes instead of {expected_size}"
+        )
+        .into());
+    }
+    Ok(())
+}
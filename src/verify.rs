@@ -0,0 +1,228 @@
+use crate::{
+    checksum_tree::ChecksumTree,
+    hash::{self, HashAlgorithm},
+    transport::{RemoteEntry, Transport, CLOCK_PROBE_FILENAME},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Component, Path, PathBuf},
+};
+
+/// How the files on the remote compare with the checksum file, each list in
+/// the order of the paths
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// In the checksum file but not on the remote
+    pub missing: Vec<PathBuf>,
+    /// On the remote with another size than recorded, or other contents
+    pub mismatched: Vec<PathBuf>,
+    /// On the remote but not in the checksum file
+    pub extra: Vec<PathBuf>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Every file and directory on the remote below `dir`, by path
+pub async fn list_all<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    dir: &Path,
+) -> Result<HashMap<PathBuf, RemoteEntry>, Box<dyn Error + Send + Sync + 'static>> {
+    let mut listing = HashMap::new();
+    let mut to_list = vec![dir.to_path_buf()];
+    while let Some(dir) = to_list.pop() {
+        for entry in transport.list(&dir).await? {
+            if entry.is_dir {
+                to_list.push(entry.path.clone());
+            }
+            listing.insert(entry.path.clone(), entry);
+        }
+    }
+    Ok(listing)
+}
+
+/// Compares the files of `tree` with `listing` by their sizes. Files
+/// syncbox keeps for itself next to `checksum_file` are not extra
+pub fn compare(
+    tree: &ChecksumTree,
+    listing: &HashMap<PathBuf, RemoteEntry>,
+    checksum_file: &Path,
+) -> Report {
+    let mut report = Report::default();
+    for (path, entry) in tree.iter() {
+        match listing.get(&path).filter(|remote| !remote.is_dir) {
+            None => report.missing.push(path),
+            Some(remote) if entry.size.is_some_and(|size| size != remote.size) => {
+                report.mismatched.push(path)
+            }
+            Some(_) => {}
+        }
+    }
+    report.extra = listing
+        .values()
+        .filter(|remote| !remote.is_dir)
+        .filter(|remote| !tree.contains(&remote.path))
+        .filter(|remote| !is_syncbox_file(&remote.path, checksum_file))
+        .map(|remote| remote.path.clone())
+        .collect();
+    report.missing.sort();
+    report.mismatched.sort();
+    report.extra.sort();
+    report
+}
+
+/// Downloads the files of `tree` nothing was found wrong with and adds
+/// those whose contents don't match their checksum to the mismatched.
+/// Files with metadata checksums can only be compared by size. Returns how
+/// many files were hashed
+pub async fn compare_hashes<T: Transport + Send + ?Sized>(
+    transport: &mut T,
+    tree: &ChecksumTree,
+    report: &mut Report,
+) -> Result<usize, Box<dyn Error + Send + Sync + 'static>> {
+    let target = std::env::temp_dir().join(format!(
+        "syncbox-verify-{}-{}",
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+    let mut hashed = 0;
+    for (path, entry) in tree.iter() {
+        let algorithm = HashAlgorithm::of(&entry.checksum);
+        if algorithm == HashAlgorithm::Metadata
+            || report.missing.contains(&path)
+            || report.mismatched.contains(&path)
+        {
+            continue;
+        }
+        let mut reader = transport.read_stream(&path).await?;
+        let mut file = tokio::fs::File::create(&target).await?;
+        tokio::io::copy(&mut reader, &mut file).await?;
+        drop(file);
+        let checksum = algorithm.checksum(&target, &std::fs::metadata(&target)?);
+        tokio::fs::remove_file(&target).await.ok();
+        if checksum? != hash::without_permissions(&entry.checksum) {
+            report.mismatched.push(path);
+        }
+        hashed += 1;
+    }
+    report.mismatched.sort();
+    Ok(hashed)
+}
+
+/// The checksum file and what's named after it like snapshots, temporary
+/// files of atomic uploads and the clock probe
+pub fn is_syncbox_file(path: &Path, checksum_file: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    is_checksum_file(path, checksum_file)
+        || name.ends_with(".syncbox.tmp")
+        || relative(path) == Path::new(CLOCK_PROBE_FILENAME)
+}
+
+/// The checksum file, its snapshots, locks and the copies `--repair` keeps
+pub fn is_checksum_file(path: &Path, checksum_file: &Path) -> bool {
+    relative(path)
+        .to_string_lossy()
+        .starts_with(&*relative(checksum_file).to_string_lossy())
+}
+
+fn relative(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum_tree::{ChecksumElement, FileEntry},
+        transport::memory::MemoryTransport,
+    };
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn reports_missing_mismatched_and_extra_files() {
+        let mut transport = MemoryTransport::new();
+        transport.mkdir(Path::new("./dir")).await.unwrap();
+        for (path, contents) in [
+            ("./same.txt", "abc"),
+            ("./dir/grown.txt", "abcd"),
+            ("./dir/extra.txt", "abc"),
+            ("./.syncbox.json.gz", "{}"),
+            ("./.syncbox.json.gz.snapshots.json", "{}"),
+            ("./dir/.upload.syncbox.tmp", ""),
+        ] {
+            transport
+                .write(
+                    Path::new(path),
+                    Box::new(Cursor::new(contents.as_bytes().to_vec())),
+                    contents.len() as u64,
+                )
+                .await
+                .unwrap();
+        }
+        let mut tree = ChecksumTree::default();
+        for path in ["./same.txt", "./dir/grown.txt", "./gone/file.txt"] {
+            let entry = FileEntry {
+                checksum: "a".to_string(),
+                size: Some(3),
+                ..Default::default()
+            };
+            tree.insert_at(Path::new(path), ChecksumElement::File(entry));
+        }
+
+        let listing = list_all(&mut transport, Path::new(".")).await.unwrap();
+        let report = compare(&tree, &listing, Path::new(".syncbox.json.gz"));
+        assert_eq!(
+            report,
+            Report {
+                missing: vec!["./gone/file.txt".into()],
+                mismatched: vec!["./dir/grown.txt".into()],
+                extra: vec!["./dir/extra.txt".into()],
+            }
+        );
+        assert!(!report.is_clean());
+    }
+
+    #[tokio::test]
+    async fn hashes_reveal_files_changed_in_place() {
+        let local = std::env::temp_dir().join(format!("syncbox-verify-{}", std::process::id()));
+        std::fs::write(&local, "abc").unwrap();
+        let checksum = HashAlgorithm::Blake3
+            .checksum(&local, &std::fs::metadata(&local).unwrap())
+            .unwrap();
+        std::fs::remove_file(&local).unwrap();
+
+        let mut transport = MemoryTransport::new();
+        let mut tree = ChecksumTree::default();
+        for (path, contents) in [("./same.txt", "abc"), ("./edited.txt", "abd")] {
+            transport
+                .write(
+                    Path::new(path),
+                    Box::new(Cursor::new(contents.as_bytes().to_vec())),
+                    3,
+                )
+                .await
+                .unwrap();
+            let entry = FileEntry {
+                checksum: checksum.clone(),
+                size: Some(3),
+                ..Default::default()
+            };
+            tree.insert_at(Path::new(path), ChecksumElement::File(entry));
+        }
+
+        let listing = list_all(&mut transport, Path::new(".")).await.unwrap();
+        let mut report = compare(&tree, &listing, Path::new(".syncbox.json.gz"));
+        assert!(report.is_clean());
+        let hashed = compare_hashes(&mut transport, &tree, &mut report)
+            .await
+            .unwrap();
+        assert_eq!(hashed, 2);
+        assert_eq!(report.mismatched, vec![PathBuf::from("./edited.txt")]);
+    }
+}
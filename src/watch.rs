@@ -0,0 +1,98 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// Watches a handful of files for changes. Their directories are watched
+/// instead of the files themselves, as editors often save by replacing the file.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    changes: mpsc::UnboundedReceiver<PathBuf>,
+    debounce: Duration,
+}
+
+impl FileWatcher {
+    pub fn new(
+        files: &[PathBuf],
+        debounce: Duration,
+    ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        // events carry absolute paths, changes are reported as the files were given
+        let watched = files
+            .iter()
+            .map(|file| Ok((std::path::absolute(file)?, file.clone())))
+            .collect::<Result<HashMap<_, _>, std::io::Error>>()?;
+        let (tx, changes) = mpsc::unbounded_channel();
+        let lookup = watched.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if matches!(event.kind, EventKind::Access(_) | EventKind::Remove(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    if let Some(file) = lookup.get(&path) {
+                        tx.send(file.clone()).ok();
+                    }
+                }
+            })?;
+        let dirs = watched
+            .keys()
+            .filter_map(|file| file.parent())
+            .collect::<HashSet<_>>();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+            debounce,
+        })
+    }
+
+    /// Waits for the next batch of changed files, a batch ends once nothing
+    /// changed for the debounce period. Returns `None` when the watcher died.
+    pub async fn changed(&mut self) -> Option<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::from([self.changes.recv().await?]);
+        while let Ok(Some(file)) = tokio::time::timeout(self.debounce, self.changes.recv()).await {
+            changed.insert(file);
+        }
+        // a save that replaces the file may leave it missing for a moment
+        changed.retain(|file| Path::new(file).is_file());
+        Some(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_changes_of_watched_files_only() {
+        let dir = std::env::temp_dir().join(format!("syncbox-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let watched = dir.join("watched.txt");
+        let other = dir.join("other.txt");
+        std::fs::write(&watched, "a").unwrap();
+
+        let mut watcher =
+            FileWatcher::new(std::slice::from_ref(&watched), Duration::from_millis(50)).unwrap();
+        std::fs::write(&other, "b").unwrap();
+        // replaced the way editors do it
+        let temporary = dir.join(".watched.txt.swp");
+        std::fs::write(&temporary, "c").unwrap();
+        std::fs::rename(&temporary, &watched).unwrap();
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed, BTreeSet::from([watched]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
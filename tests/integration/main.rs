@@ -0,0 +1,254 @@
+//! Full sync cycles against real servers started in containers with
+//! testcontainers, run with `cargo test --features integration`. Containers
+//! are removed when a test ends, also when it fails
+
+use rusoto_s3::{CreateBucketRequest, S3Client, S3};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+use syncbox::transport::{ftp::Ftp, is_not_found, s3::AwsS3, sftp::SFtp, Transport};
+use testcontainers::{
+    core::{IntoContainerPort, WaitFor},
+    runners::AsyncRunner,
+    ContainerAsync, GenericImage, ImageExt,
+};
+
+type Remote = Box<dyn Transport + Send + Sync>;
+
+/// Local directory with a small site to sync, removed on drop
+struct Site {
+    dir: PathBuf,
+}
+
+impl Site {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("syncbox-integration-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("assets/img")).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>hello</h1>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "console.log('hello')").unwrap();
+        // above the threshold for hashing, checksummed by metadata instead
+        let logo: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("assets/img/logo.bin"), logo).unwrap();
+        Self { dir }
+    }
+
+    /// Runs the syncbox binary on the site, returns its output
+    fn sync(&self, transport: &[String]) -> String {
+        let output = Command::new(env!("CARGO_BIN_EXE_syncbox"))
+            // keeps .env files of the checkout out of the way
+            .current_dir(&self.dir)
+            .arg("--directory")
+            .arg(&self.dir)
+            .arg("--verify-uploads")
+            .args(transport)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        assert!(
+            output.status.success(),
+            "sync failed:\n{stdout}\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        stdout
+    }
+}
+
+impl Drop for Site {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Uploads the site, changes and removes a file, and checks the remote
+/// follows along, through a connection of its own
+async fn sync_cycle(name: &str, transport: &[String], mut remote: Remote) {
+    let site = Site::new(name);
+    site.sync(transport);
+    for file in ["index.html", "assets/app.js", "assets/img/logo.bin"] {
+        let size = remote.size(Path::new(file)).await.unwrap();
+        assert_eq!(size, std::fs::metadata(site.dir.join(file)).unwrap().len());
+    }
+    assert_eq!(
+        remote.read(Path::new("index.html")).await.unwrap(),
+        b"<h1>hello</h1>"
+    );
+
+    std::fs::write(site.dir.join("index.html"), "<h1>hello again</h1>").unwrap();
+    std::fs::remove_file(site.dir.join("assets/app.js")).unwrap();
+    std::fs::rename(
+        site.dir.join("assets/img/logo.bin"),
+        site.dir.join("assets/logo.bin"),
+    )
+    .unwrap();
+    site.sync(transport);
+    assert_eq!(
+        remote.read(Path::new("index.html")).await.unwrap(),
+        b"<h1>hello again</h1>"
+    );
+    for file in ["assets/app.js", "assets/img/logo.bin"] {
+        let removed = remote.size(Path::new(file)).await;
+        assert!(removed.is_err_and(|e| is_not_found(e.as_ref())));
+    }
+    let moved = remote.size(Path::new("assets/logo.bin")).await.unwrap();
+    assert_eq!(moved, 3 * 1024 * 1024);
+
+    assert!(site.sync(transport).contains("Nothing to do"));
+    remote.close().await.unwrap();
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// How long a server gets to come up before the test gives up
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn start(image: ContainerRequest) -> ContainerAsync<GenericImage> {
+    image
+        .with_startup_timeout(STARTUP_TIMEOUT)
+        .start()
+        .await
+        .expect("the integration tests need docker")
+}
+
+type ContainerRequest = testcontainers::ContainerRequest<GenericImage>;
+
+#[tokio::test]
+async fn ftp() {
+    // passive ports have to be the same inside and outside
+    let image = (21100..=21110).fold(
+        GenericImage::new("fauria/vsftpd", "latest")
+            .with_exposed_port(21.tcp())
+            // printed right before vsftpd starts
+            .with_wait_for(WaitFor::message_on_stdout("SERVER SETTINGS"))
+            .with_wait_for(WaitFor::seconds(1))
+            .with_env_var("FTP_USER", "syncbox")
+            .with_env_var("FTP_PASS", "secret")
+            .with_env_var("PASV_ADDRESS", "127.0.0.1")
+            .with_env_var("PASV_MIN_PORT", "21100")
+            .with_env_var("PASV_MAX_PORT", "21110"),
+        |image, port| image.with_mapped_port(port, port.tcp()),
+    );
+    let container = start(image).await;
+    let host = format!(
+        "127.0.0.1:{}",
+        container.get_host_port_ipv4(21).await.unwrap()
+    );
+
+    let remote = Ftp::new(&host, "syncbox", "secret", ".")
+        .connect(false)
+        .await
+        .unwrap();
+    sync_cycle(
+        "ftp",
+        &args(&[
+            "ftp",
+            "--ftp-host",
+            &host,
+            "--ftp-user",
+            "syncbox",
+            "--ftp-pass",
+            "secret",
+        ]),
+        Box::new(remote),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sftp() {
+    // the chroot itself isn't writable, files go to the upload directory
+    let container = start(
+        GenericImage::new("atmoz/sftp", "latest")
+            .with_exposed_port(22.tcp())
+            .with_wait_for(WaitFor::message_on_stderr("Server listening on"))
+            .with_cmd(["syncbox:secret:::upload"]),
+    )
+    .await;
+    let host = format!(
+        "127.0.0.1:{}",
+        container.get_host_port_ipv4(22).await.unwrap()
+    );
+
+    let remote = SFtp::new(&host, "syncbox", "secret", "upload", None)
+        .await
+        .unwrap();
+    sync_cycle(
+        "sftp",
+        &args(&[
+            "sftp", "--host", &host, "--user", "syncbox", "--pass", "secret", "--dir", "upload",
+        ]),
+        Box::new(remote),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn s3() {
+    let container = start(
+        GenericImage::new("minio/minio", "latest")
+            .with_exposed_port(9000.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("API:"))
+            .with_env_var("MINIO_ROOT_USER", "syncbox")
+            .with_env_var("MINIO_ROOT_PASSWORD", "syncbox-secret")
+            .with_cmd(["server", "/data"]),
+    )
+    .await;
+    let endpoint = format!(
+        "http://127.0.0.1:{}",
+        container.get_host_port_ipv4(9000).await.unwrap()
+    );
+    let region = rusoto_core::Region::Custom {
+        name: "us-east-1".to_string(),
+        endpoint: endpoint.clone(),
+    };
+    let client = S3Client::new_with(
+        rusoto_core::request::HttpClient::new().unwrap(),
+        rusoto_credential::StaticProvider::new_minimal(
+            "syncbox".to_string(),
+            "syncbox-secret".to_string(),
+        ),
+        region,
+    );
+    client
+        .create_bucket(CreateBucketRequest {
+            bucket: "site".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let remote = AwsS3::new(
+        "site",
+        "us-east-1",
+        "syncbox",
+        "syncbox-secret",
+        "STANDARD",
+        PathBuf::from("."),
+        Some(endpoint.clone()),
+    )
+    .unwrap();
+    sync_cycle(
+        "s3",
+        &args(&[
+            "--server-side-copy",
+            "s3",
+            "--bucket",
+            "site",
+            "--region",
+            "us-east-1",
+            "--access-key",
+            "syncbox",
+            "--secret-key",
+            "syncbox-secret",
+            "--endpoint",
+            &endpoint,
+        ]),
+        Box::new(remote),
+    )
+    .await;
+}